@@ -1,5 +1,8 @@
 use std::{fmt::Debug, collections::{HashSet, HashMap}, hash::Hash};
 
+mod tree;
+pub use tree::*;
+
 /// Should be a sum type
 pub trait Entity<Id, Input, State, Res>: Sized where
   Id: Sized + Hash + Eq + Debug,
@@ -114,6 +117,66 @@ pub trait Hierarchy<Id, E, Input, FullInput, InterInput, State, Res, FullRes, Re
   }
 
 
+  /// Parent/child links of the entities, `None` for a flat hierarchy. The tree holds ids only,
+  /// the entities stay in the map of `represent`.
+  fn tree(&self) -> Option<&Tree<Id>> {
+    None
+  }
+
+  fn tree_mut(&mut self) -> Option<&mut Tree<Id>> {
+    None
+  }
+
+  /// Adds `entity` as the last child of `parent`, or as a root if `parent` is `None`. A flat
+  /// hierarchy ignores `parent`.
+  fn insert_entity(&mut self, entity: E, parent: Option<&Id>) -> Result<(), TreeError<Id>> where Id: Clone {
+    let id = entity.get_id();
+    if let Some(tree) = self.tree_mut() {
+      tree.insert(id.clone(), parent)?;
+    }
+    let (_, interior_entities) = self.represent_mut(InteriorKind::None, InteriorKind::AsMut);
+    interior_entities.to_mut().unwrap().insert(id, entity);
+    Ok(())
+  }
+
+  /// Removes the entity with given `id` and its descendants and returns them, children before
+  /// their parents. A flat hierarchy only removes `id`.
+  fn remove_subtree(&mut self, id: &Id) -> Vec<E> where Id: Clone {
+    let ids = match self.tree_mut() {
+      Some(tree) => tree.remove_subtree(id),
+      None => vec![id.clone()],
+    };
+    let (_, interior_entities) = self.represent_mut(InteriorKind::None, InteriorKind::AsMut);
+    let entities = interior_entities.to_mut().unwrap();
+    ids.iter().filter_map(|id| entities.remove(id)).collect()
+  }
+
+  /// Like `map_entity_set` for the subtree of `from`, or all entities if `from` is `None`, in
+  /// `order`. Entities missing in the tree aren't mapped. A flat hierarchy maps `from` or all
+  /// entities in any order.
+  fn map_entity_order<F>(&mut self, from: Option<&Id>, order: Order, input: &Input, f: F) -> Vec<Res>
+    where F: Fn(&mut E, &State, &Input) -> Option<Res>, Id: Clone
+  {
+    let ids = match self.tree() {
+      Some(tree) => tree.traverse(from, order),
+      None => return self.map_entity_set(&from.map(|id| HashSet::from([id.clone()])), input, f),
+    };
+    map_entity_route(self, &ids, input, |e, state, input| (f(e, state, input), Flow::Continue))
+  }
+
+  /// Passes `input` to the entities on the route of an event for `target`, see `Tree::route`,
+  /// until `f` answers `Flow::Stop`. Returns the results in the order of the route. A flat
+  /// hierarchy only passes it to `target`.
+  fn route_event<F>(&mut self, target: &Id, propagation: Propagation, input: &Input, f: F) -> Vec<Res>
+    where F: Fn(&mut E, &State, &Input) -> (Option<Res>, Flow), Id: Clone
+  {
+    let route = match self.tree() {
+      Some(tree) => tree.route(target, propagation),
+      None => vec![target.clone()],
+    };
+    map_entity_route(self, &route, input, f)
+  }
+
   /// Tries to access entity with given `id`. Fails if entity can't be represented as mutable or
   /// if `id` wasn't found.
   fn access_entity<'a, 'b: 'a, 'c: 'a>(&'c mut self, id: &'b Id) -> Option<&'a mut E> {
@@ -138,6 +201,30 @@ pub trait Hierarchy<Id, E, Input, FullInput, InterInput, State, Res, FullRes, Re
 
 }
 
+/// Applies `f` to the entities with the ids in `route` in that order until it answers
+/// `Flow::Stop`, see `Hierarchy::route_event`
+fn map_entity_route<H, Id, E, Input, FullInput, InterInput, State, Res, FullRes, ResErr, F>(this: &mut H, route: &[Id], input: &Input, f: F) -> Vec<Res>
+  where H: Hierarchy<Id, E, Input, FullInput, InterInput, State, Res, FullRes, ResErr>, E: Entity<Id, Input, State, Res>,
+    Id: Sized + Hash + Eq + Debug, F: Fn(&mut E, &State, &Input) -> (Option<Res>, Flow)
+{
+  let (interior_state, interior_entities) = this.represent_mut(InteriorKind::AsRef, InteriorKind::AsMut);
+  let (state, entities) = (interior_state.as_ref().unwrap(), interior_entities.to_mut().unwrap());
+
+  let mut es = std::mem::take(entities);
+  let mut results = Vec::with_capacity(route.len());
+  for id in route {
+    if let Some(e) = es.get_mut(id) {
+      let (res, flow) = f(e, state, input);
+      results.extend(res);
+      if flow == Flow::Stop {
+        break;
+      }
+    }
+  }
+  *entities = es;
+  results
+}
+
 /// Default implementation for Hierarchy<Id, E, Input, Input, State, Res, (), ResErr>::run
 pub fn run_hierarchy_default<H, Id, E, Input, State, Res, ResErr>(this: &mut H, ids: Option<HashSet<Id>>, input: Input) -> Result<(), ResErr>
   where H : Hierarchy<Id, E, Input, Input, Input, State, Res, (), ResErr>, E: Entity<Id, Input, State, Res>, Id: Sized + Hash + Eq + Debug
@@ -0,0 +1,221 @@
+use std::{collections::{HashMap, VecDeque}, hash::Hash};
+
+/// Parent/child links between the ids of a hierarchy's entities, e.g. dialogs owned by a window
+/// or effects on a clip on a track. Roots and children keep the order they were added in.
+#[derive(Clone, Debug)]
+pub struct Tree<Id> {
+  nodes: HashMap<Id, Node<Id>>,
+  roots: Vec<Id>,
+}
+
+#[derive(Clone, Debug)]
+struct Node<Id> {
+  parent: Option<Id>,
+  children: Vec<Id>,
+}
+
+/// Order in which `Tree::traverse` visits a subtree
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Order {
+  /// Parents before their children, depth first
+  PreOrder,
+  /// Children before their parents, depth first, e.g. to tear a subtree down
+  PostOrder,
+  /// Level by level
+  BreadthFirst,
+}
+
+/// Path of an event through the ancestors of its target, see `Tree::route`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Propagation {
+  /// From the target up to its root, e.g. a key press the focused clip doesn't handle goes on to
+  /// its track
+  Bubble,
+  /// From the root down to the target, e.g. for a window to intercept the events of its dialogs
+  Capture,
+  /// Only the target
+  Target,
+}
+
+/// Whether an event goes on along its route, see `Hierarchy::route_event`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Flow {
+  Continue,
+  /// The event was handled, the rest of the route doesn't get it
+  Stop,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TreeError<Id> {
+  /// The id is in the tree already
+  Duplicate(Id),
+  /// The id isn't in the tree
+  NotFound(Id),
+  /// The new parent is the node itself or one of its descendants
+  Cycle(Id),
+}
+
+
+impl<Id> Tree<Id> where Id: Clone + Hash + Eq {
+  pub fn new() -> Self {
+    Self { nodes: HashMap::new(), roots: Vec::new() }
+  }
+
+  pub fn len(&self) -> usize {
+    self.nodes.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.nodes.is_empty()
+  }
+
+  pub fn contains(&self, id: &Id) -> bool {
+    self.nodes.contains_key(id)
+  }
+
+  /// Adds `id` as the last child of `parent`, or as the last root if `parent` is `None`
+  pub fn insert(&mut self, id: Id, parent: Option<&Id>) -> Result<(), TreeError<Id>> {
+    if self.contains(&id) {
+      return Err(TreeError::Duplicate(id));
+    }
+    match parent {
+      Some(parent) => match self.nodes.get_mut(parent) {
+        Some(node) => node.children.push(id.clone()),
+        None => return Err(TreeError::NotFound(parent.clone())),
+      },
+      None => self.roots.push(id.clone()),
+    }
+    self.nodes.insert(id, Node { parent: parent.cloned(), children: Vec::new() });
+    Ok(())
+  }
+
+  /// Moves `id` with its subtree below `parent`, or makes it a root
+  pub fn set_parent(&mut self, id: &Id, parent: Option<&Id>) -> Result<(), TreeError<Id>> {
+    if !self.contains(id) {
+      return Err(TreeError::NotFound(id.clone()));
+    }
+    if let Some(parent) = parent {
+      if !self.contains(parent) {
+        return Err(TreeError::NotFound(parent.clone()));
+      }
+      if parent == id || self.ancestors(parent).any(|ancestor| ancestor == id) {
+        return Err(TreeError::Cycle(parent.clone()));
+      }
+    }
+    self.unlink(id);
+    match parent {
+      Some(parent) => self.nodes.get_mut(parent).unwrap().children.push(id.clone()),
+      None => self.roots.push(id.clone()),
+    }
+    self.nodes.get_mut(id).unwrap().parent = parent.cloned();
+    Ok(())
+  }
+
+  /// Removes `id` and its descendants and returns them, children before their parents. Empty if
+  /// `id` isn't in the tree.
+  pub fn remove_subtree(&mut self, id: &Id) -> Vec<Id> {
+    if !self.contains(id) {
+      return Vec::new();
+    }
+    self.unlink(id);
+    let removed = self.traverse(Some(id), Order::PostOrder);
+    for id in removed.iter() {
+      self.nodes.remove(id);
+    }
+    removed
+  }
+
+  /// Removes `id` from the children of its parent or from the roots
+  fn unlink(&mut self, id: &Id) {
+    let siblings = match self.nodes[id].parent.clone() {
+      Some(parent) => &mut self.nodes.get_mut(&parent).unwrap().children,
+      None => &mut self.roots,
+    };
+    siblings.retain(|sibling| sibling != id);
+  }
+
+  pub fn parent(&self, id: &Id) -> Option<&Id> {
+    self.nodes.get(id)?.parent.as_ref()
+  }
+
+  /// Empty if `id` isn't in the tree
+  pub fn children(&self, id: &Id) -> &[Id] {
+    self.nodes.get(id).map_or(&[], |node| &node.children)
+  }
+
+  pub fn roots(&self) -> &[Id] {
+    &self.roots
+  }
+
+  /// The parent of `id`, its parent and so on up to the root
+  pub fn ancestors<'a>(&'a self, id: &'a Id) -> impl Iterator<Item = &'a Id> + 'a {
+    std::iter::successors(self.parent(id), |&id| self.parent(id))
+  }
+
+  /// 0 for roots, `None` if `id` isn't in the tree
+  pub fn depth(&self, id: &Id) -> Option<usize> {
+    self.contains(id).then(|| self.ancestors(id).count())
+  }
+
+  /// The subtree of `from`, or all trees if `from` is `None`, in `order`. Empty if `from` isn't in
+  /// the tree.
+  pub fn traverse(&self, from: Option<&Id>, order: Order) -> Vec<Id> {
+    let starts = match from {
+      Some(id) if self.contains(id) => std::slice::from_ref(id),
+      Some(_) => return Vec::new(),
+      None => &self.roots[..],
+    };
+    let mut res = Vec::new();
+    match order {
+      Order::PreOrder | Order::PostOrder => {
+        // Nodes with whether their children were pushed already
+        let mut stack: Vec<_> = starts.iter().rev().map(|id| (id, false)).collect();
+        while let Some((id, expanded)) = stack.pop() {
+          if expanded {
+            res.push(id.clone());
+            continue;
+          }
+          if order == Order::PreOrder {
+            res.push(id.clone());
+          } else {
+            stack.push((id, true));
+          }
+          stack.extend(self.children(id).iter().rev().map(|child| (child, false)));
+        }
+      },
+      Order::BreadthFirst => {
+        let mut queue: VecDeque<_> = starts.iter().collect();
+        while let Some(id) = queue.pop_front() {
+          res.push(id.clone());
+          queue.extend(self.children(id));
+        }
+      },
+    }
+    res
+  }
+
+  /// The entities an event for `target` passes, in order. Empty if `target` isn't in the tree.
+  pub fn route(&self, target: &Id, propagation: Propagation) -> Vec<Id> {
+    if !self.contains(target) {
+      return Vec::new();
+    }
+    let mut route = vec![target.clone()];
+    match propagation {
+      Propagation::Bubble => route.extend(self.ancestors(target).cloned()),
+      Propagation::Capture => {
+        route.extend(self.ancestors(target).cloned());
+        route.reverse();
+      },
+      Propagation::Target => (),
+    }
+    route
+  }
+}
+
+
+// Derived, this would require `Id: Default`
+impl<Id> Default for Tree<Id> where Id: Clone + Hash + Eq {
+  fn default() -> Self {
+    Self::new()
+  }
+}
@@ -65,7 +65,7 @@ impl Entity<usize, f64, (), f64> for X {
 }
 
 
-impl Hierarchy<usize, X, f64, f64, (), f64, (), ()> for HX {
+impl Hierarchy<usize, X, f64, f64, f64, (), f64, (), ()> for HX {
   fn represent(&self, _state_kind: InteriorKind, _entities_kind: InteriorKind) -> (InteriorRef<()>, InteriorRef<HashMap<usize, X>>) {
     (InteriorRef::Owning(()), InteriorRef::AsRef(&self.entities))
   }
@@ -74,7 +74,7 @@ impl Hierarchy<usize, X, f64, f64, (), f64, (), ()> for HX {
     (InteriorRef::Owning(()), InteriorRef::AsMut(&mut self.entities))
   }
 
-  fn accumulate_results(&mut self, _results: Vec<f64>) -> Result<Option<(Option<HashSet<usize>>, f64)>, ()> {
+  fn accumulate_results(&mut self, _results: Vec<f64>, _input: f64) -> Result<Option<(Option<HashSet<usize>>, f64)>, ()> {
     Ok(None)
   }
 
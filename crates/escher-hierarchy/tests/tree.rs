@@ -0,0 +1,157 @@
+use std::collections::{HashMap, HashSet};
+
+use escher_hierarchy::*;
+
+/// Effects on clips on tracks, like the timeline
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Id {
+  Track(u32),
+  Clip(u32),
+  Effect(u32),
+}
+
+struct Item {
+  id: Id,
+  /// Stops the events it gets
+  handles: bool,
+}
+
+#[derive(Default)]
+struct Timeline {
+  tree: Tree<Id>,
+  entities: HashMap<Id, Item>,
+}
+
+
+/// Track 0 with clip 0, which has effects 0 and 1, and clip 1. Track 1 with clip 2.
+fn timeline() -> Timeline {
+  let mut timeline = Timeline::default();
+  let items = [
+    (Id::Track(0), None),
+    (Id::Clip(0), Some(Id::Track(0))),
+    (Id::Effect(0), Some(Id::Clip(0))),
+    (Id::Effect(1), Some(Id::Clip(0))),
+    (Id::Clip(1), Some(Id::Track(0))),
+    (Id::Track(1), None),
+    (Id::Clip(2), Some(Id::Track(1))),
+  ];
+  for (id, parent) in items {
+    timeline.insert_entity(Item { id, handles: false }, parent.as_ref()).unwrap();
+  }
+  timeline
+}
+
+/// Every entity answers with its id
+fn run(timeline: &mut Timeline, from: Option<&Id>, order: Order) -> Vec<Id> {
+  timeline.map_entity_order(from, order, &(), |item, _, _| item.run(&(), &()))
+}
+
+fn route(timeline: &mut Timeline, target: Id, propagation: Propagation) -> Vec<Id> {
+  timeline.route_event(&target, propagation, &(), |item, _, _| {
+    (item.run(&(), &()), if item.handles { Flow::Stop } else { Flow::Continue })
+  })
+}
+
+
+impl Entity<Id, (), (), Id> for Item {
+  fn get_id(&self) -> Id {
+    self.id
+  }
+
+  fn run(&mut self, _state: &(), _input: &()) -> Option<Id> {
+    Some(self.id)
+  }
+}
+
+impl Hierarchy<Id, Item, (), (), (), (), Id, (), ()> for Timeline {
+  fn represent(&self, _state_kind: InteriorKind, _entities_kind: InteriorKind) -> (InteriorRef<'_, ()>, InteriorRef<'_, HashMap<Id, Item>>) {
+    (InteriorRef::Owning(()), InteriorRef::AsRef(&self.entities))
+  }
+
+  fn represent_mut<'a, 'b, 'c: 'a + 'b>(&'c mut self, _state_kind: InteriorKind, _entities_kind: InteriorKind) -> (InteriorRef<'a, ()>, InteriorRef<'b, HashMap<Id, Item>>) {
+    (InteriorRef::Owning(()), InteriorRef::AsMut(&mut self.entities))
+  }
+
+  fn tree(&self) -> Option<&Tree<Id>> {
+    Some(&self.tree)
+  }
+
+  fn tree_mut(&mut self) -> Option<&mut Tree<Id>> {
+    Some(&mut self.tree)
+  }
+
+  fn accumulate_results(&mut self, _results: Vec<Id>, _input: ()) -> Result<Option<(Option<HashSet<Id>>, ())>, ()> {
+    Ok(None)
+  }
+
+  fn run(&mut self, ids: Option<HashSet<Id>>, input: ()) -> Result<(), ()> {
+    run_hierarchy_default(self, ids, input)
+  }
+}
+
+
+#[test]
+fn traversals_visit_in_order() {
+  use Id::*;
+  let mut timeline = timeline();
+  assert_eq!(run(&mut timeline, None, Order::PreOrder), vec![Track(0), Clip(0), Effect(0), Effect(1), Clip(1), Track(1), Clip(2)]);
+  assert_eq!(run(&mut timeline, None, Order::PostOrder), vec![Effect(0), Effect(1), Clip(0), Clip(1), Track(0), Clip(2), Track(1)]);
+  assert_eq!(run(&mut timeline, None, Order::BreadthFirst), vec![Track(0), Track(1), Clip(0), Clip(1), Clip(2), Effect(0), Effect(1)]);
+  assert_eq!(run(&mut timeline, Some(&Clip(0)), Order::PreOrder), vec![Clip(0), Effect(0), Effect(1)]);
+  assert!(run(&mut timeline, Some(&Clip(7)), Order::PreOrder).is_empty());
+}
+
+#[test]
+fn events_bubble_and_capture_through_ancestors() {
+  use Id::*;
+  let mut timeline = timeline();
+  assert_eq!(route(&mut timeline, Effect(1), Propagation::Bubble), vec![Effect(1), Clip(0), Track(0)]);
+  assert_eq!(route(&mut timeline, Effect(1), Propagation::Capture), vec![Track(0), Clip(0), Effect(1)]);
+  assert_eq!(route(&mut timeline, Effect(1), Propagation::Target), vec![Effect(1)]);
+  // The clip handles the event, its track doesn't get it
+  timeline.access_entity(&Clip(0)).unwrap().handles = true;
+  assert_eq!(route(&mut timeline, Effect(1), Propagation::Bubble), vec![Effect(1), Clip(0)]);
+  assert_eq!(route(&mut timeline, Effect(1), Propagation::Capture), vec![Track(0), Clip(0)]);
+}
+
+#[test]
+fn removing_a_parent_removes_its_subtree() {
+  use Id::*;
+  let mut timeline = timeline();
+  let removed: Vec<_> = timeline.remove_subtree(&Clip(0)).into_iter().map(|item| item.id).collect();
+  assert_eq!(removed, vec![Effect(0), Effect(1), Clip(0)]);
+  assert_eq!(timeline.tree.children(&Track(0)), &[Clip(1)]);
+  assert_eq!(timeline.tree.len(), 4);
+  assert_eq!(timeline.entities.len(), 4);
+  assert!(timeline.remove_subtree(&Effect(0)).is_empty());
+  // Roots go with their subtree as well
+  timeline.remove_subtree(&Track(1));
+  assert_eq!(timeline.tree.roots(), &[Track(0)]);
+  assert_eq!(run(&mut timeline, None, Order::PreOrder), vec![Track(0), Clip(1)]);
+}
+
+#[test]
+fn nodes_move_between_parents() {
+  use Id::*;
+  let mut timeline = timeline();
+  timeline.tree.set_parent(&Clip(1), Some(&Track(1))).unwrap();
+  assert_eq!(timeline.tree.children(&Track(0)), &[Clip(0)]);
+  assert_eq!(timeline.tree.children(&Track(1)), &[Clip(2), Clip(1)]);
+  assert_eq!(timeline.tree.parent(&Clip(1)), Some(&Track(1)));
+  assert_eq!(timeline.tree.depth(&Effect(0)), Some(2));
+  assert_eq!(timeline.tree.set_parent(&Track(0), Some(&Effect(0))), Err(TreeError::Cycle(Effect(0))));
+  assert_eq!(timeline.tree.set_parent(&Clip(0), Some(&Clip(0))), Err(TreeError::Cycle(Clip(0))));
+  timeline.tree.set_parent(&Clip(0), None).unwrap();
+  assert_eq!(timeline.tree.roots(), &[Track(0), Track(1), Clip(0)]);
+}
+
+#[test]
+fn inserts_need_a_new_id_and_an_existing_parent() {
+  use Id::*;
+  let mut timeline = timeline();
+  let duplicate = timeline.insert_entity(Item { id: Clip(0), handles: false }, None);
+  assert_eq!(duplicate, Err(TreeError::Duplicate(Clip(0))));
+  let orphan = timeline.insert_entity(Item { id: Effect(5), handles: false }, Some(&Clip(9)));
+  assert_eq!(orphan, Err(TreeError::NotFound(Clip(9))));
+  assert_eq!(timeline.entities.len(), 7);
+}
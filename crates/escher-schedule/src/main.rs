@@ -1,5 +1,3 @@
-use std::sync::mpsc;
-
 use escher_schedule::*;
 
 fn main() {
@@ -31,7 +29,6 @@ fn test_split_last_iter() {
 enum DummyReq {
   Text(&'static str),
   Idx,
-  Kill,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -41,23 +38,11 @@ enum DummyMsg {
 }
 
 fn test_schedule() {
-  let mut scheduler = Scheduler::new(1, DummyReq::Kill, |worker_idx| {
-    let worker = move |request: DummyReq, kind: RequestKind, pub_tx: &mut CallbackSender<DummyMsg>| {
-      match kind {
-        RequestKind::Plain => (),
-        RequestKind::Once(m) => {
-          let mut guard = m.lock().unwrap();
-          if *guard {
-            *guard = false;
-          } else {
-            return Response::Ok(());
-          }
-        },
-      }
+  let mut scheduler = Scheduler::new(1, |worker_idx| {
+    let worker = move |request: DummyReq, _kind: RequestKind, pub_tx: &mut CallbackSender<DummyMsg>| {
       match request {
         DummyReq::Text(s) => println!("{}", s),
         DummyReq::Idx => pub_tx.send(&DummyMsg::Idx(worker_idx)),
-        DummyReq::Kill => println!("Killing worker-thread {} requested", worker_idx),
       }
       Response::Ok(())
     };
@@ -69,11 +54,12 @@ fn test_schedule() {
   scheduler.request(DummyReq::Text("2"), BroadcastKind::MulipleTimes(2)).unwrap();
   scheduler.request(DummyReq::Text("3"), BroadcastKind::MulipleTimes(3)).unwrap();
 
-  fn handle_respones(_resp: Response<()>, _tx: &mpsc::Sender<(RequestKind, DummyReq)>) -> Result<(), ()> {
+  fn handle_respones(_resp: Response<()>, _tx: &WorkerSender<DummyReq, ()>) -> Result<(), ()> {
     Ok(())
   }
   scheduler.handle_respones(handle_respones).unwrap();
-  // scheduler.kill_all_workers(false).unwrap();
-  scheduler.kill_all_workers(true).unwrap();
+  // scheduler.shutdown(Shutdown::Abort);
+  scheduler.shutdown(Shutdown::Drain);
+  scheduler.join(std::time::Duration::from_secs(1));
 }
 
@@ -1,34 +1,210 @@
-use std::{sync::{Arc, Mutex, mpsc}, thread::JoinHandle, fmt::Debug};
+use std::{
+  any::Any,
+  cell::RefCell,
+  cmp,
+  collections::{BinaryHeap, HashMap},
+  fmt::Debug,
+  panic::{self, AssertUnwindSafe},
+  ops::Range,
+  sync::{Arc, Mutex, RwLock, mpsc, atomic::{AtomicBool, AtomicU8, AtomicU64, AtomicUsize, Ordering}},
+  task::Waker,
+  thread::{self, JoinHandle},
+  time::{Duration, Instant},
+};
 
-// mod callback;
-// mod multi_callback;
-mod channel;
+mod bus;
+#[cfg(feature = "async")]
+mod future;
+mod metrics;
 // mod transform;
-// pub use callback::ScheduleCallback;
-// pub use multi_callback::ScheduleCallbackCollection;
-pub use channel::{CallbackMessage, CallbackSender, CallbackReceiver};
+pub use bus::{Backpressure, Bus, CallbackChannel, CallbackMessage, CallbackSender, CallbackReceiver, ChannelOptions, Subscription};
+#[cfg(feature = "async")]
+pub use future::RequestFuture;
+pub use metrics::{KindMetrics, SchedulerMetrics, WorkerMetrics, registered_metrics};
+use metrics::{Metrics, MetricsSource, Outcome};
 
 pub enum RequestKind {
   Plain,
-  /// Value indicates whether request still has to be fulfilled. So a worker would try to lock the
-  /// mutex. If that's successful and the value is `true`, that worker is now tasked with the request
-  /// and has to set the value to `false` and immediately unlock the mutex again. If the value is already `false`
-  /// then the task is already assigned to another worker and can be ignored.
-  Once(Arc<Mutex<bool>>),
+  /// Sent with `Scheduler::request_latest`. Workers skip the request if a newer one of the same
+  /// group was sent in the meantime, long running requests may check `is_superseded` and stop early.
+  Latest { group: Coalesce, generation: u64 },
 }
-pub enum Response<T> {
+
+/// A group of requests of which only the most recent one matters, e.g. seeks while scrubbing
+#[derive(Clone, Debug, Default)]
+pub struct Coalesce(Arc<AtomicU64>);
+
+/// The job each worker is handling, by worker index. Workers register the job they run, so the
+/// job of a worker that panicked can be failed when its `Response::WorkerDied` arrives.
+#[derive(Debug)]
+pub struct WorkerJobs<J>(Arc<Mutex<HashMap<usize, J>>>);
+
+pub enum Response<T, E = ()> {
   Init,
   Ok(T),
   // Pong(usize),
   Public(T),
   Ready(bool),
+  /// The request failed, resolves the request's handle with `HandleError::Failed`
+  Err(E),
+  /// The worker panicked and its thread stopped, `payload` is what the panic was called with.
+  /// Sent by the scheduler, not by workers.
+  WorkerDied { worker: usize, payload: Box<dyn Any + Send> },
+}
+
+/// Order in which a worker takes queued requests. Requests of the same priority are taken in
+/// the order they were sent.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+  /// E.g. exports and proxies
+  Background,
+  /// E.g. thumbnails and decoding ahead
+  Prefetch,
+  /// E.g. the frame the viewer waits for
+  #[default]
+  Interactive,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RequestOptions {
+  pub priority: Priority,
+  /// Workers drop the request if they get to it after the deadline
+  pub deadline: Option<Instant>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TicketStatus {
+  Queued,
+  Running,
+  Done,
+  Cancelled,
+  /// The deadline passed before a worker got to the request
+  Expired,
+  /// The worker answered with `Response::Err` or panicked
+  Failed,
+}
+
+/// Handle of a sent request. Cancelling it drops the request if no worker took it yet, workers
+/// handling it may check `current_ticket` and stop early. Requests sent to several workers share
+/// the ticket.
+#[derive(Clone, Debug)]
+pub struct Ticket {
+  id: u64,
+  state: Arc<TicketState>,
+}
+
+#[derive(Debug)]
+struct TicketState {
+  cancelled: AtomicBool,
+  status: AtomicU8,
+  sent: Instant,
+}
+
+/// Why a `RequestHandle` resolved without a result
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HandleError<E = ()> {
+  /// The worker answered with `Response::Err`
+  Failed(E),
+  Cancelled,
+  /// The deadline passed before a worker got to the request
+  Expired,
+  /// The worker panicked or stopped without answering, or the result was already taken
+  WorkerDied,
+  /// `RequestHandle::wait` timed out, the request may still finish
+  Timeout,
+}
+
+/// Resolves with the value of the response to one request, see `Scheduler::request_handle`.
+/// The value goes to the handle instead of `Scheduler::handle_respones`.
+pub struct RequestHandle<T, E = ()> {
+  ticket: Ticket,
+  rx: mpsc::Receiver<Result<T, HandleError<E>>>,
+  #[cfg_attr(not(feature = "async"), allow(dead_code))]
+  wakeup: Wakeup,
+}
+
+/// Sends the result of a request to its `RequestHandle`
+pub struct Reply<T, E = ()> {
+  // Only taken on drop, so that the handle sees the disconnect when it's woken
+  tx: Option<mpsc::Sender<Result<T, HandleError<E>>>>,
+  wakeup: Wakeup,
+}
+
+/// The task waiting for a result or a message if it is polled as a future or a stream, and a
+/// waker that is woken on every one, e.g. to wake an event loop
+#[derive(Clone, Default)]
+struct Wakeup {
+  task: Arc<Mutex<Option<Waker>>>,
+  listener: Arc<Mutex<Option<Waker>>>,
+}
+
+/// A request as it is queued for a worker
+pub struct Queued<Req, T, E = ()> {
+  pub kind: RequestKind,
+  pub request: Req,
+  pub ticket: Ticket,
+  pub options: RequestOptions,
+  /// Where the value of the response goes if the request was sent with a handle
+  pub reply: Option<Reply<T, E>>,
+}
+
+/// `Queued` in the order a worker takes it
+struct Pending<Req, T, E> {
+  queued: Queued<Req, T, E>,
+  seq: u64,
+}
+
+/// What a worker thread receives
+enum Message<Req, T, E> {
+  Request(Queued<Req, T, E>),
+  /// A request was queued on one of the `WorkQueues`
+  Wake,
+  /// Out of band, ahead of the requests
+  Stop(Shutdown),
+}
+
+/// How workers stop, see `Scheduler::shutdown`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Shutdown {
+  /// Handle the requests queued so far, then stop
+  #[default]
+  Drain,
+  /// Stop after the request being handled, the queued ones are cancelled
+  Abort,
+}
+
+/// Sends requests to one worker, e.g. from a response handler
+pub struct WorkerSender<Req, T, E = ()>(mpsc::Sender<Message<Req, T, E>>);
+
+/// Requests any worker may handle, see `BroadcastKind::Any`. Every worker has a queue it takes
+/// from first, idle workers steal from the others' queues. A request is popped exactly once.
+struct WorkQueues<Req, T, E> {
+  /// One per worker ever spawned, workers are never reindexed
+  queues: RwLock<Vec<WorkQueue<Req, T, E>>>,
+  next_seq: AtomicU64,
+  metrics: Metrics,
+  /// Labels requests in `metrics`, see `Scheduler::register_metrics`
+  kind_label: RwLock<fn(&Req) -> &'static str>,
+}
+
+struct WorkQueue<Req, T, E> {
+  pending: Mutex<BinaryHeap<Pending<Req, T, E>>>,
+  /// Whether the worker is handling a request
+  busy: AtomicBool,
+  /// Requests the worker received for itself and didn't take yet
+  local: AtomicUsize,
 }
 
 pub enum BroadcastKind {
   Specific(usize),
   All,
+  /// Once, on the least busy worker. Other workers steal the request if they get free first.
+  Any,
+  /// Like `Any`, fails with `RequestError::NoReadyWorkers` if every worker is busy
   DoNow,
+  /// Like `DoNow`, but cancels the request instead of failing
   TryNow,
+  /// `Any` n times
   MulipleTimes(usize),
 }
 
@@ -40,241 +216,1154 @@ pub enum RequestError<T> where T:Debug {
   NoReadyWorkers(T)
 }
 
-pub struct Scheduler<Req, T> where Req: Debug + Clone {
-  workers: Vec<Option<WorkerHandle<Req, T>>>,
-  kill_request: Req,
+/// Order in which the workers of a `Scheduler::new_inline` scheduler take turns
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Interleaving {
+  /// One request per worker in the order of their indices
+  #[default]
+  RoundRobin,
+  /// The next worker is picked by a generator seeded with the value, e.g. to explore races.
+  /// The same seed gives the same order.
+  Seeded(u64),
+}
+
+pub struct Scheduler<Req, T, E = ()> where Req: Debug + Clone {
+  workers: Vec<Option<WorkerHandle<Req, T, E>>>,
+  work: Arc<WorkQueues<Req, T, E>>,
+  /// Spawns worker `idx`
+  spawn: Box<dyn Fn(usize) -> WorkerHandle<Req, T, E> + Send>,
+  next_ticket: AtomicU64,
+  /// Woken by the workers after each response, see `set_waker`
+  wakeup: Wakeup,
+  /// Whose turn it is, only for inline workers
+  turns: Option<Mutex<Turns>>,
+  /// Replace workers that panic, see `set_respawn`
+  respawn: bool,
   // callback: ScheduleCallback<U>,
   // weak_ref: Weak<Self>,
   // next_ping_id: usize, //ping pong was a nonsene idea
 }
 
-struct WorkerHandle<Request, T> where Request: Debug {
-  tx: mpsc::Sender<(RequestKind, Request)>,
-  rx: mpsc::Receiver<Response<T>>,
+struct WorkerHandle<Request, T, E> where Request: Debug {
+  tx: WorkerSender<Request, T, E>,
+  rx: mpsc::Receiver<Response<T, E>>,
   // pub callback_sender: CallbackSender<U>,
-  is_ready: bool,
-  thread: JoinHandle<()>
+  runner: Runner,
+  /// Told to stop, gets no more requests
+  stopping: bool,
+  /// Answered `Response::Init`, i.e. its init function didn't panic
+  initialised: bool,
+}
+
+/// Where a worker runs
+enum Runner {
+  Thread(JoinHandle<()>),
+  /// On the thread that calls `Scheduler::step`
+  Inline(Mutex<Box<dyn RunInline>>),
+}
+
+struct Turns {
+  interleaving: Interleaving,
+  /// The worker that took the last turn
+  last: Option<usize>,
+  /// State of the generator of `Interleaving::Seeded`
+  rng: u64,
+}
+
+/// The loop of a worker, one request per `step`
+struct WorkerLoop<Req, T, E, W, U> {
+  idx: usize,
+  worker: W,
+  pub_tx: CallbackSender<U>,
+  r_req: mpsc::Receiver<Message<Req, T, E>>,
+  t_resp: mpsc::Sender<Response<T, E>>,
+  work: Arc<WorkQueues<Req, T, E>>,
+  wakeup: Wakeup,
+  /// The requests sent to this worker
+  pending: BinaryHeap<Pending<Req, T, E>>,
+  seq: u64,
+  stop: Option<Shutdown>,
+}
+
+/// What a `WorkerLoop::step` did
+enum Step {
+  /// Took a request, handled or dropped it
+  Busy,
+  /// Nothing to do until the next message
+  Idle,
+  Stopped,
+}
+
+/// A worker run by `Scheduler::step`
+struct InlineWorker<Req, T, E, W, U> {
+  /// `None` once stopped
+  worker: Option<WorkerLoop<Req, T, E, W, U>>,
+  /// Whether `Response::Ready(true)` was sent last
+  idle: bool,
+}
+
+/// `InlineWorker` without its types
+trait RunInline: Send {
+  /// Whether stepping the worker makes progress
+  fn is_runnable(&mut self) -> bool;
+  fn step(&mut self);
+  fn is_finished(&self) -> bool;
+}
+
+
+thread_local! {
+  /// Ticket of the request the worker on this thread is handling
+  static CURRENT_TICKET: RefCell<Option<Ticket>> = const { RefCell::new(None) };
+}
+
+
+/// Ticket of the request being handled, for workers. `None` outside of `Worker::handle`.
+pub fn current_ticket() -> Option<Ticket> {
+  CURRENT_TICKET.with(|ticket| ticket.borrow().clone())
+}
+
+/// Whether the request being handled was cancelled, for workers that want to stop early
+pub fn is_cancelled() -> bool {
+  current_ticket().is_some_and(|ticket| ticket.is_cancelled())
+}
+
+/// The message of a panic payload, see `Response::WorkerDied`
+pub fn panic_message(payload: &(dyn Any + Send)) -> &str {
+  match payload.downcast_ref::<&str>() {
+    Some(msg) => msg,
+    None => match payload.downcast_ref::<String>() {
+      Some(msg) => msg,
+      None => "Box<dyn Any>",
+    },
+  }
+}
+
+
+impl RequestOptions {
+  pub fn new(priority: Priority) -> Self {
+    Self { priority, deadline: None }
+  }
+
+  pub fn set_deadline(mut self, deadline: Instant) -> Self {
+    self.deadline = Some(deadline);
+    self
+  }
+
+  /// Deadline `timeout` from now
+  pub fn set_timeout(self, timeout: Duration) -> Self {
+    self.set_deadline(Instant::now() + timeout)
+  }
+
+  fn is_expired(&self) -> bool {
+    self.deadline.is_some_and(|deadline| Instant::now() > deadline)
+  }
+}
+
+impl TicketStatus {
+  fn from_u8(value: u8) -> Self {
+    match value {
+      0 => Self::Queued,
+      1 => Self::Running,
+      2 => Self::Done,
+      3 => Self::Cancelled,
+      4 => Self::Expired,
+      _ => Self::Failed,
+    }
+  }
+
+  /// Whether a worker will still handle the request
+  pub fn is_pending(&self) -> bool {
+    matches!(self, Self::Queued | Self::Running)
+  }
+}
+
+impl Ticket {
+  fn new(id: u64) -> Self {
+    let state = TicketState { cancelled: AtomicBool::new(false), status: AtomicU8::new(TicketStatus::Queued as u8), sent: Instant::now() };
+    Self { id, state: Arc::new(state) }
+  }
+
+  /// Unique within the scheduler
+  pub fn id(&self) -> u64 {
+    self.id
+  }
+
+  pub fn cancel(&self) {
+    self.state.cancelled.store(true, Ordering::SeqCst);
+  }
+
+  pub fn is_cancelled(&self) -> bool {
+    self.state.cancelled.load(Ordering::SeqCst)
+  }
+
+  /// The latest state of any copy of the request
+  pub fn status(&self) -> TicketStatus {
+    TicketStatus::from_u8(self.state.status.load(Ordering::SeqCst))
+  }
+
+  fn set_status(&self, status: TicketStatus) {
+    self.state.status.store(status as u8, Ordering::SeqCst);
+  }
+
+  /// When the request was sent
+  pub fn sent(&self) -> Instant {
+    self.state.sent
+  }
+}
+
+impl<T, E> RequestHandle<T, E> {
+  pub fn id(&self) -> u64 {
+    self.ticket.id()
+  }
+
+  pub fn ticket(&self) -> &Ticket {
+    &self.ticket
+  }
+
+  pub fn cancel(&self) {
+    self.ticket.cancel()
+  }
+
+  /// The result if the request finished, without blocking. A result is returned only once.
+  pub fn try_take(&self) -> Option<Result<T, HandleError<E>>> {
+    match self.rx.try_recv() {
+      Ok(res) => Some(res),
+      Err(mpsc::TryRecvError::Empty) => None,
+      Err(mpsc::TryRecvError::Disconnected) => Some(Err(HandleError::WorkerDied)),
+    }
+  }
+
+  /// Blocks until the request finished or `timeout` passed, e.g. in tests
+  pub fn wait(&self, timeout: Duration) -> Result<T, HandleError<E>> {
+    match self.rx.recv_timeout(timeout) {
+      Ok(res) => res,
+      Err(mpsc::RecvTimeoutError::Timeout) => Err(HandleError::Timeout),
+      Err(mpsc::RecvTimeoutError::Disconnected) => Err(HandleError::WorkerDied),
+    }
+  }
+}
+
+impl<T, E> Reply<T, E> {
+  fn send(&self, res: Result<T, HandleError<E>>) {
+    if let Some(tx) = &self.tx {
+      tx.send(res).unwrap_or_default();
+    }
+    self.wakeup.wake();
+  }
+}
+
+impl Wakeup {
+  /// Wakes `waker` on the next result or message, instead of the previously registered one
+  #[cfg_attr(not(feature = "async"), allow(dead_code))]
+  fn register(&self, waker: &Waker) {
+    let mut registered = self.task.lock().unwrap();
+    match &*registered {
+      Some(old) if old.will_wake(waker) => (),
+      _ => *registered = Some(waker.clone()),
+    }
+  }
+
+  fn set_listener(&self, waker: Option<Waker>) {
+    *self.listener.lock().unwrap() = waker;
+  }
+
+  fn wake(&self) {
+    if let Some(waker) = self.task.lock().unwrap().take() {
+      waker.wake();
+    }
+    if let Some(listener) = &*self.listener.lock().unwrap() {
+      listener.wake_by_ref();
+    }
+  }
+}
+
+impl<Req, T, E> Queued<Req, T, E> {
+  /// Queues `request` on its own ticket with the default options, e.g. to send it again from a
+  /// response handler
+  pub fn new(kind: RequestKind, request: Req) -> Self {
+    Self { kind, request, ticket: Ticket::new(u64::MAX), options: RequestOptions::default(), reply: None }
+  }
+
+  /// Resolves the handle of the request if it has one
+  fn resolve(&self, res: Result<T, HandleError<E>>) {
+    if let Some(reply) = &self.reply {
+      reply.send(res);
+    }
+  }
+
+  /// Drops the request without handling it
+  fn cancel(self) {
+    self.ticket.set_status(TicketStatus::Cancelled);
+    self.resolve(Err(HandleError::Cancelled));
+  }
+}
+
+impl<Req, T, E> PartialEq for Pending<Req, T, E> {
+  fn eq(&self, other: &Self) -> bool {
+    self.cmp(other) == cmp::Ordering::Equal
+  }
+}
+
+impl<Req, T, E> Eq for Pending<Req, T, E> {}
+
+impl<Req, T, E> PartialOrd for Pending<Req, T, E> {
+  fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl<Req, T, E> Ord for Pending<Req, T, E> {
+  /// Higher priority first, then first in first out
+  fn cmp(&self, other: &Self) -> cmp::Ordering {
+    self.queued.options.priority.cmp(&other.queued.options.priority)
+      .then_with(|| other.seq.cmp(&self.seq))
+  }
+}
+
+impl<Req, T, E> WorkQueues<Req, T, E> {
+  fn new() -> Self {
+    let kind_label = RwLock::new(metrics::unlabelled as fn(&Req) -> &'static str);
+    Self { queues: RwLock::new(Vec::new()), next_seq: AtomicU64::new(0), metrics: Metrics::new(), kind_label }
+  }
+
+  fn kind_label(&self, request: &Req) -> &'static str {
+    (self.kind_label.read().unwrap())(request)
+  }
+
+  /// Adds the queue of a new worker, returns its index
+  fn add(&self) -> usize {
+    let mut queues = self.queues.write().unwrap();
+    queues.push(WorkQueue { pending: Mutex::new(BinaryHeap::new()), busy: AtomicBool::new(false), local: AtomicUsize::new(0) });
+    queues.len() - 1
+  }
+
+  /// Queued requests plus the one being handled
+  fn load(&self, worker: usize) -> usize {
+    let queues = self.queues.read().unwrap();
+    let queue = &queues[worker];
+    let pending = queue.pending.lock().unwrap().len();
+    pending + queue.busy.load(Ordering::SeqCst) as usize
+  }
+
+  /// Requests waiting for `worker`, queued here or received by the worker
+  fn queue_depth(&self, worker: usize) -> usize {
+    let queues = self.queues.read().unwrap();
+    let queue = &queues[worker];
+    let pending = queue.pending.lock().unwrap().len();
+    pending + queue.local.load(Ordering::SeqCst)
+  }
+
+  fn set_local(&self, worker: usize, local: usize) {
+    self.queues.read().unwrap()[worker].local.store(local, Ordering::SeqCst);
+  }
+
+  fn is_idle(&self, worker: usize) -> bool {
+    self.load(worker) == 0
+  }
+
+  fn set_busy(&self, worker: usize, busy: bool) {
+    self.queues.read().unwrap()[worker].busy.store(busy, Ordering::SeqCst);
+  }
+
+  fn push(&self, worker: usize, queued: Queued<Req, T, E>) {
+    let seq = self.next_seq.fetch_add(1, Ordering::SeqCst);
+    self.queues.read().unwrap()[worker].pending.lock().unwrap().push(Pending { queued, seq });
+  }
+
+  fn pop(&self, worker: usize) -> Option<Queued<Req, T, E>> {
+    self.queues.read().unwrap()[worker].pending.lock().unwrap().pop().map(|next| next.queued)
+  }
+
+  fn peek_priority(&self, worker: usize) -> Option<Priority> {
+    self.queues.read().unwrap()[worker].pending.lock().unwrap().peek().map(|next| next.queued.options.priority)
+  }
+
+  /// Pops the most urgent request queued for another worker
+  fn steal(&self, thief: usize) -> Option<Queued<Req, T, E>> {
+    loop {
+      let queues = self.queues.read().unwrap();
+      let victim = (0..queues.len())
+        .filter(|&victim| victim != thief)
+        .filter_map(|victim| {
+          let pending = queues[victim].pending.lock().unwrap();
+          let next = pending.peek()?;
+          Some(((next.queued.options.priority, cmp::Reverse(next.seq)), victim))
+        })
+        .max()?
+        .1;
+      drop(queues);
+      // Another worker may have been faster, then look again
+      if let Some(queued) = self.pop(victim) {
+        return Some(queued);
+      }
+    }
+  }
+
+  /// The next request for `worker`: the more urgent of its own ones and its work queue's, then
+  /// stolen ones if `steal`
+  fn next(&self, worker: usize, own: &mut BinaryHeap<Pending<Req, T, E>>, steal: bool) -> Option<Queued<Req, T, E>> {
+    let queued_first = match (own.peek(), self.peek_priority(worker)) {
+      (Some(next), Some(priority)) => priority > next.queued.options.priority,
+      (None, Some(_)) => true,
+      _ => false,
+    };
+    if queued_first {
+      if let Some(queued) = self.pop(worker) {
+        return Some(queued);
+      }
+    }
+    own.pop()
+      .map(|next| next.queued)
+      .or_else(|| self.pop(worker))
+      .or_else(|| if steal { self.steal(worker) } else { None })
+  }
+
+  /// Whether `worker` finds a request on the work queues, its own or others' with `steal`
+  fn has_work(&self, worker: usize, steal: bool) -> bool {
+    let queues = self.queues.read().unwrap();
+    queues.iter()
+      .enumerate()
+      .filter(|&(i, _)| steal || i == worker)
+      .any(|(_, queue)| !queue.pending.lock().unwrap().is_empty())
+  }
+
+  /// Drops `queued` without handling it and counts it as cancelled
+  fn cancel(&self, queued: Queued<Req, T, E>) {
+    self.metrics.record(None, self.kind_label(&queued.request), Outcome::Cancelled);
+    queued.cancel();
+  }
+}
+
+impl<Req, T, E> MetricsSource for WorkQueues<Req, T, E> where Req: Send, T: Send, E: Send {
+  fn snapshot(&self) -> SchedulerMetrics {
+    self.metrics.snapshot(|worker| self.queue_depth(worker))
+  }
+}
+
+impl RequestKind {
+  /// Whether a newer request of the same `Coalesce` group was sent, always false for other kinds
+  pub fn is_superseded(&self) -> bool {
+    match self {
+      RequestKind::Latest { group, generation } => group.0.load(Ordering::SeqCst) != *generation,
+      _ => false,
+    }
+  }
 }
 
+impl Coalesce {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Supersedes all requests of the group sent so far without sending a new one
+  pub fn cancel_pending(&self) {
+    self.0.fetch_add(1, Ordering::SeqCst);
+  }
+
+  fn next_generation(&self) -> u64 {
+    self.0.fetch_add(1, Ordering::SeqCst) + 1
+  }
+}
 
-impl<Req, T> Scheduler<Req, T> where Req: Send + Clone + Debug + PartialEq, T: Send {
-  pub fn new<W, F, U>(num_workers: usize, kill_request: Req, worker_init: F) -> Self
-    where W: Worker<Req, T, U>, F:Fn(usize) -> W + Send + Sync + 'static, Req: 'static, T: 'static
+impl<J> WorkerJobs<J> {
+  /// Runs `f` as worker `worker` handling `job`. The job stays registered if `f` panics.
+  pub fn run<R>(&self, worker: usize, job: J, f: impl FnOnce() -> R) -> R {
+    self.0.lock().unwrap().insert(worker, job);
+    let res = f();
+    self.0.lock().unwrap().remove(&worker);
+    res
+  }
+
+  /// Removes the job of `worker`, `None` if it isn't handling one
+  pub fn take(&self, worker: usize) -> Option<J> {
+    self.0.lock().unwrap().remove(&worker)
+  }
+}
+
+impl<J> Clone for WorkerJobs<J> {
+  fn clone(&self) -> Self {
+    Self(self.0.clone())
+  }
+}
+
+impl<J> Default for WorkerJobs<J> {
+  fn default() -> Self {
+    Self(Arc::default())
+  }
+}
+
+impl<Req, T, E> Scheduler<Req, T, E> where Req: Send + Clone + Debug, T: Send, E: Send {
+  pub fn new<W, F, U>(num_workers: usize, worker_init: F) -> Self
+    where W: Worker<Req, T, U, E>, F:Fn(usize) -> W + Send + Sync + 'static, Req: 'static, T: 'static, E: 'static
   {
-    let mut workers = Vec::with_capacity(num_workers);
     let worker_init = Arc::new(worker_init);
-    for i in 0..num_workers {
+    let work = Arc::new(WorkQueues::new());
+    let worker_work = work.clone();
+    let wakeup = Wakeup::default();
+    let worker_wakeup = wakeup.clone();
+    let spawn = Box::new(move |i| {
       let (t_resp, r_resp) = mpsc::channel();
-      let (t_req, thread) = new_worker(i, t_resp, kill_request.clone(), worker_init.clone());
-      workers.push(Some(WorkerHandle {tx: t_req, rx: r_resp, is_ready: false, thread}));
-    }
-    
-    Self { workers, kill_request }
+      let (t_req, thread) = new_worker(i, t_resp, worker_init.clone(), worker_work.clone(), worker_wakeup.clone());
+      WorkerHandle {tx: t_req, rx: r_resp, runner: Runner::Thread(thread), stopping: false, initialised: false}
+    });
+    let mut res = Self { workers: Vec::new(), work, spawn, next_ticket: AtomicU64::new(0), wakeup, turns: None, respawn: false };
+    res.add_workers(num_workers);
+    res
+  }
+
+  /// Like `new`, but the workers run on the caller's thread in `step`, `run_until_idle` and
+  /// `handle_respones`, one request at a time in the order of `interleaving`. Nothing runs in
+  /// between, so the same calls give the same results, e.g. for tests. Workers must not block
+  /// on each other, and `RequestHandle::wait` only sees requests that already ran.
+  pub fn new_inline<W, F, U>(num_workers: usize, interleaving: Interleaving, worker_init: F) -> Self
+    where W: Worker<Req, T, U, E> + Send + 'static, F: Fn(usize) -> W + Send + Sync + 'static, U: Send + 'static,
+      Req: 'static, T: 'static, E: 'static
+  {
+    let work = Arc::new(WorkQueues::new());
+    let worker_work = work.clone();
+    let wakeup = Wakeup::default();
+    let worker_wakeup = wakeup.clone();
+    let spawn = Box::new(move |i| {
+      let (t_resp, r_resp) = mpsc::channel();
+      let (t_req, r_req) = mpsc::channel();
+      let worker = WorkerLoop::init(i, &worker_init, r_req, t_resp, worker_work.clone(), worker_wakeup.clone());
+      let runner: Box<dyn RunInline> = Box::new(InlineWorker { worker, idle: false });
+      WorkerHandle { tx: WorkerSender(t_req), rx: r_resp, runner: Runner::Inline(Mutex::new(runner)), stopping: false, initialised: false }
+    });
+    let rng = match interleaving {
+      Interleaving::RoundRobin => 0,
+      Interleaving::Seeded(seed) => seed,
+    };
+    let turns = Turns { interleaving, last: None, rng };
+    let mut res = Self { workers: Vec::new(), work, spawn, next_ticket: AtomicU64::new(0), wakeup, turns: Some(Mutex::new(turns)), respawn: false };
+    res.add_workers(num_workers);
+    res
+  }
+
+  /// Lists the metrics in `registered_metrics` under `name`, e.g. for a debug overlay.
+  /// `kind_label` names the kind of a request in `SchedulerMetrics::kinds`, usually its variant.
+  pub fn register_metrics(&self, name: &str, kind_label: fn(&Req) -> &'static str) where Req: 'static, T: 'static, E: 'static {
+    self.work.metrics.set_name(name);
+    *self.work.kind_label.write().unwrap() = kind_label;
+    let work: Arc<dyn MetricsSource> = self.work.clone();
+    metrics::register(Arc::downgrade(&work));
+  }
+
+  /// Queue depths, wait and service times and busy times of the workers so far
+  pub fn metrics(&self) -> SchedulerMetrics {
+    self.work.snapshot()
+  }
+
+  pub fn reset_metrics(&self) {
+    self.work.metrics.reset()
   }
 
 }
-impl<Req, T> Scheduler<Req, T> where Req: Debug + Clone {
-  pub fn request(&self, request: Req, kind: BroadcastKind) -> Result<(), RequestError<Req>> {
+impl<Req, T, E> Scheduler<Req, T, E> where Req: Debug + Clone {
+  pub fn request(&self, request: Req, kind: BroadcastKind) -> Result<Ticket, RequestError<Req>> {
+    self.request_with(request, kind, RequestOptions::default())
+  }
+
+  /// Like `request`, with a priority and a deadline. The ticket can cancel the request.
+  pub fn request_with(&self, request: Req, kind: BroadcastKind, options: RequestOptions) -> Result<Ticket, RequestError<Req>> {
+    self.send(request, kind, options, None)
+  }
+
+  /// Like `request_with`, the value of the response resolves the returned handle. Requests sent
+  /// several times resolve it with the first response.
+  pub fn request_handle(&self, request: Req, kind: BroadcastKind, options: RequestOptions) -> Result<RequestHandle<T, E>, RequestError<Req>> {
+    let (tx, rx) = mpsc::channel();
+    let wakeup = Wakeup::default();
+    let ticket = self.send(request, kind, options, Some(Reply { tx: Some(tx), wakeup: wakeup.clone() }))?;
+    Ok(RequestHandle { ticket, rx, wakeup })
+  }
+
+  /// Like `request_handle`, the response resolves the returned future. Dropping the future
+  /// cancels the request.
+  #[cfg(feature = "async")]
+  pub fn request_async(&self, request: Req, kind: BroadcastKind, options: RequestOptions) -> Result<RequestFuture<T, E>, RequestError<Req>> {
+    self.request_handle(request, kind, options).map(RequestFuture::new)
+  }
+
+  fn send(&self, request: Req, kind: BroadcastKind, options: RequestOptions, reply: Option<Reply<T, E>>) -> Result<Ticket, RequestError<Req>> {
+    let ticket = self.new_ticket();
+    let queued = |request| Queued { kind: RequestKind::Plain, request, ticket: ticket.clone(), options, reply: reply.clone() };
+    let send = |w: &WorkerHandle<Req, T, E>, kind, request| {
+      w.send(Queued { kind, ..queued(request) })
+    };
     match kind {
       BroadcastKind::Specific(i) => match self.workers.get(i) {
-        Some(Some(w)) => w.just_send(request),
-        Some(None) => Err(RequestError::WorkerDied(request)),
-        None => Err(RequestError::IndexInvalid(request)),
-      },
-      BroadcastKind::DoNow => {
-        for w in self.workers.iter() {
-          if let Some(w) = w {
-            if w.is_ready {
-              return w.just_send(request);
-            }
-          }
-        }
-        Err(RequestError::NoReadyWorkers(request))
+        Some(Some(w)) if !w.stopping => send(w, RequestKind::Plain, request)?,
+        Some(_) => return Err(RequestError::WorkerDied(request)),
+        None => return Err(RequestError::IndexInvalid(request)),
       },
+      BroadcastKind::Any => self.send_any(queued(request), false)?,
+      BroadcastKind::DoNow => self.send_any(queued(request), true)?,
       BroadcastKind::TryNow => {
-        for w in self.workers.iter() {
-          if let Some(w) = w {
-            if w.is_ready {
-              return w.just_send(request);
-            }
-          }
+        match self.send_any(queued(request), true) {
+          Err(RequestError::NoReadyWorkers(request)) => self.work.cancel(queued(request)),
+          res => res?,
         }
-        Ok(())
       },
       BroadcastKind::All => {
-        for w in self.workers.iter() {
-          if let Some(w) = w {
-            if let Err(err) = w.just_send(request.clone()) {
-              return Err(err);
-            }
-          }
+        for w in self.running() {
+          send(w, RequestKind::Plain, request.clone())?;
         }
-        Ok(())
       },
       BroadcastKind::MulipleTimes(n) => {
-        let mut mutexes = Vec::with_capacity(n);
         for _ in 0..n {
-          mutexes.push(Arc::new(Mutex::new(true)))
-        }
-        for w in self.workers.iter() {
-          if let Some(w) = w {
-            for m in mutexes.iter() {
-              if let Err(err) = w.send(RequestKind::Once(m.clone()), request.clone()) {
-                return Err(err);
-              }
-            }
-          }
+          self.send_any(queued(request.clone()), false)?;
         }
-        Ok(())
       },
     }
+    Ok(ticket)
+  }
+
+  /// Queues `queued` for the least busy worker, or only for an idle one with `idle_only`
+  fn send_any(&self, queued: Queued<Req, T, E>, idle_only: bool) -> Result<(), RequestError<Req>> {
+    let least_busy = self.running_idx()
+      .map(|i| (self.work.load(i), i))
+      .min();
+    let (load, worker) = match least_busy {
+      Some((load, _)) if idle_only && load > 0 => return Err(RequestError::NoReadyWorkers(queued.request)),
+      Some(least_busy) => least_busy,
+      None => return Err(RequestError::WorkerDied(queued.request)),
+    };
+    self.work.push(worker, queued);
+    for i in self.running_idx() {
+      // Workers that got idle since the loads were compared steal the request
+      if i == worker || (load > 0 && self.work.is_idle(i)) {
+        self.workers[i].as_ref().unwrap().tx.wake();
+      }
+    }
+    Ok(())
+  }
+
+  /// Indices of the workers that weren't told to stop
+  fn running_idx(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+    self.workers.iter()
+      .enumerate()
+      .filter(|(_, w)| w.as_ref().is_some_and(|w| !w.stopping))
+      .map(|(i, _)| i)
   }
 
-  pub fn request_kill(&self, kind: BroadcastKind) -> Result<(), RequestError<Req>> {
-    self.request(self.kill_request.clone(), kind)
+  fn running(&self) -> impl Iterator<Item = &WorkerHandle<Req, T, E>> {
+    self.workers.iter().flatten().filter(|w| !w.stopping)
   }
 
-  pub fn kill_all_workers(&mut self, block: bool) -> Result<(), RequestError<Req>> {
-    self.request_kill(BroadcastKind::All)?;
-    if block {
-      for w in self.workers.iter_mut() {
-        if let Some(w) = std::mem::take(w) {
-          w.thread.join().unwrap();
+  /// Sends `request` to worker `worker_idx`, superseding the requests of `group` it hasn't handled
+  /// yet. A burst of requests collapses into the latest one, see `RequestKind::Latest`.
+  pub fn request_latest(&self, request: Req, group: &Coalesce, worker_idx: usize) -> Result<Ticket, RequestError<Req>> {
+    match self.workers.get(worker_idx) {
+      Some(Some(w)) if !w.stopping => {
+        let ticket = self.new_ticket();
+        let kind = RequestKind::Latest { group: group.clone(), generation: group.next_generation() };
+        w.send(Queued { kind, request, ticket: ticket.clone(), options: RequestOptions::default(), reply: None })?;
+        Ok(ticket)
+      },
+      Some(_) => Err(RequestError::WorkerDied(request)),
+      None => Err(RequestError::IndexInvalid(request)),
+    }
+  }
+
+  /// Workers that are running and weren't told to stop
+  pub fn num_workers(&self) -> usize {
+    self.running().count()
+  }
+
+  fn new_ticket(&self) -> Ticket {
+    Ticket::new(self.next_ticket.fetch_add(1, Ordering::SeqCst))
+  }
+
+  /// Woken after each response of a worker, also the ones that go to request handles. An event
+  /// loop can wake up to call `handle_respones` instead of polling.
+  pub fn set_waker(&self, waker: Option<Waker>) {
+    self.wakeup.set_listener(waker)
+  }
+
+  /// Lets the next inline worker with something to do take one request, see `new_inline`.
+  /// Returns false if none has anything to do, they are idle then. Does nothing for worker threads.
+  pub fn step(&self) -> bool {
+    let mut turns = match &self.turns {
+      Some(turns) => turns.lock().unwrap(),
+      None => return false,
+    };
+    let inline = || self.workers.iter()
+      .enumerate()
+      .filter_map(|(i, w)| match w {
+        Some(WorkerHandle { runner: Runner::Inline(worker), .. }) => Some((i, worker)),
+        _ => None,
+      });
+    let runnable: Vec<_> = inline().filter(|(_, worker)| worker.lock().unwrap().is_runnable()).collect();
+    if runnable.is_empty() {
+      // Idle workers look idle, so that `DoNow` finds them
+      for (_, worker) in inline() {
+        worker.lock().unwrap().step();
+      }
+      return false;
+    }
+    let (i, worker) = runnable[turns.next(runnable.iter().map(|(i, _)| *i))];
+    turns.last = Some(i);
+    worker.lock().unwrap().step();
+    true
+  }
+
+  /// Steps the inline workers until none has anything to do, returns the number of steps
+  pub fn run_until_idle(&self) -> usize {
+    let mut steps = 0;
+    while self.step() {
+      steps += 1;
+    }
+    steps
+  }
+
+  /// Spawns `n` more workers and returns their indices. Indices of removed workers aren't reused.
+  pub fn add_workers(&mut self, n: usize) -> Range<usize> {
+    let start = self.workers.len();
+    for _ in 0..n {
+      let i = self.work.add();
+      self.workers.push(Some((self.spawn)(i)));
+    }
+    start..self.workers.len()
+  }
+
+  /// Stops the `n` most recently added workers and returns their indices. They get no more
+  /// requests, `handle_respones` removes them once they stopped.
+  pub fn remove_workers(&mut self, n: usize, mode: Shutdown) -> Vec<usize> {
+    let removed: Vec<_> = self.running_idx().rev().take(n).collect();
+    for &i in &removed {
+      self.stop_worker(i, mode);
+    }
+    removed
+  }
+
+  /// Whether `handle_respones` replaces a worker that panicked while handling a request with a
+  /// new one. The new worker gets the same index and takes the requests queued for any worker
+  /// that were waiting for the dead one, the ones sent to it specifically are lost. Workers whose
+  /// init function panicked aren't replaced, they would most likely panic again.
+  pub fn set_respawn(&mut self, respawn: bool) {
+    self.respawn = respawn;
+  }
+
+  /// Adds or removes workers until `num_workers` are running
+  pub fn set_num_workers(&mut self, num_workers: usize, mode: Shutdown) {
+    let running = self.num_workers();
+    if num_workers > running {
+      self.add_workers(num_workers - running);
+    } else {
+      self.remove_workers(running - num_workers, mode);
+    }
+  }
+
+  /// Stops all workers without waiting for them, see `join`. With `Shutdown::Abort` the requests
+  /// for any worker are cancelled right away.
+  pub fn shutdown(&mut self, mode: Shutdown) {
+    let running: Vec<_> = self.running_idx().collect();
+    for i in running {
+      self.stop_worker(i, mode);
+    }
+    if mode == Shutdown::Abort {
+      for i in 0..self.workers.len() {
+        while let Some(queued) = self.work.pop(i) {
+          self.work.cancel(queued);
         }
       }
     }
-    self.workers = Vec::new();
-    Ok(())
   }
 
-  pub fn handle_respones<E>(&mut self, f: fn(Response<T>, &mpsc::Sender<(RequestKind, Req)>) -> Result<(), E>) -> Result<(), E> {
-  // pub fn handle_respones<E>(&mut self, f: impl Fn(Response<T>, &mpsc::Sender<(RequestKind, Req)>) -> Result<(), E>) -> Result<(), E> {
-    for w in self.workers.iter_mut() {
-      if let Some(WorkerHandle { tx, rx, is_ready, thread }) = w {
-        if thread.is_finished() {
-          *w = None;
-          continue;
-        // } else if *is_ready {
-        //   continue;
-        } else {
+  fn stop_worker(&mut self, i: usize, mode: Shutdown) {
+    if let Some(w) = &mut self.workers[i] {
+      w.stopping = true;
+      w.tx.stop(mode);
+    }
+  }
+
+  /// Waits up to `timeout` for the workers that were told to stop. Returns false if some are
+  /// still running. Their last responses are left for `handle_respones`.
+  pub fn join(&self, timeout: Duration) -> bool {
+    // Inline workers stop right here
+    self.run_until_idle();
+    let deadline = Instant::now() + timeout;
+    loop {
+      if self.workers.iter().flatten().all(|w| !w.stopping || w.runner.is_finished()) {
+        return true;
+      }
+      if Instant::now() >= deadline {
+        return false;
+      }
+      thread::sleep(Duration::from_millis(1));
+    }
+  }
+
+  /// Passes the responses of all workers to `f`, which may send requests back to the worker
+  /// that answered. Workers whose thread stopped are removed once their last responses were
+  /// handled, a worker that panicked answers with `Response::WorkerDied` first. Requests still
+  /// queued for a removed worker go to the others, or to its replacement, see `set_respawn`.
+  /// Inline workers run until idle first.
+  pub fn handle_respones<H>(&mut self, mut f: impl FnMut(Response<T, E>, &WorkerSender<Req, T, E>) -> Result<(), H>) -> Result<(), H> {
+    self.run_until_idle();
+    for i in 0..self.workers.len() {
+      if let Some(WorkerHandle { tx, rx, runner, initialised, .. }) = &mut self.workers[i] {
+        // Checked before receiving, so everything a stopped thread sent is in the channel
+        let finished = runner.is_finished();
+        let mut stopped = finished;
+        let mut panicked = false;
+        loop {
           match rx.try_recv() {
-            Ok(first_val) => {
-              *is_ready = false;
-              let iter = std::iter::once(first_val).chain(rx.try_iter());
-              let mut split_last_iter = crate::SplitLastIter::from_iter(iter);
-              loop {
-                if let Some(resp) = split_last_iter.next() {
-                  f(resp, tx)?;
-                } else {
-                  break
-                }
-              }
-              if let Some(resp) = split_last_iter.unwrap_last() {
-                if let Response::Ready(true) = &resp {
-                  *is_ready = true;
-                }
-                f(resp, tx)?;
-              }
+            Ok(resp) => {
+              *initialised |= matches!(resp, Response::Init);
+              // The thread is about to stop
+              panicked |= matches!(resp, Response::WorkerDied { .. });
+              stopped |= panicked;
+              f(resp, tx)?
             },
-            Err(mpsc::TryRecvError::Disconnected) => {*w = None},
-            Err(mpsc::TryRecvError::Empty) => {},
+            Err(mpsc::TryRecvError::Empty) => break,
+            Err(mpsc::TryRecvError::Disconnected) => {
+              stopped = true;
+              break
+            },
+          }
+        }
+        if stopped {
+          if let Some(w) = self.workers[i].take() {
+            // Finished or about to
+            w.runner.join();
+            if self.respawn && panicked && w.initialised && !w.stopping {
+              self.workers[i] = Some((self.spawn)(i));
+              continue;
+            }
           }
+          self.requeue(i);
         }
       }
     }
     Ok(())
   }
 
+  /// Moves the requests queued for the stopped worker `worker` to the others, cancels them if
+  /// none are left
+  fn requeue(&self, worker: usize) {
+    while let Some(queued) = self.work.pop(worker) {
+      if self.running_idx().next().is_some() {
+        // Can't fail with workers left
+        self.send_any(queued, false).ok();
+      } else {
+        self.work.cancel(queued);
+      }
+    }
+  }
+
 }
 
-pub trait Worker<Req, T, U> where Req: Send, T: Send {
-  fn handle(&mut self, request: Req, kind: RequestKind, pub_tx: &mut CallbackSender<U>) -> Response<T>;
+pub trait Worker<Req, T, U, E = ()> where Req: Send, T: Send {
+  fn handle(&mut self, request: Req, kind: RequestKind, pub_tx: &mut CallbackSender<U>) -> Response<T, E>;
 }
 
-impl<Req, T, U, F> Worker<Req, T, U> for F where F: FnMut(Req, RequestKind, &mut CallbackSender<U>) -> Response<T>, Req: Send, T: Send {
-  fn handle(&mut self, request: Req, kind: RequestKind, pub_tx: &mut CallbackSender<U>) -> Response<T> {
+impl<Req, T, U, E, F> Worker<Req, T, U, E> for F where F: FnMut(Req, RequestKind, &mut CallbackSender<U>) -> Response<T, E>, Req: Send, T: Send {
+  fn handle(&mut self, request: Req, kind: RequestKind, pub_tx: &mut CallbackSender<U>) -> Response<T, E> {
     self(request, kind, pub_tx)
   }
 }
 
-pub fn new_worker<Req, T, W, F, U>(thread_idx: usize, t_resp: mpsc::Sender<Response<T>>, kill_request: Req, worker_init: Arc<F>) -> (mpsc::Sender<(RequestKind, Req)>, JoinHandle<()>)
-  where F: Fn(usize) -> W + Send + Sync + 'static, W: Worker<Req, T, U>, Req: Send + PartialEq + 'static, T: Send + 'static
+/// Spawns a worker thread, see `WorkerLoop`. It blocks while the worker has nothing to do.
+fn new_worker<Req, T, W, F, U, E>(thread_idx: usize, t_resp: mpsc::Sender<Response<T, E>>, worker_init: Arc<F>, work: Arc<WorkQueues<Req, T, E>>, wakeup: Wakeup) -> (WorkerSender<Req, T, E>, JoinHandle<()>)
+  where F: Fn(usize) -> W + Send + Sync + 'static, W: Worker<Req, T, U, E>, Req: Send + Debug + 'static, T: Send + 'static, E: Send + 'static
 {
   let (tx, rx) = mpsc::channel();
-  let t = std::thread::spawn(move || {
-    let (t_req, r_req) = mpsc::channel::<(RequestKind, Req)>();
-    let mut pub_tx = CallbackSender::default();
+  let t = thread::spawn(move || {
+    let (t_req, r_req) = mpsc::channel::<Message<Req, T, E>>();
     tx.send(t_req).unwrap();
-    let mut worker = worker_init(thread_idx);
-    drop((worker_init, thread_idx));
-    if t_resp.send(Response::Init).is_err() {
-      return;
-    }
-    // let mut worker = Self { thread_idx, rx: r_req, tx: t_resp, state: worker_state};
-
-    let mut handle_response = |resp| {
-      match resp {
-        Ok((kind, req)) => match t_resp.send(worker.handle(req, kind, &mut pub_tx)) {
-          Ok(()) => {},
-          Err(e) => eprintln!("Response Error: {:?}", e)
+    let worker = WorkerLoop::init(thread_idx, worker_init.as_ref(), r_req, t_resp, work, wakeup);
+    drop(worker_init);
+    if let Some(worker) = worker {
+      worker.run();
+    }
+  });
+  (WorkerSender(rx.recv().unwrap()), t)
+}
+
+impl<Req, T, E, W, U> WorkerLoop<Req, T, E, W, U> where W: Worker<Req, T, U, E>, Req: Send + Debug, T: Send, E: Send {
+  /// Creates the worker with `worker_init`, `None` if it panicked
+  fn init(idx: usize, worker_init: &impl Fn(usize) -> W, r_req: mpsc::Receiver<Message<Req, T, E>>, t_resp: mpsc::Sender<Response<T, E>>, work: Arc<WorkQueues<Req, T, E>>, wakeup: Wakeup)
+    -> Option<Self>
+  {
+    let worker = match panic::catch_unwind(AssertUnwindSafe(|| worker_init(idx))) {
+      Ok(worker) => worker,
+      Err(payload) => {
+        work.set_busy(idx, true);
+        t_resp.send(Response::WorkerDied { worker: idx, payload }).unwrap_or_default();
+        wakeup.wake();
+        return None;
+      },
+    };
+    work.metrics.add_worker(idx);
+    // Responses may go nowhere once the scheduler is dropped, workers still drain their queues then
+    t_resp.send(Response::Init).unwrap_or_default();
+    let pub_tx = CallbackSender::default();
+    Some(Self { idx, worker, pub_tx, r_req, t_resp, work, wakeup, pending: BinaryHeap::new(), seq: 0, stop: None })
+  }
+
+  /// Steps until stopped, waits for the next message while idle
+  fn run(mut self) {
+    loop {
+      match self.step() {
+        Step::Busy => (),
+        Step::Idle => {
+          self.t_resp.send(Response::Ready(true)).unwrap_or_default();
+          match self.r_req.recv() {
+            Ok(msg) => self.receive(msg),
+            Err(e) => {
+              eprintln!("Request Error: {:?}", e);
+              break;
+            }
+          }
+          self.t_resp.send(Response::Ready(false)).unwrap_or_default();
         },
-        Err(e) => eprintln!("Request Error: {:?}", e)
+        Step::Stopped => break,
       }
+    }
+    self.finish()
+  }
+
+  fn receive(&mut self, msg: Message<Req, T, E>) {
+    match msg {
+      Message::Request(queued) => {
+        self.seq += 1;
+        self.pending.push(Pending { queued, seq: self.seq });
+      },
+      Message::Wake => (),
+      Message::Stop(mode) => self.stop = Some(mode),
+    }
+  }
+
+  fn receive_all(&mut self) {
+    while let Ok(msg) = self.r_req.try_recv() {
+      self.receive(msg);
+    }
+  }
+
+  /// Whether `step` makes progress, i.e. doesn't answer `Step::Idle`
+  fn is_runnable(&mut self) -> bool {
+    self.receive_all();
+    self.stop.is_some() || !self.pending.is_empty() || self.work.has_work(self.idx, true)
+  }
+
+  /// Takes the most urgent request, the ones sent to this worker and the ones queued on `work`,
+  /// and handles it. Cancelled, superseded and expired ones are dropped without handling them.
+  /// If the worker panics it sends `Response::WorkerDied` and stops.
+  fn step(&mut self) -> Step {
+    self.receive_all();
+    if self.stop == Some(Shutdown::Abort) {
+      return Step::Stopped;
+    }
+    let (idx, work) = (self.idx, &self.work);
+    // Stopping workers only drain their own queues
+    let steal = self.stop.is_none();
+    work.set_busy(idx, true);
+    let mut next = work.next(idx, &mut self.pending, steal);
+    if next.is_none() && steal {
+      // Requests queued on `work` from now on come with a wake up
+      work.set_busy(idx, false);
+      next = work.next(idx, &mut self.pending, steal);
+      work.set_busy(idx, next.is_some());
+    }
+    let queued = match next {
+      Some(queued) => queued,
+      None if self.stop.is_some() => return Step::Stopped,
+      None => return Step::Idle,
     };
 
-    'request_loop: loop {
-      for resp in r_req.try_iter() {
-        let kill_worker = resp.1 == kill_request;
-        handle_response(Ok(resp));
-        if kill_worker {
-          break 'request_loop;
+    work.set_local(idx, self.pending.len());
+
+    if queued.ticket.is_cancelled() || queued.kind.is_superseded() {
+      work.cancel(queued);
+      return Step::Busy;
+    }
+    let kind_name = work.kind_label(&queued.request);
+    if queued.options.is_expired() {
+      work.metrics.record(None, kind_name, Outcome::Expired);
+      queued.ticket.set_status(TicketStatus::Expired);
+      queued.resolve(Err(HandleError::Expired));
+      return Step::Busy;
+    }
+    let Queued { kind, request, ticket, reply, .. } = queued;
+    ticket.set_status(TicketStatus::Running);
+    CURRENT_TICKET.with(|current| *current.borrow_mut() = Some(ticket.clone()));
+    let span = tracing::debug_span!("handle", worker = idx, kind = %kind_name, ticket = ticket.id());
+    let wait = ticket.sent().elapsed();
+    let started = Instant::now();
+    work.metrics.start(idx);
+    let (worker, pub_tx) = (&mut self.worker, &mut self.pub_tx);
+    let resp = span.in_scope(|| panic::catch_unwind(AssertUnwindSafe(|| worker.handle(request, kind, pub_tx))));
+    let service = started.elapsed();
+    CURRENT_TICKET.with(|current| *current.borrow_mut() = None);
+    let failed = matches!(resp, Ok(Response::Err(_)) | Err(_));
+    work.metrics.record(Some(idx), kind_name, Outcome::Handled { wait, service, failed });
+    let resp = match resp {
+      Ok(resp) => resp,
+      Err(payload) => {
+        ticket.set_status(TicketStatus::Failed);
+        if let Some(reply) = reply {
+          reply.send(Err(HandleError::WorkerDied));
         }
+        self.t_resp.send(Response::WorkerDied { worker: idx, payload }).unwrap_or_default();
+        self.wakeup.wake();
+        return Step::Stopped;
+      },
+    };
+    ticket.set_status(if let Response::Err(_) = &resp { TicketStatus::Failed } else { TicketStatus::Done });
+    match (reply, resp) {
+      (Some(reply), Response::Ok(value) | Response::Public(value)) => reply.send(Ok(value)),
+      (Some(reply), Response::Err(e)) => reply.send(Err(HandleError::Failed(e))),
+      (_, resp) => self.t_resp.send(resp).unwrap_or_default(),
+    }
+    self.wakeup.wake();
+    Step::Busy
+  }
+
+  fn finish(mut self) {
+    if self.stop == Some(Shutdown::Abort) {
+      for next in self.pending.drain() {
+        self.work.cancel(next.queued);
       }
-      if t_resp.send(Response::Ready(true)).is_err() {
-        break 'request_loop; 
-      }
-      let next_request = r_req.recv();
-      let kill_worker = match &next_request {
-        Ok((_, req)) => *req == kill_request,
-        _ => false
-      };
-      if t_resp.send(Response::Ready(false)).is_err() && !kill_worker {
-        break 'request_loop; 
-      }
-      handle_response(next_request);
-      if kill_worker {
-        break 'request_loop;
+    }
+    // Stopped workers look busy, so that requests for any worker go to the others
+    self.work.set_busy(self.idx, true);
+    self.work.set_local(self.idx, 0);
+    self.work.metrics.remove_worker(self.idx);
+  }
+}
+
+impl<Req, T, E, W, U> RunInline for InlineWorker<Req, T, E, W, U>
+  where W: Worker<Req, T, U, E> + Send, Req: Send + Debug, T: Send, E: Send, U: Send
+{
+  fn is_runnable(&mut self) -> bool {
+    self.worker.as_mut().is_some_and(|worker| worker.is_runnable())
+  }
+
+  fn step(&mut self) {
+    let worker = match &mut self.worker {
+      Some(worker) => worker,
+      None => return,
+    };
+    if self.idle {
+      if !worker.is_runnable() {
+        worker.step();
+        return;
       }
+      worker.t_resp.send(Response::Ready(false)).unwrap_or_default();
+      self.idle = false;
+    }
+    match worker.step() {
+      Step::Busy => (),
+      Step::Idle => {
+        worker.t_resp.send(Response::Ready(true)).unwrap_or_default();
+        self.idle = true;
+      },
+      Step::Stopped => {
+        if let Some(worker) = self.worker.take() {
+          worker.finish();
+        }
+      },
+    }
+  }
+
+  fn is_finished(&self) -> bool {
+    self.worker.is_none()
   }
-  });
-  (rx.recv().unwrap(), t)
 }
 
-impl<Req, T> WorkerHandle<Req, T> where Req: Debug {
-  pub fn send(&self, kind: RequestKind, request: Req) -> Result<(), RequestError<Req>> {
-    match self.tx.send((kind, request)) {
+impl Runner {
+  fn is_finished(&self) -> bool {
+    match self {
+      Runner::Thread(thread) => thread.is_finished(),
+      Runner::Inline(worker) => worker.lock().unwrap().is_finished(),
+    }
+  }
+
+  fn join(self) {
+    if let Runner::Thread(thread) = self {
+      thread.join().unwrap_or_default();
+    }
+  }
+}
+
+impl Turns {
+  /// Index into `runnable` of the worker whose turn it is
+  fn next(&mut self, runnable: impl Iterator<Item = usize> + Clone) -> usize {
+    match self.interleaving {
+      Interleaving::RoundRobin => {
+        // The first one after the last turn, wrapping around
+        let after_last = |&(_, i): &(usize, usize)| self.last.is_none_or(|last| i > last);
+        runnable.clone().enumerate().find(after_last).map_or(0, |(pos, _)| pos)
+      },
+      Interleaving::Seeded(_) => {
+        // SplitMix64
+        self.rng = self.rng.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^= z >> 31;
+        (z % runnable.count() as u64) as usize
+      },
+    }
+  }
+}
+
+impl<Req, T, E> WorkerHandle<Req, T, E> where Req: Debug {
+  pub fn send(&self, queued: Queued<Req, T, E>) -> Result<(), RequestError<Req>> {
+    self.tx.send(queued)
+  }
+}
+
+impl<Req, T, E> WorkerSender<Req, T, E> where Req: Debug {
+  pub fn send(&self, queued: Queued<Req, T, E>) -> Result<(), RequestError<Req>> {
+    match self.0.send(Message::Request(queued)) {
       Ok(()) => Ok(()),
       Err(e) => Err(e.into())
     }
   }
 
-  pub fn just_send(&self, request: Req) -> Result<(), RequestError<Req>> {
-    self.send(RequestKind::Plain, request)
+  fn wake(&self) {
+    self.0.send(Message::Wake).unwrap_or_default()
+  }
+
+  fn stop(&self, mode: Shutdown) {
+    self.0.send(Message::Stop(mode)).unwrap_or_default()
   }
 }
 
 
 
-impl<Req, T> From<mpsc::SendError<(T, Req)>> for RequestError<Req> where Req: Debug {
-  fn from(value: mpsc::SendError<(T, Req)>) -> Self {
-    RequestError::SendError(value.0.1)
+impl<Req, T, E> From<mpsc::SendError<Message<Req, T, E>>> for RequestError<Req> where Req: Debug {
+  fn from(value: mpsc::SendError<Message<Req, T, E>>) -> Self {
+    match value.0 {
+      Message::Request(queued) => RequestError::SendError(queued.request),
+      Message::Wake | Message::Stop(_) => unreachable!("Control messages are not sent with `?`"),
+    }
   }
 }
 
 
-impl<Req, T> Drop for Scheduler<Req, T> where Req: Debug + Clone {
+// Derived, this would require `T: Clone`
+impl<T, E> Clone for Reply<T, E> {
+  fn clone(&self) -> Self {
+    Self { tx: self.tx.clone(), wakeup: self.wakeup.clone() }
+  }
+}
+
+impl<T, E> Drop for Reply<T, E> {
   fn drop(&mut self) {
-    self.kill_all_workers(false).unwrap_or_default()
+    // The handle resolves with `WorkerDied` if this was the last reply
+    drop(self.tx.take());
+    self.wakeup.wake();
   }
 }
 
+impl<Req, T, E> Drop for Scheduler<Req, T, E> where Req: Debug + Clone {
+  /// Workers finish the queued requests in the background, inline workers right away
+  fn drop(&mut self) {
+    self.shutdown(Shutdown::Drain);
+    self.run_until_idle();
+  }
+}
@@ -0,0 +1,524 @@
+use std::{
+  collections::{HashMap, VecDeque},
+  sync::{Arc, Condvar, Mutex, Weak, mpsc},
+  task::Waker,
+  time::{Duration, Instant},
+};
+#[cfg(feature = "async")]
+use std::{pin::Pin, task::{Context, Poll}};
+
+use super::Wakeup;
+
+
+pub struct CallbackMessage<T> {
+  id: u64,
+  callback_id: u64,
+  message: T,
+}
+
+/// What sending to a full bounded channel does
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backpressure {
+  /// Waits until the receiver took a message or was dropped, the sender can't outrun the receiver
+  #[default]
+  Block,
+  /// Drops the message being sent
+  DropNewest,
+  /// Drops the oldest queued message, e.g. for progress of which only the latest report matters
+  DropOldest,
+}
+
+/// The channel of a `CallbackReceiver` or `Subscription`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChannelOptions {
+  /// Messages the channel holds at most, unbounded if `None`
+  pub capacity: Option<usize>,
+  pub backpressure: Backpressure,
+}
+
+/// The messages sent to one receiver
+struct Queue<T> {
+  state: Mutex<QueueState<T>>,
+  /// Notified when a message was sent
+  not_empty: Condvar,
+  /// Notified when a message was taken or the receiver was dropped
+  not_full: Condvar,
+  options: ChannelOptions,
+}
+
+struct QueueState<T> {
+  messages: VecDeque<CallbackMessage<T>>,
+  /// Messages dropped by the backpressure policy
+  dropped: u64,
+  /// The receiver was dropped, senders drop the channel on their next message
+  closed: bool,
+}
+
+/// The receiving end of a `Queue`, closes it when dropped
+struct Inbox<T> {
+  queue: Arc<Queue<T>>,
+  wakeup: Wakeup,
+}
+
+/// The channel of a `CallbackReceiver` or `Subscription`, as it is added to a sender
+pub struct CallbackChannel<T> {
+  queue: Arc<Queue<T>>,
+  wakeup: Wakeup,
+}
+
+#[derive(Clone)]
+pub struct CallbackSender<T> {
+  senders: Vec<(u64, CallbackChannel<T>)>,
+  id: u64,
+  next_message_id: u64,
+}
+
+
+pub struct CallbackReceiver<T> {
+  inbox: Inbox<T>,
+  callback_to_proxy: HashMap<u64, u64>,
+}
+
+/// Publishes messages by topic to any number of `Subscription`s, e.g. the progress of a job to
+/// every panel that shows it. Clones publish to the same subscribers.
+pub struct Bus<T> {
+  topics: Arc<Mutex<HashMap<String, Topic<T>>>>,
+  id: u64,
+}
+
+struct Topic<T> {
+  subscribers: Vec<(u64, CallbackChannel<T>)>,
+  next_message_id: u64,
+}
+
+/// The messages of a topic of a `Bus`. Unsubscribes when dropped.
+pub struct Subscription<T> {
+  inbox: Inbox<T>,
+  topics: Weak<Mutex<HashMap<String, Topic<T>>>>,
+  topic: String,
+  id: u64,
+}
+
+static NEXT_ID: Mutex<u64> = Mutex::new(0);
+
+fn new_id() -> u64 {
+  let mut guard = NEXT_ID.lock().unwrap();
+  let id = *guard;
+  *guard += 1;
+  id
+}
+
+
+impl<T> CallbackMessage<T> {
+  /// Number of the message among the messages of its sender, or of its topic for a `Bus`
+  pub fn id(&self) -> u64 {
+    self.id
+  }
+
+  /// Id of the `CallbackSender` or `Bus` that sent the message
+  pub fn callback_id(&self) -> u64 {
+    self.callback_id
+  }
+
+  pub fn message(&self) -> &T {
+    &self.message
+  }
+
+  pub fn into_message(self) -> T {
+    self.message
+  }
+}
+
+impl ChannelOptions {
+  /// Holds at most `capacity` messages, at least one
+  pub fn bounded(capacity: usize, backpressure: Backpressure) -> Self {
+    Self { capacity: Some(capacity.max(1)), backpressure }
+  }
+}
+
+impl<T> Queue<T> {
+  fn new(options: ChannelOptions) -> Self {
+    let state = QueueState { messages: VecDeque::new(), dropped: 0, closed: false };
+    Self { state: Mutex::new(state), not_empty: Condvar::new(), not_full: Condvar::new(), options }
+  }
+
+  /// Whether the message was queued, `Err` if the receiver was dropped
+  fn push(&self, message: CallbackMessage<T>) -> Result<bool, ()> {
+    let mut state = self.state.lock().unwrap();
+    if let Some(capacity) = self.options.capacity {
+      while !state.closed && state.messages.len() >= capacity {
+        match self.options.backpressure {
+          Backpressure::Block => state = self.not_full.wait(state).unwrap(),
+          Backpressure::DropNewest => {
+            state.dropped += 1;
+            return Ok(false);
+          },
+          Backpressure::DropOldest => {
+            state.messages.pop_front();
+            state.dropped += 1;
+          },
+        }
+      }
+    }
+    if state.closed {
+      return Err(());
+    }
+    state.messages.push_back(message);
+    self.not_empty.notify_one();
+    Ok(true)
+  }
+
+  fn try_pop(&self) -> Option<CallbackMessage<T>> {
+    let message = self.state.lock().unwrap().messages.pop_front();
+    if message.is_some() {
+      self.not_full.notify_one();
+    }
+    message
+  }
+
+  fn pop_timeout(&self, timeout: Duration) -> Option<CallbackMessage<T>> {
+    let deadline = Instant::now() + timeout;
+    let mut state = self.state.lock().unwrap();
+    loop {
+      if let Some(message) = state.messages.pop_front() {
+        self.not_full.notify_one();
+        return Some(message);
+      }
+      let now = Instant::now();
+      if now >= deadline {
+        return None;
+      }
+      state = self.not_empty.wait_timeout(state, deadline - now).unwrap().0;
+    }
+  }
+
+  /// Drops the queued messages and lets blocked senders go
+  fn close(&self) {
+    let mut state = self.state.lock().unwrap();
+    state.closed = true;
+    state.messages.clear();
+    self.not_full.notify_all();
+  }
+
+  fn is_closed(&self) -> bool {
+    self.state.lock().unwrap().closed
+  }
+}
+
+impl<T> Inbox<T> {
+  fn new(options: ChannelOptions) -> Self {
+    Self { queue: Arc::new(Queue::new(options)), wakeup: Wakeup::default() }
+  }
+
+  fn channel(&self) -> CallbackChannel<T> {
+    CallbackChannel { queue: self.queue.clone(), wakeup: self.wakeup.clone() }
+  }
+
+  fn dropped(&self) -> u64 {
+    self.queue.state.lock().unwrap().dropped
+  }
+
+  #[cfg(feature = "async")]
+  fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<CallbackMessage<T>>> {
+    // Registered first, a message sent in between wakes the task again
+    self.wakeup.register(cx.waker());
+    match self.queue.try_pop() {
+      Some(message) => Poll::Ready(Some(message)),
+      None => Poll::Pending,
+    }
+  }
+}
+
+impl<T> CallbackChannel<T> {
+  /// `Err` if the receiver was dropped. Blocks on a full channel with `Backpressure::Block`.
+  fn send(&self, message: CallbackMessage<T>) -> Result<(), ()> {
+    if self.queue.push(message)? {
+      self.wakeup.wake();
+    }
+    Ok(())
+  }
+
+  /// Whether the receiver was dropped
+  pub fn is_closed(&self) -> bool {
+    self.queue.is_closed()
+  }
+}
+
+impl<T> CallbackSender<T> {
+  pub fn add_callback(&mut self, callback: CallbackChannel<T>) -> u64 {
+    let id = new_id();
+    self.senders.push((id, callback));
+    id
+  }
+
+  pub fn remove_callback(&mut self, id: u64) -> Option<CallbackChannel<T>> {
+    let i = self.senders.iter().position(|(i_id, _)| *i_id == id)?;
+    Some(self.senders.swap_remove(i).1)
+  }
+
+  pub fn iter_callback_ids(&self) -> impl Iterator<Item=u64> + '_ {
+    self.senders.iter().map(|(id, _)| *id)
+  }
+
+  pub fn id(&self) -> u64 {
+    self.id
+  }
+
+  pub fn next_message_id(&self) -> u64 {
+    self.next_message_id
+  }
+
+}
+
+impl<T> CallbackReceiver<T> {
+  pub fn new(options: ChannelOptions) -> Self {
+    Self { inbox: Inbox::new(options), callback_to_proxy: HashMap::new() }
+  }
+
+  pub fn try_recv(&self) -> Option<CallbackMessage<T>> {
+    self.inbox.queue.try_pop()
+  }
+
+  /// Waits at most `timeout` for a message
+  pub fn recv_timeout(&self, timeout: Duration) -> Option<CallbackMessage<T>> {
+    self.inbox.queue.pop_timeout(timeout)
+  }
+
+  /// The messages queued so far
+  pub fn try_iter(&self) -> impl Iterator<Item=CallbackMessage<T>> + '_ {
+    std::iter::from_fn(|| self.try_recv())
+  }
+
+  /// Messages dropped because the channel was full, see `Backpressure`
+  pub fn dropped(&self) -> u64 {
+    self.inbox.dropped()
+  }
+
+  pub fn add_proxy(&mut self, callback: &mut CallbackSender<T>) -> Option<u64> {
+    let callback_id = callback.id;
+    if !self.callback_to_proxy.contains_key(&callback_id) {
+      let proxy_id = callback.add_callback(self.inbox.channel());
+      if self.callback_to_proxy.insert(callback_id, proxy_id).is_some() {
+        panic!("Id collision for {}", callback_id)
+      }
+      Some(proxy_id)
+    } else {
+      None
+    }
+  }
+
+  pub fn add_proxy_remotely(&mut self, callback_id: u64, remote: mpsc::SyncSender<CallbackChannel<T>>, remote_response: mpsc::Receiver<u64>, )
+    -> Option<impl FnMut() -> Option<u64> + '_>
+  {
+    if !self.callback_to_proxy.contains_key(&callback_id) {
+      if remote.send(self.inbox.channel()).is_err() {
+        return None;
+      }
+      Some(move || {
+        let proxy_id = match remote_response.recv() {
+          Ok(id) => id,
+          Err(_) => return None
+        };
+        if self.callback_to_proxy.insert(callback_id, proxy_id).is_some() {
+          panic!("Id collision for {}", callback_id)
+        }
+        Some(proxy_id)
+      })
+    } else {
+      None
+    }
+  }
+
+  pub fn remove_proxy(&mut self, callback: &mut CallbackSender<T>) -> Option<u64> {
+    if let Some(proxy_id) = self.callback_to_proxy.remove(&callback.id) {
+      callback.remove_callback(proxy_id).and(Some(proxy_id))
+    } else {
+      None
+    }
+  }
+
+  /// Woken on every message of the proxies, e.g. to wake an event loop instead of polling
+  pub fn set_waker(&self, waker: Option<Waker>) {
+    self.inbox.wakeup.set_listener(waker)
+  }
+}
+
+impl<T> Bus<T> {
+  pub fn new() -> Self {
+    Self { topics: Arc::new(Mutex::new(HashMap::new())), id: new_id() }
+  }
+
+  /// The `callback_id` of the messages
+  pub fn id(&self) -> u64 {
+    self.id
+  }
+
+  /// Receives the messages published on `topic` from now on
+  pub fn subscribe(&self, topic: &str, options: ChannelOptions) -> Subscription<T> {
+    let inbox = Inbox::new(options);
+    let id = new_id();
+    self.topics.lock().unwrap()
+      .entry(topic.to_string())
+      .or_insert_with(|| Topic { subscribers: Vec::new(), next_message_id: 0 })
+      .subscribers.push((id, inbox.channel()));
+    Subscription { inbox, topics: Arc::downgrade(&self.topics), topic: topic.to_string(), id }
+  }
+
+  pub fn subscribers(&self, topic: &str) -> usize {
+    self.topics.lock().unwrap().get(topic).map_or(0, |t| t.subscribers.len())
+  }
+}
+
+impl<T: Clone> Bus<T> {
+  /// Sends `message` to the subscribers of `topic` and returns how many got it. Blocks while a
+  /// subscriber with `Backpressure::Block` is full, without blocking the other publishers.
+  pub fn publish(&self, topic: &str, message: &T) -> usize {
+    let (id, subscribers) = {
+      let mut topics = self.topics.lock().unwrap();
+      match topics.get_mut(topic) {
+        Some(topic) if !topic.subscribers.is_empty() => {
+          topic.next_message_id += 1;
+          (topic.next_message_id - 1, topic.subscribers.clone())
+        },
+        _ => return 0,
+      }
+    };
+    let mut sent = 0;
+    for (_, subscriber) in subscribers.iter() {
+      let message = CallbackMessage { id, callback_id: self.id, message: message.clone() };
+      if subscriber.send(message).is_ok() {
+        sent += 1;
+      }
+    }
+    sent
+  }
+}
+
+impl<T> Subscription<T> {
+  pub fn topic(&self) -> &str {
+    &self.topic
+  }
+
+  pub fn try_recv(&self) -> Option<CallbackMessage<T>> {
+    self.inbox.queue.try_pop()
+  }
+
+  /// Waits at most `timeout` for a message
+  pub fn recv_timeout(&self, timeout: Duration) -> Option<CallbackMessage<T>> {
+    self.inbox.queue.pop_timeout(timeout)
+  }
+
+  /// The messages queued so far
+  pub fn try_iter(&self) -> impl Iterator<Item=CallbackMessage<T>> + '_ {
+    std::iter::from_fn(|| self.try_recv())
+  }
+
+  /// Messages dropped because the channel was full, see `Backpressure`
+  pub fn dropped(&self) -> u64 {
+    self.inbox.dropped()
+  }
+
+  /// Woken on every message, e.g. to wake an event loop instead of polling
+  pub fn set_waker(&self, waker: Option<Waker>) {
+    self.inbox.wakeup.set_listener(waker)
+  }
+}
+
+
+impl<T: Clone> CallbackSender<T> {
+  pub fn send(&mut self, x: &T) {
+    let mut i = 0;
+    while let Some((_, sender)) = self.senders.get(i) {
+      match sender.send(CallbackMessage { id: self.next_message_id, callback_id: self.id, message: x.clone() }) {
+        Ok(()) => i += 1,
+        Err(()) => { self.senders.swap_remove(i); }
+      }
+    }
+    if i > 0 {
+      self.next_message_id += 1;
+    }
+  }
+}
+
+
+/// The messages of all proxies. The stream doesn't end, the receiver keeps its channel open to add
+/// more proxies.
+#[cfg(feature = "async")]
+impl<T> futures_core::Stream for CallbackReceiver<T> {
+  type Item = CallbackMessage<T>;
+
+  fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    self.get_mut().inbox.poll_next(cx)
+  }
+}
+
+/// The messages of the topic. The stream doesn't end, even if the bus was dropped.
+#[cfg(feature = "async")]
+impl<T> futures_core::Stream for Subscription<T> {
+  type Item = CallbackMessage<T>;
+
+  fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    self.get_mut().inbox.poll_next(cx)
+  }
+}
+
+
+impl<T> Drop for Inbox<T> {
+  /// Senders drop the channel on their next message
+  fn drop(&mut self) {
+    self.queue.close()
+  }
+}
+
+impl<T> Drop for Subscription<T> {
+  fn drop(&mut self) {
+    // Closed first, lets a publisher blocked on this subscription go before the bus is locked
+    self.inbox.queue.close();
+    if let Some(topics) = self.topics.upgrade() {
+      let mut topics = topics.lock().unwrap();
+      if let Some(topic) = topics.get_mut(&self.topic) {
+        topic.subscribers.retain(|(id, _)| *id != self.id);
+        if topic.subscribers.is_empty() {
+          topics.remove(&self.topic);
+        }
+      }
+    }
+  }
+}
+
+
+// Derived, this would require `T: Clone`
+impl<T> Clone for CallbackChannel<T> {
+  fn clone(&self) -> Self {
+    Self { queue: self.queue.clone(), wakeup: self.wakeup.clone() }
+  }
+}
+
+impl<T> Clone for Bus<T> {
+  fn clone(&self) -> Self {
+    Self { topics: self.topics.clone(), id: self.id }
+  }
+}
+
+// Derived, this would require `T: Default`
+impl<T> Default for CallbackReceiver<T> {
+  fn default() -> Self {
+    Self::new(ChannelOptions::default())
+  }
+}
+
+impl<T> Default for CallbackSender<T> {
+  fn default() -> Self {
+    Self {
+      senders: Vec::new(),
+      id: new_id(),
+      next_message_id: 0,
+    }
+  }
+}
+
+impl<T> Default for Bus<T> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
@@ -0,0 +1,65 @@
+use std::{
+  future::{Future, IntoFuture},
+  pin::Pin,
+  task::{Context, Poll},
+};
+
+use super::{HandleError, RequestHandle, Ticket};
+
+
+/// A `RequestHandle` as a future, see `Scheduler::request_async`. It doesn't depend on an async
+/// runtime, the worker wakes the task that polled it last.
+pub struct RequestFuture<T, E = ()> {
+  handle: RequestHandle<T, E>,
+  done: bool,
+}
+
+
+impl<T, E> RequestFuture<T, E> {
+  pub(super) fn new(handle: RequestHandle<T, E>) -> Self {
+    Self { handle, done: false }
+  }
+
+  pub fn id(&self) -> u64 {
+    self.handle.id()
+  }
+
+  pub fn ticket(&self) -> &Ticket {
+    self.handle.ticket()
+  }
+}
+
+impl<T, E> Future for RequestFuture<T, E> {
+  type Output = Result<T, HandleError<E>>;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    let this = self.get_mut();
+    // Registered first, a result sent in between wakes the task again
+    this.handle.wakeup.register(cx.waker());
+    match this.handle.try_take() {
+      Some(res) => {
+        this.done = true;
+        Poll::Ready(res)
+      },
+      None => Poll::Pending,
+    }
+  }
+}
+
+impl<T, E> IntoFuture for RequestHandle<T, E> {
+  type Output = Result<T, HandleError<E>>;
+  type IntoFuture = RequestFuture<T, E>;
+
+  fn into_future(self) -> Self::IntoFuture {
+    RequestFuture::new(self)
+  }
+}
+
+impl<T, E> Drop for RequestFuture<T, E> {
+  /// Nobody is waiting for the result anymore
+  fn drop(&mut self) {
+    if !self.done {
+      self.handle.cancel();
+    }
+  }
+}
@@ -0,0 +1,233 @@
+use std::{
+  collections::BTreeMap,
+  sync::{Mutex, Weak},
+  time::{Duration, Instant},
+};
+
+
+/// What the workers of a scheduler did since the metrics were reset, see `Scheduler::metrics`
+#[derive(Clone, Debug)]
+pub struct SchedulerMetrics {
+  /// See `Scheduler::register_metrics`, empty for unregistered schedulers
+  pub name: String,
+  /// Running workers by index
+  pub workers: BTreeMap<usize, WorkerMetrics>,
+  /// By the label `Scheduler::register_metrics` gives the requests, e.g. `Seek` for
+  /// `ScrubRequest::Seek { .. }`
+  pub kinds: BTreeMap<&'static str, KindMetrics>,
+  /// When the metrics were reset
+  pub since: Instant,
+  /// When the metrics were taken
+  pub at: Instant,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WorkerMetrics {
+  /// Requests waiting for the worker, including the ones other workers may steal
+  pub queue_depth: usize,
+  pub handled: u64,
+  /// Time spent handling requests, including the one being handled
+  pub busy: Duration,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KindMetrics {
+  pub handled: u64,
+  /// Answered with `Response::Err` or panicked
+  pub failed: u64,
+  /// Cancelled or superseded before a worker got to them
+  pub cancelled: u64,
+  /// The deadline passed before a worker got to them
+  pub expired: u64,
+  /// Sum of the times between sending the requests and a worker taking them
+  pub wait: Duration,
+  pub max_wait: Duration,
+  /// Sum of the times the workers spent handling the requests
+  pub service: Duration,
+  pub max_service: Duration,
+}
+
+/// What became of a request
+pub(super) enum Outcome {
+  Handled { wait: Duration, service: Duration, failed: bool },
+  Cancelled,
+  Expired,
+}
+
+/// Recorded by a scheduler and its workers
+pub(super) struct Metrics {
+  state: Mutex<MetricsState>,
+}
+
+struct MetricsState {
+  metrics: SchedulerMetrics,
+  /// When the workers took the request they are handling
+  handling: BTreeMap<usize, Instant>,
+}
+
+/// A scheduler whose metrics are listed by `registered_metrics`
+pub(super) trait MetricsSource: Send + Sync {
+  fn snapshot(&self) -> SchedulerMetrics;
+}
+
+static REGISTERED: Mutex<Vec<Weak<dyn MetricsSource>>> = Mutex::new(Vec::new());
+
+
+/// Metrics of the schedulers registered with `Scheduler::register_metrics` that still exist, e.g.
+/// for a debug overlay
+pub fn registered_metrics() -> Vec<SchedulerMetrics> {
+  let sources: Vec<_> = {
+    let mut registered = REGISTERED.lock().unwrap();
+    registered.retain(|source| source.strong_count() > 0);
+    registered.iter().filter_map(Weak::upgrade).collect()
+  };
+  sources.iter().map(|source| source.snapshot()).collect()
+}
+
+pub(super) fn register(source: Weak<dyn MetricsSource>) {
+  REGISTERED.lock().unwrap().push(source)
+}
+
+/// Label of the requests of schedulers without `Scheduler::register_metrics`
+pub(super) fn unlabelled<Req>(_request: &Req) -> &'static str {
+  "request"
+}
+
+
+impl SchedulerMetrics {
+  fn new() -> Self {
+    let now = Instant::now();
+    Self { name: String::new(), workers: BTreeMap::new(), kinds: BTreeMap::new(), since: now, at: now }
+  }
+
+  /// Fraction of the time since `since` that `worker` spent handling requests
+  pub fn busy_ratio(&self, worker: usize) -> f32 {
+    let busy = self.workers.get(&worker).map_or(Duration::ZERO, |w| w.busy);
+    ratio(busy, self.at - self.since)
+  }
+
+  /// Like `busy_ratio`, since the `earlier` metrics of the same scheduler were taken, e.g. for a
+  /// live graph
+  pub fn busy_ratio_since(&self, earlier: &SchedulerMetrics, worker: usize) -> f32 {
+    match earlier.workers.get(&worker) {
+      Some(before) if earlier.since == self.since && earlier.at < self.at => {
+        let busy = self.workers.get(&worker).map_or(Duration::ZERO, |w| w.busy);
+        ratio(busy.saturating_sub(before.busy), self.at - earlier.at)
+      },
+      _ => self.busy_ratio(worker),
+    }
+  }
+
+  /// Requests waiting for any worker
+  pub fn queue_depth(&self) -> usize {
+    self.workers.values().map(|w| w.queue_depth).sum()
+  }
+}
+
+fn ratio(part: Duration, total: Duration) -> f32 {
+  if total.is_zero() {
+    0.
+  } else {
+    (part.as_secs_f64() / total.as_secs_f64()).min(1.) as f32
+  }
+}
+
+impl KindMetrics {
+  pub fn mean_wait(&self) -> Duration {
+    mean(self.wait, self.handled)
+  }
+
+  pub fn mean_service(&self) -> Duration {
+    mean(self.service, self.handled)
+  }
+}
+
+fn mean(sum: Duration, count: u64) -> Duration {
+  match count {
+    0 => Duration::ZERO,
+    count => sum / count.min(u32::MAX as u64) as u32,
+  }
+}
+
+impl Metrics {
+  pub(super) fn new() -> Self {
+    Self { state: Mutex::new(MetricsState { metrics: SchedulerMetrics::new(), handling: BTreeMap::new() }) }
+  }
+
+  pub(super) fn set_name(&self, name: &str) {
+    self.state.lock().unwrap().metrics.name = name.to_string();
+  }
+
+  pub(super) fn add_worker(&self, worker: usize) {
+    self.state.lock().unwrap().metrics.workers.entry(worker).or_default();
+  }
+
+  pub(super) fn remove_worker(&self, worker: usize) {
+    let mut state = self.state.lock().unwrap();
+    state.metrics.workers.remove(&worker);
+    state.handling.remove(&worker);
+  }
+
+  /// `worker` took a request
+  pub(super) fn start(&self, worker: usize) {
+    self.state.lock().unwrap().handling.insert(worker, Instant::now());
+  }
+
+  /// Handled requests count for `worker`, dropped ones may come from the scheduler itself
+  pub(super) fn record(&self, worker: Option<usize>, kind: &'static str, outcome: Outcome) {
+    let mut state = self.state.lock().unwrap();
+    let kind = state.metrics.kinds.entry(kind).or_default();
+    match outcome {
+      Outcome::Handled { wait, service, failed } => {
+        kind.handled += 1;
+        kind.failed += failed as u64;
+        kind.wait += wait;
+        kind.max_wait = kind.max_wait.max(wait);
+        kind.service += service;
+        kind.max_service = kind.max_service.max(service);
+        if let Some(worker) = worker {
+          state.handling.remove(&worker);
+          let worker = state.metrics.workers.entry(worker).or_default();
+          worker.handled += 1;
+          worker.busy += service;
+        }
+      },
+      Outcome::Cancelled => kind.cancelled += 1,
+      Outcome::Expired => kind.expired += 1,
+    }
+  }
+
+  /// The metrics so far, with the queue depths of `queue_depth`
+  pub(super) fn snapshot(&self, queue_depth: impl Fn(usize) -> usize) -> SchedulerMetrics {
+    let mut res = {
+      let state = self.state.lock().unwrap();
+      let mut res = state.metrics.clone();
+      res.at = Instant::now();
+      for (worker, started) in state.handling.iter() {
+        if let Some(metrics) = res.workers.get_mut(worker) {
+          metrics.busy += res.at.saturating_duration_since(*started);
+        }
+      }
+      res
+    };
+    for (&worker, metrics) in res.workers.iter_mut() {
+      metrics.queue_depth = queue_depth(worker);
+    }
+    res
+  }
+
+  /// Starts over, the requests being handled count from now on
+  pub(super) fn reset(&self) {
+    let mut state = self.state.lock().unwrap();
+    let now = Instant::now();
+    let MetricsState { metrics, handling } = &mut *state;
+    metrics.since = now;
+    metrics.kinds.clear();
+    for worker in metrics.workers.values_mut() {
+      *worker = WorkerMetrics::default();
+    }
+    for started in handling.values_mut() {
+      *started = now;
+    }
+  }
+}
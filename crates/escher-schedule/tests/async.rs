@@ -0,0 +1,115 @@
+#![cfg(feature = "async")]
+
+mod common;
+
+use std::{
+  future::Future,
+  pin::{Pin, pin},
+  sync::{Arc, Barrier},
+  task::{Context, Poll, Wake, Waker},
+  thread::{self, Thread},
+  time::{Duration, Instant},
+};
+
+use escher_schedule::*;
+use futures_core::Stream;
+
+use common::*;
+
+/// Unparks the thread blocked in `block_on`
+struct Unpark(Thread);
+
+
+/// Polls `future` on this thread until it's ready, without an async runtime
+fn block_on<F: Future>(future: F) -> F::Output {
+  let mut future = pin!(future);
+  let waker = Waker::from(Arc::new(Unpark(thread::current())));
+  let mut cx = Context::from_waker(&waker);
+  let start = Instant::now();
+  loop {
+    if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+      return output;
+    }
+    assert!(start.elapsed() < Duration::from_secs(10), "the future wasn't woken");
+    thread::park_timeout(Duration::from_secs(1));
+  }
+}
+
+/// The next `n` items of `stream`
+async fn take<S: Stream + Unpin>(stream: &mut S, n: usize) -> Vec<S::Item> {
+  let mut items = Vec::new();
+  while items.len() < n {
+    let next = std::future::poll_fn(|cx| Pin::new(&mut *stream).poll_next(cx)).await;
+    match next {
+      Some(item) => items.push(item),
+      None => break,
+    }
+  }
+  items
+}
+
+
+impl Wake for Unpark {
+  fn wake(self: Arc<Self>) {
+    self.0.unpark();
+  }
+}
+
+
+#[test]
+fn requests_can_be_chained() {
+  let scheduler = scheduler(2, runs(0));
+  let res = block_on(async {
+    let mut x = 1;
+    for _ in 0..10 {
+      x = scheduler.request_async(Job::Double(x), BroadcastKind::Any, RequestOptions::default()).unwrap().await?;
+    }
+    Ok::<_, HandleError<()>>(x)
+  });
+  assert_eq!(res, Ok(1024));
+}
+
+#[test]
+fn errors_resolve_the_future() {
+  let scheduler = scheduler(1, runs(0));
+  let failed = scheduler.request_async(Job::Fail, BroadcastKind::Any, RequestOptions::default()).unwrap();
+  assert_eq!(block_on(failed), Err(HandleError::Failed(())));
+  let handle = scheduler.request_handle(Job::Double(4), BroadcastKind::Any, RequestOptions::default()).unwrap();
+  assert_eq!(block_on(async { handle.await }), Ok(8));
+}
+
+#[test]
+fn dropping_the_future_cancels_the_request() {
+  let scheduler = scheduler(1, runs(0));
+  let barrier = Arc::new(Barrier::new(2));
+  let stuck = scheduler.request_handle(Job::Wait(barrier.clone()), BroadcastKind::Any, RequestOptions::default()).unwrap();
+  let future = scheduler.request_async(Job::Double(1), BroadcastKind::Any, RequestOptions::default()).unwrap();
+  let ticket = future.ticket().clone();
+  drop(future);
+  barrier.wait();
+  assert_eq!(stuck.wait(Duration::from_secs(10)), Ok(0));
+  assert!(ticket.is_cancelled());
+}
+
+#[test]
+fn callback_receiver_is_a_stream() {
+  let mut receiver = CallbackReceiver::default();
+  let (remote_tx, remote_rx) = std::sync::mpsc::sync_channel(1);
+  let (id_tx, id_rx) = std::sync::mpsc::channel();
+  // Like a worker adding the channel to its sender, see `add_proxy_remotely`
+  let subscriber = thread::spawn(move || {
+    let mut pub_tx = CallbackSender::default();
+    let channel: CallbackChannel<usize> = remote_rx.recv().unwrap();
+    id_tx.send(pub_tx.add_callback(channel)).unwrap();
+    for i in 0..5 {
+      thread::sleep(Duration::from_millis(5));
+      pub_tx.send(&i);
+    }
+  });
+  if let Some(mut wait_for_sender) = receiver.add_proxy_remotely(0, remote_tx, id_rx) {
+    wait_for_sender();
+  }
+  let messages = block_on(take(&mut receiver, 5));
+  subscriber.join().unwrap();
+  assert_eq!(messages.iter().map(|m| *m.message()).collect::<Vec<_>>(), vec![0, 1, 2, 3, 4]);
+}
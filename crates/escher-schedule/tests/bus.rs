@@ -0,0 +1,111 @@
+use std::{
+  sync::mpsc,
+  thread,
+  time::Duration,
+};
+
+use escher_schedule::*;
+
+
+fn messages<T: Copy>(subscription: &Subscription<T>) -> Vec<T> {
+  subscription.try_iter().map(|m| *m.message()).collect()
+}
+
+
+#[test]
+fn every_subscriber_of_a_topic_gets_the_messages() {
+  let bus = Bus::new();
+  let first = bus.subscribe("export", ChannelOptions::default());
+  let second = bus.subscribe("export", ChannelOptions::default());
+  let other = bus.subscribe("proxies", ChannelOptions::default());
+  assert_eq!(bus.publish("export", &1), 2);
+  assert_eq!(bus.clone().publish("export", &2), 2);
+  assert_eq!(messages(&first), vec![1, 2]);
+  assert_eq!(messages(&second), vec![1, 2]);
+  assert!(other.try_recv().is_none());
+  assert_eq!(bus.publish("nobody", &3), 0);
+}
+
+#[test]
+fn dropped_subscriptions_unsubscribe() {
+  let bus = Bus::new();
+  let kept = bus.subscribe("export", ChannelOptions::default());
+  let dropped = bus.subscribe("export", ChannelOptions::default());
+  assert_eq!(bus.subscribers("export"), 2);
+  drop(dropped);
+  assert_eq!(bus.subscribers("export"), 1);
+  assert_eq!(bus.publish("export", &1), 1);
+  assert_eq!(messages(&kept), vec![1]);
+  // Outlives the bus
+  drop(bus);
+  assert!(kept.try_recv().is_none());
+}
+
+#[test]
+fn full_channels_drop_by_their_policy() {
+  let bus = Bus::new();
+  let oldest = bus.subscribe("progress", ChannelOptions::bounded(2, Backpressure::DropOldest));
+  let newest = bus.subscribe("progress", ChannelOptions::bounded(2, Backpressure::DropNewest));
+  for i in 0..5 {
+    bus.publish("progress", &i);
+  }
+  assert_eq!(messages(&oldest), vec![3, 4]);
+  assert_eq!(oldest.dropped(), 3);
+  assert_eq!(messages(&newest), vec![0, 1]);
+  assert_eq!(newest.dropped(), 3);
+}
+
+#[test]
+fn blocking_channels_hold_the_publisher_back() {
+  let bus = Bus::new();
+  let subscription = bus.subscribe("frames", ChannelOptions::bounded(1, Backpressure::Block));
+  let (done_tx, done_rx) = mpsc::channel();
+  let publisher = {
+    let bus = bus.clone();
+    thread::spawn(move || {
+      for i in 0..3 {
+        bus.publish("frames", &i);
+      }
+      done_tx.send(()).unwrap();
+    })
+  };
+  assert!(done_rx.recv_timeout(Duration::from_millis(50)).is_err());
+  let received: Vec<_> = (0..3)
+    .map(|_| *subscription.recv_timeout(Duration::from_secs(10)).unwrap().message())
+    .collect();
+  assert_eq!(received, vec![0, 1, 2]);
+  assert!(done_rx.recv_timeout(Duration::from_secs(10)).is_ok());
+  publisher.join().unwrap();
+  assert_eq!(subscription.dropped(), 0);
+}
+
+#[test]
+fn dropping_a_subscription_releases_a_blocked_publisher() {
+  let bus = Bus::new();
+  let subscription = bus.subscribe("frames", ChannelOptions::bounded(1, Backpressure::Block));
+  bus.publish("frames", &0);
+  let publisher = {
+    let bus = bus.clone();
+    thread::spawn(move || bus.publish("frames", &1))
+  };
+  thread::sleep(Duration::from_millis(20));
+  drop(subscription);
+  assert_eq!(publisher.join().unwrap(), 0);
+}
+
+#[test]
+fn senders_forget_dropped_receivers() {
+  let mut sender = CallbackSender::default();
+  let mut kept = CallbackReceiver::default();
+  let mut dropped = CallbackReceiver::new(ChannelOptions::bounded(1, Backpressure::Block));
+  kept.add_proxy(&mut sender);
+  dropped.add_proxy(&mut sender);
+  assert_eq!(sender.iter_callback_ids().count(), 2);
+  sender.send(&1);
+  drop(dropped);
+  // Would block on the full channel if it were still open
+  sender.send(&2);
+  assert_eq!(sender.iter_callback_ids().count(), 1);
+  let received: Vec<_> = kept.try_iter().map(|m| (m.id(), m.callback_id(), m.into_message())).collect();
+  assert_eq!(received, vec![(0, sender.id(), 1), (1, sender.id(), 2)]);
+}
@@ -0,0 +1,111 @@
+// Every test binary uses a different part of this
+#![allow(dead_code)]
+
+use std::{
+  sync::{Arc, Barrier, Mutex, atomic::{AtomicUsize, Ordering}},
+  thread,
+  time::Duration,
+};
+
+use escher_schedule::*;
+
+/// Responds with the index of the worker that ran it unless noted otherwise
+#[derive(Clone, Debug)]
+pub enum Job {
+  /// Counts a run of job `i`, see `Runs`
+  Count(usize),
+  /// Responds with twice the value
+  Double(usize),
+  Sleep(u64),
+  Wait(Arc<Barrier>),
+  /// Responds with `Response::Err`
+  Fail,
+  Panic,
+}
+
+/// What the workers ran
+pub struct Runs {
+  counts: Vec<AtomicUsize>,
+  /// Which worker ran which `Count` job, in the order they ran
+  log: Mutex<Vec<(usize, usize)>>,
+  /// Which worker ran each job that didn't fail, in the order they ran
+  pub workers: Mutex<Vec<usize>>,
+}
+
+
+impl Job {
+  /// The variant, for `Scheduler::register_metrics`
+  pub fn kind(&self) -> &'static str {
+    match self {
+      Job::Count(_) => "Count",
+      Job::Double(_) => "Double",
+      Job::Sleep(_) => "Sleep",
+      Job::Wait(_) => "Wait",
+      Job::Fail => "Fail",
+      Job::Panic => "Panic",
+    }
+  }
+}
+
+/// Records for `Count(0)` to `Count(num_jobs - 1)`
+pub fn runs(num_jobs: usize) -> Arc<Runs> {
+  Arc::new(Runs {
+    counts: (0..num_jobs).map(|_| AtomicUsize::new(0)).collect(),
+    log: Mutex::new(Vec::new()),
+    workers: Mutex::new(Vec::new()),
+  })
+}
+
+/// Runs of each `Count` job
+pub fn counts(runs: &Runs) -> Vec<usize> {
+  runs.counts.iter().map(|count| count.load(Ordering::SeqCst)).collect()
+}
+
+/// The `Count` jobs run since the last call with their workers
+pub fn take_log(runs: &Runs) -> Vec<(usize, usize)> {
+  std::mem::take(&mut *runs.log.lock().unwrap())
+}
+
+fn handle(runs: &Runs, worker_idx: usize, job: Job) -> Response<usize> {
+  let resp = match job {
+    Job::Count(i) => {
+      runs.counts[i].fetch_add(1, Ordering::SeqCst);
+      runs.log.lock().unwrap().push((worker_idx, i));
+      Response::Ok(worker_idx)
+    },
+    Job::Double(x) => Response::Ok(2 * x),
+    Job::Sleep(ms) => {
+      thread::sleep(Duration::from_millis(ms));
+      Response::Ok(worker_idx)
+    },
+    Job::Wait(barrier) => {
+      barrier.wait();
+      Response::Ok(worker_idx)
+    },
+    Job::Fail => return Response::Err(()),
+    Job::Panic => panic!("job panicked"),
+  };
+  runs.workers.lock().unwrap().push(worker_idx);
+  resp
+}
+
+pub fn scheduler(num_workers: usize, runs: Arc<Runs>) -> Scheduler<Job, usize> {
+  Scheduler::new(num_workers, move |worker_idx| {
+    let runs = runs.clone();
+    move |job: Job, _: RequestKind, _: &mut CallbackSender<()>| handle(&runs, worker_idx, job)
+  })
+}
+
+/// Like `scheduler`, the workers run on the calling thread, see `Scheduler::step`
+pub fn inline_scheduler(num_workers: usize, interleaving: Interleaving, runs: Arc<Runs>) -> Scheduler<Job, usize> {
+  Scheduler::new_inline(num_workers, interleaving, move |worker_idx| {
+    let runs = runs.clone();
+    move |job: Job, _: RequestKind, _: &mut CallbackSender<()>| handle(&runs, worker_idx, job)
+  })
+}
+
+/// Stops after the queued jobs
+pub fn finish(scheduler: &mut Scheduler<Job, usize>) {
+  scheduler.shutdown(Shutdown::Drain);
+  assert!(scheduler.join(Duration::from_secs(10)));
+}
@@ -0,0 +1,143 @@
+mod common;
+
+use std::{
+  sync::{Arc, Barrier, Mutex},
+  thread,
+  time::{Duration, Instant},
+};
+
+use escher_schedule::*;
+
+use common::*;
+
+
+#[test]
+fn any_runs_every_request_once() {
+  const JOBS: usize = 20_000;
+  let runs = runs(JOBS);
+  let mut scheduler = scheduler(8, runs.clone());
+  for i in 0..JOBS {
+    scheduler.request(Job::Count(i), BroadcastKind::Any).unwrap();
+  }
+  finish(&mut scheduler);
+  assert!(counts(&runs).iter().all(|&count| count == 1));
+}
+
+#[test]
+fn multiple_times_runs_n_times() {
+  let runs = runs(3);
+  let mut scheduler = scheduler(4, runs.clone());
+  for _ in 0..500 {
+    scheduler.request(Job::Count(0), BroadcastKind::MulipleTimes(1)).unwrap();
+    scheduler.request(Job::Count(1), BroadcastKind::MulipleTimes(3)).unwrap();
+    scheduler.request(Job::Count(2), BroadcastKind::MulipleTimes(7)).unwrap();
+  }
+  finish(&mut scheduler);
+  assert_eq!(counts(&runs), vec![500, 1500, 3500]);
+}
+
+#[test]
+fn any_from_several_threads_runs_once() {
+  const JOBS: usize = 4_000;
+  let runs = runs(JOBS);
+  let scheduler = Arc::new(Mutex::new(scheduler(6, runs.clone())));
+  let senders: Vec<_> = (0..4)
+    .map(|t| {
+      let scheduler = scheduler.clone();
+      thread::spawn(move || {
+        for i in (t..JOBS).step_by(4) {
+          scheduler.lock().unwrap().request(Job::Count(i), BroadcastKind::Any).unwrap();
+          if i % 64 == 0 {
+            scheduler.lock().unwrap().handle_respones(|_, _| Ok::<(), ()>(())).unwrap();
+          }
+        }
+      })
+    })
+    .collect();
+  for sender in senders {
+    sender.join().unwrap();
+  }
+  finish(&mut scheduler.lock().unwrap());
+  assert!(counts(&runs).iter().all(|&count| count == 1));
+}
+
+#[test]
+fn idle_workers_steal_from_busy_ones() {
+  let runs = runs(0);
+  let scheduler = scheduler(4, runs.clone());
+  // One worker is stuck, everything queued for it has to be stolen
+  let barrier = Arc::new(Barrier::new(2));
+  let stuck = scheduler.request_handle(Job::Wait(barrier.clone()), BroadcastKind::Specific(0), RequestOptions::default()).unwrap();
+  while stuck.ticket().status() != TicketStatus::Running {
+    thread::yield_now();
+  }
+  let start = Instant::now();
+  let handles: Vec<_> = (0..200)
+    .map(|_| scheduler.request_handle(Job::Sleep(1), BroadcastKind::Any, RequestOptions::default()).unwrap())
+    .collect();
+  for handle in handles {
+    assert_ne!(handle.wait(Duration::from_secs(10)), Ok(0));
+  }
+  assert!(start.elapsed() < Duration::from_secs(10));
+  barrier.wait();
+  assert_eq!(stuck.wait(Duration::from_secs(10)), Ok(0));
+}
+
+#[test]
+fn any_spreads_over_workers() {
+  let runs = runs(0);
+  let mut scheduler = scheduler(4, runs.clone());
+  for _ in 0..200 {
+    scheduler.request(Job::Sleep(1), BroadcastKind::Any).unwrap();
+  }
+  finish(&mut scheduler);
+  let workers = runs.workers.lock().unwrap();
+  for worker in 0..4 {
+    assert!(workers.iter().filter(|&&w| w == worker).count() > 10, "worker {} was left out", worker);
+  }
+}
+
+#[test]
+fn do_now_needs_an_idle_worker() {
+  let runs = runs(1);
+  let mut scheduler = scheduler(2, runs.clone());
+  let barrier = Arc::new(Barrier::new(3));
+  scheduler.request(Job::Wait(barrier.clone()), BroadcastKind::DoNow).unwrap();
+  scheduler.request(Job::Wait(barrier.clone()), BroadcastKind::DoNow).unwrap();
+  // Both workers are waiting at the barrier now or are about to
+  match scheduler.request(Job::Count(0), BroadcastKind::DoNow) {
+    Err(RequestError::NoReadyWorkers(_)) => (),
+    res => panic!("DoNow wasn't refused: {:?}", res),
+  }
+  let ticket = scheduler.request(Job::Count(0), BroadcastKind::TryNow).unwrap();
+  assert_eq!(ticket.status(), TicketStatus::Cancelled);
+  barrier.wait();
+  finish(&mut scheduler);
+  assert_eq!(counts(&runs), vec![0]);
+}
+
+#[test]
+fn requests_of_a_panicked_worker_go_to_the_others() {
+  const JOBS: usize = 2_000;
+  let runs = runs(JOBS);
+  let mut scheduler = scheduler(3, runs.clone());
+  scheduler.request(Job::Panic, BroadcastKind::Specific(1)).unwrap();
+  for i in 0..JOBS {
+    scheduler.request(Job::Count(i), BroadcastKind::Any).unwrap();
+  }
+  let mut died = Vec::new();
+  let start = Instant::now();
+  while (died.is_empty() || counts(&runs).contains(&0)) && start.elapsed() < Duration::from_secs(10) {
+    scheduler.handle_respones(|resp, _| {
+      if let Response::WorkerDied { worker, payload } = resp {
+        died.push((worker, panic_message(payload.as_ref()).to_string()));
+      }
+      Ok::<(), ()>(())
+    }).unwrap();
+    thread::sleep(Duration::from_millis(1));
+  }
+  assert_eq!(died, vec![(1, "job panicked".to_string())]);
+  assert_eq!(scheduler.num_workers(), 2);
+  finish(&mut scheduler);
+  assert!(counts(&runs).iter().all(|&count| count == 1));
+}
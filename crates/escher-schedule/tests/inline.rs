@@ -0,0 +1,332 @@
+mod common;
+
+use std::{
+  collections::BTreeSet,
+  thread,
+  time::Duration,
+};
+
+use escher_schedule::*;
+
+use common::*;
+
+
+/// Three jobs for each of three workers, in the order the workers ran them
+fn interleave(seed: u64) -> Vec<(usize, usize)> {
+  let runs = runs(9);
+  let scheduler = inline_scheduler(3, Interleaving::Seeded(seed), runs.clone());
+  for worker in 0..3 {
+    for i in 0..3 {
+      scheduler.request(Job::Count(worker * 3 + i), BroadcastKind::Specific(worker)).unwrap();
+    }
+  }
+  scheduler.run_until_idle();
+  take_log(&runs)
+}
+
+
+#[test]
+fn nothing_runs_before_a_step() {
+  let runs = runs(9);
+  let scheduler = inline_scheduler(2, Interleaving::RoundRobin, runs.clone());
+  for i in 0..3 {
+    scheduler.request(Job::Count(i), BroadcastKind::Any).unwrap();
+  }
+  assert!(take_log(&runs).is_empty());
+  assert!(scheduler.step());
+  assert_eq!(take_log(&runs), vec![(0, 0)]);
+  assert_eq!(scheduler.run_until_idle(), 2);
+  assert_eq!(take_log(&runs), vec![(1, 1), (0, 2)]);
+  assert!(!scheduler.step());
+}
+
+#[test]
+fn specific_runs_on_that_worker() {
+  let runs = runs(9);
+  let scheduler = inline_scheduler(3, Interleaving::RoundRobin, runs.clone());
+  scheduler.request(Job::Count(0), BroadcastKind::Specific(2)).unwrap();
+  scheduler.request(Job::Count(1), BroadcastKind::Specific(2)).unwrap();
+  assert!(matches!(scheduler.request(Job::Count(2), BroadcastKind::Specific(3)), Err(RequestError::IndexInvalid(Job::Count(2)))));
+  scheduler.run_until_idle();
+  assert_eq!(take_log(&runs), vec![(2, 0), (2, 1)]);
+}
+
+#[test]
+fn all_runs_on_every_worker() {
+  let runs = runs(9);
+  let scheduler = inline_scheduler(3, Interleaving::RoundRobin, runs.clone());
+  let ticket = scheduler.request(Job::Count(0), BroadcastKind::All).unwrap();
+  scheduler.run_until_idle();
+  assert_eq!(take_log(&runs), vec![(0, 0), (1, 0), (2, 0)]);
+  assert_eq!(ticket.status(), TicketStatus::Done);
+}
+
+#[test]
+fn any_runs_once_on_the_least_busy_worker() {
+  let runs = runs(9);
+  let scheduler = inline_scheduler(3, Interleaving::RoundRobin, runs.clone());
+  for i in 0..4 {
+    scheduler.request(Job::Count(i), BroadcastKind::Any).unwrap();
+  }
+  scheduler.run_until_idle();
+  assert_eq!(take_log(&runs), vec![(0, 0), (1, 1), (2, 2), (0, 3)]);
+}
+
+#[test]
+fn any_is_stolen_by_idle_workers() {
+  let runs = runs(9);
+  let scheduler = inline_scheduler(2, Interleaving::RoundRobin, runs.clone());
+  // Requests sent to a worker don't count for `Any`, so 1 is queued for worker 0 and 2 for worker 1
+  scheduler.request(Job::Count(0), BroadcastKind::Specific(1)).unwrap();
+  for i in 1..3 {
+    scheduler.request(Job::Count(i), BroadcastKind::Any).unwrap();
+  }
+  assert!(scheduler.step());
+  assert!(scheduler.step());
+  assert!(scheduler.step());
+  // Worker 1 took the request sent to it first, meanwhile worker 0 stole 2
+  assert_eq!(take_log(&runs), vec![(0, 1), (1, 0), (0, 2)]);
+}
+
+#[test]
+fn do_now_needs_an_idle_worker() {
+  let runs = runs(9);
+  let scheduler = inline_scheduler(2, Interleaving::RoundRobin, runs.clone());
+  scheduler.request(Job::Count(0), BroadcastKind::DoNow).unwrap();
+  scheduler.request(Job::Count(1), BroadcastKind::DoNow).unwrap();
+  assert!(matches!(scheduler.request(Job::Count(2), BroadcastKind::DoNow), Err(RequestError::NoReadyWorkers(Job::Count(2)))));
+  scheduler.run_until_idle();
+  scheduler.request(Job::Count(2), BroadcastKind::DoNow).unwrap();
+  scheduler.run_until_idle();
+  assert_eq!(take_log(&runs), vec![(0, 0), (1, 1), (0, 2)]);
+}
+
+#[test]
+fn try_now_cancels_without_an_idle_worker() {
+  let runs = runs(9);
+  let scheduler = inline_scheduler(1, Interleaving::RoundRobin, runs.clone());
+  scheduler.register_metrics("try now test", Job::kind);
+  let ran = scheduler.request(Job::Count(0), BroadcastKind::TryNow).unwrap();
+  let cancelled = scheduler.request(Job::Count(1), BroadcastKind::TryNow).unwrap();
+  assert_eq!(cancelled.status(), TicketStatus::Cancelled);
+  scheduler.run_until_idle();
+  assert_eq!(take_log(&runs), vec![(0, 0)]);
+  assert_eq!(ran.status(), TicketStatus::Done);
+  assert_eq!(scheduler.metrics().kinds["Count"].cancelled, 1);
+}
+
+#[test]
+fn multiple_times_runs_n_times() {
+  let runs = runs(9);
+  let scheduler = inline_scheduler(3, Interleaving::RoundRobin, runs.clone());
+  scheduler.request(Job::Count(0), BroadcastKind::MulipleTimes(5)).unwrap();
+  scheduler.run_until_idle();
+  let workers: Vec<_> = take_log(&runs).into_iter().map(|(worker, _)| worker).collect();
+  assert_eq!(workers, vec![0, 1, 2, 0, 1]);
+}
+
+#[test]
+fn higher_priorities_run_first() {
+  let runs = runs(9);
+  let scheduler = inline_scheduler(1, Interleaving::RoundRobin, runs.clone());
+  let priorities = [Priority::Background, Priority::Interactive, Priority::Prefetch, Priority::Interactive, Priority::Background];
+  for (i, priority) in priorities.into_iter().enumerate() {
+    scheduler.request_with(Job::Count(i), BroadcastKind::Any, RequestOptions::new(priority)).unwrap();
+  }
+  scheduler.run_until_idle();
+  // Same priorities in the order they were sent
+  assert_eq!(take_log(&runs), vec![(0, 1), (0, 3), (0, 2), (0, 0), (0, 4)]);
+}
+
+#[test]
+fn priorities_count_across_queues() {
+  let runs = runs(9);
+  let scheduler = inline_scheduler(1, Interleaving::RoundRobin, runs.clone());
+  // The worker's own queue and the shared one
+  scheduler.request_with(Job::Count(0), BroadcastKind::Specific(0), RequestOptions::new(Priority::Background)).unwrap();
+  scheduler.request_with(Job::Count(1), BroadcastKind::Any, RequestOptions::new(Priority::Prefetch)).unwrap();
+  scheduler.request_with(Job::Count(2), BroadcastKind::Specific(0), RequestOptions::new(Priority::Interactive)).unwrap();
+  scheduler.request_with(Job::Count(3), BroadcastKind::Any, RequestOptions::new(Priority::Background)).unwrap();
+  scheduler.run_until_idle();
+  // On a tie the worker's own request goes first
+  assert_eq!(take_log(&runs), vec![(0, 2), (0, 1), (0, 0), (0, 3)]);
+}
+
+#[test]
+fn expired_requests_are_dropped() {
+  let runs = runs(9);
+  let mut scheduler = inline_scheduler(1, Interleaving::RoundRobin, runs.clone());
+  scheduler.register_metrics("deadline test", Job::kind);
+  let expired = scheduler.request_with(Job::Count(0), BroadcastKind::Any, RequestOptions::default().set_timeout(Duration::ZERO)).unwrap();
+  let handle = scheduler.request_handle(Job::Count(1), BroadcastKind::Any, RequestOptions::default().set_timeout(Duration::ZERO)).unwrap();
+  let in_time = scheduler.request_with(Job::Count(2), BroadcastKind::Any, RequestOptions::default().set_timeout(Duration::from_secs(60))).unwrap();
+  thread::sleep(Duration::from_millis(1));
+  assert_eq!(expired.status(), TicketStatus::Queued);
+  scheduler.run_until_idle();
+  assert_eq!(take_log(&runs), vec![(0, 2)]);
+  assert_eq!(expired.status(), TicketStatus::Expired);
+  assert_eq!(in_time.status(), TicketStatus::Done);
+  assert!(matches!(handle.try_take(), Some(Err(HandleError::Expired))));
+  assert_eq!(scheduler.metrics().kinds["Count"].expired, 2);
+  finish(&mut scheduler);
+}
+
+#[test]
+fn cancelled_requests_are_skipped() {
+  let runs = runs(9);
+  let scheduler = inline_scheduler(1, Interleaving::RoundRobin, runs.clone());
+  let cancelled = scheduler.request(Job::Count(0), BroadcastKind::Any).unwrap();
+  let handle = scheduler.request_handle(Job::Count(1), BroadcastKind::Specific(0), RequestOptions::default()).unwrap();
+  let ticket = scheduler.request(Job::Count(2), BroadcastKind::Any).unwrap();
+  cancelled.cancel();
+  handle.cancel();
+  assert!(cancelled.is_cancelled());
+  scheduler.run_until_idle();
+  assert_eq!(take_log(&runs), vec![(0, 2)]);
+  assert_eq!(cancelled.status(), TicketStatus::Cancelled);
+  assert!(matches!(handle.try_take(), Some(Err(HandleError::Cancelled))));
+  assert_eq!(ticket.status(), TicketStatus::Done);
+  // Too late once it ran
+  ticket.cancel();
+  assert_eq!(ticket.status(), TicketStatus::Done);
+}
+
+#[test]
+fn only_the_latest_request_of_a_group_runs() {
+  let runs = runs(9);
+  let scheduler = inline_scheduler(2, Interleaving::RoundRobin, runs.clone());
+  let seeks = Coalesce::new();
+  let superseded: Vec<_> = (0..3).map(|i| scheduler.request_latest(Job::Count(i), &seeks, 0).unwrap()).collect();
+  let latest = scheduler.request_latest(Job::Count(3), &seeks, 0).unwrap();
+  // Other groups aren't affected
+  let other = scheduler.request_latest(Job::Count(4), &Coalesce::new(), 0).unwrap();
+  scheduler.run_until_idle();
+  assert_eq!(take_log(&runs), vec![(0, 3), (0, 4)]);
+  assert!(superseded.iter().all(|ticket| ticket.status() == TicketStatus::Cancelled));
+  assert_eq!(latest.status(), TicketStatus::Done);
+  assert_eq!(other.status(), TicketStatus::Done);
+
+  // Requests after the latest one was handled run again, until they are cancelled
+  scheduler.request_latest(Job::Count(5), &seeks, 1).unwrap();
+  scheduler.run_until_idle();
+  scheduler.request_latest(Job::Count(6), &seeks, 1).unwrap();
+  seeks.cancel_pending();
+  scheduler.run_until_idle();
+  assert_eq!(take_log(&runs), vec![(1, 5)]);
+}
+
+#[test]
+fn seeded_interleavings_are_reproducible() {
+  let orders: BTreeSet<_> = (0..16).map(interleave).collect();
+  assert!(orders.len() > 1, "the seed doesn't change the interleaving");
+  for seed in 0..16 {
+    let order = interleave(seed);
+    assert_eq!(order, interleave(seed));
+    // Every worker still runs its own jobs in order
+    for worker in 0..3 {
+      let jobs: Vec<_> = order.iter().filter(|(w, _)| *w == worker).map(|(_, i)| *i).collect();
+      assert_eq!(jobs, vec![worker * 3, worker * 3 + 1, worker * 3 + 2]);
+    }
+  }
+}
+
+#[test]
+fn responses_arrive_inline() {
+  let runs = runs(9);
+  let mut scheduler = inline_scheduler(2, Interleaving::RoundRobin, runs.clone());
+  let handle = scheduler.request_handle(Job::Count(0), BroadcastKind::Specific(1), RequestOptions::default()).unwrap();
+  scheduler.request(Job::Count(1), BroadcastKind::Specific(0)).unwrap();
+  assert!(handle.try_take().is_none());
+  let mut values = Vec::new();
+  scheduler.handle_respones(|resp, _| {
+    if let Response::Ok(value) = resp {
+      values.push(value);
+    }
+    Ok::<(), ()>(())
+  }).unwrap();
+  assert_eq!(values, vec![0]);
+  assert_eq!(handle.try_take(), Some(Ok(1)));
+}
+
+#[test]
+fn requests_of_a_panicked_worker_go_to_the_others() {
+  let runs = runs(9);
+  let mut scheduler = inline_scheduler(2, Interleaving::RoundRobin, runs.clone());
+  scheduler.request(Job::Panic, BroadcastKind::Specific(0)).unwrap();
+  scheduler.request(Job::Count(0), BroadcastKind::Any).unwrap();
+  scheduler.request(Job::Count(1), BroadcastKind::Any).unwrap();
+  let mut died = Vec::new();
+  scheduler.handle_respones(|resp, _| {
+    if let Response::WorkerDied { worker, payload } = resp {
+      died.push((worker, panic_message(payload.as_ref()).to_string()));
+    }
+    Ok::<(), ()>(())
+  }).unwrap();
+  assert_eq!(died, vec![(0, "job panicked".to_string())]);
+  assert_eq!(scheduler.num_workers(), 1);
+  assert_eq!(take_log(&runs), vec![(1, 1), (1, 0)]);
+}
+
+#[test]
+fn panicked_workers_are_respawned() {
+  let runs = runs(9);
+  let mut scheduler = inline_scheduler(2, Interleaving::RoundRobin, runs.clone());
+  scheduler.set_respawn(true);
+  scheduler.request(Job::Panic, BroadcastKind::Specific(0)).unwrap();
+  scheduler.request(Job::Count(0), BroadcastKind::Specific(1)).unwrap();
+  let mut died = Vec::new();
+  scheduler.handle_respones(|resp, _| {
+    if let Response::WorkerDied { worker, payload } = resp {
+      died.push((worker, panic_message(payload.as_ref()).to_string()));
+    }
+    Ok::<(), ()>(())
+  }).unwrap();
+  assert_eq!(died, vec![(0, "job panicked".to_string())]);
+  assert_eq!(scheduler.num_workers(), 2);
+  assert_eq!(take_log(&runs), vec![(1, 0)]);
+  // The replacement has the same index
+  scheduler.request(Job::Count(1), BroadcastKind::Specific(0)).unwrap();
+  scheduler.request(Job::Count(2), BroadcastKind::Specific(1)).unwrap();
+  scheduler.run_until_idle();
+  assert_eq!(take_log(&runs), vec![(0, 1), (1, 2)]);
+}
+
+#[test]
+fn jobs_of_panicked_workers_stay_registered() {
+  let jobs = WorkerJobs::default();
+  let worker_jobs = jobs.clone();
+  let mut scheduler: Scheduler<Job, usize> = Scheduler::new_inline(2, Interleaving::RoundRobin, move |worker_idx| {
+    let jobs = worker_jobs.clone();
+    move |job: Job, _: RequestKind, _: &mut CallbackSender<()>| {
+      let name = job.kind();
+      jobs.run(worker_idx, name, || match job {
+        Job::Panic => panic!("job panicked"),
+        _ => Response::Ok(worker_idx),
+      })
+    }
+  });
+  scheduler.request(Job::Double(1), BroadcastKind::Specific(0)).unwrap();
+  scheduler.request(Job::Panic, BroadcastKind::Specific(1)).unwrap();
+  let mut died = Vec::new();
+  scheduler.handle_respones(|resp, _| {
+    if let Response::WorkerDied { worker, .. } = resp {
+      died.push((worker, jobs.take(worker)));
+    }
+    Ok::<(), ()>(())
+  }).unwrap();
+  assert_eq!(died, vec![(1, Some("Panic"))]);
+  assert_eq!(jobs.take(0), None);
+}
+
+#[test]
+fn shutdown_stops_inline_workers() {
+  let runs = runs(9);
+  let mut scheduler = inline_scheduler(2, Interleaving::RoundRobin, runs.clone());
+  for i in 0..3 {
+    scheduler.request(Job::Count(i), BroadcastKind::Any).unwrap();
+  }
+  scheduler.shutdown(Shutdown::Drain);
+  assert!(scheduler.join(Duration::ZERO));
+  assert_eq!(take_log(&runs), vec![(0, 0), (1, 1), (0, 2)]);
+  assert!(!scheduler.step());
+}
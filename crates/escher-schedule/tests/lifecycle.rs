@@ -0,0 +1,118 @@
+mod common;
+
+use std::{
+  sync::{Arc, Barrier},
+  thread,
+  time::Duration,
+};
+
+use escher_schedule::*;
+
+use common::*;
+
+
+#[test]
+fn drain_finishes_queued_requests() {
+  let runs = runs(1);
+  let mut scheduler = scheduler(3, runs.clone());
+  for i in 0..3_000 {
+    let kind = if i % 2 == 0 { BroadcastKind::Any } else { BroadcastKind::Specific(i % 3) };
+    scheduler.request(Job::Count(0), kind).unwrap();
+  }
+  scheduler.shutdown(Shutdown::Drain);
+  assert!(scheduler.join(Duration::from_secs(10)));
+  assert_eq!(counts(&runs), vec![3_000]);
+  assert_eq!(scheduler.num_workers(), 0);
+  assert!(matches!(scheduler.request(Job::Count(0), BroadcastKind::Any), Err(RequestError::WorkerDied(_))));
+}
+
+#[test]
+fn abort_cancels_queued_requests() {
+  let runs = runs(1);
+  let mut scheduler = scheduler(2, runs.clone());
+  let barrier = Arc::new(Barrier::new(3));
+  for worker in 0..2 {
+    let stuck = scheduler.request_handle(Job::Wait(barrier.clone()), BroadcastKind::Specific(worker), RequestOptions::default()).unwrap();
+    while stuck.ticket().status() != TicketStatus::Running {
+      thread::yield_now();
+    }
+  }
+  let handles: Vec<_> = (0..100)
+    .map(|i| {
+      let kind = if i % 2 == 0 { BroadcastKind::Any } else { BroadcastKind::Specific(i % 2) };
+      scheduler.request_handle(Job::Count(0), kind, RequestOptions::default()).unwrap()
+    })
+    .collect();
+  scheduler.shutdown(Shutdown::Abort);
+  barrier.wait();
+  assert!(scheduler.join(Duration::from_secs(10)));
+  for handle in handles {
+    assert_eq!(handle.wait(Duration::from_secs(1)), Err(HandleError::Cancelled));
+    assert_eq!(handle.ticket().status(), TicketStatus::Cancelled);
+  }
+  assert_eq!(counts(&runs), vec![0]);
+}
+
+#[test]
+fn join_times_out_on_busy_workers() {
+  let runs = runs(1);
+  let mut scheduler = scheduler(1, runs);
+  let barrier = Arc::new(Barrier::new(2));
+  scheduler.request(Job::Wait(barrier.clone()), BroadcastKind::Any).unwrap();
+  scheduler.shutdown(Shutdown::Drain);
+  assert!(!scheduler.join(Duration::from_millis(50)));
+  barrier.wait();
+  assert!(scheduler.join(Duration::from_secs(10)));
+}
+
+#[test]
+fn workers_can_be_added_and_removed() {
+  let runs = runs(1);
+  let mut scheduler = scheduler(2, runs.clone());
+  assert_eq!(scheduler.add_workers(3), 2..5);
+  assert_eq!(scheduler.num_workers(), 5);
+  let handles: Vec<_> = (0..500)
+    .map(|_| scheduler.request_handle(Job::Count(0), BroadcastKind::Any, RequestOptions::default()).unwrap())
+    .collect();
+  assert_eq!(scheduler.remove_workers(2, Shutdown::Drain), vec![4, 3]);
+  assert_eq!(scheduler.num_workers(), 3);
+  assert!(matches!(scheduler.request(Job::Count(0), BroadcastKind::Specific(4)), Err(RequestError::WorkerDied(_))));
+  assert!(scheduler.join(Duration::from_secs(10)));
+  for handle in handles {
+    assert!(handle.wait(Duration::from_secs(10)).is_ok());
+  }
+  // Requests for any worker only go to the remaining ones
+  let workers: Vec<_> = (0..100)
+    .map(|_| scheduler.request_handle(Job::Count(0), BroadcastKind::Any, RequestOptions::default()).unwrap())
+    .map(|handle| handle.wait(Duration::from_secs(10)).unwrap())
+    .collect();
+  assert!(workers.iter().all(|&worker| worker < 3));
+  scheduler.set_num_workers(1, Shutdown::Drain);
+  assert_eq!(scheduler.num_workers(), 1);
+  scheduler.set_num_workers(4, Shutdown::Drain);
+  assert_eq!(scheduler.num_workers(), 4);
+  assert!(scheduler.join(Duration::from_secs(10)));
+  scheduler.handle_respones(|_, _| Ok::<(), ()>(())).unwrap();
+  assert_eq!(counts(&runs), vec![600]);
+}
+
+#[test]
+fn aborted_worker_leaves_requests_to_the_others() {
+  let runs = runs(1);
+  let mut scheduler = scheduler(2, runs.clone());
+  let barrier = Arc::new(Barrier::new(2));
+  let stuck = scheduler.request_handle(Job::Wait(barrier.clone()), BroadcastKind::Specific(1), RequestOptions::default()).unwrap();
+  while stuck.ticket().status() != TicketStatus::Running {
+    thread::yield_now();
+  }
+  let handles: Vec<_> = (0..50)
+    .map(|_| scheduler.request_handle(Job::Count(0), BroadcastKind::Any, RequestOptions::default()).unwrap())
+    .collect();
+  scheduler.remove_workers(1, Shutdown::Abort);
+  barrier.wait();
+  assert!(scheduler.join(Duration::from_secs(10)));
+  scheduler.handle_respones(|_, _| Ok::<(), ()>(())).unwrap();
+  for handle in handles {
+    assert_eq!(handle.wait(Duration::from_secs(10)), Ok(0));
+  }
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use std::{
+  sync::{Arc, Barrier},
+  thread,
+  time::Duration,
+};
+
+use escher_schedule::*;
+
+use common::*;
+
+
+#[test]
+fn requests_are_counted_by_kind() {
+  let mut scheduler = scheduler(2, runs(0));
+  scheduler.register_metrics("counted test", Job::kind);
+  for _ in 0..10 {
+    scheduler.request(Job::Sleep(2), BroadcastKind::Any).unwrap();
+  }
+  scheduler.request(Job::Fail, BroadcastKind::Specific(0)).unwrap();
+  let cancelled = scheduler.request(Job::Sleep(0), BroadcastKind::Specific(1)).unwrap();
+  cancelled.cancel();
+  scheduler.shutdown(Shutdown::Drain);
+  assert!(scheduler.join(Duration::from_secs(10)));
+
+  let metrics = scheduler.metrics();
+  let sleep = metrics.kinds["Sleep"];
+  assert_eq!(sleep.handled + sleep.cancelled, 11);
+  assert!(sleep.handled >= 10);
+  assert!(sleep.mean_service() >= Duration::from_millis(2));
+  assert!(sleep.max_wait >= sleep.mean_wait());
+  assert_eq!(metrics.kinds["Fail"].failed, 1);
+  // Stopped workers are left out
+  assert!(metrics.workers.is_empty());
+}
+
+#[test]
+fn busy_workers_show_their_queue() {
+  let scheduler = scheduler(1, runs(0));
+  let barrier = Arc::new(Barrier::new(2));
+  let stuck = scheduler.request(Job::Wait(barrier.clone()), BroadcastKind::Any).unwrap();
+  while stuck.status() != TicketStatus::Running {
+    thread::yield_now();
+  }
+  for _ in 0..5 {
+    scheduler.request(Job::Sleep(0), BroadcastKind::Any).unwrap();
+  }
+  thread::sleep(Duration::from_millis(20));
+  let metrics = scheduler.metrics();
+  assert_eq!(metrics.queue_depth(), 5);
+  assert!(metrics.workers[&0].busy >= Duration::from_millis(20));
+  assert!(metrics.busy_ratio(0) > 0.);
+  barrier.wait();
+}
+
+#[test]
+fn registered_metrics_go_with_their_scheduler() {
+  let scheduler = scheduler(1, runs(0));
+  scheduler.register_metrics("registered test", Job::kind);
+  let registered = || registered_metrics().iter().filter(|m| m.name == "registered test").count();
+  assert_eq!(registered(), 1);
+  drop(scheduler);
+  // The workers hold the metrics until they stopped
+  for _ in 0..1000 {
+    if registered() == 0 {
+      break;
+    }
+    thread::sleep(Duration::from_millis(10));
+  }
+  assert_eq!(registered(), 0);
+}
@@ -0,0 +1,408 @@
+use serde::{Serialize, Deserialize};
+
+use crate::{video::RationalTime, effects::ParamValue};
+
+/// Values that can be interpolated between keyframes
+pub trait Animatable: Copy {
+  /// `a` for `t == 0`, `b` for `t == 1`. `t` can leave that range for overshooting curves.
+  fn lerp(a: Self, b: Self, t: f32) -> Self;
+}
+
+/// Direction of a bezier handle in the unit square spanned by a segment, where x is the fraction
+/// of the segment's duration and y the fraction of the value change.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Tangent {
+  pub x: f32,
+  pub y: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Ease {
+  In,
+  Out,
+  InOut,
+}
+
+/// How the value changes between a keyframe and the next one
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Interpolation {
+  /// Keeps the value until the next keyframe
+  Hold,
+  #[default]
+  Linear,
+  /// Cubic bezier using the out tangent of this keyframe and the in tangent of the next one
+  Bezier,
+  Ease(Ease),
+}
+
+/// A value at a point in clip-local time, i.e. relative to the start of the clip on the timeline
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Keyframe<V> {
+  pub time: RationalTime,
+  pub value: V,
+  /// Interpolation towards the next keyframe
+  pub interpolation: Interpolation,
+  /// Handle towards the previous keyframe, measured from this keyframe backwards
+  pub in_tangent: Tangent,
+  /// Handle towards the next keyframe
+  pub out_tangent: Tangent,
+}
+
+/// Keyframes sorted by time. Before the first and after the last keyframe the value is held.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Keyframes<V> {
+  keyframes: Vec<Keyframe<V>>,
+}
+
+/// A parameter that is either constant or animated by keyframes
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Animated<V> {
+  /// Used while there are no keyframes
+  pub value: V,
+  pub keyframes: Keyframes<V>,
+}
+
+
+impl Tangent {
+  pub const LINEAR: Self = Self { x: 1. / 3., y: 1. / 3. };
+  /// Flat handle, the value starts or stops changing smoothly
+  pub const FLAT: Self = Self { x: 1. / 3., y: 0. };
+}
+
+impl Default for Tangent {
+  fn default() -> Self { Self::LINEAR }
+}
+
+impl Ease {
+  pub const ALL: [Ease; 3] = [Ease::In, Ease::Out, Ease::InOut];
+
+  /// Control points of the preset's curve, the same as the CSS timing functions
+  pub fn control_points(&self) -> ([f32; 2], [f32; 2]) {
+    match self {
+      Ease::In => ([0.42, 0.], [1., 1.]),
+      Ease::Out => ([0., 0.], [0.58, 1.]),
+      Ease::InOut => ([0.42, 0.], [0.58, 1.]),
+    }
+  }
+}
+
+/// Evaluates the bezier curve from `(0, 0)` over `p1` and `p2` to `(1, 1)` at `x`. The x
+/// coordinates of the control points are clamped to `[0, 1]` so the curve is a function of x.
+pub fn cubic_bezier(p1: [f32; 2], p2: [f32; 2], x: f32) -> f32 {
+  let (x1, x2) = (p1[0].clamp(0., 1.), p2[0].clamp(0., 1.));
+  let bezier = |a: f32, b: f32, s: f32| {
+    let r = 1. - s;
+    3.*r*r*s*a + 3.*r*s*s*b + s*s*s
+  };
+  let derivative = |a: f32, b: f32, s: f32| {
+    let r = 1. - s;
+    3.*r*r*a + 6.*r*s*(b - a) + 3.*s*s*(1. - b)
+  };
+
+  let x = x.clamp(0., 1.);
+  let mut s = x;
+  for _ in 0..8 {
+    let err = bezier(x1, x2, s) - x;
+    if err.abs() < 1e-6 {
+      return bezier(p1[1], p2[1], s);
+    }
+    let d = derivative(x1, x2, s);
+    if d.abs() < 1e-6 {
+      break;
+    }
+    s = (s - err / d).clamp(0., 1.);
+  }
+
+  // Newton didn't converge, fall back to bisection
+  let (mut lo, mut hi) = (0f32, 1f32);
+  s = x;
+  for _ in 0..32 {
+    let err = bezier(x1, x2, s) - x;
+    if err.abs() < 1e-6 {
+      break;
+    }
+    if err > 0. { hi = s } else { lo = s }
+    s = (lo + hi) / 2.;
+  }
+  bezier(p1[1], p2[1], s)
+}
+
+
+impl<V: Animatable> Keyframe<V> {
+  pub fn new(time: RationalTime, value: V, interpolation: Interpolation) -> Self {
+    Self { time, value, interpolation, in_tangent: Tangent::LINEAR, out_tangent: Tangent::LINEAR }
+  }
+
+  /// Progress of the value at `time` on the segment from `self` to `next`
+  fn progress(&self, next: &Self, time: RationalTime) -> f32 {
+    let x = time.fraction_between(self.time, next.time) as f32;
+    match self.interpolation {
+      Interpolation::Hold => if time >= next.time { 1. } else { 0. },
+      Interpolation::Linear => x,
+      Interpolation::Bezier => {
+        let p1 = [self.out_tangent.x, self.out_tangent.y];
+        let p2 = [1. - next.in_tangent.x, 1. - next.in_tangent.y];
+        cubic_bezier(p1, p2, x)
+      },
+      Interpolation::Ease(ease) => {
+        let (p1, p2) = ease.control_points();
+        cubic_bezier(p1, p2, x)
+      },
+    }
+  }
+}
+
+impl<V> Keyframes<V> {
+  pub fn new() -> Self {
+    Self { keyframes: Vec::new() }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.keyframes.is_empty()
+  }
+
+  pub fn len(&self) -> usize {
+    self.keyframes.len()
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item=&Keyframe<V>> + '_ {
+    self.keyframes.iter()
+  }
+
+  /// Mutable access to a keyframe. Its time can't be changed this way, use `move_keyframe`.
+  pub fn get_mut(&mut self, time: RationalTime) -> Option<(&mut V, &mut Interpolation, &mut Tangent, &mut Tangent)> {
+    let idx = self.keyframes.binary_search_by(|k| k.time.cmp(&time)).ok()?;
+    let k = &mut self.keyframes[idx];
+    Some((&mut k.value, &mut k.interpolation, &mut k.in_tangent, &mut k.out_tangent))
+  }
+
+  /// Adds `keyframe`, replacing a keyframe at the same time
+  pub fn insert(&mut self, keyframe: Keyframe<V>) {
+    match self.keyframes.binary_search_by(|k| k.time.cmp(&keyframe.time)) {
+      Ok(idx) => self.keyframes[idx] = keyframe,
+      Err(idx) => self.keyframes.insert(idx, keyframe),
+    }
+  }
+
+  pub fn remove(&mut self, time: RationalTime) -> Option<Keyframe<V>> {
+    let idx = self.keyframes.binary_search_by(|k| k.time.cmp(&time)).ok()?;
+    Some(self.keyframes.remove(idx))
+  }
+
+  /// Moves the keyframe at `from` to `to`, replacing a keyframe at `to`. Returns `false` if there
+  /// is no keyframe at `from`.
+  pub fn move_keyframe(&mut self, from: RationalTime, to: RationalTime) -> bool {
+    match self.remove(from) {
+      Some(mut k) => {
+        k.time = to;
+        self.insert(k);
+        true
+      },
+      None => false,
+    }
+  }
+
+  /// Shifts all keyframes by `offset`, e.g. after trimming the start of a clip
+  pub fn shift(&mut self, offset: RationalTime) {
+    for k in self.keyframes.iter_mut() {
+      k.time += offset;
+    }
+  }
+}
+
+impl<V: Animatable> Keyframes<V> {
+  /// Sets the value at `time`, keeping interpolation and tangents of an existing keyframe
+  pub fn set(&mut self, time: RationalTime, value: V) {
+    match self.get_mut(time) {
+      Some((v, ..)) => *v = value,
+      None => self.insert(Keyframe::new(time, value, Interpolation::default())),
+    }
+  }
+
+  /// Value at the clip-local `time`, `None` if there are no keyframes
+  pub fn value_at(&self, time: RationalTime) -> Option<V> {
+    let idx = self.keyframes.partition_point(|k| k.time <= time);
+    match (idx.checked_sub(1).map(|i| &self.keyframes[i]), self.keyframes.get(idx)) {
+      (Some(prev), Some(next)) => Some(V::lerp(prev.value, next.value, prev.progress(next, time))),
+      (Some(k), None) | (None, Some(k)) => Some(k.value),
+      (None, None) => None,
+    }
+  }
+}
+
+impl<V> Default for Keyframes<V> {
+  fn default() -> Self { Self::new() }
+}
+
+
+impl<V: Animatable> Animated<V> {
+  pub fn new(value: V) -> Self {
+    Self { value, keyframes: Keyframes::new() }
+  }
+
+  pub fn is_animated(&self) -> bool {
+    !self.keyframes.is_empty()
+  }
+
+  pub fn value_at(&self, time: RationalTime) -> V {
+    self.keyframes.value_at(time).unwrap_or(self.value)
+  }
+
+  /// Sets the value at `time`. If the parameter is animated, a keyframe is added or updated,
+  /// otherwise the constant value changes.
+  pub fn set(&mut self, time: RationalTime, value: V) {
+    if self.is_animated() {
+      self.keyframes.set(time, value)
+    } else {
+      self.value = value
+    }
+  }
+}
+
+impl<V: Animatable> From<V> for Animated<V> {
+  fn from(value: V) -> Self { Self::new(value) }
+}
+
+impl<V: Animatable + Default> Default for Animated<V> {
+  fn default() -> Self { Self::new(V::default()) }
+}
+
+
+impl Animatable for f32 {
+  fn lerp(a: Self, b: Self, t: f32) -> Self { a + (b - a) * t }
+}
+
+impl Animatable for f64 {
+  fn lerp(a: Self, b: Self, t: f32) -> Self { a + (b - a) * t as f64 }
+}
+
+impl<const N: usize> Animatable for [f32; N] {
+  fn lerp(a: Self, b: Self, t: f32) -> Self {
+    std::array::from_fn(|i| f32::lerp(a[i], b[i], t))
+  }
+}
+
+impl Animatable for ParamValue {
+  /// Integers are rounded, booleans and mismatched types switch at the end of the segment
+  fn lerp(a: Self, b: Self, t: f32) -> Self {
+    match (a, b) {
+      (ParamValue::Float(a), ParamValue::Float(b)) => ParamValue::Float(f32::lerp(a, b, t)),
+      (ParamValue::Int(a), ParamValue::Int(b)) => ParamValue::Int(f32::lerp(a as _, b as _, t).round() as _),
+      (ParamValue::Color(a), ParamValue::Color(b)) => ParamValue::Color(<[f32; 4]>::lerp(a, b, t)),
+      _ => if t >= 1. { b } else { a },
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// CSS `ease`, which isn't one of the presets
+  const EASE: ([f32; 2], [f32; 2]) = ([0.25, 0.1], [0.25, 1.]);
+
+  fn secs(num: i64, den: i64) -> RationalTime {
+    RationalTime::new(num, den)
+  }
+
+  #[test]
+  fn bezier_hits_its_endpoints() {
+    for (p1, p2) in Ease::ALL.map(|ease| ease.control_points()).into_iter().chain([EASE]) {
+      assert_eq!(cubic_bezier(p1, p2, 0.), 0.);
+      assert!((cubic_bezier(p1, p2, 1.) - 1.).abs() < 1e-5);
+      // Outside of the segment the ends are held
+      assert_eq!(cubic_bezier(p1, p2, -1.), cubic_bezier(p1, p2, 0.));
+      assert_eq!(cubic_bezier(p1, p2, 2.), cubic_bezier(p1, p2, 1.));
+    }
+  }
+
+  #[test]
+  fn bezier_is_monotonic() {
+    for (p1, p2) in Ease::ALL.map(|ease| ease.control_points()).into_iter().chain([EASE]) {
+      let mut prev = 0.;
+      for i in 1..=1000 {
+        let y = cubic_bezier(p1, p2, i as f32 / 1000.);
+        assert!(y >= prev - 1e-6, "{:?} {:?} decreases at {}", p1, p2, i);
+        prev = y;
+      }
+    }
+  }
+
+  #[test]
+  fn bezier_matches_css_timing_functions() {
+    let references = [
+      (EASE, [(0.1, 0.094796), (0.25, 0.408511), (0.5, 0.802403), (0.75, 0.960459)]),
+      (Ease::In.control_points(), [(0.1, 0.017027), (0.25, 0.093465), (0.5, 0.315357), (0.75, 0.621862)]),
+      (Ease::Out.control_points(), [(0.1, 0.160572), (0.25, 0.378138), (0.5, 0.684643), (0.75, 0.906535)]),
+      (Ease::InOut.control_points(), [(0.1, 0.019722), (0.25, 0.129162), (0.5, 0.5), (0.75, 0.870838)]),
+    ];
+    for ((p1, p2), values) in references {
+      for (x, y) in values {
+        let res = cubic_bezier(p1, p2, x);
+        assert!((res - y).abs() < 1e-4, "{:?} {:?} at {}: {} instead of {}", p1, p2, x, res, y);
+      }
+    }
+  }
+
+  #[test]
+  fn linear_tangents_are_linear() {
+    for i in 0..=10 {
+      let x = i as f32 / 10.;
+      let res = cubic_bezier([Tangent::LINEAR.x, Tangent::LINEAR.y], [1. - Tangent::LINEAR.x, 1. - Tangent::LINEAR.y], x);
+      assert!((res - x).abs() < 1e-5);
+    }
+  }
+
+  #[test]
+  fn keyframes_interpolate_and_hold_their_ends() {
+    let mut keyframes = Keyframes::new();
+    assert_eq!(keyframes.value_at(secs(0, 1)), None);
+    keyframes.insert(Keyframe::new(secs(1, 1), 10f32, Interpolation::Linear));
+    keyframes.insert(Keyframe::new(secs(3, 1), 20., Interpolation::Hold));
+    keyframes.insert(Keyframe::new(secs(5, 1), 0., Interpolation::Linear));
+    assert_eq!(keyframes.value_at(secs(0, 1)), Some(10.));
+    assert_eq!(keyframes.value_at(secs(1, 1)), Some(10.));
+    assert_eq!(keyframes.value_at(secs(2, 1)), Some(15.));
+    assert_eq!(keyframes.value_at(secs(3, 1)), Some(20.));
+    // Held until the next keyframe
+    assert_eq!(keyframes.value_at(secs(49, 10)), Some(20.));
+    assert_eq!(keyframes.value_at(secs(5, 1)), Some(0.));
+    assert_eq!(keyframes.value_at(secs(100, 1)), Some(0.));
+  }
+
+  #[test]
+  fn keyframes_ease_between_values() {
+    let mut keyframes = Keyframes::new();
+    keyframes.insert(Keyframe::new(secs(0, 1), 0f32, Interpolation::Ease(Ease::InOut)));
+    keyframes.insert(Keyframe::new(secs(2, 1), 100., Interpolation::Linear));
+    let (p1, p2) = Ease::InOut.control_points();
+    assert_eq!(keyframes.value_at(secs(1, 2)), Some(100.*cubic_bezier(p1, p2, 0.25)));
+    assert!((keyframes.value_at(secs(1, 1)).unwrap() - 50.).abs() < 1e-3);
+    // Flat tangents on both ends behave like ease-in-out
+    keyframes.insert(Keyframe {
+      in_tangent: Tangent::FLAT,
+      out_tangent: Tangent::FLAT,
+      ..Keyframe::new(secs(0, 1), 0., Interpolation::Bezier)
+    });
+    let (_, _, in_tangent, _) = keyframes.get_mut(secs(2, 1)).unwrap();
+    *in_tangent = Tangent::FLAT;
+    let slow_start = keyframes.value_at(secs(1, 10)).unwrap();
+    assert!(slow_start > 0. && slow_start < 1., "{}", slow_start);
+    assert!((keyframes.value_at(secs(1, 1)).unwrap() - 50.).abs() < 1e-3);
+  }
+
+  #[test]
+  fn animated_values_only_keyframe_once_animated() {
+    let mut animated = Animated::new(1f32);
+    animated.set(secs(1, 1), 2.);
+    assert!(!animated.is_animated());
+    assert_eq!(animated.value_at(secs(7, 1)), 2.);
+    animated.keyframes.set(secs(0, 1), 0.);
+    animated.set(secs(1, 1), 4.);
+    assert_eq!(animated.keyframes.len(), 2);
+    assert_eq!(animated.value_at(secs(1, 2)), 2.);
+    assert_eq!(animated.value, 2.);
+  }
+}
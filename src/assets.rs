@@ -1,8 +1,14 @@
-use std::{collections::{HashMap, self}, sync::{Arc, Mutex}, path::Path, fmt::Debug};
+use std::{collections::{HashMap, self}, sync::{Arc, Mutex}, path::{Path, PathBuf}, fmt::Debug};
 
 use egui_winit::egui::{self, Widget};
 use epaint::vec2;
 
+use crate::{
+  clip::{packed_rgba, FileSource, FrameSource},
+  proxy::{ProxyJob, ProxyStatus},
+  video::{ffi::AVMediaType, probe::probe, RationalTime, VideoStreamErr},
+};
+
 pub trait Asset: Debug {
   fn get_name(&self) -> String;
   fn get_texture_handle(&self) -> egui::TextureHandle;
@@ -11,6 +17,8 @@ pub trait Asset: Debug {
     let tex_handle = self.get_texture_handle();
     Box::new(|ui| asset_ui(name, tex_handle, ui))
   }
+  /// Video files return themselves, e.g. to generate their proxies
+  fn as_media_mut(&mut self) -> Option<&mut MediaAsset> { None }
 }
 
 #[derive(Default)]
@@ -41,6 +49,21 @@ pub struct DummyAsset {
   pub tex_handle: egui::TextureHandle,
 }
 
+/// A video stream of a file. The proxy, if one was requested, stands in for it in the viewer.
+pub struct MediaAsset {
+  pub name: String,
+  pub path: PathBuf,
+  pub stream_idx: u32,
+  pub tex_handle: egui::TextureHandle,
+  /// Selected in the assets panel
+  pub selected: bool,
+  pub proxy: Option<Arc<ProxyJob>>,
+}
+
+/// Width of the thumbnails of `MediaAsset`s
+const THUMBNAIL_WIDTH: u32 = 128;
+
+
 impl DummyAsset {
   pub fn load(path: &Path, ctx: &egui::Context) -> Self {
     let path = Box::new(path.clone());
@@ -82,11 +105,57 @@ impl Asset for DummyAsset {
 
 }
 
+impl MediaAsset {
+  /// Loads the first video stream of `path` with its first frame as the thumbnail
+  pub fn load(path: &Path, ctx: &egui::Context) -> Result<Self, VideoStreamErr> {
+    let info = probe(path)?;
+    let stream = info.first_stream(AVMediaType::AVMEDIA_TYPE_VIDEO).ok_or(VideoStreamErr::StreamNotFound)?;
+    let height = (THUMBNAIL_WIDTH as u64 * stream.height as u64 / stream.width.max(1) as u64).max(1) as u32;
+    let frame_duration = stream.frame_duration().unwrap_or(RationalTime::new(1, 25));
+    let mut source = FileSource::open(path, stream.index, [THUMBNAIL_WIDTH, height], frame_duration, stream.duration)?;
+    let frame = source.frame_at(RationalTime::ZERO)?;
+    let image = egui::ColorImage::from_rgba_unmultiplied([frame.width(), frame.height()], &packed_rgba(&frame));
+    let name = path.file_stem().map_or("Datei".to_string(), |stem| stem.to_string_lossy().into_owned());
+    let tex_handle = ctx.load_texture(name.clone(), image, egui::TextureOptions::default());
+    Ok(Self { name, path: path.to_path_buf(), stream_idx: stream.index, tex_handle, selected: false, proxy: None })
+  }
+
+  /// Short state of the proxy for the assets panel
+  pub fn proxy_text(&self) -> Option<String> {
+    Some(match self.proxy.as_ref()?.status() {
+      ProxyStatus::Running(progress) => format!("Proxy {:.0}%", progress * 100.),
+      ProxyStatus::Ready => "Proxy".to_string(),
+      ProxyStatus::Failed(err) => format!("Proxy failed: {:?}", err),
+      ProxyStatus::Crashed => format!("Proxy crashed: {}", self.proxy.as_ref()?.panic_message().unwrap_or_default()),
+      ProxyStatus::Cancelled => "Proxy cancelled".to_string(),
+    })
+  }
+}
+
+impl Asset for MediaAsset {
+  fn get_name(&self) -> String {
+    let mut name = self.name.clone();
+    if let Some(proxy) = self.proxy_text() {
+      name = format!("{} ({})", name, proxy);
+    }
+    if self.selected {
+      name = format!("▶ {}", name);
+    }
+    name
+  }
+
+  fn get_texture_handle(&self) -> egui::TextureHandle {
+    self.tex_handle.clone()
+  }
+
+  fn as_media_mut(&mut self) -> Option<&mut MediaAsset> { Some(self) }
+}
+
 pub fn asset_ui(name: String, tex_handle: egui::TextureHandle, ui: &mut egui::Ui) -> egui::Response {
   let img_size = 64.;
   let label_size = 20.;
   // egui::PaintCallback
-  let (rect, resp) = ui.allocate_exact_size(vec2(img_size, img_size + label_size), egui::Sense::hover());
+  let (rect, resp) = ui.allocate_exact_size(vec2(img_size, img_size + label_size), egui::Sense::click());
 
   let img_rect = egui::Rect { max: egui::pos2(rect.right(), rect.top() + img_size), ..rect };
   let text_rect = egui::Rect { min: egui::pos2(rect.left(), rect.top() + img_size), ..rect };
@@ -119,6 +188,16 @@ pub fn asset_ui(name: String, tex_handle: egui::TextureHandle, ui: &mut egui::Ui
 }
 
 
+impl Debug for MediaAsset {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("MediaAsset")
+      .field("path", &self.path)
+      .field("stream_idx", &self.stream_idx)
+      .field("proxy", &self.proxy)
+      .finish()
+  }
+}
+
 impl Debug for DummyAsset {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     f.debug_struct("DummyAsset").field("name", &self.name).field("tex_handle", &self.tex_handle.id()).finish()
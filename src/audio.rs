@@ -0,0 +1,233 @@
+use std::{collections::HashMap, path::{Path, PathBuf}, time::Instant};
+
+use crate::{
+  timeline::{ClipSource, Timeline},
+  video::{AudioStream, RationalTime, VideoStreamErr},
+};
+
+pub mod mixer;
+pub mod meter;
+pub mod loudness;
+pub mod output;
+pub mod analysis;
+
+pub use crate::video::StereoSample;
+pub use mixer::Mixer;
+pub use meter::Meter;
+pub use loudness::{LoudnessMeter, LoudnessReport};
+pub use output::AudioOutput;
+pub use analysis::{AnalysisTarget, LoudnessAnalyzer, LoudnessJob};
+
+/// Provides the samples of clip sources to the mixer
+pub trait SampleSource {
+  /// Fills `out` with the samples of `source` from `source_time` on at `sample_rate`
+  fn read(&mut self, source: &ClipSource, source_time: RationalTime, sample_rate: u32, out: &mut [StereoSample]) -> Result<(), VideoStreamErr>;
+}
+
+impl<F> SampleSource for F where F: FnMut(&ClipSource, RationalTime, u32, &mut [StereoSample]) -> Result<(), VideoStreamErr> {
+  fn read(&mut self, source: &ClipSource, source_time: RationalTime, sample_rate: u32, out: &mut [StereoSample]) -> Result<(), VideoStreamErr> {
+    self(source, source_time, sample_rate, out)
+  }
+}
+
+/// Decodes file sources with one `AudioStream` each. Streams beyond `max_streams` are closed,
+/// least recently used first.
+pub struct AudioDecoders {
+  streams: HashMap<(PathBuf, u32), (AudioStream, u64)>,
+  pub max_streams: usize,
+  use_counter: u64,
+}
+
+impl AudioDecoders {
+  pub fn new(max_streams: usize) -> Self {
+    Self { streams: HashMap::new(), max_streams: max_streams.max(1), use_counter: 0 }
+  }
+
+  fn stream(&mut self, path: &Path, stream_idx: u32) -> Result<&mut AudioStream, VideoStreamErr> {
+    self.use_counter += 1;
+    let key = (path.to_path_buf(), stream_idx);
+    if !self.streams.contains_key(&key) {
+      let stream = AudioStream::open(path, stream_idx)?;
+      while self.streams.len() >= self.max_streams {
+        let oldest = self.streams.iter()
+          .min_by_key(|(_, (_, last_used))| *last_used)
+          .map(|(key, _)| key.clone())
+          .unwrap();
+        self.streams.remove(&oldest);
+      }
+      self.streams.insert(key.clone(), (stream, 0));
+    }
+    let (stream, last_used) = self.streams.get_mut(&key).unwrap();
+    *last_used = self.use_counter;
+    Ok(stream)
+  }
+}
+
+impl SampleSource for AudioDecoders {
+  fn read(&mut self, source: &ClipSource, source_time: RationalTime, sample_rate: u32, out: &mut [StereoSample]) -> Result<(), VideoStreamErr> {
+    match source {
+      ClipSource::File { path, stream_idx } => self.stream(path, *stream_idx)?.read(source_time, sample_rate, out),
+      ClipSource::Generator(_) => {
+        out.fill([0.; 2]);
+        Ok(())
+      },
+    }
+  }
+}
+
+
+/// The clock playback runs on. Video frames are picked by its time and the audio engine follows
+/// it, see `AudioEngine::update`.
+#[derive(Clone, Debug)]
+pub struct PlaybackClock {
+  /// Timeline time at `started`
+  start: RationalTime,
+  started: Option<Instant>,
+  /// Playback speed, 1 is real time
+  pub rate: f64,
+}
+
+impl PlaybackClock {
+  pub fn new() -> Self {
+    Self { start: RationalTime::ZERO, started: None, rate: 1. }
+  }
+
+  pub fn is_playing(&self) -> bool {
+    self.started.is_some()
+  }
+
+  pub fn time(&self) -> RationalTime {
+    match self.started {
+      Some(started) => self.start + RationalTime::from_secs_f64(started.elapsed().as_secs_f64() * self.rate),
+      None => self.start,
+    }
+  }
+
+  pub fn play(&mut self) {
+    if self.started.is_none() {
+      self.started = Some(Instant::now());
+    }
+  }
+
+  pub fn pause(&mut self) {
+    self.start = self.time();
+    self.started = None;
+  }
+
+  pub fn seek(&mut self, time: RationalTime) {
+    self.start = time;
+    if self.started.is_some() {
+      self.started = Some(Instant::now());
+    }
+  }
+}
+
+impl Default for PlaybackClock {
+  fn default() -> Self { Self::new() }
+}
+
+
+/// Plays the mix of a timeline through an output. The engine doesn't keep time itself, it is
+/// slaved to the playback clock: the output is kept filled `buffer_ahead` samples in front of
+/// the clock, and whenever the audible position drifts further than `max_drift` from it the
+/// queued audio is dropped and mixing restarts at the clock's time.
+pub struct AudioEngine {
+  pub mixer: Mixer,
+  output: Box<dyn AudioOutput>,
+  /// Timeline sample index of the next sample to mix
+  next_sample: i64,
+  playing: bool,
+  /// In samples
+  pub buffer_ahead: usize,
+  /// In samples
+  pub max_drift: usize,
+  buffer: Vec<StereoSample>,
+}
+
+impl AudioEngine {
+  /// Mixes at the sample rate of `output`
+  pub fn new(output: Box<dyn AudioOutput>) -> Self {
+    let sample_rate = output.sample_rate();
+    Self {
+      mixer: Mixer::new(sample_rate),
+      output,
+      next_sample: 0,
+      playing: false,
+      buffer_ahead: sample_rate as usize / 10,
+      max_drift: sample_rate as usize / 25,
+      buffer: Vec::new(),
+    }
+  }
+
+  pub fn output(&self) -> &dyn AudioOutput {
+    self.output.as_ref()
+  }
+
+  /// Call regularly, e.g. once per UI frame, with the current time of the playback clock
+  pub fn update(&mut self, timeline: &Timeline, clock: &PlaybackClock, source: &mut impl SampleSource) {
+    if !clock.is_playing() {
+      if self.playing {
+        self.output.flush();
+        self.playing = false;
+      }
+      return;
+    }
+
+    let clock_sample = clock.time().to_frames(self.mixer.sample_rate as _, 1);
+    let audible = self.next_sample - self.output.queued() as i64;
+    if !self.playing || (audible - clock_sample).unsigned_abs() as usize > self.max_drift {
+      self.output.flush();
+      self.next_sample = clock_sample;
+      self.playing = true;
+    }
+
+    let target = clock_sample + self.buffer_ahead as i64;
+    while self.next_sample < target {
+      let len = ((target - self.next_sample) as usize).min(mixer::BLOCK_SIZE * 16);
+      self.buffer.resize(len, [0.; 2]);
+      self.mixer.mix(timeline, self.next_sample, source, &mut self.buffer);
+      let written = self.output.write(&self.buffer);
+      self.next_sample += written as i64;
+      if written < len {
+        break;
+      }
+    }
+  }
+}
+
+
+/// Mixes `[from, to)` of the timeline into `output` as fast as possible, e.g. for export. This
+/// runs the same mixer as playback, so both sound identical. `progress` is called after every
+/// chunk with the fraction done and can cancel by returning `false`. Returns `false` if
+/// cancelled or the output stopped accepting samples.
+pub fn mix_offline(
+  timeline: &Timeline,
+  from: RationalTime,
+  to: RationalTime,
+  mixer: &mut Mixer,
+  source: &mut impl SampleSource,
+  output: &mut dyn AudioOutput,
+  mut progress: impl FnMut(f32) -> bool,
+) -> bool {
+  let rate = mixer.sample_rate as i64;
+  let (first, end) = (from.to_frames(rate, 1), to.to_frames(rate, 1));
+  let mut buffer = vec![[0.; 2]; mixer::BLOCK_SIZE * 64];
+  let mut next = first;
+  while next < end {
+    let len = ((end - next) as usize).min(buffer.len());
+    mixer.mix(timeline, next, source, &mut buffer[..len]);
+    let mut written = 0;
+    while written < len {
+      match output.write(&buffer[written..len]) {
+        // The output failed or is full and won't drain by itself
+        0 => return false,
+        n => written += n,
+      }
+    }
+    next += len as i64;
+    if !progress((next - first) as f32 / (end - first) as f32) {
+      return false;
+    }
+  }
+  true
+}
@@ -0,0 +1,205 @@
+use std::{fmt, sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}}, task::Waker};
+
+use crate::{
+  schedule::{self, BroadcastKind, CallbackSender, Priority, RequestKind, RequestOptions, Response, Scheduler, WorkerJobs},
+  timeline::{ClipSource, Timeline},
+  video::{RationalTime, VideoStreamErr},
+};
+
+use super::{AudioDecoders, LoudnessMeter, LoudnessReport, Mixer, SampleSource, StereoSample, output::NullOutput};
+
+/// What a loudness job measures
+#[derive(Clone, Debug)]
+pub enum AnalysisTarget {
+  /// The mix of `[from, to)`, exactly as playback and export produce it
+  Timeline { timeline: Timeline, from: RationalTime, to: RationalTime },
+  /// `[from, to)` of a source in source time, without any clip or track processing
+  Clip { source: ClipSource, from: RationalTime, to: RationalTime },
+}
+
+/// A loudness measurement running on a worker. The job is shared between the UI and the worker,
+/// the UI polls `progress` and `result`.
+pub struct LoudnessJob {
+  pub target: AnalysisTarget,
+  progress: Mutex<f32>,
+  cancelled: AtomicBool,
+  result: Mutex<Option<Result<LoudnessReport, VideoStreamErr>>>,
+  panic_message: Mutex<Option<String>>,
+}
+
+#[derive(Clone)]
+pub enum AnalysisRequest {
+  Analyze(Arc<LoudnessJob>),
+}
+
+pub type AnalysisError = schedule::RequestError<AnalysisRequest>;
+
+/// Runs loudness jobs on a pool of workers, each with its own decoders
+pub struct LoudnessAnalyzer {
+  scheduler: Scheduler<AnalysisRequest, ()>,
+  running: WorkerJobs<Arc<LoudnessJob>>,
+  jobs: Vec<Arc<LoudnessJob>>,
+}
+
+/// Samples analyzed per step, progress and cancellation are checked in between
+const CHUNK_LEN: usize = 1 << 14;
+
+
+impl LoudnessJob {
+  pub fn new(target: AnalysisTarget) -> Self {
+    Self { target, progress: Mutex::new(0.), cancelled: AtomicBool::new(false), result: Mutex::new(None), panic_message: Mutex::new(None) }
+  }
+
+  /// Between 0 and 1
+  pub fn progress(&self) -> f32 {
+    *self.progress.lock().unwrap()
+  }
+
+  pub fn cancel(&self) {
+    self.cancelled.store(true, Ordering::Relaxed)
+  }
+
+  pub fn is_cancelled(&self) -> bool {
+    self.cancelled.load(Ordering::Relaxed)
+  }
+
+  /// `None` while the job runs, after it was cancelled and if it crashed
+  pub fn result(&self) -> Option<Result<LoudnessReport, VideoStreamErr>> {
+    *self.result.lock().unwrap()
+  }
+
+  /// What the worker panicked with if the job crashed it
+  pub fn panic_message(&self) -> Option<String> {
+    self.panic_message.lock().unwrap().clone()
+  }
+
+  pub fn is_finished(&self) -> bool {
+    self.result.lock().unwrap().is_some() || self.is_cancelled() || self.panic_message.lock().unwrap().is_some()
+  }
+
+  fn set_progress(&self, progress: f32) -> bool {
+    *self.progress.lock().unwrap() = progress;
+    !self.is_cancelled()
+  }
+
+  /// Measures the target on the calling thread
+  pub fn run(&self, sample_rate: u32, source: &mut impl SampleSource) {
+    let res = match &self.target {
+      AnalysisTarget::Timeline { timeline, from, to } => {
+        let mut mixer = Mixer::new(sample_rate);
+        let mut output = NullOutput::new(sample_rate);
+        if !super::mix_offline(timeline, *from, *to, &mut mixer, source, &mut output, |p| self.set_progress(p)) {
+          return;
+        }
+        match mixer.last_error {
+          Some(err) => Err(err),
+          None => Ok(mixer.master_loudness.report()),
+        }
+      },
+      AnalysisTarget::Clip { source: clip_source, from, to } => {
+        let mut meter = LoudnessMeter::new(sample_rate);
+        let rate = sample_rate as i64;
+        let (first, end) = (from.to_frames(rate, 1), to.to_frames(rate, 1));
+        let mut buffer: Vec<StereoSample> = vec![[0.; 2]; CHUNK_LEN];
+        let mut next = first;
+        loop {
+          if next >= end {
+            break Ok(meter.report());
+          }
+          let len = ((end - next) as usize).min(CHUNK_LEN);
+          if let Err(err) = source.read(clip_source, RationalTime::new(next, rate), sample_rate, &mut buffer[..len]) {
+            break Err(err);
+          }
+          meter.process(&buffer[..len]);
+          next += len as i64;
+          if !self.set_progress((next - first) as f32 / (end - first) as f32) {
+            return;
+          }
+        }
+      },
+    };
+    *self.result.lock().unwrap() = Some(res);
+  }
+}
+
+impl fmt::Debug for LoudnessJob {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("LoudnessJob")
+      .field("progress", &self.progress())
+      .field("cancelled", &self.is_cancelled())
+      .field("result", &self.result())
+      .finish()
+  }
+}
+
+impl fmt::Debug for AnalysisRequest {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      AnalysisRequest::Analyze(job) => f.debug_tuple("Analyze").field(&Arc::as_ptr(job)).finish(),
+    }
+  }
+}
+
+impl LoudnessAnalyzer {
+  /// Jobs measure at `sample_rate`, sources are resampled to it
+  pub fn new(num_workers: usize, sample_rate: u32) -> Self {
+    let running = WorkerJobs::default();
+    let worker_running = running.clone();
+    let mut scheduler = Scheduler::new(num_workers, move |idx| {
+      let mut decoders = AudioDecoders::new(4);
+      let running = worker_running.clone();
+      move |request: AnalysisRequest, _: RequestKind, _: &mut CallbackSender<()>| {
+        let AnalysisRequest::Analyze(job) = request;
+        if !job.is_cancelled() {
+          running.run(idx, job.clone(), || job.run(sample_rate, &mut decoders));
+        }
+        Response::Ok(())
+      }
+    });
+    scheduler.set_respawn(true);
+    scheduler.register_metrics("Loudness", |_| "Analyze");
+    Self { scheduler, running, jobs: Vec::new() }
+  }
+
+  /// Queues a measurement. The first free worker takes it.
+  pub fn analyze(&mut self, target: AnalysisTarget) -> Result<Arc<LoudnessJob>, AnalysisError> {
+    let job = Arc::new(LoudnessJob::new(target));
+    let options = RequestOptions::new(Priority::Background);
+    self.scheduler.request_with(AnalysisRequest::Analyze(job.clone()), BroadcastKind::Any, options)?;
+    self.jobs.push(job.clone());
+    Ok(job)
+  }
+
+  /// Jobs that were queued and haven't been collected by `take_finished` yet
+  pub fn jobs(&self) -> &[Arc<LoudnessJob>] {
+    &self.jobs
+  }
+
+  /// Removes finished and cancelled jobs from `jobs` and returns them
+  pub fn take_finished(&mut self) -> Vec<Arc<LoudnessJob>> {
+    let (finished, running) = std::mem::take(&mut self.jobs).into_iter().partition(|job| job.is_finished());
+    self.jobs = running;
+    finished
+  }
+
+  /// Woken when a job is done, progress in between doesn't wake it
+  pub fn set_waker(&self, waker: Option<Waker>) {
+    self.scheduler.set_waker(waker)
+  }
+
+  /// Call regularly to keep track of the workers' state. The job of a worker that panicked
+  /// crashes, a new worker takes the next job.
+  pub fn handle_responses(&mut self) {
+    let running = &self.running;
+    self.scheduler.handle_respones(|resp, _| {
+      if let Response::WorkerDied { worker, payload } = resp {
+        let message = schedule::panic_message(payload.as_ref()).to_string();
+        match running.take(worker) {
+          Some(job) => *job.panic_message.lock().unwrap() = Some(message),
+          None => eprintln!("Analysis worker {} died: {}", worker, message),
+        }
+      }
+      Ok::<(), ()>(())
+    }).unwrap_or_default()
+  }
+}
@@ -0,0 +1,405 @@
+use std::{collections::VecDeque, f64::consts::PI};
+
+use super::StereoSample;
+
+/// Loudness of silence and of measurements without enough audio
+pub const SILENCE_LUFS: f64 = f64::NEG_INFINITY;
+/// EBU R128 target for broadcast programmes
+pub const TARGET_LUFS: f64 = -23.;
+
+/// Blocks below this loudness are ignored for integrated loudness and loudness range
+const ABSOLUTE_GATE_LUFS: f64 = -70.;
+/// Relative gate for integrated loudness, in LU below the absolute-gated loudness
+const INTEGRATED_RELATIVE_GATE: f64 = -10.;
+/// Relative gate for the loudness range
+const RANGE_RELATIVE_GATE: f64 = -20.;
+
+/// Loudness is measured on 100 ms sub-blocks, momentary over 4 of them, short-term over 30
+const SUB_BLOCKS_PER_SEC: u32 = 10;
+const MOMENTARY_SUB_BLOCKS: usize = 4;
+const SHORT_TERM_SUB_BLOCKS: usize = 30;
+
+/// Taps of each phase of the true-peak interpolation filter
+const TRUE_PEAK_TAPS: usize = 16;
+/// True peak is estimated on the signal upsampled by this factor
+const TRUE_PEAK_OVERSAMPLING: usize = 4;
+
+/// Second order IIR filter in direct form I
+#[derive(Clone, Copy, Debug, Default)]
+struct Biquad {
+  b: [f64; 3],
+  a: [f64; 3],
+  x: [f64; 2],
+  y: [f64; 2],
+}
+
+/// Loudness measurement after ITU-R BS.1770-4 and EBU R128 / Tech 3342: momentary, short-term
+/// and integrated loudness in LUFS, loudness range in LU and true peak in dBTP.
+#[derive(Clone, Debug)]
+pub struct LoudnessMeter {
+  sample_rate: u32,
+  /// K-weighting per channel: high shelf, then high pass
+  filters: [[Biquad; 2]; 2],
+  sub_block_len: usize,
+  sub_block_pos: usize,
+  /// Sum of the squared, K-weighted samples of the current sub-block over both channels
+  sub_block_sum: f64,
+  /// Mean squares of the last sub-blocks, newest last
+  sub_blocks: VecDeque<f64>,
+  /// Mean squares of all momentary (400 ms) blocks, gated for integrated loudness
+  blocks: Vec<f64>,
+  /// Mean squares of all short-term (3 s) blocks, gated for the loudness range
+  short_term_blocks: Vec<f64>,
+  max_momentary: f64,
+  max_short_term: f64,
+  true_peak_coefficients: [[f32; TRUE_PEAK_TAPS]; TRUE_PEAK_OVERSAMPLING],
+  true_peak_history: [VecDeque<f32>; 2],
+  true_peak: [f32; 2],
+}
+
+/// Summary of a finished measurement
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LoudnessReport {
+  pub integrated: f64,
+  pub range: f64,
+  pub true_peak: f64,
+  pub max_momentary: f64,
+  pub max_short_term: f64,
+}
+
+
+impl Biquad {
+  fn new(b: [f64; 3], a: [f64; 3]) -> Self {
+    Self { b, a, ..Default::default() }
+  }
+
+  fn process(&mut self, x: f64) -> f64 {
+    let y = self.b[0]*x + self.b[1]*self.x[0] + self.b[2]*self.x[1] - self.a[1]*self.y[0] - self.a[2]*self.y[1];
+    self.x = [x, self.x[0]];
+    self.y = [y, self.y[0]];
+    y
+  }
+}
+
+/// The two stages of the K-weighting filter for `sample_rate`, derived from the analog
+/// prototypes so rates other than 48 kHz work too
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+  let rate = sample_rate as f64;
+
+  // High shelf modelling the acoustic effect of the head
+  let (f0, gain_db, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+  let k = (PI * f0 / rate).tan();
+  let vh = 10f64.powf(gain_db / 20.);
+  let vb = vh.powf(0.4996667741545416);
+  let a0 = 1. + k/q + k*k;
+  let shelf = Biquad::new(
+    [(vh + vb*k/q + k*k) / a0, 2.*(k*k - vh) / a0, (vh - vb*k/q + k*k) / a0],
+    [1., 2.*(k*k - 1.) / a0, (1. - k/q + k*k) / a0],
+  );
+
+  // RLB high pass
+  let (f0, q) = (38.13547087602444, 0.5003270373238773);
+  let k = (PI * f0 / rate).tan();
+  let a0 = 1. + k/q + k*k;
+  let high_pass = Biquad::new(
+    [1., -2., 1.],
+    [1., 2.*(k*k - 1.) / a0, (1. - k/q + k*k) / a0],
+  );
+  [shelf, high_pass]
+}
+
+/// Windowed sinc interpolation filter, one set of taps per phase of the upsampled signal
+fn true_peak_coefficients() -> [[f32; TRUE_PEAK_TAPS]; TRUE_PEAK_OVERSAMPLING] {
+  let half = (TRUE_PEAK_TAPS / 2) as f64;
+  std::array::from_fn(|phase| std::array::from_fn(|k| {
+    let t = k as f64 - half + phase as f64 / TRUE_PEAK_OVERSAMPLING as f64;
+    let sinc = if t == 0. { 1. } else { (PI*t).sin() / (PI*t) };
+    let window = 0.5 * (1. + (PI * t / half).cos());
+    (sinc * window) as f32
+  }))
+}
+
+/// Loudness in LUFS of a mean square summed over the channels
+pub fn loudness_of(mean_square: f64) -> f64 {
+  if mean_square > 0. { -0.691 + 10. * mean_square.log10() } else { SILENCE_LUFS }
+}
+
+fn mean(values: impl Iterator<Item=f64>) -> Option<f64> {
+  let (sum, n) = values.fold((0., 0usize), |(sum, n), x| (sum + x, n + 1));
+  if n > 0 { Some(sum / n as f64) } else { None }
+}
+
+impl LoudnessMeter {
+  pub fn new(sample_rate: u32) -> Self {
+    let filters = k_weighting(sample_rate);
+    Self {
+      sample_rate,
+      filters: [filters, filters],
+      sub_block_len: (sample_rate / SUB_BLOCKS_PER_SEC).max(1) as usize,
+      sub_block_pos: 0,
+      sub_block_sum: 0.,
+      sub_blocks: VecDeque::with_capacity(SHORT_TERM_SUB_BLOCKS),
+      blocks: Vec::new(),
+      short_term_blocks: Vec::new(),
+      max_momentary: SILENCE_LUFS,
+      max_short_term: SILENCE_LUFS,
+      true_peak_coefficients: true_peak_coefficients(),
+      true_peak_history: std::array::from_fn(|_| VecDeque::from(vec![0.; TRUE_PEAK_TAPS])),
+      true_peak: [0.; 2],
+    }
+  }
+
+  pub fn sample_rate(&self) -> u32 {
+    self.sample_rate
+  }
+
+  pub fn reset(&mut self) {
+    *self = Self::new(self.sample_rate);
+  }
+
+  pub fn process(&mut self, samples: &[StereoSample]) {
+    for s in samples {
+      for (c, x) in s.iter().enumerate() {
+        let [shelf, high_pass] = &mut self.filters[c];
+        let y = high_pass.process(shelf.process(*x as f64));
+        self.sub_block_sum += y*y;
+        self.update_true_peak(c, *x);
+      }
+      self.sub_block_pos += 1;
+      if self.sub_block_pos == self.sub_block_len {
+        self.finish_sub_block();
+      }
+    }
+  }
+
+  fn update_true_peak(&mut self, channel: usize, x: f32) {
+    let history = &mut self.true_peak_history[channel];
+    history.pop_back();
+    history.push_front(x);
+    for taps in self.true_peak_coefficients.iter() {
+      let y: f32 = taps.iter().zip(history.iter()).map(|(h, x)| h*x).sum();
+      self.true_peak[channel] = self.true_peak[channel].max(y.abs());
+    }
+  }
+
+  fn finish_sub_block(&mut self) {
+    if self.sub_blocks.len() == SHORT_TERM_SUB_BLOCKS {
+      self.sub_blocks.pop_front();
+    }
+    self.sub_blocks.push_back(self.sub_block_sum / self.sub_block_len as f64);
+    (self.sub_block_pos, self.sub_block_sum) = (0, 0.);
+
+    // Blocks overlap by 75% for momentary loudness and are measured every 100 ms for the range
+    if let Some(block) = self.window(MOMENTARY_SUB_BLOCKS) {
+      self.blocks.push(block);
+      self.max_momentary = self.max_momentary.max(loudness_of(block));
+    }
+    if let Some(block) = self.window(SHORT_TERM_SUB_BLOCKS) {
+      self.short_term_blocks.push(block);
+      self.max_short_term = self.max_short_term.max(loudness_of(block));
+    }
+  }
+
+  /// Mean square over the last `n` sub-blocks, `None` until there are that many
+  fn window(&self, n: usize) -> Option<f64> {
+    if self.sub_blocks.len() < n {
+      return None;
+    }
+    mean(self.sub_blocks.iter().rev().take(n).copied())
+  }
+
+  /// Loudness of the last 400 ms
+  pub fn momentary(&self) -> f64 {
+    self.window(MOMENTARY_SUB_BLOCKS).map_or(SILENCE_LUFS, loudness_of)
+  }
+
+  /// Loudness of the last 3 s
+  pub fn short_term(&self) -> f64 {
+    self.window(SHORT_TERM_SUB_BLOCKS).map_or(SILENCE_LUFS, loudness_of)
+  }
+
+  /// Gated loudness of everything measured since the start or the last `reset`
+  pub fn integrated(&self) -> f64 {
+    let above_absolute = || self.blocks.iter().copied().filter(|b| loudness_of(*b) > ABSOLUTE_GATE_LUFS);
+    let relative_gate = match mean(above_absolute()) {
+      Some(mean) => loudness_of(mean) + INTEGRATED_RELATIVE_GATE,
+      None => return SILENCE_LUFS,
+    };
+    mean(above_absolute().filter(|b| loudness_of(*b) > relative_gate)).map_or(SILENCE_LUFS, loudness_of)
+  }
+
+  /// Loudness range in LU, the spread between the 10th and 95th percentile of the gated
+  /// short-term loudness
+  pub fn loudness_range(&self) -> f64 {
+    let above_absolute: Vec<f64> = self.short_term_blocks.iter().copied()
+      .filter(|b| loudness_of(*b) > ABSOLUTE_GATE_LUFS)
+      .collect();
+    let relative_gate = match mean(above_absolute.iter().copied()) {
+      Some(mean) => loudness_of(mean) + RANGE_RELATIVE_GATE,
+      None => return 0.,
+    };
+    let mut gated: Vec<f64> = above_absolute.into_iter().map(loudness_of).filter(|l| *l > relative_gate).collect();
+    if gated.is_empty() {
+      return 0.;
+    }
+    gated.sort_by(|a, b| a.total_cmp(b));
+    let percentile = |p: f64| gated[((gated.len() - 1) as f64 * p).round() as usize];
+    percentile(0.95) - percentile(0.10)
+  }
+
+  /// Highest true peak per channel, linear
+  pub fn true_peak(&self) -> [f32; 2] {
+    self.true_peak
+  }
+
+  /// Highest true peak of both channels in dBTP
+  pub fn true_peak_db(&self) -> f64 {
+    20. * (self.true_peak[0].max(self.true_peak[1]) as f64).log10()
+  }
+
+  pub fn max_momentary(&self) -> f64 {
+    self.max_momentary
+  }
+
+  pub fn max_short_term(&self) -> f64 {
+    self.max_short_term
+  }
+
+  pub fn report(&self) -> LoudnessReport {
+    LoudnessReport {
+      integrated: self.integrated(),
+      range: self.loudness_range(),
+      true_peak: self.true_peak_db(),
+      max_momentary: self.max_momentary,
+      max_short_term: self.max_short_term,
+    }
+  }
+}
+
+
+impl LoudnessReport {
+  /// Whether the integrated loudness is within `tolerance` LU of `target` and the true peak
+  /// doesn't exceed `max_true_peak`, e.g. `(TARGET_LUFS, 1., -1.)` for EBU R128
+  pub fn complies(&self, target: f64, tolerance: f64, max_true_peak: f64) -> bool {
+    (self.integrated - target).abs() <= tolerance && self.true_peak <= max_true_peak
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use std::f64::consts::PI;
+
+  use super::*;
+
+  /// `secs` of a sine with a peak of `dbfs`, `offset` in samples shifts its phase
+  fn sine(rate: u32, freq: f64, dbfs: f64, secs: f64, offset: f64) -> Vec<f32> {
+    let amplitude = 10f64.powf(dbfs / 20.);
+    (0..(rate as f64 * secs) as usize)
+      .map(|i| (amplitude * (2.*PI*freq*(i as f64 + offset) / rate as f64).sin()) as f32)
+      .collect()
+  }
+
+  fn stereo(samples: &[f32]) -> Vec<StereoSample> {
+    samples.iter().map(|x| [*x, *x]).collect()
+  }
+
+  fn left(samples: &[f32]) -> Vec<StereoSample> {
+    samples.iter().map(|x| [*x, 0.]).collect()
+  }
+
+  fn measure(rate: u32, parts: &[Vec<StereoSample>]) -> LoudnessMeter {
+    let mut meter = LoudnessMeter::new(rate);
+    for samples in parts {
+      meter.process(samples);
+    }
+    meter
+  }
+
+  #[test]
+  fn sine_reads_its_reference_loudness() {
+    for rate in [44100, 48000] {
+      // BS.1770: a 1 kHz sine at -20 dBFS in one channel reads -23 LUFS
+      let meter = measure(rate, &[left(&sine(rate, 1000., -20., 5., 0.))]);
+      assert!((meter.integrated() + 23.).abs() < 0.1, "{} Hz: {}", rate, meter.integrated());
+      assert!((meter.momentary() + 23.).abs() < 0.1);
+      assert!((meter.short_term() + 23.).abs() < 0.1);
+      assert!(meter.loudness_range() < 0.1);
+      // Both channels add up
+      let meter = measure(rate, &[stereo(&sine(rate, 1000., -20., 5., 0.))]);
+      assert!((meter.integrated() + 20.).abs() < 0.1, "{} Hz: {}", rate, meter.integrated());
+    }
+  }
+
+  #[test]
+  fn silence_has_no_loudness() {
+    let meter = measure(48000, &[vec![[0.; 2]; 48000 * 5]]);
+    assert_eq!(meter.integrated(), SILENCE_LUFS);
+    assert_eq!(meter.momentary(), SILENCE_LUFS);
+    assert_eq!(meter.loudness_range(), 0.);
+    // Less than a momentary block
+    let meter = measure(48000, &[stereo(&sine(48000, 1000., -20., 0.3, 0.))]);
+    assert_eq!(meter.integrated(), SILENCE_LUFS);
+  }
+
+  #[test]
+  fn absolute_gate_ignores_near_silence() {
+    let rate = 48000;
+    let loud = left(&sine(rate, 1000., -20., 5., 0.));
+    // -77 LUFS, below the absolute gate of -70
+    let quiet = left(&sine(rate, 1000., -74., 5., 0.));
+    let meter = measure(rate, &[loud, quiet.clone()]);
+    assert!((meter.integrated() + 23.).abs() < 0.2, "{}", meter.integrated());
+    let meter = measure(rate, &[quiet]);
+    assert_eq!(meter.integrated(), SILENCE_LUFS);
+  }
+
+  #[test]
+  fn relative_gate_ignores_quiet_passages() {
+    let rate = 48000;
+    let loud = left(&sine(rate, 1000., -20., 5., 0.));
+    // -38 LUFS is more than 10 LU below the -26 LUFS of both parts together
+    let meter = measure(rate, &[loud.clone(), left(&sine(rate, 1000., -35., 5., 0.))]);
+    assert!((meter.integrated() + 23.).abs() < 0.2, "{}", meter.integrated());
+    // -28 LUFS is within the gate and lowers the loudness to the mean power of both parts
+    let meter = measure(rate, &[loud, left(&sine(rate, 1000., -25., 5., 0.))]);
+    let expected = 10. * ((10f64.powf(-2.3) + 10f64.powf(-2.8)) / 2.).log10();
+    assert!((meter.integrated() - expected).abs() < 0.2, "{} instead of {}", meter.integrated(), expected);
+  }
+
+  #[test]
+  fn loudness_range_spans_the_levels() {
+    let rate = 48000;
+    let meter = measure(rate, &[
+      left(&sine(rate, 1000., -30., 10., 0.)),
+      left(&sine(rate, 1000., -20., 10., 0.)),
+    ]);
+    // -33 and -23 LUFS, both within the relative gate of the range
+    assert!((meter.loudness_range() - 10.).abs() < 0.5, "{}", meter.loudness_range());
+    assert!((meter.max_short_term() + 23.).abs() < 0.1);
+  }
+
+  #[test]
+  fn true_peak_finds_peaks_between_samples() {
+    let rate = 48000;
+    // A sine at a quarter of the sample rate shifted by half a sample has all of its samples at
+    // +-0.707, i.e. -3 dBFS, while its peaks at 0 dBFS fall between them
+    let samples = sine(rate, rate as f64 / 4., 0., 1., 0.5);
+    let sample_peak = samples.iter().fold(0f32, |peak, x| peak.max(x.abs()));
+    assert!((20. * (sample_peak as f64).log10() + 3.01).abs() < 0.01);
+    let meter = measure(rate, &[stereo(&samples)]);
+    assert!(meter.true_peak_db().abs() < 0.5, "{}", meter.true_peak_db());
+    // Without offset the samples hit the peaks
+    let meter = measure(rate, &[left(&sine(rate, 1000., -6., 1., 0.))]);
+    assert!((meter.true_peak_db() + 6.).abs() < 0.2, "{}", meter.true_peak_db());
+    assert_eq!(meter.true_peak()[1], 0.);
+  }
+
+  #[test]
+  fn compliance_checks_loudness_and_peak() {
+    let report = LoudnessReport { integrated: -23.4, range: 5., true_peak: -1.5, max_momentary: -18., max_short_term: -20. };
+    assert!(report.complies(TARGET_LUFS, 0.5, -1.));
+    assert!(!report.complies(TARGET_LUFS, 0.3, -1.));
+    assert!(!report.complies(TARGET_LUFS, 0.5, -2.));
+  }
+}
@@ -0,0 +1,81 @@
+use super::StereoSample;
+
+/// Peaks are held this long before they fall
+const PEAK_HOLD_SECS: f32 = 1.;
+/// Fall rate of peaks after the hold time
+const PEAK_FALL_DB_PER_SEC: f32 = 20.;
+/// Time constant of the RMS average
+const RMS_WINDOW_SECS: f32 = 0.3;
+
+/// Level meter for a stereo signal with held peaks and a running RMS
+#[derive(Clone, Debug)]
+pub struct Meter {
+  sample_rate: u32,
+  peak: [f32; 2],
+  /// Samples until the held peak starts to fall
+  hold: [u32; 2],
+  /// Running mean of the squared samples
+  mean_square: [f32; 2],
+}
+
+
+/// Converts a linear amplitude to dBFS, silence is `-inf`
+pub fn to_db(amplitude: f32) -> f32 {
+  20. * amplitude.log10()
+}
+
+pub fn from_db(db: f32) -> f32 {
+  10f32.powf(db / 20.)
+}
+
+impl Meter {
+  pub fn new(sample_rate: u32) -> Self {
+    Self { sample_rate, peak: [0.; 2], hold: [0; 2], mean_square: [0.; 2] }
+  }
+
+  pub fn reset(&mut self) {
+    *self = Self::new(self.sample_rate);
+  }
+
+  pub fn process(&mut self, samples: &[StereoSample]) {
+    let rate = self.sample_rate as f32;
+    let fall = from_db(-PEAK_FALL_DB_PER_SEC / rate);
+    let alpha = 1. - (-1. / (RMS_WINDOW_SECS * rate)).exp();
+    for s in samples {
+      for (c, x) in s.iter().map(|x| x.abs()).enumerate() {
+        if x >= self.peak[c] {
+          self.peak[c] = x;
+          self.hold[c] = (PEAK_HOLD_SECS * rate) as u32;
+        } else if self.hold[c] > 0 {
+          self.hold[c] -= 1;
+        } else {
+          self.peak[c] *= fall;
+        }
+        self.mean_square[c] += (x*x - self.mean_square[c]) * alpha;
+      }
+    }
+  }
+
+  /// Held peak per channel, linear
+  pub fn peak(&self) -> [f32; 2] {
+    self.peak
+  }
+
+  /// RMS per channel over roughly the last 300 ms, linear
+  pub fn rms(&self) -> [f32; 2] {
+    self.mean_square.map(f32::sqrt)
+  }
+
+  pub fn peak_db(&self) -> [f32; 2] {
+    self.peak.map(to_db)
+  }
+
+  pub fn rms_db(&self) -> [f32; 2] {
+    self.rms().map(to_db)
+  }
+
+  /// Whether a sample reached full scale within the hold time
+  pub fn is_clipping(&self) -> bool {
+    self.peak.iter().any(|p| *p >= 1.)
+  }
+}
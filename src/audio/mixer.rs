@@ -0,0 +1,245 @@
+use std::ptr;
+
+use crate::{
+  timeline::{Timeline, TrackKind},
+  video::{RationalTime, VideoStreamErr},
+};
+
+use super::{LoudnessMeter, Meter, SampleSource, StereoSample};
+
+/// Gains are evaluated at the edges of blocks of this many samples and ramped in between, and
+/// clips start and stop on block edges. Blocks are aligned to the timeline's sample index, so the
+/// mix doesn't depend on how it is split into calls of `Mixer::mix`.
+pub const BLOCK_SIZE: usize = 64;
+
+/// Mixes the audio tracks of a timeline: clip volume and crossfades, then track gain and pan,
+/// then the master bus. Playback and export both use this.
+pub struct Mixer {
+  pub sample_rate: u32,
+  /// Linear gain of the master bus
+  pub master_gain: f32,
+  pub master_meter: Meter,
+  /// EBU R128 loudness of the master bus, reset it to start a new measurement
+  pub master_loudness: LoudnessMeter,
+  track_meters: Vec<Meter>,
+  /// Last error of a source. Sources are silent while they fail.
+  pub last_error: Option<VideoStreamErr>,
+  source_buf: Vec<StereoSample>,
+  track_buf: Vec<StereoSample>,
+}
+
+
+/// Channel gains for a track's `pan`. Pan works as a balance: the center leaves both channels
+/// untouched, panning attenuates the opposite channel.
+pub fn pan_gains(pan: f32) -> [f32; 2] {
+  let pan = pan.clamp(-1., 1.);
+  [(1. - pan).min(1.), (1. + pan).min(1.)]
+}
+
+impl Mixer {
+  pub fn new(sample_rate: u32) -> Self {
+    Self {
+      sample_rate,
+      master_gain: 1.,
+      master_meter: Meter::new(sample_rate),
+      master_loudness: LoudnessMeter::new(sample_rate),
+      track_meters: Vec::new(),
+      last_error: None,
+      source_buf: Vec::new(),
+      track_buf: Vec::new(),
+    }
+  }
+
+  /// Timeline time of the sample index `sample`
+  pub fn time_of(&self, sample: i64) -> RationalTime {
+    RationalTime::new(sample, self.sample_rate as _)
+  }
+
+  /// Meter of the track at `track_idx` after its gain and pan
+  pub fn track_meter(&self, track_idx: usize) -> Option<&Meter> {
+    self.track_meters.get(track_idx)
+  }
+
+  /// Mixes `out.len()` samples of the timeline starting at the sample index `start`
+  pub fn mix(&mut self, timeline: &Timeline, start: i64, source: &mut impl SampleSource, out: &mut [StereoSample]) {
+    let sample_rate = self.sample_rate;
+    self.track_meters.resize_with(timeline.tracks.len(), || Meter::new(sample_rate));
+    let block = BLOCK_SIZE as i64;
+    let end = start + out.len() as i64;
+    let mut pos = start;
+    while pos < end {
+      let block_start = pos.div_euclid(block) * block;
+      let chunk_end = (block_start + block).min(end);
+      let offset = pos - block_start;
+      let out = &mut out[(pos - start) as usize..(chunk_end - start) as usize];
+      let len = out.len();
+      out.fill([0.; 2]);
+
+      let samples = timeline.audio_at(self.time_of(block_start));
+      let next = timeline.audio_at(self.time_of(block_start + block));
+      for (track_idx, track) in timeline.tracks.iter().enumerate().filter(|(_, t)| t.kind == TrackKind::Audio) {
+        self.track_buf.clear();
+        self.track_buf.resize(len, [0.; 2]);
+        for sample in samples.iter().filter(|s| s.track_idx == track_idx) {
+          // A clip that ends at the block edge keeps its gain
+          let gain_end = next.iter()
+            .find(|n| n.track_idx == track_idx && ptr::eq(n.clip, sample.clip))
+            .map_or(sample.gain, |n| n.gain);
+          self.source_buf.clear();
+          self.source_buf.resize(len, [0.; 2]);
+          let source_time = sample.source_time + self.time_of(offset);
+          if let Err(err) = source.read(&sample.clip.source, source_time, sample_rate, &mut self.source_buf) {
+            self.source_buf.fill([0.; 2]);
+            self.last_error = Some(err);
+          }
+          for (i, (dst, src)) in self.track_buf.iter_mut().zip(self.source_buf.iter()).enumerate() {
+            let t = (offset + i as i64) as f32 / block as f32;
+            let gain = sample.gain + (gain_end - sample.gain)*t;
+            dst[0] += src[0]*gain;
+            dst[1] += src[1]*gain;
+          }
+        }
+
+        let [left, right] = pan_gains(track.pan).map(|g| g*track.gain);
+        for (dst, src) in out.iter_mut().zip(self.track_buf.iter_mut()) {
+          *src = [src[0]*left, src[1]*right];
+          dst[0] += src[0];
+          dst[1] += src[1];
+        }
+        self.track_meters[track_idx].process(&self.track_buf);
+      }
+
+      for s in out.iter_mut() {
+        *s = [s[0]*self.master_gain, s[1]*self.master_gain];
+      }
+      self.master_meter.process(out);
+      self.master_loudness.process(out);
+      pos = chunk_end;
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use std::path::PathBuf;
+
+  use crate::timeline::{ClipSource, TimelineClip, Track};
+  use super::*;
+  use crate::audio::{output::NullOutput, AudioOutput};
+
+  /// One second is 20 blocks
+  const RATE: u32 = 64 * 20;
+
+  /// A clip whose source plays `level` on both channels
+  fn clip(level: f32, start: i64, duration: i64) -> TimelineClip {
+    let source = ClipSource::File { path: PathBuf::from(level.to_string()), stream_idx: 0 };
+    TimelineClip::new(source, RationalTime::from_secs(start), RationalTime::from_secs(duration))
+  }
+
+  fn track(clips: impl IntoIterator<Item=TimelineClip>) -> Track {
+    panned_track(1., 0., clips)
+  }
+
+  fn panned_track(gain: f32, pan: f32, clips: impl IntoIterator<Item=TimelineClip>) -> Track {
+    let mut track = Track::new("A", TrackKind::Audio);
+    track.gain = gain;
+    track.pan = pan;
+    for clip in clips {
+      track.add_clip(clip).unwrap();
+    }
+    track
+  }
+
+  fn read_level(source: &ClipSource, _: RationalTime, _: u32, out: &mut [StereoSample]) -> Result<(), VideoStreamErr> {
+    match source {
+      ClipSource::File { path, .. } => out.fill([path.to_str().unwrap().parse().unwrap(); 2]),
+      ClipSource::Generator(_) => unreachable!(),
+    }
+    Ok(())
+  }
+
+  /// Mixes `len` samples from the start in chunks of `chunk` samples into a `NullOutput`
+  fn play(mixer: &mut Mixer, timeline: &Timeline, len: usize, chunk: usize) -> Vec<StereoSample> {
+    let mut output = NullOutput::capturing(RATE);
+    let mut buf = vec![[0.; 2]; chunk];
+    let mut pos = 0;
+    while pos < len {
+      let buf = &mut buf[..chunk.min(len - pos)];
+      mixer.mix(timeline, pos as i64, &mut read_level, buf);
+      pos += output.write(buf);
+    }
+    assert_eq!(output.written, len as u64);
+    output.capture.unwrap()
+  }
+
+  #[test]
+  fn pan_is_a_balance() {
+    assert_eq!(pan_gains(0.), [1., 1.]);
+    assert_eq!(pan_gains(-1.), [1., 0.]);
+    assert_eq!(pan_gains(1.), [0., 1.]);
+    assert_eq!(pan_gains(0.5), [0.5, 1.]);
+    assert_eq!(pan_gains(-3.), [1., 0.]);
+  }
+
+  #[test]
+  fn track_and_master_gains() {
+    let mut timeline = Timeline::new((25, 1));
+    timeline.tracks.push(panned_track(0.5, 0.5, [clip(0.5, 0, 1)]));
+    let mut mixer = Mixer::new(RATE);
+    let mixed = play(&mut mixer, &timeline, RATE as usize, 256);
+    assert!(mixed.iter().all(|s| *s == [0.125, 0.25]), "{:?}", &mixed[..4]);
+    assert_eq!(mixer.track_meter(0).unwrap().peak(), [0.125, 0.25]);
+
+    mixer.master_gain = 2.;
+    let mixed = play(&mut mixer, &timeline, RATE as usize, 256);
+    assert!(mixed.iter().all(|s| *s == [0.25, 0.5]));
+    // The track meter is before the master gain
+    assert_eq!(mixer.track_meter(0).unwrap().peak(), [0.125, 0.25]);
+  }
+
+  #[test]
+  fn tracks_are_summed() {
+    let mut timeline = Timeline::new((25, 1));
+    timeline.tracks.push(track([clip(0.25, 0, 2)]));
+    timeline.tracks.push(Track::new("V", TrackKind::Video));
+    timeline.tracks.push(track([clip(0.5, 1, 1)]));
+    let mut mixer = Mixer::new(RATE);
+    let mixed = play(&mut mixer, &timeline, 3 * RATE as usize, 1000);
+    let second = |s: usize| &mixed[s * RATE as usize..(s + 1) * RATE as usize];
+    assert!(second(0).iter().all(|s| *s == [0.25; 2]));
+    assert!(second(1).iter().all(|s| *s == [0.75; 2]));
+    assert!(second(2).iter().all(|s| *s == [0.; 2]));
+
+    timeline.tracks[0].muted = true;
+    let mixed = play(&mut mixer, &timeline, 2 * RATE as usize, 1000);
+    assert_eq!(mixed[..RATE as usize], vec![[0.; 2]; RATE as usize]);
+    assert!(mixed[RATE as usize..].iter().all(|s| *s == [0.5; 2]));
+  }
+
+  #[test]
+  fn chunking_does_not_change_the_mix() {
+    let mut timeline = Timeline::new((25, 1));
+    timeline.tracks.push(panned_track(1., -0.3, [clip(0.25, 0, 1), clip(0.75, 1, 1)]));
+    timeline.tracks.push(panned_track(0.8, 0., [clip(0.5, 1, 1)]));
+    let whole = play(&mut Mixer::new(RATE), &timeline, 2 * RATE as usize, 2 * RATE as usize);
+    for chunk in [1, 63, 100, 777] {
+      assert_eq!(play(&mut Mixer::new(RATE), &timeline, 2 * RATE as usize, chunk), whole, "chunks of {}", chunk);
+    }
+  }
+
+  #[test]
+  fn failing_sources_are_silent() {
+    let mut timeline = Timeline::new((25, 1));
+    timeline.tracks.push(track([clip(0.5, 0, 1)]));
+    let mut mixer = Mixer::new(RATE);
+    let mut out = vec![[1.; 2]; 100];
+    let mut fail = |_: &ClipSource, _, _, out: &mut [StereoSample]| {
+      out.fill([1.; 2]);
+      Err(VideoStreamErr::IO)
+    };
+    mixer.mix(&timeline, 0, &mut fail, &mut out);
+    assert_eq!(out, vec![[0.; 2]; 100]);
+    assert!(matches!(mixer.last_error, Some(VideoStreamErr::IO)));
+  }
+}
@@ -0,0 +1,231 @@
+use std::{fmt, fs, io::{self, Seek, SeekFrom, Write}, path::Path};
+
+use super::StereoSample;
+
+/// Where mixed audio goes: a sound device, a file or nowhere
+pub trait AudioOutput {
+  fn sample_rate(&self) -> u32;
+
+  /// Queues `samples` for playback and returns how many were accepted. Fewer than given means
+  /// the output is full for now.
+  fn write(&mut self, samples: &[StereoSample]) -> usize;
+
+  /// Number of samples written but not played yet
+  fn queued(&self) -> usize;
+
+  /// Drops all queued samples, e.g. after seeking
+  fn flush(&mut self);
+}
+
+#[derive(Debug)]
+pub enum OutputError {
+  NoDevice,
+  Device(String),
+  IO(io::Error),
+}
+
+/// Accepts everything and plays nothing. Keeps the samples if `capture` is set, for tests.
+#[derive(Clone, Debug, Default)]
+pub struct NullOutput {
+  pub sample_rate: u32,
+  /// Number of samples written so far
+  pub written: u64,
+  pub capture: Option<Vec<StereoSample>>,
+}
+
+/// Writes 32 bit float WAV. The header is completed by `finish`.
+pub struct WavOutput<W: Write + Seek> {
+  writer: W,
+  sample_rate: u32,
+  samples: u32,
+  /// First error of a write, the output accepts no samples after it
+  error: Option<io::Error>,
+}
+
+
+impl fmt::Display for OutputError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      OutputError::NoDevice => write!(f, "No audio output device"),
+      OutputError::Device(err) => write!(f, "Audio device error: {}", err),
+      OutputError::IO(err) => write!(f, "IO error: {}", err),
+    }
+  }
+}
+
+impl From<io::Error> for OutputError {
+  fn from(err: io::Error) -> Self { OutputError::IO(err) }
+}
+
+
+impl NullOutput {
+  pub fn new(sample_rate: u32) -> Self {
+    Self { sample_rate, written: 0, capture: None }
+  }
+
+  pub fn capturing(sample_rate: u32) -> Self {
+    Self { sample_rate, written: 0, capture: Some(Vec::new()) }
+  }
+}
+
+impl AudioOutput for NullOutput {
+  fn sample_rate(&self) -> u32 { self.sample_rate }
+
+  fn write(&mut self, samples: &[StereoSample]) -> usize {
+    self.written += samples.len() as u64;
+    if let Some(capture) = &mut self.capture {
+      capture.extend_from_slice(samples);
+    }
+    samples.len()
+  }
+
+  fn queued(&self) -> usize { 0 }
+
+  fn flush(&mut self) {}
+}
+
+
+const WAV_HEADER_LEN: u32 = 44;
+
+impl WavOutput<io::BufWriter<fs::File>> {
+  pub fn create(path: &Path, sample_rate: u32) -> io::Result<Self> {
+    Self::new(io::BufWriter::new(fs::File::create(path)?), sample_rate)
+  }
+}
+
+impl<W: Write + Seek> WavOutput<W> {
+  pub fn new(mut writer: W, sample_rate: u32) -> io::Result<Self> {
+    write_wav_header(&mut writer, sample_rate, 0)?;
+    Ok(Self { writer, sample_rate, samples: 0, error: None })
+  }
+
+  /// Completes the header and returns the writer
+  pub fn finish(mut self) -> io::Result<W> {
+    if let Some(err) = self.error.take() {
+      return Err(err);
+    }
+    self.writer.seek(SeekFrom::Start(0))?;
+    write_wav_header(&mut self.writer, self.sample_rate, self.samples)?;
+    self.writer.seek(SeekFrom::End(0))?;
+    self.writer.flush()?;
+    Ok(self.writer)
+  }
+}
+
+fn write_wav_header(w: &mut impl Write, sample_rate: u32, samples: u32) -> io::Result<()> {
+  let (channels, bytes_per_sample) = (2u16, 4u16);
+  let block_align = channels * bytes_per_sample;
+  let data_len = samples.saturating_mul(block_align as u32);
+  w.write_all(b"RIFF")?;
+  w.write_all(&(WAV_HEADER_LEN - 8).saturating_add(data_len).to_le_bytes())?;
+  w.write_all(b"WAVEfmt ")?;
+  w.write_all(&16u32.to_le_bytes())?;
+  // WAVE_FORMAT_IEEE_FLOAT
+  w.write_all(&3u16.to_le_bytes())?;
+  w.write_all(&channels.to_le_bytes())?;
+  w.write_all(&sample_rate.to_le_bytes())?;
+  w.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+  w.write_all(&block_align.to_le_bytes())?;
+  w.write_all(&(bytes_per_sample * 8).to_le_bytes())?;
+  w.write_all(b"data")?;
+  w.write_all(&data_len.to_le_bytes())
+}
+
+impl<W: Write + Seek> AudioOutput for WavOutput<W> {
+  fn sample_rate(&self) -> u32 { self.sample_rate }
+
+  fn write(&mut self, samples: &[StereoSample]) -> usize {
+    if self.error.is_some() {
+      return 0;
+    }
+    let bytes: Vec<u8> = samples.iter().flatten().flat_map(|s| s.to_le_bytes()).collect();
+    match self.writer.write_all(&bytes) {
+      Ok(()) => {
+        self.samples = self.samples.saturating_add(samples.len() as u32);
+        samples.len()
+      },
+      Err(err) => {
+        self.error = Some(err);
+        0
+      },
+    }
+  }
+
+  fn queued(&self) -> usize { 0 }
+
+  fn flush(&mut self) {}
+}
+
+
+#[cfg(feature = "cpal")]
+pub use device::DeviceOutput;
+
+#[cfg(feature = "cpal")]
+mod device {
+  use std::{collections::VecDeque, sync::{Arc, Mutex}};
+
+  use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+  use super::{AudioOutput, OutputError, StereoSample};
+
+  /// Plays through the default output device. The device pulls samples from a queue of at most
+  /// `capacity` samples on its own thread, gaps play as silence.
+  pub struct DeviceOutput {
+    _stream: cpal::Stream,
+    queue: Arc<Mutex<VecDeque<StereoSample>>>,
+    sample_rate: u32,
+    capacity: usize,
+  }
+
+  impl DeviceOutput {
+    pub fn open_default(capacity: usize) -> Result<Self, OutputError> {
+      let device = cpal::default_host().default_output_device().ok_or(OutputError::NoDevice)?;
+      let config = device.default_output_config().map_err(|err| OutputError::Device(err.to_string()))?;
+      let channels = config.channels() as usize;
+      let sample_rate = config.sample_rate().0;
+
+      let queue = Arc::new(Mutex::new(VecDeque::with_capacity(capacity)));
+      let stream_queue = queue.clone();
+      let stream = device.build_output_stream(
+        &config.config(),
+        move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+          let mut queue = stream_queue.lock().unwrap();
+          for frame in data.chunks_mut(channels) {
+            let [l, r] = queue.pop_front().unwrap_or([0.; 2]);
+            match frame {
+              [mono] => *mono = (l + r) / 2.,
+              [left, right, rest @ ..] => {
+                (*left, *right) = (l, r);
+                rest.fill(0.);
+              },
+              [] => {},
+            }
+          }
+        },
+        |err| eprintln!("Audio output error: {}", err),
+        None,
+      ).map_err(|err| OutputError::Device(err.to_string()))?;
+      stream.play().map_err(|err| OutputError::Device(err.to_string()))?;
+      Ok(Self { _stream: stream, queue, sample_rate, capacity })
+    }
+  }
+
+  impl AudioOutput for DeviceOutput {
+    fn sample_rate(&self) -> u32 { self.sample_rate }
+
+    fn write(&mut self, samples: &[StereoSample]) -> usize {
+      let mut queue = self.queue.lock().unwrap();
+      let n = samples.len().min(self.capacity.saturating_sub(queue.len()));
+      queue.extend(&samples[..n]);
+      n
+    }
+
+    fn queued(&self) -> usize {
+      self.queue.lock().unwrap().len()
+    }
+
+    fn flush(&mut self) {
+      self.queue.lock().unwrap().clear();
+    }
+  }
+}
@@ -0,0 +1,424 @@
+//! Headless front end for render farms and scripts. Every failure has its own exit code, see
+//! `exit_codes`.
+
+use std::{
+  path::{Path, PathBuf},
+  process::ExitCode,
+  thread,
+  time::Duration,
+};
+
+use clap::{Args, Parser, Subcommand};
+use escher::{
+  audio::{loudness::TARGET_LUFS, AnalysisTarget, LoudnessAnalyzer},
+  clip::{packed_rgba, FileSource, FrameSource},
+  effects::EffectRegistry,
+  export::{ExportError, ExportStatus, PresetLibrary, RenderQueue},
+  project::Project,
+  timeline::ClipSource,
+  video::{self, ffi::AVMediaType, probe::probe, MediaInfo, RationalTime, RawImageRef, VideoStreamErr},
+};
+
+#[derive(Parser)]
+#[command(name = "escher-cli", version, about = "Probe, preview and render media and Escher projects without a window")]
+#[command(after_help = exit_codes::HELP)]
+struct Cli {
+  #[command(subcommand)]
+  command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+  /// Lists the container format and the streams of a media file
+  Probe {
+    input: PathBuf,
+  },
+  /// Writes one frame as an image, the format follows the extension of the output
+  Thumbnail {
+    input: PathBuf,
+    /// Time of the frame, e.g. 90, 1m30s or 1:30
+    #[arg(long, default_value = "0")]
+    at: RationalTime,
+    #[arg(short, long)]
+    output: PathBuf,
+    #[command(flatten)]
+    frames: FrameArgs,
+  },
+  /// Writes every frame of a range as numbered images into a directory
+  ExtractFrames {
+    input: PathBuf,
+    /// `from..to`, either side may be left out, e.g. 10s..20s or ..1:00
+    #[arg(long, default_value = "..")]
+    range: TimeRange,
+    /// Directory of the images, created if it doesn't exist
+    #[arg(short, long)]
+    output: PathBuf,
+    /// Only writes every n-th frame
+    #[arg(long, default_value_t = 1)]
+    every: u32,
+    /// Image format of the frames
+    #[arg(long, default_value = "png")]
+    extension: String,
+    #[command(flatten)]
+    frames: FrameArgs,
+  },
+  /// Renders a project with an export preset
+  Render {
+    project: PathBuf,
+    #[arg(short, long, required_unless_present = "list_presets")]
+    output: Option<PathBuf>,
+    /// Name of the export preset
+    #[arg(long, default_value = "YouTube 1080p H.264")]
+    preset: String,
+    /// `from..to` of the timeline, the whole timeline by default
+    #[arg(long, default_value = "..")]
+    range: TimeRange,
+    /// Lists the presets and what the linked FFmpeg lacks for them, then exits
+    #[arg(long)]
+    list_presets: bool,
+  },
+  /// Measures the loudness of a media file or of the mix of a project (EBU R128)
+  Loudness {
+    /// Media file or `.escher` project
+    input: PathBuf,
+    /// Audio stream of a media file
+    #[arg(long, default_value_t = 0)]
+    stream: u32,
+    #[arg(long, default_value = "..")]
+    range: TimeRange,
+    /// Fails if the result misses -23 LUFS ±1 LU or the true peak exceeds -1 dBTP
+    #[arg(long)]
+    check: bool,
+  },
+}
+
+#[derive(Args)]
+struct FrameArgs {
+  /// Video stream, the first one by default
+  #[arg(long)]
+  stream: Option<u32>,
+  /// Width of the images, the height keeps the aspect ratio. The stream's size by default.
+  #[arg(long)]
+  width: Option<u32>,
+}
+
+/// `from..to` with open ends
+#[derive(Clone, Copy, Debug)]
+struct TimeRange {
+  from: Option<RationalTime>,
+  to: Option<RationalTime>,
+}
+
+/// Why the command failed, mapped to the exit code by `exit_code`
+#[derive(Debug)]
+enum CliError {
+  Video(VideoStreamErr),
+  Project(String),
+  /// The preset doesn't exist or the linked FFmpeg lacks something it needs
+  Preset(String),
+  Export(ExportError),
+  Image(String),
+  /// `loudness --check` measured a result outside of EBU R128
+  LoudnessOutOfSpec,
+  Other(String),
+}
+
+mod exit_codes {
+  pub const OTHER: u8 = 1;
+  pub const LOUDNESS_OUT_OF_SPEC: u8 = 3;
+  pub const PROJECT: u8 = 4;
+  pub const PRESET: u8 = 5;
+  pub const NO_GPU: u8 = 6;
+  pub const IMAGE: u8 = 7;
+  /// `VideoStreamErr`s start here, in the order of the enum
+  pub const VIDEO: u8 = 10;
+
+  pub const HELP: &str = "\
+Exit codes:
+  0   success
+  1   other failure
+  2   invalid arguments
+  3   loudness out of spec (--check)
+  4   invalid project
+  5   preset unknown or not supported by the linked FFmpeg
+  6   no GPU to render with
+  7   image can't be written
+  10  FFmpeg error          11  timestamp out of bounds  12  end of file
+  13  IO                    14  encoder tries to decode  15  decoder tries to encode
+  16  index out of bounds   17  stream not found         18  decoder not found
+  19  null reference        20  encoder not found        21  muxer not found";
+}
+
+type CliResult = Result<(), CliError>;
+
+
+fn exit_code(err: &CliError) -> u8 {
+  match err {
+    CliError::Video(err) | CliError::Export(ExportError::Encoder(err)) => exit_codes::VIDEO + match err {
+      VideoStreamErr::FFMPEGErr { .. } => 0,
+      VideoStreamErr::TimeStampOutOfBounds => 1,
+      VideoStreamErr::EOF => 2,
+      VideoStreamErr::IO => 3,
+      VideoStreamErr::EncoderTrysToDecode => 4,
+      VideoStreamErr::DecoderTrysToEncode => 5,
+      VideoStreamErr::IndexOutOfBounds => 6,
+      VideoStreamErr::StreamNotFound => 7,
+      VideoStreamErr::DecoderNotFound => 8,
+      VideoStreamErr::NullReference => 9,
+      VideoStreamErr::EncoderNotFound => 10,
+      VideoStreamErr::MuxerNotFound => 11,
+    },
+    CliError::Export(ExportError::InvalidPath) => exit_codes::VIDEO + 3,
+    CliError::Export(ExportError::NoAdapter) => exit_codes::NO_GPU,
+    CliError::Export(_) | CliError::Other(_) => exit_codes::OTHER,
+    CliError::Project(_) => exit_codes::PROJECT,
+    CliError::Preset(_) => exit_codes::PRESET,
+    CliError::Image(_) => exit_codes::IMAGE,
+    CliError::LoudnessOutOfSpec => exit_codes::LOUDNESS_OUT_OF_SPEC,
+  }
+}
+
+fn format_duration(duration: Duration) -> String {
+  let secs = duration.as_secs();
+  format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+fn format_time(time: Option<RationalTime>) -> String {
+  match time {
+    Some(time) => format!("{:.3}s", time.as_secs_f64()),
+    None => "unknown".to_string(),
+  }
+}
+
+fn save_image(path: &Path, frame: &RawImageRef) -> CliResult {
+  image::save_buffer(path, &packed_rgba(frame), frame.width() as _, frame.height() as _, image::ColorType::Rgba8)
+    .map_err(|err| CliError::Image(format!("{}: {}", path.display(), err)))
+}
+
+/// Opens a video stream of `input` scaled to `--width`
+fn open_frames(input: &Path, info: &MediaInfo, args: &FrameArgs) -> Result<(FileSource, RationalTime), CliError> {
+  let stream = match args.stream {
+    Some(idx) => info.streams.get(idx as usize).filter(|s| s.media_type == AVMediaType::AVMEDIA_TYPE_VIDEO),
+    None => info.first_stream(AVMediaType::AVMEDIA_TYPE_VIDEO),
+  }.ok_or(CliError::Video(VideoStreamErr::StreamNotFound))?;
+  let size = match args.width {
+    Some(width) if stream.width > 0 => [width, ((width as u64 * stream.height as u64 / stream.width as u64).max(1)) as u32],
+    _ => [stream.width, stream.height],
+  };
+  let frame_duration = stream.frame_duration().unwrap_or(RationalTime::new(1, 25));
+  let duration = stream.duration.or(info.duration);
+  let source = FileSource::open(input, stream.index, size, frame_duration, duration).map_err(CliError::Video)?;
+  Ok((source, frame_duration))
+}
+
+fn run_probe(input: &Path) -> CliResult {
+  let info = probe(input).map_err(CliError::Video)?;
+  println!("{}", input.display());
+  println!("  format    {}", info.format);
+  println!("  duration  {}", format_time(info.best_duration()));
+  if info.bit_rate > 0 {
+    println!("  bit rate  {} kb/s", info.bit_rate / 1000);
+  }
+  for stream in info.streams.iter() {
+    let format = stream.format.as_deref().unwrap_or("?");
+    let details = match stream.media_type {
+      AVMediaType::AVMEDIA_TYPE_VIDEO => {
+        let fps = stream.frame_rate.map_or("? fps".to_string(), |(num, den)| format!("{:.3} fps", num as f64 / den as f64));
+        format!("video  {} {} {}x{} {}", stream.codec, format, stream.width, stream.height, fps)
+      },
+      AVMediaType::AVMEDIA_TYPE_AUDIO =>
+        format!("audio  {} {} {} Hz {} ch", stream.codec, format, stream.sample_rate, stream.channels),
+      AVMediaType::AVMEDIA_TYPE_SUBTITLE => format!("subtitle  {}", stream.codec),
+      _ => format!("data  {}", stream.codec),
+    };
+    println!("  #{}  {}  {}", stream.index, details, format_time(stream.duration));
+  }
+  Ok(())
+}
+
+fn run_thumbnail(input: &Path, at: RationalTime, output: &Path, args: &FrameArgs) -> CliResult {
+  let info = probe(input).map_err(CliError::Video)?;
+  let (mut source, _) = open_frames(input, &info, args)?;
+  let frame = source.frame_at(at).map_err(CliError::Video)?;
+  save_image(output, &frame)
+}
+
+fn run_extract_frames(input: &Path, range: TimeRange, output: &Path, every: u32, extension: &str, args: &FrameArgs) -> CliResult {
+  let info = probe(input).map_err(CliError::Video)?;
+  let (mut source, frame_duration) = open_frames(input, &info, args)?;
+  let from = range.from.unwrap_or(RationalTime::ZERO);
+  let to = range.to.or(source.duration()).ok_or_else(|| CliError::Other("The duration is unknown, pass --range".to_string()))?;
+  std::fs::create_dir_all(output).map_err(|_| CliError::Video(VideoStreamErr::IO))?;
+
+  let (rate_num, rate_den) = (frame_duration.den(), frame_duration.num());
+  let first = from.to_frames(rate_num, rate_den);
+  let mut written = 0;
+  for frame_idx in (first..).step_by(every.max(1) as usize) {
+    let time = RationalTime::from_frames(frame_idx, rate_num, rate_den);
+    if time >= to {
+      break;
+    }
+    let frame = match source.frame_at(time) {
+      Ok(frame) => frame,
+      // The stream may end before its stated duration
+      Err(VideoStreamErr::EOF) => break,
+      Err(err) => return Err(CliError::Video(err)),
+    };
+    save_image(&output.join(format!("frame_{:06}.{}", frame_idx, extension)), &frame)?;
+    written += 1;
+  }
+  eprintln!("Wrote {} frames to {}", written, output.display());
+  Ok(())
+}
+
+fn load_project(path: &Path) -> Result<Project, CliError> {
+  let (project, missing) = Project::load(path, &EffectRegistry::with_builtins())
+    .map_err(|err| CliError::Project(format!("{}: {}", path.display(), err)))?;
+  for name in missing {
+    eprintln!("Warning: effect {:?} isn't available and is skipped", name);
+  }
+  Ok(project)
+}
+
+fn list_presets(presets: &PresetLibrary) {
+  for available in presets.iter() {
+    if available.is_available() {
+      println!("{}", available.preset.name);
+    } else {
+      println!("{}  ({})", available.preset.name, available.missing_text());
+    }
+  }
+  for (path, err) in presets.errors.iter() {
+    eprintln!("{}: {}", path.display(), err);
+  }
+}
+
+fn run_render(project: &Path, output: &Path, preset: &str, range: TimeRange) -> CliResult {
+  let presets = PresetLibrary::load();
+  let available = presets.get(preset).ok_or_else(|| CliError::Preset(format!("There is no preset {:?}", preset)))?;
+  if !available.is_available() {
+    return Err(CliError::Preset(format!("{}: {}", preset, available.missing_text())));
+  }
+  let project = load_project(project)?;
+  let settings = available.preset.settings(project.size, project.timeline.frame_rate)
+    .map_err(|missing| CliError::Preset(format!("{}: missing {}", preset, missing)))?;
+  let from = range.from.unwrap_or(RationalTime::ZERO);
+  let to = range.to.unwrap_or_else(|| project.timeline.duration());
+
+  let mut queue = RenderQueue::new(1);
+  let id = queue.push(project.timeline, from, to, settings, output.to_path_buf()).map_err(CliError::Export)?;
+  let mut last_frame = -1;
+  loop {
+    queue.update();
+    let progress = match queue.jobs().iter().find(|j| j.job.id == id) {
+      Some(queued) => queued.progress,
+      None => return Err(CliError::Export(ExportError::InvalidJob(id))),
+    };
+    if progress.frame != last_frame && progress.status == ExportStatus::Running {
+      last_frame = progress.frame;
+      let eta = progress.eta.map_or(String::new(), |eta| format!(" ETA {}", format_duration(eta)));
+      eprint!("\rframe {}/{} ({:.1}%) {:.1} fps{}   ", progress.frame, progress.total_frames,
+        progress.fraction() * 100., progress.fps, eta);
+    }
+    match progress.status {
+      ExportStatus::Finished => {
+        eprintln!("\nWrote {}", output.display());
+        return Ok(());
+      },
+      ExportStatus::Failed(err) => {
+        eprintln!();
+        return Err(CliError::Export(err));
+      },
+      ExportStatus::Cancelled => return Err(CliError::Other("The export was cancelled".to_string())),
+      _ => thread::sleep(Duration::from_millis(100)),
+    }
+  }
+}
+
+fn run_loudness(input: &Path, stream: u32, range: TimeRange, check: bool) -> CliResult {
+  let target = if input.extension().is_some_and(|ext| ext == "escher") {
+    let project = load_project(input)?;
+    let to = range.to.unwrap_or_else(|| project.timeline.duration());
+    AnalysisTarget::Timeline { timeline: project.timeline, from: range.from.unwrap_or(RationalTime::ZERO), to }
+  } else {
+    let info = probe(input).map_err(CliError::Video)?;
+    let to = range.to.or(info.best_duration())
+      .ok_or_else(|| CliError::Other("The duration is unknown, pass --range".to_string()))?;
+    let source = ClipSource::File { path: input.to_path_buf(), stream_idx: stream };
+    AnalysisTarget::Clip { source, from: range.from.unwrap_or(RationalTime::ZERO), to }
+  };
+
+  let mut analyzer = LoudnessAnalyzer::new(1, 48000);
+  let job = analyzer.analyze(target).map_err(|err| CliError::Other(format!("{:?}", err)))?;
+  let report = loop {
+    analyzer.handle_responses();
+    match job.result() {
+      Some(result) => break result.map_err(CliError::Video)?,
+      None if job.is_finished() => {
+        return Err(CliError::Other(format!("The analysis crashed: {}", job.panic_message().unwrap_or_default())));
+      },
+      None => {
+        eprint!("\r{:.0}%", job.progress() * 100.);
+        thread::sleep(Duration::from_millis(100));
+      },
+    }
+  };
+  eprintln!("\r    ");
+  println!("Integrated      {:.1} LUFS", report.integrated);
+  println!("Loudness range  {:.1} LU", report.range);
+  println!("True peak       {:.1} dBTP", report.true_peak);
+  println!("Max momentary   {:.1} LUFS", report.max_momentary);
+  println!("Max short-term  {:.1} LUFS", report.max_short_term);
+  // EBU R128: -23 LUFS ±1 LU, true peak at most -1 dBTP
+  if check && !report.complies(TARGET_LUFS, 1., -1.) {
+    return Err(CliError::LoudnessOutOfSpec);
+  }
+  Ok(())
+}
+
+
+impl std::str::FromStr for TimeRange {
+  type Err = video::time::ParseTimeError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let (from, to) = s.split_once("..").ok_or_else(|| video::time::ParseTimeError(s.to_string()))?;
+    let parse = |t: &str| if t.trim().is_empty() { Ok(None) } else { t.parse().map(Some) };
+    Ok(Self { from: parse(from)?, to: parse(to)? })
+  }
+}
+
+impl std::fmt::Display for CliError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      CliError::Video(err) => write!(f, "{:?}", err),
+      CliError::Project(err) | CliError::Preset(err) | CliError::Image(err) | CliError::Other(err) => write!(f, "{}", err),
+      CliError::Export(err) => write!(f, "{}", err),
+      CliError::LoudnessOutOfSpec => write!(f, "The loudness doesn't comply with EBU R128"),
+    }
+  }
+}
+
+
+fn main() -> ExitCode {
+  let cli = Cli::parse();
+  let res = match &cli.command {
+    Command::Probe { input } => run_probe(input),
+    Command::Thumbnail { input, at, output, frames } => run_thumbnail(input, *at, output, frames),
+    Command::ExtractFrames { input, range, output, every, extension, frames } =>
+      run_extract_frames(input, *range, output, *every, extension, frames),
+    Command::Render { list_presets: true, .. } => {
+      list_presets(&PresetLibrary::load());
+      Ok(())
+    },
+    Command::Render { project, output, preset, range, .. } =>
+      run_render(project, output.as_deref().unwrap_or(Path::new("")), preset, *range),
+    Command::Loudness { input, stream, range, check } => run_loudness(input, *stream, *range, *check),
+  };
+  match res {
+    Ok(()) => ExitCode::SUCCESS,
+    Err(err) => {
+      eprintln!("Error: {}", err);
+      ExitCode::from(exit_code(&err))
+    },
+  }
+}
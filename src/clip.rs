@@ -1,6 +1,12 @@
-use std::path;
+use std::{path, sync::Arc};
 use egui_winit::egui;
-use crate::video;
+use serde::{Serialize, Deserialize};
+use crate::{
+  video::{self, ffi::rc::RcFrame, FrameCache, FrameKey, RationalTime, RawImageRef, VideoStreamErr},
+  effects::EffectStack,
+  animation::Animated,
+  wgpustate::compositor::Layer,
+};
 
 
 trait VisibleT<T> {
@@ -19,6 +25,198 @@ impl<T> VisibleT<T> for Visible<T> {
   fn get(&self) -> &T { &self.var }
 }
 
+/// Where the frames of a clip come from. File clips decode them, generators render them.
+pub trait FrameSource {
+  /// Size of the frames returned by `frame_at`
+  fn size(&self) -> [u32; 2];
+
+  /// Length of the media, `None` if the source is unbounded
+  fn duration(&self) -> Option<RationalTime>;
+
+  /// Frame at the source time `time` as RGBA
+  fn frame_at(&mut self, time: RationalTime) -> Result<RawImageRef<'_>, VideoStreamErr>;
+}
+
+/// Provides the frames of the clips of a timeline, e.g. for `wgpustate::OffscreenRenderer`
+pub trait ClipFrames {
+  /// Frame of `clip`'s source at the source time `source_time` as RGBA, `None` to leave the clip
+  /// out
+  fn frame(&mut self, clip: &crate::timeline::TimelineClip, source_time: RationalTime) -> Option<RawImageRef<'_>>;
+}
+
+/// Frames of a video stream, scaled to a fixed size
+pub struct FileSource {
+  path: path::PathBuf,
+  stream_idx: u32,
+  stream: video::VideoStream,
+  frame_ctx: video::VideoFrameContext,
+  size: [u32; 2],
+  frame_duration: RationalTime,
+  duration: Option<RationalTime>,
+  /// Source time of the frame in `frame_ctx`
+  current: Option<RationalTime>,
+  cache: Option<Arc<FrameCache>>,
+  /// Frame returned by the last `frame_at` if it came from `cache`
+  cached: Option<Arc<RcFrame>>,
+}
+
+impl FileSource {
+  pub fn open(path: &path::Path, stream_idx: u32, size: [u32; 2], frame_duration: RationalTime, duration: Option<RationalTime>) -> Result<Self, VideoStreamErr> {
+    let stream = video::VideoStreamBuilder::default()
+      .set_path(path).map_err(|_| VideoStreamErr::IO)?
+      .set_stream_idx(stream_idx)
+      .set_thread_to_all()
+      .finish()?;
+    let frame_ctx = video::VideoFrameContext::new(stream.get_frm());
+    Ok(Self {
+      path: path.to_path_buf(),
+      stream_idx,
+      stream,
+      frame_ctx,
+      size,
+      frame_duration,
+      duration,
+      current: None,
+      cache: None,
+      cached: None,
+    })
+  }
+
+  /// Looks frames up in `cache` before decoding them and caches the decoded ones
+  pub fn with_cache(mut self, cache: Arc<FrameCache>) -> Self {
+    self.cache = Some(cache);
+    self
+  }
+
+  /// The keyframe at or before `time` and its source time, or the exact frame if it is cached.
+  /// Skips decoding the frames between the keyframe and `time`, e.g. for scrubbing.
+  pub fn keyframe_at(&mut self, time: RationalTime) -> Result<(RationalTime, RawImageRef<'_>), VideoStreamErr> {
+    let key = self.cache.as_ref().map(|_| self.frame_key(time));
+    if self.lookup(key.as_ref()) {
+      return Ok((time, RawImageRef::new(self.cached.as_deref().unwrap())));
+    }
+    self.current = None;
+    self.stream.seek(time, video::Seek::NoPreciseMode | video::Seek::Backward)?;
+    self.stream.decode_frames(1)?;
+    let frame_time = self.stream.frame_time().unwrap_or(time);
+    self.convert()?;
+    self.current = Some(frame_time);
+    let key = self.cache.as_ref().map(|_| self.frame_key(frame_time));
+    self.store(key);
+    Ok((frame_time, self.frame_ctx.converted_frm()))
+  }
+
+  /// Converts the decoded frame to RGBA at `size`
+  fn convert(&mut self) -> Result<(), VideoStreamErr> {
+    self.frame_ctx.frm_src = self.stream.get_frm();
+    let [width, height] = self.size;
+    self.frame_ctx.convert(width as _, height as _, video::AVPixelFormat::AV_PIX_FMT_RGBA, video::SWS_Scaling::Bilinear)
+  }
+
+  /// Puts the frame of `key` into `cached` if the cache has it
+  fn lookup(&mut self, key: Option<&FrameKey>) -> bool {
+    self.cached = match (&self.cache, key) {
+      (Some(cache), Some(key)) => cache.get(key),
+      _ => None,
+    };
+    self.cached.is_some()
+  }
+
+  /// Caches the converted frame
+  fn store(&self, key: Option<FrameKey>) {
+    if let (Some(cache), Some(key)) = (&self.cache, key) {
+      cache.insert(key, Arc::new(self.frame_ctx.converted_ref()));
+    }
+  }
+
+  /// Key of the frame at `time`. Times within the same frame share the key.
+  fn frame_key(&self, time: RationalTime) -> FrameKey {
+    let (num, den) = (self.frame_duration.den(), self.frame_duration.num());
+    let start = RationalTime::from_frames(time.to_frames(num, den), num, den);
+    FrameKey {
+      asset: self.path.clone(),
+      stream_idx: self.stream_idx,
+      pts: start.to_pts(self.stream.time_base()),
+      size: self.size,
+      pix_fmt: video::AVPixelFormat::AV_PIX_FMT_RGBA,
+    }
+  }
+}
+
+impl FrameSource for FileSource {
+  fn size(&self) -> [u32; 2] { self.size }
+
+  fn duration(&self) -> Option<RationalTime> { self.duration }
+
+  fn frame_at(&mut self, time: RationalTime) -> Result<RawImageRef<'_>, VideoStreamErr> {
+    let key = self.cache.as_ref().map(|_| self.frame_key(time));
+    if self.lookup(key.as_ref()) {
+      return Ok(RawImageRef::new(self.cached.as_deref().unwrap()));
+    }
+    match self.current {
+      Some(current) if current == time => return Ok(self.frame_ctx.converted_frm()),
+      Some(current) if current + self.frame_duration == time => self.stream.decode_frames(1)?,
+      _ => self.stream.seek(time, video::Seek::empty())?,
+    }
+    self.current = None;
+    self.convert()?;
+    self.current = Some(time);
+    self.store(key);
+    Ok(self.frame_ctx.converted_frm())
+  }
+}
+
+/// Rows of the first plane of an RGBA frame without the padding of `linesize`, e.g. for encoders
+/// and image files
+pub fn packed_rgba(frame: &RawImageRef) -> Vec<u8> {
+  let (width, height) = (frame.width(), frame.height());
+  let (data, linesize) = (frame.planes()[0], frame.linesize()[0]);
+  let mut res = Vec::with_capacity(width * height * 4);
+  for row in 0..height {
+    res.extend_from_slice(&data[row * linesize..row * linesize + width * 4]);
+  }
+  res
+}
+
+/// Animatable properties every clip has. Times are clip-local.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ClipProperties {
+  pub opacity: Animated<f32>,
+  /// Offset from the center of the frame in pixels
+  pub position: Animated<[f32; 2]>,
+  pub scale: Animated<[f32; 2]>,
+  /// Clockwise in radians
+  pub rotation: Animated<f32>,
+  /// Linear gain of the clip's audio
+  pub volume: Animated<f32>,
+}
+
+impl Default for ClipProperties {
+  fn default() -> Self {
+    Self {
+      opacity: Animated::new(1.),
+      position: Animated::new([0.; 2]),
+      scale: Animated::new([1.; 2]),
+      rotation: Animated::new(0.),
+      volume: Animated::new(1.),
+    }
+  }
+}
+
+impl ClipProperties {
+  /// Sets opacity and transform of `layer` to the values at `time`
+  pub fn apply_to_layer(&self, time: RationalTime, layer: &mut Layer) {
+    layer.opacity = self.opacity.value_at(time).clamp(0., 1.);
+    layer.transform.position = self.position.value_at(time);
+    layer.transform.scale = self.scale.value_at(time);
+    layer.transform.rotation = self.rotation.value_at(time);
+  }
+
+  pub fn volume_at(&self, time: RationalTime) -> f32 {
+    self.volume.value_at(time).max(0.)
+  }
+}
+
 trait Clip<Render, Timeline, Preview>
   where Render: VisibleT<egui::TextureHandle>{
   fn name(&self) -> String;
@@ -27,6 +225,13 @@ trait Clip<Render, Timeline, Preview>
   fn render_texture(&self) -> &Render;
   fn timeline_texture(&self) -> &Timeline;
   fn preview_texture(&self) -> &Preview;
+
+  fn properties(&self) -> &ClipProperties;
+  fn properties_mut(&mut self) -> &mut ClipProperties;
+
+  /// Effects applied to the clip's frames in order
+  fn effects(&self) -> &EffectStack;
+  fn effects_mut(&mut self) -> &mut EffectStack;
 }
 
 struct FileClip {
@@ -35,6 +240,8 @@ struct FileClip {
   timeline_tex: Visible<egui::TextureHandle>,
   preview_tex: Visible<egui::TextureHandle>,
   vstream: video::VideoStream,
+  properties: ClipProperties,
+  effects: EffectStack,
 }
 
 impl Clip<
@@ -48,6 +255,12 @@ impl Clip<
   fn render_texture(&self) -> &Visible<egui::TextureHandle> { &self.render_tex }
   fn timeline_texture(&self) -> &Visible<egui::TextureHandle> { &self.timeline_tex }
   fn preview_texture(&self) -> &Visible<egui::TextureHandle> { &self.preview_tex }
+
+  fn properties(&self) -> &ClipProperties { &self.properties }
+  fn properties_mut(&mut self) -> &mut ClipProperties { &mut self.properties }
+
+  fn effects(&self) -> &EffectStack { &self.effects }
+  fn effects_mut(&mut self) -> &mut EffectStack { &mut self.effects }
 }
 
 impl FileClip {
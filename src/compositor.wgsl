@@ -0,0 +1,80 @@
+// See compositor::CompositeVertex
+struct CompositeVertex {          // 16 bytes
+  @location(0) pos: vec2<f32>,    // 8 bytes, already in clip space
+  @location(1) uv: vec2<f32>,     // 8 bytes
+}
+
+// See compositor::LayerUniform
+struct LayerUniform {             // 64 bytes
+  uv_min: vec2<f32>,              // 8 bytes
+  uv_max: vec2<f32>,              // 8 bytes
+  opacity: f32,                   // 4 bytes
+  decode_srgb: u32,               // 4 bytes
+  use_fill: u32,                  // 4 bytes
+  mask_softness: f32,             // 4 bytes
+  mask: vec4<f32>,                // 16 bytes, direction/size, threshold, enabled
+  fill: vec4<f32>,                // 16 bytes
+}
+
+struct VertexOutput {
+  @builtin(position) pos: vec4<f32>,
+  @location(0) uv: vec2<f32>,
+};
+
+
+// Vertex shader
+
+@vertex
+fn main_vs(vert: CompositeVertex) -> VertexOutput {
+  var res: VertexOutput;
+  res.pos = vec4(vert.pos, 0., 1.);
+  res.uv = vert.uv;
+  return res;
+}
+
+
+// Fragment shader
+
+@group(0) @binding(0)
+var layer_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var layer_sampler: sampler;
+
+@group(1) @binding(0)
+var<uniform> layer: LayerUniform;
+
+fn srgb_to_linear(c: vec3<f32>) -> vec3<f32> {
+  let cutoff = c <= vec3<f32>(0.04045);
+  let lower = c/12.92;
+  let higher = pow((c + 0.055)/1.055, vec3<f32>(2.4));
+  return select(higher, lower, cutoff);
+}
+
+// Coverage of the wipe mask at the fragment position in target pixels
+fn mask_coverage(pos: vec2<f32>) -> f32 {
+  if (layer.mask.w == 0.) {
+    return 1.;
+  }
+  let c = dot(pos, layer.mask.xy);
+  let threshold = layer.mask.z;
+  if (layer.mask_softness <= 0.) {
+    return select(0., 1., c < threshold);
+  }
+  return 1. - smoothstep(threshold - layer.mask_softness/2., threshold + layer.mask_softness/2., c);
+}
+
+@fragment
+fn main_fs(vert: VertexOutput) -> @location(0) vec4<f32> {
+  let uv = mix(layer.uv_min, layer.uv_max, vert.uv);
+  var tex = textureSample(layer_texture, layer_sampler, uv);
+  var rgb = tex.rgb;
+  if (layer.use_fill != 0u) {
+    tex = layer.fill;
+    rgb = tex.rgb;
+  } else if (layer.decode_srgb != 0u) {
+    rgb = srgb_to_linear(rgb);
+  }
+  // Output is premultiplied linear light, the blend state of each pipeline does the rest
+  let alpha = tex.a*layer.opacity*mask_coverage(vert.pos.xy);
+  return vec4<f32>(rgb*alpha, alpha);
+}
@@ -0,0 +1,441 @@
+use std::{collections::HashMap, fmt::Debug, sync::Arc};
+
+use serde::{Serialize, Deserialize};
+
+use crate::{animation::Keyframes, video::RationalTime};
+
+pub mod builtin;
+pub mod lut;
+
+/// Describes which values a parameter accepts. Used by the UI and to validate values.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParamKind {
+  Float { min: f32, max: f32 },
+  Int { min: i32, max: i32 },
+  Bool,
+  /// Unpremultiplied sRGBA
+  Color,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ParamValue {
+  Float(f32),
+  Int(i32),
+  Bool(bool),
+  Color([f32; 4]),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ParamSpec {
+  pub name: &'static str,
+  pub kind: ParamKind,
+  pub default: ParamValue,
+}
+
+/// Number of `vec4<f32>` an effect can use for its parameters in WGSL, see `EFFECT_PRELUDE_WGSL`
+pub const MAX_PARAM_VEC4S: usize = 4;
+
+pub type PackedParams = [[f32; 4]; MAX_PARAM_VEC4S];
+
+/// Image in linear light with unpremultiplied alpha. This is what the CPU fallback of an effect
+/// operates on, and it matches what `Compositor::read_target` returns.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CpuImage {
+  pub width: usize,
+  pub height: usize,
+  pub pixels: Vec<[f32; 4]>,
+}
+
+/// WGSL source of an effect. `fragment` has to define `fn effect(uv: vec2<f32>) -> vec4<f32>` and
+/// is placed after `EFFECT_PRELUDE_WGSL`, which provides the bindings and helpers like `load`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EffectShader {
+  /// Used as key for the pipeline cache, so it has to be unique among all effects
+  pub id: &'static str,
+  pub fragment: &'static str,
+  /// Number of times the shader is run in a row, e.g. 2 for separable filters. The current pass
+  /// is available as `frame.pass_idx`.
+  pub passes: u32,
+}
+
+/// An image effect with typed parameters. Every effect has a CPU implementation. Effects which
+/// also return a `shader` are run on the GPU by `wgpustate::effect_pipeline::EffectRenderer`,
+/// otherwise the renderer falls back to `apply_cpu`.
+pub trait Effect: Debug + Send + Sync {
+  fn name(&self) -> &str;
+
+  fn params(&self) -> &[ParamSpec];
+
+  fn shader(&self) -> Option<EffectShader> { None }
+
+  /// 3D lookup table bound in WGSL as `aux_lut`. Only used by the GPU path.
+  fn lut(&self) -> Option<&lut::Lut3d> { None }
+
+  /// Layout of the parameters in `frame.params` in WGSL. By default the values are packed in
+  /// order, where a color occupies a full `vec4` and everything else a single component.
+  fn pack_params(&self, values: &[ParamValue]) -> PackedParams {
+    pack_params_default(values)
+  }
+
+  fn apply_cpu(&self, values: &[ParamValue], image: &mut CpuImage);
+}
+
+/// An effect together with its parameter values as stored in a clip's effect stack. Saved by the
+/// name of the effect, see `EffectStack::resolve`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(into = "StoredEffect", from = "StoredEffect")]
+pub struct EffectInstance {
+  pub effect: Arc<dyn Effect>,
+  /// Values of parameters without keyframes
+  pub values: Vec<ParamValue>,
+  /// Keyframes in clip-local time, one entry per parameter
+  pub keyframes: Vec<Keyframes<ParamValue>>,
+  pub enabled: bool,
+}
+
+/// How an `EffectInstance` is saved in a project
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct StoredEffect {
+  name: String,
+  values: Vec<ParamValue>,
+  keyframes: Vec<Keyframes<ParamValue>>,
+  enabled: bool,
+}
+
+/// Stand-in for an effect which isn't registered, e.g. a loaded project uses an in-house effect
+/// this build doesn't have. It passes the image through and keeps the saved values.
+#[derive(Clone, Debug)]
+pub struct MissingEffect {
+  pub name: String,
+}
+
+/// Ordered list of effects. The first effect is applied first.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct EffectStack {
+  pub entries: Vec<EffectInstance>,
+}
+
+/// Maps effect names to constructors. In-house effects are added with `register` and are then
+/// handled exactly like the built-in ones.
+pub struct EffectRegistry {
+  constructors: HashMap<String, Box<dyn Fn() -> Arc<dyn Effect> + Send + Sync>>,
+}
+
+
+pub const EFFECT_PRELUDE_WGSL: &str = include_str!("effects/prelude.wgsl");
+pub const EFFECT_EPILOGUE_WGSL: &str = include_str!("effects/epilogue.wgsl");
+
+
+pub fn pack_params_default(values: &[ParamValue]) -> PackedParams {
+  let mut res = [[0.; 4]; MAX_PARAM_VEC4S];
+  let mut i: usize = 0;
+  for value in values {
+    let floats = match value {
+      ParamValue::Float(x) => vec![*x],
+      ParamValue::Int(x) => vec![*x as f32],
+      ParamValue::Bool(x) => vec![if *x { 1. } else { 0. }],
+      ParamValue::Color(c) => {
+        i = i.div_ceil(4) * 4;
+        c.to_vec()
+      },
+    };
+    for x in floats {
+      if i >= 4 * MAX_PARAM_VEC4S {
+        return res;
+      }
+      res[i / 4][i % 4] = x;
+      i += 1;
+    }
+  }
+  res
+}
+
+
+impl EffectShader {
+  /// Complete WGSL module of the effect
+  pub fn source(&self) -> String {
+    format!("{}\n{}\n{}", EFFECT_PRELUDE_WGSL, self.fragment, EFFECT_EPILOGUE_WGSL)
+  }
+}
+
+impl ParamValue {
+  pub fn as_f32(&self) -> f32 {
+    match self {
+      ParamValue::Float(x) => *x,
+      ParamValue::Int(x) => *x as _,
+      ParamValue::Bool(x) => if *x { 1. } else { 0. },
+      ParamValue::Color(c) => c[0],
+    }
+  }
+
+  pub fn as_color(&self) -> [f32; 4] {
+    match self {
+      ParamValue::Color(c) => *c,
+      x => { let v = x.as_f32(); [v, v, v, 1.] }
+    }
+  }
+
+  pub fn as_bool(&self) -> bool {
+    self.as_f32() != 0.
+  }
+}
+
+impl ParamSpec {
+  pub const fn float(name: &'static str, min: f32, max: f32, default: f32) -> Self {
+    Self { name, kind: ParamKind::Float { min, max }, default: ParamValue::Float(default) }
+  }
+
+  pub const fn color(name: &'static str, default: [f32; 4]) -> Self {
+    Self { name, kind: ParamKind::Color, default: ParamValue::Color(default) }
+  }
+
+  /// Clamps `value` into the range of this parameter. Returns `None` if the types don't match.
+  pub fn validate(&self, value: ParamValue) -> Option<ParamValue> {
+    match (self.kind, value) {
+      (ParamKind::Float { min, max }, ParamValue::Float(x)) => Some(ParamValue::Float(x.clamp(min, max))),
+      (ParamKind::Int { min, max }, ParamValue::Int(x)) => Some(ParamValue::Int(x.clamp(min, max))),
+      (ParamKind::Bool, ParamValue::Bool(_)) => Some(value),
+      (ParamKind::Color, ParamValue::Color(c)) => Some(ParamValue::Color(c.map(|x| x.clamp(0., 1.)))),
+      _ => None,
+    }
+  }
+}
+
+
+impl CpuImage {
+  pub fn new(width: usize, height: usize) -> Self {
+    Self { width, height, pixels: vec![[0.; 4]; width * height] }
+  }
+
+  /// Converts 8 bit sRGBA rows (e.g. a decoded RGBA frame) to linear light
+  pub fn from_srgba8(data: &[u8], width: usize, height: usize, linesize: usize) -> Self {
+    let mut pixels = Vec::with_capacity(width * height);
+    for row in data.chunks(linesize).take(height) {
+      for px in row[..width * 4].chunks_exact(4) {
+        pixels.push([
+          srgb_to_linear(px[0] as f32 / 255.),
+          srgb_to_linear(px[1] as f32 / 255.),
+          srgb_to_linear(px[2] as f32 / 255.),
+          px[3] as f32 / 255.,
+        ]);
+      }
+    }
+    Self { width, height, pixels }
+  }
+
+  pub fn to_srgba8(&self) -> Vec<u8> {
+    self.pixels.iter().flat_map(|[r, g, b, a]| [
+      (linear_to_srgb(*r).clamp(0., 1.) * 255.).round() as u8,
+      (linear_to_srgb(*g).clamp(0., 1.) * 255.).round() as u8,
+      (linear_to_srgb(*b).clamp(0., 1.) * 255.).round() as u8,
+      (a.clamp(0., 1.) * 255.).round() as u8,
+    ]).collect()
+  }
+
+  pub fn get(&self, x: usize, y: usize) -> [f32; 4] {
+    self.pixels[y * self.width + x]
+  }
+
+  pub fn map_pixels(&mut self, f: impl Fn([f32; 4]) -> [f32; 4]) {
+    for px in self.pixels.iter_mut() {
+      *px = f(*px);
+    }
+  }
+}
+
+
+impl EffectInstance {
+  pub fn new(effect: Arc<dyn Effect>) -> Self {
+    let values: Vec<_> = effect.params().iter().map(|p| p.default).collect();
+    let keyframes = values.iter().map(|_| Keyframes::new()).collect();
+    Self { effect, values, keyframes, enabled: true }
+  }
+
+  /// Sets the parameter `name`. Returns `false` if there is no such parameter or the value has the
+  /// wrong type.
+  pub fn set(&mut self, name: &str, value: ParamValue) -> bool {
+    let params = self.effect.params();
+    match params.iter().position(|p| p.name == name) {
+      Some(i) => match params[i].validate(value) {
+        Some(value) => { self.values[i] = value; true },
+        None => false,
+      },
+      None => false,
+    }
+  }
+
+  pub fn get(&self, name: &str) -> Option<ParamValue> {
+    let i = self.effect.params().iter().position(|p| p.name == name)?;
+    self.values.get(i).copied()
+  }
+
+  /// Adds or updates a keyframe of the parameter `name`. Returns `false` if there is no such
+  /// parameter or the value has the wrong type.
+  pub fn set_keyframe(&mut self, name: &str, time: RationalTime, value: ParamValue) -> bool {
+    let params = self.effect.params();
+    match params.iter().position(|p| p.name == name) {
+      Some(i) => match params[i].validate(value) {
+        Some(value) => { self.keyframes[i].set(time, value); true },
+        None => false,
+      },
+      None => false,
+    }
+  }
+
+  /// Parameter values at the clip-local `time`
+  pub fn values_at(&self, time: RationalTime) -> Vec<ParamValue> {
+    let params = self.effect.params();
+    self.values.iter().enumerate().map(|(i, value)| {
+      self.keyframes.get(i)
+        .and_then(|k| k.value_at(time))
+        .and_then(|v| params.get(i)?.validate(v))
+        .unwrap_or(*value)
+    }).collect()
+  }
+
+  pub fn is_animated(&self) -> bool {
+    self.keyframes.iter().any(|k| !k.is_empty())
+  }
+}
+
+impl From<EffectInstance> for StoredEffect {
+  fn from(e: EffectInstance) -> Self {
+    Self { name: e.effect.name().to_string(), values: e.values, keyframes: e.keyframes, enabled: e.enabled }
+  }
+}
+
+impl From<StoredEffect> for EffectInstance {
+  fn from(e: StoredEffect) -> Self {
+    Self { effect: Arc::new(MissingEffect { name: e.name }), values: e.values, keyframes: e.keyframes, enabled: e.enabled }
+  }
+}
+
+impl Effect for MissingEffect {
+  fn name(&self) -> &str { &self.name }
+
+  fn params(&self) -> &[ParamSpec] { &[] }
+
+  fn apply_cpu(&self, _values: &[ParamValue], _image: &mut CpuImage) {}
+}
+
+impl EffectStack {
+  pub fn push(&mut self, effect: EffectInstance) {
+    self.entries.push(effect)
+  }
+
+  pub fn insert(&mut self, idx: usize, effect: EffectInstance) {
+    self.entries.insert(idx.min(self.entries.len()), effect)
+  }
+
+  pub fn remove(&mut self, idx: usize) -> Option<EffectInstance> {
+    if idx < self.entries.len() {
+      Some(self.entries.remove(idx))
+    } else {
+      None
+    }
+  }
+
+  pub fn move_effect(&mut self, from: usize, to: usize) {
+    if let Some(effect) = self.remove(from) {
+      self.insert(to, effect)
+    }
+  }
+
+  pub fn iter_enabled(&self) -> impl Iterator<Item=&EffectInstance> + '_ {
+    self.entries.iter().filter(|e| e.enabled)
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.iter_enabled().next().is_none()
+  }
+
+  /// Copy of the stack with the animated values resolved at the clip-local `time`. The result
+  /// is what gets passed to the renderer, which only looks at `values`.
+  pub fn evaluate(&self, time: RationalTime) -> EffectStack {
+    let entries = self.entries.iter().map(|e| EffectInstance {
+      effect: e.effect.clone(),
+      values: e.values_at(time),
+      keyframes: e.keyframes.iter().map(|_| Keyframes::new()).collect(),
+      enabled: e.enabled,
+    }).collect();
+    EffectStack { entries }
+  }
+
+  /// Replaces the `MissingEffect`s of a loaded stack with the effects of `registry`. Keeps the
+  /// saved values where they still fit the parameter. Returns the names which aren't registered.
+  pub fn resolve(&mut self, registry: &EffectRegistry) -> Vec<String> {
+    let mut missing = Vec::new();
+    for entry in self.entries.iter_mut() {
+      let name = entry.effect.name().to_string();
+      let mut resolved = match registry.create(&name) {
+        Some(resolved) => resolved,
+        None => {
+          missing.push(name);
+          continue;
+        },
+      };
+      let params = resolved.effect.params();
+      for (i, param) in params.iter().enumerate() {
+        if let Some(value) = entry.values.get(i).and_then(|v| param.validate(*v)) {
+          resolved.values[i] = value;
+        }
+        if let Some(keyframes) = entry.keyframes.get(i) {
+          resolved.keyframes[i] = keyframes.clone();
+        }
+      }
+      resolved.enabled = entry.enabled;
+      *entry = resolved;
+    }
+    missing
+  }
+
+  /// Applies all enabled effects on the CPU
+  pub fn apply_cpu(&self, image: &mut CpuImage) {
+    for e in self.iter_enabled() {
+      e.effect.apply_cpu(&e.values, image)
+    }
+  }
+}
+
+
+impl EffectRegistry {
+  pub fn empty() -> Self {
+    Self { constructors: HashMap::new() }
+  }
+
+  /// Registry with the starter set of `builtin`
+  pub fn with_builtins() -> Self {
+    let mut res = Self::empty();
+    builtin::register_all(&mut res);
+    res
+  }
+
+  /// Adds an effect. Returns `false` if an effect with the same name was already registered, in
+  /// which case it is replaced.
+  pub fn register(&mut self, name: impl Into<String>, constructor: impl Fn() -> Arc<dyn Effect> + Send + Sync + 'static) -> bool {
+    self.constructors.insert(name.into(), Box::new(constructor)).is_none()
+  }
+
+  pub fn create(&self, name: &str) -> Option<EffectInstance> {
+    self.constructors.get(name).map(|c| EffectInstance::new(c()))
+  }
+
+  pub fn names(&self) -> impl Iterator<Item=&str> + '_ {
+    self.constructors.keys().map(|s| s.as_str())
+  }
+}
+
+impl Default for EffectRegistry {
+  fn default() -> Self {
+    Self::with_builtins()
+  }
+}
+
+
+pub fn srgb_to_linear(c: f32) -> f32 {
+  if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+pub fn linear_to_srgb(c: f32) -> f32 {
+  if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1. / 2.4) - 0.055 }
+}
@@ -0,0 +1,7 @@
+// params[0].x: brightness, params[0].y: contrast
+fn effect(uv: vec2<f32>) -> vec4<f32> {
+  let c = load(uv);
+  let pivot = 0.18;
+  let rgb = max((c.rgb + frame.params[0].x - pivot)*frame.params[0].y + pivot, vec3<f32>(0.));
+  return vec4<f32>(rgb, c.a);
+}
@@ -0,0 +1,286 @@
+use std::{sync::Arc, path::Path};
+
+use super::{
+  Effect,
+  EffectRegistry,
+  EffectShader,
+  CpuImage,
+  PackedParams,
+  ParamSpec,
+  ParamValue,
+  lut::{Lut3d, LutError},
+  srgb_to_linear,
+  linear_to_srgb,
+};
+
+
+pub fn register_all(registry: &mut EffectRegistry) {
+  registry.register("Brightness/Contrast", || Arc::new(BrightnessContrast));
+  registry.register("Saturation", || Arc::new(Saturation));
+  registry.register("LUT", || Arc::new(LutEffect::new(Lut3d::identity(2))));
+  registry.register("Gaussian Blur", || Arc::new(GaussianBlur));
+  registry.register("Chroma Key", || Arc::new(ChromaKey));
+  registry.register("Crop", || Arc::new(Crop));
+}
+
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BrightnessContrast;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Saturation;
+
+/// Applies a `.cube` LUT to the sRGB encoded image
+#[derive(Debug, Clone)]
+pub struct LutEffect {
+  pub lut: Lut3d,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GaussianBlur;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ChromaKey;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Crop;
+
+
+const LUMA: [f32; 3] = [0.2126, 0.7152, 0.0722];
+
+fn luma(px: &[f32; 4]) -> f32 {
+  LUMA[0]*px[0] + LUMA[1]*px[1] + LUMA[2]*px[2]
+}
+
+
+impl Effect for BrightnessContrast {
+  fn name(&self) -> &str { "Brightness/Contrast" }
+
+  fn params(&self) -> &[ParamSpec] {
+    const PARAMS: [ParamSpec; 2] = [
+      ParamSpec::float("brightness", -1., 1., 0.),
+      ParamSpec::float("contrast", 0., 4., 1.),
+    ];
+    &PARAMS
+  }
+
+  fn shader(&self) -> Option<EffectShader> {
+    Some(EffectShader { id: "brightness_contrast", fragment: include_str!("brightness_contrast.wgsl"), passes: 1 })
+  }
+
+  fn apply_cpu(&self, values: &[ParamValue], image: &mut CpuImage) {
+    let (brightness, contrast) = (values[0].as_f32(), values[1].as_f32());
+    let pivot = 0.18;
+    image.map_pixels(|[r, g, b, a]| {
+      let f = |c: f32| ((c + brightness - pivot)*contrast + pivot).max(0.);
+      [f(r), f(g), f(b), a]
+    })
+  }
+}
+
+
+impl Effect for Saturation {
+  fn name(&self) -> &str { "Saturation" }
+
+  fn params(&self) -> &[ParamSpec] {
+    const PARAMS: [ParamSpec; 1] = [
+      ParamSpec::float("saturation", 0., 4., 1.),
+    ];
+    &PARAMS
+  }
+
+  fn shader(&self) -> Option<EffectShader> {
+    Some(EffectShader { id: "saturation", fragment: include_str!("saturation.wgsl"), passes: 1 })
+  }
+
+  fn apply_cpu(&self, values: &[ParamValue], image: &mut CpuImage) {
+    let saturation = values[0].as_f32();
+    image.map_pixels(|px| {
+      let y = luma(&px);
+      let f = |c: f32| (y + (c - y)*saturation).max(0.);
+      [f(px[0]), f(px[1]), f(px[2]), px[3]]
+    })
+  }
+}
+
+
+impl LutEffect {
+  pub fn new(lut: Lut3d) -> Self {
+    Self { lut }
+  }
+
+  pub fn load(path: &Path) -> Result<Self, LutError> {
+    Ok(Self::new(Lut3d::load(path)?))
+  }
+}
+
+impl Effect for LutEffect {
+  fn name(&self) -> &str { "LUT" }
+
+  fn params(&self) -> &[ParamSpec] {
+    const PARAMS: [ParamSpec; 1] = [
+      ParamSpec::float("intensity", 0., 1., 1.),
+    ];
+    &PARAMS
+  }
+
+  fn shader(&self) -> Option<EffectShader> {
+    Some(EffectShader { id: "lut", fragment: include_str!("lut.wgsl"), passes: 1 })
+  }
+
+  fn lut(&self) -> Option<&Lut3d> { Some(&self.lut) }
+
+  fn pack_params(&self, values: &[ParamValue]) -> PackedParams {
+    let [r0, g0, b0] = self.lut.domain_min;
+    let [r1, g1, b1] = self.lut.domain_max;
+    [
+      [values[0].as_f32(), self.lut.size as _, 0., 0.],
+      [r0, g0, b0, 0.],
+      [r1, g1, b1, 0.],
+      [0.; 4],
+    ]
+  }
+
+  fn apply_cpu(&self, values: &[ParamValue], image: &mut CpuImage) {
+    let intensity = values[0].as_f32();
+    image.map_pixels(|[r, g, b, a]| {
+      let encoded = [r, g, b].map(|c| linear_to_srgb(c.clamp(0., 1.)));
+      let graded = self.lut.sample(encoded).map(srgb_to_linear);
+      let f = |c: f32, d: f32| c + (d - c)*intensity;
+      [f(r, graded[0]), f(g, graded[1]), f(b, graded[2]), a]
+    })
+  }
+}
+
+
+impl GaussianBlur {
+  /// Normalized kernel with `2*radius + 1` taps
+  pub fn kernel(radius: f32) -> Vec<f32> {
+    let radius = radius.round().clamp(0., 64.) as i32;
+    let sigma = (radius as f32 / 2.).max(0.5);
+    let kernel: Vec<_> = (-radius..=radius)
+      .map(|i| (-((i*i) as f32)/(2.*sigma*sigma)).exp())
+      .collect();
+    let sum: f32 = kernel.iter().sum();
+    kernel.into_iter().map(|w| w/sum).collect()
+  }
+
+  fn blur_1d(image: &CpuImage, kernel: &[f32], horizontal: bool) -> Vec<[f32; 4]> {
+    let radius = (kernel.len() / 2) as isize;
+    let (w, h) = (image.width as isize, image.height as isize);
+    let mut res = Vec::with_capacity(image.pixels.len());
+    for y in 0..h {
+      for x in 0..w {
+        let mut sum = [0.; 4];
+        for (k, weight) in kernel.iter().enumerate() {
+          let d = k as isize - radius;
+          let (sx, sy) = if horizontal { ((x + d).clamp(0, w - 1), y) } else { (x, (y + d).clamp(0, h - 1)) };
+          let [r, g, b, a] = image.get(sx as _, sy as _);
+          sum[0] += weight*r*a;
+          sum[1] += weight*g*a;
+          sum[2] += weight*b*a;
+          sum[3] += weight*a;
+        }
+        res.push(if sum[3] > 0. { [sum[0]/sum[3], sum[1]/sum[3], sum[2]/sum[3], sum[3]] } else { [0.; 4] });
+      }
+    }
+    res
+  }
+}
+
+impl Effect for GaussianBlur {
+  fn name(&self) -> &str { "Gaussian Blur" }
+
+  fn params(&self) -> &[ParamSpec] {
+    const PARAMS: [ParamSpec; 1] = [
+      ParamSpec::float("radius", 0., 64., 4.),
+    ];
+    &PARAMS
+  }
+
+  fn shader(&self) -> Option<EffectShader> {
+    Some(EffectShader { id: "gaussian_blur", fragment: include_str!("gaussian_blur.wgsl"), passes: 2 })
+  }
+
+  fn apply_cpu(&self, values: &[ParamValue], image: &mut CpuImage) {
+    let kernel = Self::kernel(values[0].as_f32());
+    if kernel.len() <= 1 || image.pixels.is_empty() {
+      return;
+    }
+    image.pixels = Self::blur_1d(image, &kernel, true);
+    image.pixels = Self::blur_1d(image, &kernel, false);
+  }
+}
+
+
+impl ChromaKey {
+  /// CbCr of BT.709, applied to encoded values
+  fn chroma(rgb: [f32; 3]) -> [f32; 2] {
+    [
+      -0.1146*rgb[0] - 0.3854*rgb[1] + 0.5*rgb[2],
+      0.5*rgb[0] - 0.4542*rgb[1] - 0.0458*rgb[2],
+    ]
+  }
+}
+
+impl Effect for ChromaKey {
+  fn name(&self) -> &str { "Chroma Key" }
+
+  fn params(&self) -> &[ParamSpec] {
+    const PARAMS: [ParamSpec; 3] = [
+      ParamSpec::color("key", [0., 1., 0., 1.]),
+      ParamSpec::float("tolerance", 0., 1., 0.15),
+      ParamSpec::float("softness", 0., 1., 0.1),
+    ];
+    &PARAMS
+  }
+
+  fn shader(&self) -> Option<EffectShader> {
+    Some(EffectShader { id: "chroma_key", fragment: include_str!("chroma_key.wgsl"), passes: 1 })
+  }
+
+  fn apply_cpu(&self, values: &[ParamValue], image: &mut CpuImage) {
+    let [kr, kg, kb, _] = values[0].as_color();
+    let key = Self::chroma([kr, kg, kb]);
+    let (tolerance, softness) = (values[1].as_f32(), values[2].as_f32().max(0.0001));
+    image.map_pixels(|[r, g, b, a]| {
+      let c = Self::chroma([r, g, b].map(|c| linear_to_srgb(c.clamp(0., 1.))));
+      let dist = ((c[0] - key[0]).powi(2) + (c[1] - key[1]).powi(2)).sqrt();
+      let t = ((dist - tolerance)/softness).clamp(0., 1.);
+      let alpha = t*t*(3. - 2.*t);
+      [r, g, b, a*alpha]
+    })
+  }
+}
+
+
+impl Effect for Crop {
+  fn name(&self) -> &str { "Crop" }
+
+  fn params(&self) -> &[ParamSpec] {
+    const PARAMS: [ParamSpec; 4] = [
+      ParamSpec::float("left", 0., 1., 0.),
+      ParamSpec::float("top", 0., 1., 0.),
+      ParamSpec::float("right", 0., 1., 0.),
+      ParamSpec::float("bottom", 0., 1., 0.),
+    ];
+    &PARAMS
+  }
+
+  fn shader(&self) -> Option<EffectShader> {
+    Some(EffectShader { id: "crop", fragment: include_str!("crop.wgsl"), passes: 1 })
+  }
+
+  fn apply_cpu(&self, values: &[ParamValue], image: &mut CpuImage) {
+    let [left, top, right, bottom] = [0, 1, 2, 3].map(|i| values[i].as_f32());
+    let (w, h) = (image.width, image.height);
+    for y in 0..h {
+      for x in 0..w {
+        let (u, v) = ((x as f32 + 0.5)/w as f32, (y as f32 + 0.5)/h as f32);
+        if u < left || v < top || u > 1. - right || v > 1. - bottom {
+          image.pixels[y*w + x] = [0.; 4];
+        }
+      }
+    }
+  }
+}
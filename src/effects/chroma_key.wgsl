@@ -0,0 +1,16 @@
+// params[0]: key color (sRGBA), params[1].x: tolerance, params[1].y: softness
+fn chroma(rgb: vec3<f32>) -> vec2<f32> {
+  // CbCr of BT.709 on encoded values
+  return vec2<f32>(
+    dot(rgb, vec3<f32>(-0.1146, -0.3854, 0.5)),
+    dot(rgb, vec3<f32>(0.5, -0.4542, -0.0458)));
+}
+
+fn effect(uv: vec2<f32>) -> vec4<f32> {
+  let c = load(uv);
+  let encoded = linear_to_srgb(clamp(c.rgb, vec3<f32>(0.), vec3<f32>(1.)));
+  let dist = distance(chroma(encoded), chroma(frame.params[0].rgb));
+  let tolerance = frame.params[1].x;
+  let alpha = smoothstep(tolerance, tolerance + max(frame.params[1].y, 0.0001), dist);
+  return vec4<f32>(c.rgb, c.a*alpha);
+}
@@ -0,0 +1,7 @@
+// params[0]: left, top, right, bottom as fractions of the image size
+fn effect(uv: vec2<f32>) -> vec4<f32> {
+  let c = load(uv);
+  let inside = uv.x >= frame.params[0].x && uv.y >= frame.params[0].y
+    && uv.x <= 1. - frame.params[0].z && uv.y <= 1. - frame.params[0].w;
+  return select(vec4<f32>(0.), c, inside);
+}
@@ -0,0 +1,6 @@
+// See prelude.wgsl
+
+@fragment
+fn main_fs(vert: VertexOutput) -> @location(0) vec4<f32> {
+  return effect(vert.uv);
+}
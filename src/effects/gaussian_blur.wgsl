@@ -0,0 +1,27 @@
+// params[0].x: radius in pixels. Pass 0 blurs horizontally, pass 1 vertically
+fn effect(uv: vec2<f32>) -> vec4<f32> {
+  let radius = i32(min(round(frame.params[0].x), 64.));
+  if (radius <= 0) {
+    return load(uv);
+  }
+  let sigma = max(f32(radius)/2., 0.5);
+  var dir = vec2<f32>(1., 0.);
+  if (frame.pass_idx == 1u) {
+    dir = vec2<f32>(0., 1.);
+  }
+  var sum = vec4<f32>(0.);
+  var weights = 0.;
+  for (var i = -radius; i <= radius; i++) {
+    let x = f32(i);
+    let w = exp(-x*x/(2.*sigma*sigma));
+    let c = load_px(uv, dir*x);
+    // Premultiply so transparent pixels don't bleed their color
+    sum += w*vec4<f32>(c.rgb*c.a, c.a);
+    weights += w;
+  }
+  sum /= weights;
+  if (sum.a > 0.) {
+    return vec4<f32>(sum.rgb/sum.a, sum.a);
+  }
+  return vec4<f32>(0.);
+}
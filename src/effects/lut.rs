@@ -0,0 +1,194 @@
+use std::{path::Path, fmt};
+
+/// 3D lookup table as defined by the Adobe/Resolve `.cube` format. The table is indexed with red
+/// varying fastest.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lut3d {
+  pub title: Option<String>,
+  pub size: usize,
+  pub domain_min: [f32; 3],
+  pub domain_max: [f32; 3],
+  pub data: Vec<[f32; 3]>,
+}
+
+#[derive(Debug)]
+pub enum LutError {
+  IO(std::io::Error),
+  Parse { line: usize, msg: String },
+  Unsupported(String),
+  WrongEntryCount { expected: usize, found: usize },
+}
+
+
+impl Lut3d {
+  pub fn load(path: &Path) -> Result<Self, LutError> {
+    let src = std::fs::read_to_string(path).map_err(LutError::IO)?;
+    Self::parse_cube(&src)
+  }
+
+  pub fn parse_cube(src: &str) -> Result<Self, LutError> {
+    let mut title = None;
+    let mut size = None;
+    let mut domain_min = [0.; 3];
+    let mut domain_max = [1.; 3];
+    let mut data = Vec::new();
+
+    for (i, line) in src.lines().enumerate() {
+      let line_nr = i + 1;
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+      let mut words = line.split_whitespace();
+      let keyword = words.next().unwrap();
+      match keyword {
+        "TITLE" => title = Some(line["TITLE".len()..].trim().trim_matches('"').to_string()),
+        "LUT_3D_SIZE" => {
+          let n = words.next()
+            .and_then(|w| w.parse::<usize>().ok())
+            .filter(|n| *n >= 2)
+            .ok_or_else(|| LutError::Parse { line: line_nr, msg: "Invalid LUT_3D_SIZE".to_string() })?;
+          size = Some(n);
+        },
+        "LUT_1D_SIZE" => return Err(LutError::Unsupported("1D LUTs".to_string())),
+        "DOMAIN_MIN" => domain_min = parse_triple(words, line_nr)?,
+        "DOMAIN_MAX" => domain_max = parse_triple(words, line_nr)?,
+        _ if keyword.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '.') => {
+          data.push(parse_triple(line.split_whitespace(), line_nr)?)
+        },
+        // Unknown keywords are allowed by the specification
+        _ => {},
+      }
+    }
+
+    let size = size.ok_or(LutError::Parse { line: 0, msg: "Missing LUT_3D_SIZE".to_string() })?;
+    let expected = size * size * size;
+    if data.len() != expected {
+      return Err(LutError::WrongEntryCount { expected, found: data.len() });
+    }
+    Ok(Self { title, size, domain_min, domain_max, data })
+  }
+
+  /// Identity table, mostly useful as a placeholder
+  pub fn identity(size: usize) -> Self {
+    let size = size.max(2);
+    let scale = 1. / (size - 1) as f32;
+    let mut data = Vec::with_capacity(size * size * size);
+    for b in 0..size {
+      for g in 0..size {
+        for r in 0..size {
+          data.push([r as f32 * scale, g as f32 * scale, b as f32 * scale]);
+        }
+      }
+    }
+    Self { title: None, size, domain_min: [0.; 3], domain_max: [1.; 3], data }
+  }
+
+  fn entry(&self, r: usize, g: usize, b: usize) -> [f32; 3] {
+    self.data[(b * self.size + g) * self.size + r]
+  }
+
+  /// Looks up `rgb` with trilinear interpolation. Values outside of the domain are clamped.
+  pub fn sample(&self, rgb: [f32; 3]) -> [f32; 3] {
+    let n = (self.size - 1) as f32;
+    let pos: [f32; 3] = std::array::from_fn(|i| {
+      let range = self.domain_max[i] - self.domain_min[i];
+      let x = if range != 0. { (rgb[i] - self.domain_min[i]) / range } else { 0. };
+      x.clamp(0., 1.) * n
+    });
+    let i0 = pos.map(|x| (x.floor() as usize).min(self.size - 2));
+    let t: [f32; 3] = std::array::from_fn(|i| pos[i] - i0[i] as f32);
+
+    let mut res = [0.; 3];
+    for corner in 0..8 {
+      let d = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1];
+      let w: f32 = (0..3).map(|i| if d[i] == 1 { t[i] } else { 1. - t[i] }).product();
+      let e = self.entry(i0[0] + d[0], i0[1] + d[1], i0[2] + d[2]);
+      for i in 0..3 {
+        res[i] += w * e[i];
+      }
+    }
+    res
+  }
+}
+
+fn parse_triple<'a>(mut words: impl Iterator<Item=&'a str>, line: usize) -> Result<[f32; 3], LutError> {
+  let mut res = [0.; 3];
+  for x in res.iter_mut() {
+    *x = words.next()
+      .and_then(|w| w.parse().ok())
+      .ok_or_else(|| LutError::Parse { line, msg: "Expected three numbers".to_string() })?;
+  }
+  Ok(res)
+}
+
+
+impl fmt::Display for LutError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      LutError::IO(err) => write!(f, "Could not read LUT: {}", err),
+      LutError::Parse { line, msg } => write!(f, "Line {}: {}", line, msg),
+      LutError::Unsupported(what) => write!(f, "{} are not supported", what),
+      LutError::WrongEntryCount { expected, found } => write!(f, "Expected {} entries, found {}", expected, found),
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const IDENTITY_2: &str = "0 0 0\n1 0 0\n0 1 0\n1 1 0\n0 0 1\n1 0 1\n0 1 1\n1 1 1\n";
+
+  fn cube(header: &str) -> String {
+    format!("{}\n{}", header, IDENTITY_2)
+  }
+
+  fn parse_error_line(src: &str) -> usize {
+    match Lut3d::parse_cube(src) {
+      Err(LutError::Parse { line, .. }) => line,
+      res => panic!("expected a parse error, got {:?}", res),
+    }
+  }
+
+  #[test]
+  fn parses_header_and_data() {
+    let src = format!("# comment\nTITLE \"Warm look\"\nLUT_3D_SIZE 2\nLUT_3D_INPUT_RANGE 0 1\n\n{}", IDENTITY_2);
+    let lut = Lut3d::parse_cube(&src).unwrap();
+    assert_eq!(lut.title.as_deref(), Some("Warm look"));
+    assert_eq!(lut, Lut3d { title: lut.title.clone(), ..Lut3d::identity(2) });
+    assert_eq!(lut.sample([0.25, 0.5, 1.]), [0.25, 0.5, 1.]);
+  }
+
+  #[test]
+  fn rejects_malformed_sizes() {
+    assert_eq!(parse_error_line(&cube("LUT_3D_SIZE")), 1);
+    assert_eq!(parse_error_line(&cube("LUT_3D_SIZE two")), 1);
+    assert_eq!(parse_error_line(&cube("# size\nLUT_3D_SIZE 1")), 2);
+    assert_eq!(parse_error_line(&cube("LUT_3D_SIZE -2")), 1);
+    assert_eq!(parse_error_line(IDENTITY_2), 0);
+    assert!(matches!(Lut3d::parse_cube(&cube("LUT_1D_SIZE 2")), Err(LutError::Unsupported(_))));
+  }
+
+  #[test]
+  fn rejects_wrong_entry_counts() {
+    let src = format!("LUT_3D_SIZE 2\n{}0 0 0\n", IDENTITY_2);
+    assert!(matches!(Lut3d::parse_cube(&src), Err(LutError::WrongEntryCount { expected: 8, found: 9 })));
+    assert!(matches!(Lut3d::parse_cube("LUT_3D_SIZE 3\n0 0 0\n"), Err(LutError::WrongEntryCount { expected: 27, found: 1 })));
+    // A short entry is a parse error on its line
+    assert_eq!(parse_error_line("LUT_3D_SIZE 2\n0 0 0\n1 0\n"), 3);
+  }
+
+  #[test]
+  fn domain_scales_lookups() {
+    let lut = Lut3d::parse_cube(&cube("LUT_3D_SIZE 2\nDOMAIN_MIN -1 0 0\nDOMAIN_MAX 1 2 0.5")).unwrap();
+    assert_eq!(lut.domain_min, [-1., 0., 0.]);
+    assert_eq!(lut.domain_max, [1., 2., 0.5]);
+    assert_eq!(lut.sample([0., 1., 0.25]), [0.5, 0.5, 0.5]);
+    // Clamped outside of the domain
+    assert_eq!(lut.sample([-3., 5., 1.]), [0., 1., 1.]);
+    assert_eq!(parse_error_line(&cube("LUT_3D_SIZE 2\nDOMAIN_MIN 0 0")), 2);
+    assert_eq!(parse_error_line(&cube("LUT_3D_SIZE 2\nDOMAIN_MAX 1 x 1")), 2);
+  }
+}
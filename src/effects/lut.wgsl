@@ -0,0 +1,12 @@
+// params[0].x: intensity, params[0].y: LUT size, params[1].xyz: domain min, params[2].xyz: domain max
+// The LUT is applied to sRGB encoded values
+fn effect(uv: vec2<f32>) -> vec4<f32> {
+  let c = load(uv);
+  let encoded = linear_to_srgb(clamp(c.rgb, vec3<f32>(0.), vec3<f32>(1.)));
+  let n = frame.params[0].y;
+  let t = clamp((encoded - frame.params[1].xyz)/(frame.params[2].xyz - frame.params[1].xyz), vec3<f32>(0.), vec3<f32>(1.));
+  // Sample at texel centers
+  let lut_uv = (t*(n - 1.) + 0.5)/n;
+  let graded = srgb_to_linear(textureSampleLevel(aux_lut, aux_sampler, lut_uv, 0.).rgb);
+  return vec4<f32>(mix(c.rgb, graded, frame.params[0].x), c.a);
+}
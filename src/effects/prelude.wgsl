@@ -0,0 +1,77 @@
+// Shared part of every effect shader. An effect defines
+//   fn effect(uv: vec2<f32>) -> vec4<f32>
+// which returns linear, unpremultiplied RGBA. It is placed between this prelude and epilogue.wgsl
+
+// See effects::EffectFrame
+struct EffectFrame {                  // 16 + 64 = 80 bytes
+  size: vec2<f32>,                    // 8 bytes, input size in pixels
+  decode_srgb: u32,                   // 4 bytes
+  pass_idx: u32,                      // 4 bytes
+  params: array<vec4<f32>, 4>,        // 64 bytes, see Effect::pack_params
+}
+
+struct VertexOutput {
+  @builtin(position) pos: vec4<f32>,
+  @location(0) uv: vec2<f32>,
+};
+
+
+// Vertex shader
+
+// Fullscreen triangle, no vertex buffer needed
+@vertex
+fn main_vs(@builtin(vertex_index) idx: u32) -> VertexOutput {
+  var res: VertexOutput;
+  let uv = vec2<f32>(f32((idx << 1u) & 2u), f32(idx & 2u));
+  res.pos = vec4(uv.x*2. - 1., 1. - uv.y*2., 0., 1.);
+  res.uv = uv;
+  return res;
+}
+
+
+// Fragment shader
+
+@group(0) @binding(0)
+var input_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var input_sampler: sampler;
+
+@group(1) @binding(0)
+var<uniform> frame: EffectFrame;
+
+@group(2) @binding(0)
+var aux_lut: texture_3d<f32>;
+@group(2) @binding(1)
+var aux_sampler: sampler;
+
+fn srgb_to_linear(c: vec3<f32>) -> vec3<f32> {
+  let cutoff = c <= vec3<f32>(0.04045);
+  return select(pow((c + 0.055)/1.055, vec3<f32>(2.4)), c/12.92, cutoff);
+}
+
+fn linear_to_srgb(c: vec3<f32>) -> vec3<f32> {
+  let cutoff = c <= vec3<f32>(0.0031308);
+  return select(1.055*pow(c, vec3<f32>(1./2.4)) - 0.055, c*12.92, cutoff);
+}
+
+fn luma(c: vec3<f32>) -> f32 {
+  return dot(c, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+// Samples the input in linear light
+fn load(uv: vec2<f32>) -> vec4<f32> {
+  let tex = textureSample(input_texture, input_sampler, uv);
+  if (frame.decode_srgb != 0u) {
+    return vec4<f32>(srgb_to_linear(tex.rgb), tex.a);
+  }
+  return tex;
+}
+
+// Like load, but offset by whole pixels. Usable in non-uniform control flow.
+fn load_px(uv: vec2<f32>, offset: vec2<f32>) -> vec4<f32> {
+  let tex = textureSampleLevel(input_texture, input_sampler, uv + offset/frame.size, 0.);
+  if (frame.decode_srgb != 0u) {
+    return vec4<f32>(srgb_to_linear(tex.rgb), tex.a);
+  }
+  return tex;
+}
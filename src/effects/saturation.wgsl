@@ -0,0 +1,6 @@
+// params[0].x: saturation
+fn effect(uv: vec2<f32>) -> vec4<f32> {
+  let c = load(uv);
+  let y = luma(c.rgb);
+  return vec4<f32>(max(mix(vec3<f32>(y), c.rgb, frame.params[0].x), vec3<f32>(0.)), c.a);
+}
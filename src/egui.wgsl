@@ -84,3 +84,22 @@ fn main_fs(vert: VertexOutput) -> @location(0) vec4<f32> {
   // return vert.color*textureLoad(egui_texture, coord, mip_level);
 }
 
+
+fn linear_to_srgb(c: vec3<f32>) -> vec3<f32> {
+  let cutoff = c <= vec3<f32>(0.0031308);
+  let lower = c*12.92;
+  let higher = 1.055*pow(c, vec3<f32>(1./2.4)) - 0.055;
+  return select(higher, lower, cutoff);
+}
+
+// The compositor's target is premultiplied linear light (see compositor.wgsl), the surface expects
+// premultiplied sRGB like egui's meshes
+@fragment
+fn composite_fs(vert: VertexOutput) -> @location(0) vec4<f32> {
+  let tex = textureSample(egui_texture, egui_sampler, vert.uv);
+  if (tex.a <= 0.) {
+    return vec4<f32>(0.);
+  }
+  let rgb = linear_to_srgb(clamp(tex.rgb/tex.a, vec3<f32>(0.), vec3<f32>(1.)));
+  return vert.color*vec4<f32>(rgb*tex.a, tex.a);
+}
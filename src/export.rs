@@ -0,0 +1,696 @@
+use std::{
+  collections::HashMap,
+  fmt,
+  path::{Path, PathBuf},
+  sync::{Arc, Condvar, Mutex},
+  task::Waker,
+  time::{Duration, Instant},
+};
+
+use crate::{
+  audio::{AudioDecoders, Mixer, StereoSample},
+  clip::ClipFrames,
+  generator::{Generator, TextRenderer},
+  schedule::{self, BroadcastKind, Bus, CallbackSender, ChannelOptions, Priority, RequestKind, RequestOptions, Response, Scheduler, Shutdown, Subscription, WorkerJobs},
+  timeline::{ClipSource, Timeline, TimelineClip, TrackKind, TrackSample},
+  video::{self, AudioEncoding, DecodeAhead, EncoderBuilder, RationalTime, RawImageRef, VideoEncoding, VideoStreamErr},
+  wgpustate::OffscreenRenderer,
+};
+
+pub mod presets;
+pub use presets::{ExportPreset, PresetLibrary};
+
+/// Container and codecs of an export. Without `video` only the mix is written, e.g. to a WAV file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExportSettings {
+  /// Short name of the muxer, `None` guesses it from the extension of the output path
+  pub format: Option<String>,
+  pub video: Option<VideoEncoding>,
+  pub audio: Option<AudioEncoding>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportError {
+  /// Opening, encoding or writing the output failed
+  Encoder(VideoStreamErr),
+  /// There is no GPU to render the frames with
+  NoAdapter,
+  /// Rendering the frame with this number failed
+  Render(i64),
+  InvalidPath,
+  /// The queue has no job with this id or the job can't do what was asked in its state
+  InvalidJob(u64),
+  /// No worker could take the job
+  WorkerDied,
+  /// The worker running the job panicked, see `QueuedJob::message`
+  WorkerPanicked,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportStatus {
+  Queued,
+  Running,
+  Paused,
+  Finished,
+  Failed(ExportError),
+  Cancelled,
+}
+
+/// Progress of a job as reported by the worker running it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExportProgress {
+  pub job: u64,
+  /// Counts the runs of the job, `RenderQueue::retry` starts the next one
+  pub attempt: u32,
+  pub status: ExportStatus,
+  /// Frames written so far
+  pub frame: i64,
+  pub total_frames: i64,
+  /// Frames per second, averaged over the time the job ran
+  pub fps: f32,
+  /// Estimated time until the job is done, `None` until the first frame is written
+  pub eta: Option<Duration>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum JobControl {
+  Run,
+  Pause,
+  Cancel,
+}
+
+/// Renders `[from, to)` of a timeline to `output`. The job is shared between the queue and the
+/// worker running it, the queue steers it with `pause`, `resume` and `cancel`.
+pub struct ExportJob {
+  pub id: u64,
+  pub timeline: Timeline,
+  pub from: RationalTime,
+  pub to: RationalTime,
+  pub settings: ExportSettings,
+  pub output: PathBuf,
+  control: Mutex<JobControl>,
+  control_changed: Condvar,
+}
+
+#[derive(Clone)]
+pub enum RenderRequest {
+  /// The job with the attempt it is run for
+  Export(Arc<ExportJob>, u32),
+}
+
+/// Resources a worker keeps between jobs
+struct RenderWorker {
+  idx: usize,
+  progress: Bus<ExportProgress>,
+  /// Id and attempt of the job each worker runs
+  running: WorkerJobs<(u64, u32)>,
+  renderer: Option<OffscreenRenderer>,
+  frames: ExportFrames,
+  audio_decoders: AudioDecoders,
+}
+
+/// Removes an incomplete output when dropped unless it's kept, so cancelling, errors and panics
+/// of `ExportJob::run` don't leave parts of it behind
+struct PartialOutput<'a> {
+  output: &'a Path,
+  /// Frames handed to the encoder, the files to remove of an image sequence
+  frames: i64,
+  /// The outermost directory created for the output, nothing above it is removed
+  created_dir: Option<PathBuf>,
+  keep: bool,
+}
+
+/// Frames of file sources from their decoders and of generators, rendered once and kept as long
+/// as they are used
+pub struct ExportFrames {
+  decoders: DecodeAhead<(PathBuf, u32)>,
+  converters: HashMap<(PathBuf, u32), video::VideoFrameContext>,
+  generators: Vec<(Generator, [u32; 2], Vec<u8>)>,
+  text_renderer: TextRenderer,
+  /// Size generators are rendered at
+  pub size: [u32; 2],
+  pub last_error: Option<VideoStreamErr>,
+}
+
+/// A job and what the queue knows about it
+pub struct QueuedJob {
+  pub job: Arc<ExportJob>,
+  pub progress: ExportProgress,
+  /// Details of a failure the status can't hold, e.g. the panic message of a render worker
+  pub message: Option<String>,
+}
+
+/// Runs export jobs in the background, one per worker. Progress is published by the workers on
+/// `PROGRESS_TOPIC` of a `Bus` and picked up by `update`, other panels may `subscribe` as well.
+pub struct RenderQueue {
+  scheduler: Scheduler<RenderRequest, ()>,
+  progress: Bus<ExportProgress>,
+  /// Unbounded, the queue must not miss the last report of a job
+  subscription: Subscription<ExportProgress>,
+  jobs: Vec<QueuedJob>,
+  next_id: u64,
+  running: WorkerJobs<(u64, u32)>,
+}
+
+/// Generators rendered by `ExportFrames` at most, least recently used are dropped first
+const MAX_GENERATOR_FRAMES: usize = 8;
+/// How far ahead of the current frame `ExportFrames::prepare` looks for clips in seconds
+pub const PREPARE_AHEAD_SECS: i64 = 1;
+/// Minimum time between two progress reports of a running job
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+/// Topic of the progress reports on the bus of a `RenderQueue`
+pub const PROGRESS_TOPIC: &str = "export";
+
+
+/// Path of the file of `frame` if `output` is the pattern of an image sequence, i.e. its file
+/// name contains `%d` or `%0Nd`. FFmpeg numbers the files from 1.
+fn sequence_file(output: &Path, frame: i64) -> Option<PathBuf> {
+  let name = output.file_name()?.to_str()?;
+  let start = name.find('%')?;
+  let digits = name[start + 1..].find(|c: char| !c.is_ascii_digit())?;
+  let end = start + 1 + digits;
+  if !name[end..].starts_with('d') {
+    return None;
+  }
+  let width: usize = name[start + 1..end].parse().unwrap_or(0);
+  Some(output.with_file_name(format!("{}{:0width$}{}", &name[..start], frame, &name[end + 1..], width = width)))
+}
+
+
+impl fmt::Display for ExportError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ExportError::Encoder(err) => write!(f, "Encoding failed: {:?}", err),
+      ExportError::NoAdapter => write!(f, "No GPU available for rendering"),
+      ExportError::Render(frame) => write!(f, "Rendering frame {} failed", frame),
+      ExportError::InvalidPath => write!(f, "Invalid output path"),
+      ExportError::InvalidJob(id) => write!(f, "Job {} can't do that right now", id),
+      ExportError::WorkerDied => write!(f, "No render worker available"),
+      ExportError::WorkerPanicked => write!(f, "The render worker crashed"),
+    }
+  }
+}
+
+impl From<VideoStreamErr> for ExportError {
+  fn from(err: VideoStreamErr) -> Self {
+    ExportError::Encoder(err)
+  }
+}
+
+
+impl ExportStatus {
+  /// Whether the job won't change anymore unless it is retried
+  pub fn is_done(&self) -> bool {
+    matches!(self, ExportStatus::Finished | ExportStatus::Failed(_) | ExportStatus::Cancelled)
+  }
+}
+
+impl ExportProgress {
+  fn new(job: u64, attempt: u32, status: ExportStatus) -> Self {
+    Self { job, attempt, status, frame: 0, total_frames: 0, fps: 0., eta: None }
+  }
+
+  /// Between 0 and 1
+  pub fn fraction(&self) -> f32 {
+    if self.total_frames > 0 { self.frame as f32 / self.total_frames as f32 } else { 0. }
+  }
+}
+
+
+impl ExportJob {
+  pub fn new(id: u64, timeline: Timeline, from: RationalTime, to: RationalTime, settings: ExportSettings, output: PathBuf) -> Self {
+    Self { id, timeline, from, to, settings, output, control: Mutex::new(JobControl::Run), control_changed: Condvar::new() }
+  }
+
+  fn set_control(&self, control: JobControl) {
+    *self.control.lock().unwrap() = control;
+    self.control_changed.notify_all();
+  }
+
+  pub fn pause(&self) {
+    let mut guard = self.control.lock().unwrap();
+    if *guard == JobControl::Run {
+      *guard = JobControl::Pause;
+    }
+  }
+
+  pub fn resume(&self) {
+    let mut guard = self.control.lock().unwrap();
+    if *guard == JobControl::Pause {
+      *guard = JobControl::Run;
+      self.control_changed.notify_all();
+    }
+  }
+
+  pub fn cancel(&self) {
+    self.set_control(JobControl::Cancel)
+  }
+
+  pub fn is_cancelled(&self) -> bool {
+    *self.control.lock().unwrap() == JobControl::Cancel
+  }
+
+  /// Frames per second of the output as `(numerator, denominator)`. Audio-only jobs step through
+  /// the timeline with its frame rate.
+  pub fn frame_rate(&self) -> (i64, i64) {
+    match &self.settings.video {
+      Some(video) => video.frame_rate,
+      None => self.timeline.frame_rate,
+    }
+  }
+
+  pub fn total_frames(&self) -> i64 {
+    let (rate_num, rate_den) = self.frame_rate();
+    (self.to - self.from).to_frames(rate_num, rate_den).max(0)
+  }
+
+  /// Blocks while the job is paused. Returns `false` if it was cancelled.
+  fn wait_while_paused(&self, mut on_pause: impl FnMut()) -> bool {
+    let mut guard = self.control.lock().unwrap();
+    let mut paused = false;
+    loop {
+      match *guard {
+        JobControl::Run => return true,
+        JobControl::Cancel => return false,
+        JobControl::Pause => {
+          if !paused {
+            paused = true;
+            on_pause();
+          }
+          guard = self.control_changed.wait(guard).unwrap();
+        },
+      }
+    }
+  }
+
+  /// Renders and encodes the job on the calling thread. Returns `Ok(false)` if it was cancelled.
+  /// Unless the job succeeds, the incomplete output is removed.
+  fn run(&self, attempt: u32, worker: &mut RenderWorker, mut report: impl FnMut(ExportProgress)) -> Result<bool, ExportError> {
+    let total_frames = self.total_frames();
+    let mut progress = ExportProgress { total_frames, ..ExportProgress::new(self.id, attempt, ExportStatus::Running) };
+    report(progress);
+
+    // Declared before the encoder, which has to be closed first
+    let mut partial = PartialOutput::create(&self.output)?;
+    let mut builder = EncoderBuilder::default()
+      .set_path(&self.output).map_err(|_| ExportError::InvalidPath)?
+      .set_thread_to_all();
+    if let Some(format) = &self.settings.format {
+      builder = builder.set_format(format).map_err(|_| ExportError::Encoder(VideoStreamErr::MuxerNotFound))?;
+    }
+    if let Some(video) = &self.settings.video {
+      builder = builder.set_video(video.clone());
+      let size = [video.width, video.height];
+      worker.frames.size = size;
+      if worker.renderer.as_ref().map(|r| r.size()) != Some(size) {
+        // Drop the old device first
+        worker.renderer = None;
+        worker.renderer = Some(OffscreenRenderer::new(size[0], size[1]).ok_or(ExportError::NoAdapter)?);
+      }
+    }
+    let mut mixer = None;
+    if let Some(audio) = &self.settings.audio {
+      builder = builder.set_audio(audio.clone());
+      mixer = Some(Mixer::new(audio.sample_rate));
+    }
+    let mut encoder = builder.finish()?;
+
+    let (rate_num, rate_den) = self.frame_rate();
+    let frame_duration = RationalTime::new(rate_den, rate_num);
+    let mut samples: Vec<StereoSample> = Vec::new();
+    let started = Instant::now();
+    let mut paused_for = Duration::ZERO;
+    let mut last_report = started;
+    for frame in 0..total_frames {
+      let pause_start = Instant::now();
+      let keep_going = self.wait_while_paused(|| report(ExportProgress { status: ExportStatus::Paused, ..progress }));
+      if !keep_going {
+        return Ok(false);
+      }
+      if pause_start.elapsed() > Duration::from_millis(1) {
+        paused_for += pause_start.elapsed();
+        report(progress);
+      }
+
+      let time = self.from + RationalTime::from_frames(frame, rate_num, rate_den);
+      if self.settings.video.is_some() {
+        worker.frames.prepare(&self.timeline, time, (time + RationalTime::from_secs(PREPARE_AHEAD_SECS)).min(self.to));
+        let renderer = worker.renderer.as_mut().unwrap();
+        let rgba = renderer.render_timeline(&self.timeline, time, &mut worker.frames).ok_or(ExportError::Render(frame))?;
+        encoder.write_rgba(&rgba)?;
+        partial.frames = frame + 1;
+      }
+      if let Some(mixer) = &mut mixer {
+        let rate = mixer.sample_rate as i64;
+        let (start, end) = (time.to_frames(rate, 1), (time + frame_duration).min(self.to).to_frames(rate, 1));
+        samples.resize((end - start).max(0) as usize, [0.; 2]);
+        mixer.mix(&self.timeline, start, &mut worker.audio_decoders, &mut samples);
+        encoder.write_audio(&samples)?;
+      }
+
+      progress.frame = frame + 1;
+      let elapsed = started.elapsed().saturating_sub(paused_for).as_secs_f32();
+      if elapsed > 0. {
+        progress.fps = progress.frame as f32 / elapsed;
+        progress.eta = Some(Duration::from_secs_f32((total_frames - progress.frame) as f32 / progress.fps));
+      }
+      if last_report.elapsed() >= PROGRESS_INTERVAL {
+        last_report = Instant::now();
+        report(progress);
+      }
+    }
+    encoder.finish()?;
+    partial.keep = true;
+    Ok(true)
+  }
+}
+
+impl<'a> PartialOutput<'a> {
+  /// Creates the missing directories of `output`, e.g. sequences are written into their own
+  fn create(output: &'a Path) -> Result<Self, ExportError> {
+    let dir = output.parent().filter(|dir| !dir.as_os_str().is_empty());
+    let created_dir = dir.and_then(|dir| dir.ancestors()
+      .take_while(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+      .last()
+      .map(Path::to_path_buf));
+    // Removes what was created if it fails halfway
+    let res = Self { output, frames: 0, created_dir, keep: false };
+    if let Some(dir) = dir {
+      std::fs::create_dir_all(dir).map_err(|_| ExportError::InvalidPath)?;
+    }
+    Ok(res)
+  }
+}
+
+impl Drop for PartialOutput<'_> {
+  fn drop(&mut self) {
+    if self.keep {
+      return;
+    }
+    if sequence_file(self.output, 1).is_none() {
+      std::fs::remove_file(self.output).unwrap_or_default();
+    }
+    for frame in 1..=self.frames {
+      if let Some(path) = sequence_file(self.output, frame) {
+        std::fs::remove_file(path).unwrap_or_default();
+      }
+    }
+    // Only directories created for the output and only if nothing else is in there
+    if let Some(created_dir) = &self.created_dir {
+      for dir in self.output.ancestors().skip(1) {
+        if std::fs::remove_dir(dir).is_err() || dir == created_dir {
+          break;
+        }
+      }
+    }
+  }
+}
+
+
+impl ExportFrames {
+  pub fn new(max_streams: usize) -> Self {
+    let decoders = DecodeAhead::new(max_streams, |(path, stream_idx): &(PathBuf, u32)| {
+      video::VideoStreamBuilder::default()
+        .set_path(path).map_err(|_| VideoStreamErr::IO)?
+        .set_stream_idx(*stream_idx)
+        .set_thread_to_all()
+        .finish()
+    });
+    Self {
+      decoders,
+      converters: HashMap::new(),
+      generators: Vec::new(),
+      text_renderer: TextRenderer::new(),
+      size: [1920, 1080],
+      last_error: None,
+    }
+  }
+
+  /// Opens the decoders of the video clips in `[from, to)` and seeks them to where they start
+  /// playing, so the first frame of a cut doesn't wait for the seek. Sources shown at `from`
+  /// keep their position. The last failure ends up in `last_error`.
+  pub fn prepare(&mut self, timeline: &Timeline, from: RationalTime, to: RationalTime) {
+    let key = |source: &ClipSource| match source {
+      ClipSource::File { path, stream_idx } => Some((path.clone(), *stream_idx)),
+      ClipSource::Generator(_) => None,
+    };
+    let shown: Vec<_> = timeline.tracks.iter()
+      .filter(|track| track.kind == TrackKind::Video)
+      .flat_map(|track| match track.sample_at(from) {
+        TrackSample::Gap => vec![],
+        TrackSample::Clip { clip, .. } => vec![&clip.source],
+        TrackSample::Transition { from, to, .. } => vec![&from.0.source, &to.0.source],
+      })
+      .filter_map(&key)
+      .collect();
+    // A decoder can only be at one place, the first clip of a source gets it
+    let mut upcoming: Vec<((PathBuf, u32), RationalTime)> = Vec::new();
+    for range in timeline.sources_in(from, to) {
+      if timeline.tracks[range.track_idx].kind != TrackKind::Video {
+        continue;
+      }
+      match key(range.source) {
+        Some(key) if !shown.contains(&key) && !upcoming.iter().any(|(k, _)| *k == key) => upcoming.push((key, range.from)),
+        _ => {},
+      }
+    }
+    let failed = self.decoders.prepare(upcoming.iter().map(|(key, start)| (key, *start)));
+    if let Some((_, err)) = failed.into_iter().last() {
+      self.last_error = Some(err);
+    }
+  }
+}
+
+impl ClipFrames for ExportFrames {
+  fn frame(&mut self, clip: &TimelineClip, source_time: RationalTime) -> Option<RawImageRef<'_>> {
+    match &clip.source {
+      ClipSource::File { path, stream_idx } => {
+        let key = (path.clone(), *stream_idx);
+        let frm = match self.decoders.frame_at(&key, source_time) {
+          Ok(frm) => frm,
+          Err(err) => {
+            self.last_error = Some(err);
+            return None;
+          },
+        };
+        let (width, height) = (frm.width, frm.height);
+        let converter = self.converters.entry(key).or_insert_with(|| video::VideoFrameContext::new(frm.clone()));
+        converter.frm_src = frm;
+        if let Err(err) = converter.convert(width, height, video::AVPixelFormat::AV_PIX_FMT_RGBA, video::SWS_Scaling::Bilinear) {
+          self.last_error = Some(err);
+          return None;
+        }
+        Some(converter.converted_frm())
+      },
+      ClipSource::Generator(generator) => {
+        // Generators fill the frame, the compositor places them like any other layer
+        let size = self.size;
+        let idx = match self.generators.iter().position(|(g, s, _)| g == generator && *s == size) {
+          Some(idx) => idx,
+          None => {
+            if self.generators.len() >= MAX_GENERATOR_FRAMES {
+              self.generators.remove(0);
+            }
+            let frame = generator.render(size, Some(&mut self.text_renderer));
+            self.generators.push((generator.clone(), size, frame));
+            self.generators.len() - 1
+          },
+        };
+        // Most recently used last
+        let entry = self.generators.remove(idx);
+        self.generators.push(entry);
+        let (_, [w, h], frame) = self.generators.last().unwrap();
+        Some(RawImageRef::new_rgba32(frame, *w as _, *h as _))
+      },
+    }
+  }
+}
+
+
+impl fmt::Debug for RenderRequest {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      RenderRequest::Export(job, attempt) => f.debug_tuple("Export").field(&job.id).field(attempt).finish(),
+    }
+  }
+}
+
+impl RenderWorker {
+  fn new(idx: usize, progress: Bus<ExportProgress>, running: WorkerJobs<(u64, u32)>) -> Self {
+    Self { idx, progress, running, renderer: None, frames: ExportFrames::new(8), audio_decoders: AudioDecoders::new(8) }
+  }
+
+  fn handle(&mut self, request: RenderRequest) -> Response<()> {
+    match request {
+      RenderRequest::Export(job, attempt) => {
+        let total_frames = job.total_frames();
+        let bus = self.progress.clone();
+        let mut report = |progress: ExportProgress| { bus.publish(PROGRESS_TOPIC, &progress); };
+        let status = if job.is_cancelled() {
+          ExportStatus::Cancelled
+        } else {
+          let running = self.running.clone();
+          match running.run(self.idx, (job.id, attempt), || job.run(attempt, self, &mut report)) {
+            Ok(true) => ExportStatus::Finished,
+            Ok(false) => ExportStatus::Cancelled,
+            Err(err) => ExportStatus::Failed(err),
+          }
+        };
+        let frame = if status == ExportStatus::Finished { total_frames } else { 0 };
+        report(ExportProgress { frame, total_frames, ..ExportProgress::new(job.id, attempt, status) });
+      },
+    }
+    Response::Ok(())
+  }
+}
+
+
+impl QueuedJob {
+  pub fn status(&self) -> ExportStatus {
+    self.progress.status
+  }
+}
+
+impl RenderQueue {
+  pub fn new(num_workers: usize) -> Self {
+    let progress = Bus::new();
+    let subscription = progress.subscribe(PROGRESS_TOPIC, ChannelOptions::default());
+    let workers_progress = progress.clone();
+    let running = WorkerJobs::default();
+    let workers_running = running.clone();
+    let mut scheduler = Scheduler::new(num_workers, move |idx| {
+      let mut worker = RenderWorker::new(idx, workers_progress.clone(), workers_running.clone());
+      move |request: RenderRequest, _: RequestKind, _: &mut CallbackSender<()>| worker.handle(request)
+    });
+    // A job that crashes its worker fails, the others keep going on a new one
+    scheduler.set_respawn(true);
+    scheduler.register_metrics("Render Queue", |_| "Export");
+    Self { scheduler, progress, subscription, jobs: Vec::new(), next_id: 0, running }
+  }
+
+  /// Queues an export. The first free worker takes it.
+  pub fn push(&mut self, timeline: Timeline, from: RationalTime, to: RationalTime, settings: ExportSettings, output: PathBuf) -> Result<u64, ExportError> {
+    let id = self.next_id;
+    self.next_id += 1;
+    let job = Arc::new(ExportJob::new(id, timeline, from, to, settings, output));
+    let total_frames = job.total_frames();
+    self.submit(job.clone(), 0)?;
+    self.jobs.push(QueuedJob { job, progress: ExportProgress { total_frames, ..ExportProgress::new(id, 0, ExportStatus::Queued) }, message: None });
+    Ok(id)
+  }
+
+  fn submit(&self, job: Arc<ExportJob>, attempt: u32) -> Result<(), ExportError> {
+    let options = RequestOptions::new(Priority::Background);
+    self.scheduler.request_with(RenderRequest::Export(job, attempt), BroadcastKind::Any, options)
+      .map(|_| ())
+      .map_err(|_: schedule::RequestError<RenderRequest>| ExportError::WorkerDied)
+  }
+
+  pub fn jobs(&self) -> &[QueuedJob] {
+    &self.jobs
+  }
+
+  fn job(&self, id: u64) -> Result<&QueuedJob, ExportError> {
+    self.jobs.iter().find(|j| j.job.id == id).ok_or(ExportError::InvalidJob(id))
+  }
+
+  pub fn pause(&self, id: u64) -> Result<(), ExportError> {
+    self.job(id)?.job.pause();
+    Ok(())
+  }
+
+  pub fn resume(&self, id: u64) -> Result<(), ExportError> {
+    self.job(id)?.job.resume();
+    Ok(())
+  }
+
+  pub fn cancel(&self, id: u64) -> Result<(), ExportError> {
+    self.job(id)?.job.cancel();
+    Ok(())
+  }
+
+  /// Queues a failed or cancelled job again, starting from the beginning
+  pub fn retry(&mut self, id: u64) -> Result<(), ExportError> {
+    let idx = self.jobs.iter().position(|j| j.job.id == id).ok_or(ExportError::InvalidJob(id))?;
+    let queued = &self.jobs[idx];
+    if !matches!(queued.status(), ExportStatus::Failed(_) | ExportStatus::Cancelled) {
+      return Err(ExportError::InvalidJob(id));
+    }
+    queued.job.set_control(JobControl::Run);
+    let attempt = queued.progress.attempt + 1;
+    self.submit(queued.job.clone(), attempt)?;
+    let total_frames = queued.job.total_frames();
+    self.jobs[idx].progress = ExportProgress { total_frames, ..ExportProgress::new(id, attempt, ExportStatus::Queued) };
+    self.jobs[idx].message = None;
+    Ok(())
+  }
+
+  /// Removes the jobs that are done from `jobs`
+  pub fn clear_done(&mut self) {
+    self.jobs.retain(|j| !j.status().is_done());
+  }
+
+  /// Whether any job is queued or running
+  pub fn is_busy(&self) -> bool {
+    self.jobs.iter().any(|j| !j.status().is_done())
+  }
+
+  /// The progress reports of all jobs from now on, e.g. for a panel besides the queue window.
+  /// A bounded channel with `Backpressure::Block` holds the workers back while it is full.
+  pub fn subscribe(&self, options: ChannelOptions) -> Subscription<ExportProgress> {
+    self.progress.subscribe(PROGRESS_TOPIC, options)
+  }
+
+  /// Woken on every progress report and when a worker stopped, e.g. to redraw the window that
+  /// shows the queue
+  pub fn set_waker(&self, waker: Option<Waker>) {
+    self.subscription.set_waker(waker.clone());
+    self.scheduler.set_waker(waker);
+  }
+
+  /// Picks up the progress reports of the workers. Call regularly, e.g. once per UI frame.
+  /// The job of a worker that panicked fails with its panic message.
+  pub fn update(&mut self) {
+    let mut died = Vec::new();
+    self.scheduler.handle_respones(|resp, _| {
+      if let Response::WorkerDied { worker, payload } = resp {
+        died.push((worker, schedule::panic_message(payload.as_ref()).to_string()));
+      }
+      Ok::<(), ()>(())
+    }).unwrap_or_default();
+    for (worker, message) in died {
+      let queued = self.running.take(worker).and_then(|(id, attempt)| {
+        self.jobs.iter_mut().find(|j| j.job.id == id && j.progress.attempt == attempt)
+      });
+      match queued {
+        Some(queued) => {
+          queued.progress.status = ExportStatus::Failed(ExportError::WorkerPanicked);
+          queued.message = Some(message);
+        },
+        None => eprintln!("Render worker {} died: {}", worker, message),
+      }
+    }
+    for message in self.subscription.try_iter() {
+      let progress = message.into_message();
+      if let Some(queued) = self.jobs.iter_mut().find(|j| j.job.id == progress.job) {
+        // A report of the previous run may arrive after a retry, one sent before the worker died
+        // after the job failed
+        let crashed = queued.status() == ExportStatus::Failed(ExportError::WorkerPanicked);
+        if progress.attempt > queued.progress.attempt || progress.attempt == queued.progress.attempt && !crashed {
+          queued.progress = progress;
+        }
+      }
+    }
+  }
+}
+
+impl Drop for RenderQueue {
+  fn drop(&mut self) {
+    // The stop message only reaches a worker after its job, running jobs stop at their next frame
+    // once cancelled. Aborting instead of draining cancels the queued ones before they start.
+    for queued in self.jobs.iter() {
+      queued.job.cancel();
+    }
+    self.scheduler.shutdown(Shutdown::Abort);
+  }
+}
@@ -0,0 +1,392 @@
+use std::{
+  fmt,
+  fs,
+  path::{Path, PathBuf},
+};
+
+use serde::{Serialize, Deserialize};
+use crate::video::{encoder, AudioEncoding, RationalTime, VideoEncoding};
+use super::ExportSettings;
+
+/// The presets shipped with Escher
+pub const BUILTIN_PRESETS: &str = include_str!("presets.toml");
+
+/// Video part of a preset. Size and frame rate default to the project's.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct VideoPreset {
+  pub encoder: String,
+  /// FFmpeg's name of the pixel format, e.g. `yuv420p`
+  pub pix_fmt: String,
+  pub width: Option<u32>,
+  pub height: Option<u32>,
+  pub frame_rate: Option<[i64; 2]>,
+  #[serde(default)]
+  pub bit_rate: u64,
+  #[serde(default)]
+  pub options: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AudioPreset {
+  pub encoder: String,
+  #[serde(default = "default_sample_rate")]
+  pub sample_rate: u32,
+  #[serde(default = "default_channels")]
+  pub channels: u32,
+  #[serde(default)]
+  pub bit_rate: u64,
+  #[serde(default)]
+  pub options: String,
+}
+
+/// Named export settings, see `presets.toml` for the format
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExportPreset {
+  pub name: String,
+  pub extension: String,
+  pub format: Option<String>,
+  /// One file per frame, e.g. PNG sequences
+  #[serde(default)]
+  pub sequence: bool,
+  pub video: Option<VideoPreset>,
+  pub audio: Option<AudioPreset>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PresetFile {
+  #[serde(default)]
+  preset: Vec<ExportPreset>,
+}
+
+/// Something a preset needs which the linked FFmpeg doesn't have
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Capability {
+  Encoder(String),
+  Muxer(String),
+  PixelFormat(String),
+  /// The encoder exists but doesn't take the pixel format
+  EncoderPixelFormat { encoder: String, pix_fmt: String },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PresetOrigin {
+  Builtin,
+  User,
+}
+
+/// A loaded preset and what of it is missing
+#[derive(Clone, Debug)]
+pub struct AvailablePreset {
+  pub preset: ExportPreset,
+  pub origin: PresetOrigin,
+  /// Empty if the preset can be rendered
+  pub missing: Vec<Capability>,
+}
+
+/// Built-in and user presets, validated against the linked FFmpeg
+#[derive(Debug, Default)]
+pub struct PresetLibrary {
+  presets: Vec<AvailablePreset>,
+  /// Files which couldn't be read or parsed with the reason
+  pub errors: Vec<(PathBuf, String)>,
+}
+
+
+fn default_sample_rate() -> u32 {
+  48000
+}
+
+fn default_channels() -> u32 {
+  2
+}
+
+/// `$XDG_CONFIG_HOME/escher/presets`, `~/.config/escher/presets` or `%APPDATA%\escher\presets`
+pub fn user_preset_dir() -> Option<PathBuf> {
+  let config_dir = std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+    .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+    .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))?;
+  Some(config_dir.join("escher").join("presets"))
+}
+
+/// Parses the presets of a TOML document
+pub fn parse_presets(toml: &str) -> Result<Vec<ExportPreset>, String> {
+  let presets = toml::from_str::<PresetFile>(toml).map_err(|err| err.to_string())?.preset;
+  for preset in &presets {
+    if let Some([num, den]) = preset.video.as_ref().and_then(|video| video.frame_rate) {
+      if RationalTime::from_frame_rate(num, den).is_none() {
+        return Err(format!("preset {}: invalid frame rate {}/{}", preset.name, num, den));
+      }
+    }
+  }
+  Ok(presets)
+}
+
+
+impl fmt::Display for Capability {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Capability::Encoder(name) => write!(f, "encoder {}", name),
+      Capability::Muxer(name) => write!(f, "muxer {}", name),
+      Capability::PixelFormat(name) => write!(f, "pixel format {}", name),
+      Capability::EncoderPixelFormat { encoder, pix_fmt } => write!(f, "pixel format {} for {}", pix_fmt, encoder),
+    }
+  }
+}
+
+impl ExportPreset {
+  /// What the linked FFmpeg lacks to render this preset
+  pub fn missing(&self) -> Vec<Capability> {
+    let mut missing = Vec::new();
+    if let Some(format) = &self.format {
+      if !encoder::has_muxer(format) {
+        missing.push(Capability::Muxer(format.clone()));
+      }
+    }
+    if let Some(video) = &self.video {
+      let pix_fmt = encoder::pix_fmt_from_name(&video.pix_fmt);
+      if !encoder::has_encoder(&video.encoder) {
+        missing.push(Capability::Encoder(video.encoder.clone()));
+      } else {
+        match pix_fmt {
+          None => missing.push(Capability::PixelFormat(video.pix_fmt.clone())),
+          Some(pix_fmt) if !encoder::encoder_supports_pix_fmt(&video.encoder, pix_fmt) =>
+            missing.push(Capability::EncoderPixelFormat { encoder: video.encoder.clone(), pix_fmt: video.pix_fmt.clone() }),
+          Some(_) => {},
+        }
+      }
+    }
+    if let Some(audio) = &self.audio {
+      if !encoder::has_encoder(&audio.encoder) {
+        missing.push(Capability::Encoder(audio.encoder.clone()));
+      }
+    }
+    missing
+  }
+
+  /// Settings of an export of a project with that size and frame rate
+  pub fn settings(&self, project_size: [u32; 2], project_frame_rate: (i64, i64)) -> Result<ExportSettings, Capability> {
+    let video = match &self.video {
+      Some(video) => {
+        let pix_fmt = encoder::pix_fmt_from_name(&video.pix_fmt)
+          .ok_or_else(|| Capability::PixelFormat(video.pix_fmt.clone()))?;
+        let [width, height] = scaled_size(project_size, video.width, video.height);
+        Some(VideoEncoding {
+          encoder: video.encoder.clone(),
+          width,
+          height,
+          pix_fmt,
+          frame_rate: video.frame_rate.map_or(project_frame_rate, |[num, den]| (num, den)),
+          bit_rate: video.bit_rate,
+          options: video.options.clone(),
+        })
+      },
+      None => None,
+    };
+    let audio = self.audio.as_ref().map(|audio| AudioEncoding {
+      encoder: audio.encoder.clone(),
+      sample_rate: audio.sample_rate,
+      channels: audio.channels,
+      bit_rate: audio.bit_rate,
+      options: audio.options.clone(),
+    });
+    Ok(ExportSettings { format: self.format.clone(), video, audio })
+  }
+
+  /// Path of an output named `stem` in `dir`. Sequences get their own directory and a frame
+  /// number pattern.
+  pub fn output_path(&self, dir: &Path, stem: &str) -> PathBuf {
+    if self.sequence {
+      dir.join(stem).join(format!("{}_%05d.{}", stem, self.extension))
+    } else {
+      dir.join(format!("{}.{}", stem, self.extension))
+    }
+  }
+}
+
+/// A size of `project_size` with the given sides. With one side given the other keeps the aspect
+/// ratio, rounded to an even number for chroma subsampling.
+fn scaled_size(project_size: [u32; 2], width: Option<u32>, height: Option<u32>) -> [u32; 2] {
+  let [w, h] = project_size.map(|s| s.max(1) as u64);
+  let even = |x: u64| (x.div_ceil(2) * 2).max(2) as u32;
+  match (width, height) {
+    (Some(width), Some(height)) => [width, height],
+    (Some(width), None) => [width, even(width as u64 * h / w)],
+    (None, Some(height)) => [even(height as u64 * w / h), height],
+    (None, None) => project_size,
+  }
+}
+
+impl PresetLibrary {
+  /// Loads the built-in presets and the `.toml` files of `user_preset_dir`. User presets replace
+  /// built-in ones with the same name.
+  pub fn load() -> Self {
+    let mut res = Self::default();
+    match parse_presets(BUILTIN_PRESETS) {
+      Ok(presets) => for preset in presets {
+        res.insert(preset, PresetOrigin::Builtin);
+      },
+      Err(err) => res.errors.push((PathBuf::from("presets.toml"), err)),
+    }
+    if let Some(dir) = user_preset_dir() {
+      res.load_dir(&dir);
+    }
+    res
+  }
+
+  /// Loads the `.toml` files of `dir` as user presets. A missing directory is no error.
+  pub fn load_dir(&mut self, dir: &Path) {
+    let entries = match fs::read_dir(dir) {
+      Ok(entries) => entries,
+      Err(_) => return,
+    };
+    let mut paths: Vec<_> = entries
+      .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+      .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+      .collect();
+    paths.sort();
+    for path in paths {
+      match fs::read_to_string(&path).map_err(|err| err.to_string()).and_then(|toml| parse_presets(&toml)) {
+        Ok(presets) => for preset in presets {
+          self.insert(preset, PresetOrigin::User);
+        },
+        Err(err) => self.errors.push((path, err)),
+      }
+    }
+  }
+
+  /// Adds `preset`, replacing one with the same name
+  pub fn insert(&mut self, preset: ExportPreset, origin: PresetOrigin) {
+    let missing = preset.missing();
+    let available = AvailablePreset { preset, origin, missing };
+    match self.presets.iter_mut().find(|p| p.preset.name == available.preset.name) {
+      Some(old) => *old = available,
+      None => self.presets.push(available),
+    }
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item=&AvailablePreset> {
+    self.presets.iter()
+  }
+
+  pub fn get(&self, name: &str) -> Option<&AvailablePreset> {
+    self.presets.iter().find(|p| p.preset.name == name)
+  }
+}
+
+impl AvailablePreset {
+  pub fn is_available(&self) -> bool {
+    self.missing.is_empty()
+  }
+
+  /// The missing capabilities as one line for the UI
+  pub fn missing_text(&self) -> String {
+    let missing: Vec<_> = self.missing.iter().map(|c| c.to_string()).collect();
+    format!("Missing {}", missing.join(", "))
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn parse_one(toml: &str) -> Result<ExportPreset, String> {
+    let mut presets = parse_presets(toml)?;
+    assert_eq!(presets.len(), 1);
+    Ok(presets.remove(0))
+  }
+
+  fn to_toml(presets: Vec<ExportPreset>) -> String {
+    toml::to_string(&PresetFile { preset: presets }).unwrap()
+  }
+
+  #[test]
+  fn builtin_presets_parse() {
+    let presets = parse_presets(BUILTIN_PRESETS).unwrap();
+    assert!(!presets.is_empty());
+    for (i, preset) in presets.iter().enumerate() {
+      assert!(presets[..i].iter().all(|p| p.name != preset.name), "{} is defined twice", preset.name);
+      assert!(preset.video.is_some() || preset.audio.is_some(), "{} exports nothing", preset.name);
+    }
+  }
+
+  #[test]
+  fn presets_round_trip() {
+    let builtin = parse_presets(BUILTIN_PRESETS).unwrap();
+    assert_eq!(parse_presets(&to_toml(builtin.clone())).unwrap(), builtin);
+
+    let preset = ExportPreset {
+      name: "Frames".to_string(),
+      extension: "png".to_string(),
+      format: Some("image2".to_string()),
+      sequence: true,
+      video: Some(VideoPreset {
+        encoder: "png".to_string(),
+        pix_fmt: "rgba".to_string(),
+        width: Some(640),
+        height: None,
+        frame_rate: Some([30000, 1001]),
+        bit_rate: 0,
+        options: "compression_level=9".to_string(),
+      }),
+      audio: None,
+    };
+    assert_eq!(parse_presets(&to_toml(vec![preset.clone()])).unwrap(), vec![preset]);
+    assert_eq!(parse_presets("").unwrap(), vec![]);
+  }
+
+  #[test]
+  fn missing_fields_default() {
+    let preset = parse_one("[[preset]]\nname = \"Wav\"\nextension = \"wav\"\n[preset.audio]\nencoder = \"pcm_s16le\"\n").unwrap();
+    assert_eq!(preset.format, None);
+    assert!(!preset.sequence);
+    assert_eq!(preset.video, None);
+    assert_eq!(preset.audio, Some(AudioPreset {
+      encoder: "pcm_s16le".to_string(),
+      sample_rate: 48000,
+      channels: 2,
+      bit_rate: 0,
+      options: String::new(),
+    }));
+  }
+
+  #[test]
+  fn invalid_presets_are_rejected() {
+    let video = |fields: &str| format!("[[preset]]\nname = \"Broken\"\nextension = \"mp4\"\n[preset.video]\n{}\n", fields);
+    for toml in [
+      "[[preset]]\nextension = \"mp4\"\n".to_string(),
+      "[[preset]]\nname = \"No extension\"\n".to_string(),
+      "[[preset]\nname = \"Syntax\"\n".to_string(),
+      "preset = 3\n".to_string(),
+      video("pix_fmt = \"yuv420p\""),
+      video("encoder = \"libx264\"\npix_fmt = \"yuv420p\"\nwidth = \"wide\""),
+      video("encoder = \"libx264\"\npix_fmt = \"yuv420p\"\nwidth = -1"),
+      video("encoder = \"libx264\"\npix_fmt = \"yuv420p\"\nframe_rate = [25]"),
+    ] {
+      assert!(parse_presets(&toml).is_err(), "{}", toml);
+    }
+    for frame_rate in ["[0, 1]", "[25, 0]", "[-25, 1]", "[25, -1]"] {
+      let err = parse_one(&video(&format!("encoder = \"libx264\"\npix_fmt = \"yuv420p\"\nframe_rate = {}", frame_rate)));
+      assert!(err.unwrap_err().contains("Broken"), "{}", frame_rate);
+    }
+    let valid = parse_one(&video("encoder = \"libx264\"\npix_fmt = \"yuv420p\"\nframe_rate = [24000, 1001]")).unwrap();
+    assert_eq!(valid.video.unwrap().frame_rate, Some([24000, 1001]));
+  }
+
+  #[test]
+  fn output_paths_and_sizes() {
+    let mut preset = parse_presets(BUILTIN_PRESETS).unwrap().remove(0);
+    preset.extension = "mov".to_string();
+    preset.sequence = false;
+    assert_eq!(preset.output_path(Path::new("out"), "cut"), Path::new("out/cut.mov"));
+    preset.sequence = true;
+    assert_eq!(preset.output_path(Path::new("out"), "cut"), Path::new("out/cut/cut_%05d.mov"));
+
+    assert_eq!(scaled_size([1920, 1080], None, None), [1920, 1080]);
+    assert_eq!(scaled_size([1920, 1080], Some(1280), Some(100)), [1280, 100]);
+    assert_eq!(scaled_size([1920, 1080], None, Some(720)), [1280, 720]);
+    // Rounded up to even sides
+    assert_eq!(scaled_size([1920, 1080], Some(1001), None), [1001, 564]);
+    assert_eq!(scaled_size([4, 1000], None, Some(10)), [2, 10]);
+  }
+}
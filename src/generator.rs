@@ -0,0 +1,519 @@
+use std::sync::Arc;
+
+use epaint::{
+  text::{Fonts, FontDefinitions, FontData, FontFamily, FontId, LayoutJob, Galley},
+  emath::Align,
+  Color32, FontImage, ImageData,
+};
+use serde::{Serialize, Deserialize};
+
+use crate::{
+  clip::FrameSource,
+  effects::{srgb_to_linear, linear_to_srgb},
+  video::{RationalTime, RawImageRef, VideoStreamErr},
+};
+
+/// Colors of generators are unpremultiplied sRGBA, like `effects::ParamKind::Color`
+pub type Srgba = [f32; 4];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HAlign {
+  Left,
+  #[default]
+  Center,
+  Right,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VAlign {
+  Top,
+  #[default]
+  Center,
+  Bottom,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Outline {
+  pub color: Srgba,
+  /// In pixels
+  pub width: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Shadow {
+  pub color: Srgba,
+  /// In pixels, positive values move the shadow to the bottom right
+  pub offset: [f32; 2],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BackgroundBox {
+  pub color: Srgba,
+  /// Space between the text and the edge of the box in pixels
+  pub padding: f32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TextTitle {
+  pub text: String,
+  /// `"Proportional"`, `"Monospace"` or the name a font was added with to `TextRenderer`
+  pub font: String,
+  /// In pixels
+  pub size: f32,
+  pub color: Srgba,
+  pub outline: Option<Outline>,
+  pub shadow: Option<Shadow>,
+  pub background: Option<BackgroundBox>,
+  pub halign: HAlign,
+  pub valign: VAlign,
+  /// Distance of the text from the edges of the frame in pixels
+  pub margin: f32,
+  /// Lines are wrapped at this width, `None` means the frame width minus the margins
+  pub wrap_width: Option<f32>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GradientKind {
+  #[default]
+  Linear,
+  Radial,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Gradient {
+  pub kind: GradientKind,
+  pub start: Srgba,
+  pub end: Srgba,
+  /// Clockwise direction of linear gradients in radians, 0 goes from left to right
+  pub angle: f32,
+}
+
+/// A clip source which renders its frames instead of decoding them. Generators are still
+/// images, the frame doesn't depend on the time.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Generator {
+  Text(TextTitle),
+  Solid { color: Srgba },
+  Gradient(Gradient),
+  SmpteBars,
+  /// The red/green test gradient of `RawImageRef::new_dummy_rgba32`
+  TestGradient,
+}
+
+/// Lays out and rasterizes text with `epaint` on the CPU, the same way egui draws text, see
+/// `assets::asset_ui`
+pub struct TextRenderer {
+  definitions: FontDefinitions,
+  fonts: Fonts,
+  /// CPU copy of the font atlas, kept up to date with the deltas of `fonts`
+  font_image: FontImage,
+}
+
+/// `FrameSource` of a generator. The frame is rendered once and kept until the generator or
+/// the size changes.
+pub struct GeneratorSource {
+  generator: Generator,
+  size: [u32; 2],
+  buffer: Vec<u8>,
+  text_renderer: Option<TextRenderer>,
+  dirty: bool,
+}
+
+
+impl Default for TextTitle {
+  fn default() -> Self {
+    Self {
+      text: String::new(),
+      font: "Proportional".to_string(),
+      size: 64.,
+      color: [1.; 4],
+      outline: None,
+      shadow: None,
+      background: None,
+      halign: HAlign::default(),
+      valign: VAlign::default(),
+      margin: 32.,
+      wrap_width: None,
+    }
+  }
+}
+
+impl Generator {
+  pub fn name(&self) -> &'static str {
+    match self {
+      Generator::Text(_) => "Text",
+      Generator::Solid { .. } => "Solid Color",
+      Generator::Gradient(_) => "Gradient",
+      Generator::SmpteBars => "SMPTE Bars",
+      Generator::TestGradient => "Test Gradient",
+    }
+  }
+
+  /// Renders the generator as 8 bit sRGBA rows without padding. Text needs a `TextRenderer`,
+  /// without one the frame stays transparent.
+  pub fn render(&self, size: [u32; 2], text_renderer: Option<&mut TextRenderer>) -> Vec<u8> {
+    let [w, h] = size.map(|x| x as usize);
+    match self {
+      Generator::Text(title) => match text_renderer {
+        Some(renderer) => renderer.render(title, size),
+        None => vec![0; w*h*4],
+      },
+      Generator::Solid { color } => {
+        let px = srgba_to_u8(*color);
+        px.repeat(w*h)
+      },
+      Generator::Gradient(gradient) => render_gradient(gradient, w, h),
+      Generator::SmpteBars => render_smpte_bars(w, h),
+      Generator::TestGradient => {
+        let mut data = Vec::new();
+        RawImageRef::new_dummy_rgba32(&mut data, w.max(2), h.max(2));
+        data
+      },
+    }
+  }
+}
+
+
+fn srgba_to_u8(color: Srgba) -> [u8; 4] {
+  color.map(|c| (c.clamp(0., 1.)*255.).round() as u8)
+}
+
+/// Mixes in linear light
+fn mix_srgba(a: Srgba, b: Srgba, t: f32) -> Srgba {
+  let t = t.clamp(0., 1.);
+  let mix = |x: f32, y: f32| linear_to_srgb(srgb_to_linear(x) + (srgb_to_linear(y) - srgb_to_linear(x))*t);
+  [mix(a[0], b[0]), mix(a[1], b[1]), mix(a[2], b[2]), a[3] + (b[3] - a[3])*t]
+}
+
+fn render_gradient(gradient: &Gradient, w: usize, h: usize) -> Vec<u8> {
+  let (sin, cos) = gradient.angle.sin_cos();
+  // Projection of the frame onto the direction covers [-extent, extent]
+  let extent = (cos.abs()*w as f32 + sin.abs()*h as f32) / 2.;
+  let radius = ((w*w + h*h) as f32).sqrt() / 2.;
+  let mut data = Vec::with_capacity(w*h*4);
+  for y in 0..h {
+    for x in 0..w {
+      let (dx, dy) = (x as f32 + 0.5 - w as f32/2., y as f32 + 0.5 - h as f32/2.);
+      let t = match gradient.kind {
+        GradientKind::Linear => (dx*cos + dy*sin) / (2.*extent.max(1.)) + 0.5,
+        GradientKind::Radial => (dx*dx + dy*dy).sqrt() / radius.max(1.),
+      };
+      data.extend(srgba_to_u8(mix_srgba(gradient.start, gradient.end, t)));
+    }
+  }
+  data
+}
+
+/// SMPTE EG 1 color bars with 75% bars, the reverse blue bars and the -I/white/+Q/PLUGE row
+fn render_smpte_bars(w: usize, h: usize) -> Vec<u8> {
+  const TOP: [[u8; 3]; 7] = [
+    [191, 191, 191], [191, 191, 0], [0, 191, 191], [0, 191, 0], [191, 0, 191], [191, 0, 0], [0, 0, 191],
+  ];
+  const MIDDLE: [[u8; 3]; 7] = [
+    [0, 0, 191], [19, 19, 19], [191, 0, 191], [19, 19, 19], [0, 191, 191], [19, 19, 19], [191, 191, 191],
+  ];
+  // Widths in 1/12 of a bar: -I, white, +Q, black, PLUGE (below black, black, above black), black
+  const BOTTOM: [([u8; 3], usize); 8] = [
+    ([0, 33, 76], 15), ([255, 255, 255], 15), ([50, 0, 106], 15), ([19, 19, 19], 15),
+    ([9, 9, 9], 4), ([19, 19, 19], 4), ([29, 29, 29], 4), ([19, 19, 19], 12),
+  ];
+  let mut data = Vec::with_capacity(w*h*4);
+  for y in 0..h {
+    let row = y*12 / h.max(1);
+    for x in 0..w {
+      let bar = |n: usize| (x*n / w.max(1)).min(n - 1);
+      let rgb = match row {
+        0..=7 => TOP[bar(7)],
+        8 => MIDDLE[bar(7)],
+        _ => {
+          let mut pos = bar(84);
+          let mut res = BOTTOM[BOTTOM.len() - 1].0;
+          for (rgb, width) in BOTTOM {
+            if pos < width {
+              res = rgb;
+              break;
+            }
+            pos -= width;
+          }
+          res
+        },
+      };
+      data.extend([rgb[0], rgb[1], rgb[2], 255]);
+    }
+  }
+  data
+}
+
+
+impl TextRenderer {
+  pub fn new() -> Self {
+    Self::with_definitions(FontDefinitions::default())
+  }
+
+  pub fn with_definitions(definitions: FontDefinitions) -> Self {
+    // Titles are rendered in pixels, so one point is one pixel
+    let fonts = Fonts::new(1., 8192, definitions.clone());
+    Self { definitions, fonts, font_image: FontImage::new([0, 0]) }
+  }
+
+  /// Adds a font file (TTF or OTF) which titles can use with `name` as their `font`
+  pub fn add_font(&mut self, name: &str, data: Vec<u8>) {
+    let mut definitions = self.definitions.clone();
+    definitions.font_data.insert(name.to_string(), FontData::from_owned(data));
+    definitions.families.insert(FontFamily::Name(name.into()), vec![name.to_string()]);
+    *self = Self::with_definitions(definitions);
+  }
+
+  fn family(&self, name: &str) -> FontFamily {
+    let family = match name {
+      "Proportional" => FontFamily::Proportional,
+      "Monospace" => FontFamily::Monospace,
+      name => FontFamily::Name(name.into()),
+    };
+    if self.definitions.families.contains_key(&family) { family } else { FontFamily::Proportional }
+  }
+
+  fn layout(&mut self, title: &TextTitle, size: [u32; 2]) -> Arc<Galley> {
+    let wrap_width = title.wrap_width.unwrap_or(size[0] as f32 - 2.*title.margin).max(1.);
+    let font_id = FontId::new(title.size.max(1.), self.family(&title.font));
+    let mut job = LayoutJob::simple(title.text.clone(), font_id, Color32::WHITE, wrap_width);
+    job.halign = match title.halign {
+      HAlign::Left => Align::LEFT,
+      HAlign::Center => Align::Center,
+      HAlign::Right => Align::RIGHT,
+    };
+    let galley = self.fonts.layout_job(job);
+
+    // Layout may have added glyphs to the atlas
+    if let Some(delta) = self.fonts.font_image_delta() {
+      if let ImageData::Font(image) = delta.image {
+        match delta.pos {
+          None => self.font_image = image,
+          Some([x0, y0]) => {
+            for y in 0..image.size[1] {
+              for x in 0..image.size[0] {
+                let dst = (y0 + y)*self.font_image.size[0] + x0 + x;
+                self.font_image.pixels[dst] = image.pixels[y*image.size[0] + x];
+              }
+            }
+          },
+        }
+      }
+    }
+    galley
+  }
+
+  /// Coverage of the font atlas at the texel position `uv`
+  fn coverage(&self, uv: [f32; 2]) -> f32 {
+    let [w, h] = self.font_image.size;
+    if w == 0 || h == 0 {
+      return 0.;
+    }
+    let x = (uv[0] - 0.5).clamp(0., (w - 1) as f32);
+    let y = (uv[1] - 0.5).clamp(0., (h - 1) as f32);
+    let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+    let (x1, y1) = ((x0 + 1).min(w - 1), (y0 + 1).min(h - 1));
+    let (tx, ty) = (x - x0 as f32, y - y0 as f32);
+    let px = |x: usize, y: usize| self.font_image.pixels[y*w + x];
+    let top = px(x0, y0) + (px(x1, y0) - px(x0, y0))*tx;
+    let bottom = px(x0, y1) + (px(x1, y1) - px(x0, y1))*tx;
+    top + (bottom - top)*ty
+  }
+
+  /// Draws the glyph meshes of `galley` at `offset` into the linear, premultiplied `canvas`
+  fn draw_galley(&self, canvas: &mut Canvas, galley: &Galley, offset: [f32; 2], color: Srgba) {
+    let color = premultiplied_linear(color);
+    for row in galley.rows.iter() {
+      let mesh = &row.visuals.mesh;
+      for tri in mesh.indices.chunks_exact(3) {
+        let v = [0, 1, 2].map(|i| &mesh.vertices[tri[i] as usize]);
+        let pos = v.map(|v| [v.pos.x + offset[0], v.pos.y + offset[1]]);
+        let uv = v.map(|v| [v.uv.x, v.uv.y]);
+        canvas.fill_triangle(pos, |b| {
+          let u = b[0]*uv[0][0] + b[1]*uv[1][0] + b[2]*uv[2][0];
+          let v = b[0]*uv[0][1] + b[1]*uv[1][1] + b[2]*uv[2][1];
+          let c = self.coverage([u, v]);
+          color.map(|x| x*c)
+        });
+      }
+    }
+  }
+
+  /// Renders `title` into a transparent frame as 8 bit sRGBA rows
+  pub fn render(&mut self, title: &TextTitle, size: [u32; 2]) -> Vec<u8> {
+    let mut canvas = Canvas::new(size[0] as _, size[1] as _);
+    if title.text.is_empty() {
+      return canvas.to_srgba8();
+    }
+    let galley = self.layout(title, size);
+
+    // Place the bounding box of the text according to the alignment
+    let (w, h) = (size[0] as f32, size[1] as f32);
+    let text_size = galley.rect.size();
+    let x = match title.halign {
+      HAlign::Left => title.margin,
+      HAlign::Center => (w - text_size.x) / 2.,
+      HAlign::Right => w - title.margin - text_size.x,
+    };
+    let y = match title.valign {
+      VAlign::Top => title.margin,
+      VAlign::Center => (h - text_size.y) / 2.,
+      VAlign::Bottom => h - title.margin - text_size.y,
+    };
+    let offset = [x - galley.rect.min.x, y - galley.rect.min.y];
+
+    if let Some(BackgroundBox { color, padding }) = title.background {
+      let color = premultiplied_linear(color);
+      canvas.fill_rect([x - padding, y - padding], [x + text_size.x + padding, y + text_size.y + padding], color);
+    }
+    if let Some(Shadow { color, offset: [dx, dy] }) = title.shadow {
+      self.draw_galley(&mut canvas, &galley, [offset[0] + dx, offset[1] + dy], color);
+    }
+    if let Some(Outline { color, width }) = title.outline {
+      // Stamps the text around a circle, which is close enough for the widths titles use
+      let steps = (width.ceil() as usize * 8).clamp(8, 64);
+      for i in 0..steps {
+        let (sin, cos) = (i as f32 / steps as f32 * std::f32::consts::TAU).sin_cos();
+        self.draw_galley(&mut canvas, &galley, [offset[0] + cos*width, offset[1] + sin*width], color);
+      }
+    }
+    self.draw_galley(&mut canvas, &galley, offset, title.color);
+    canvas.to_srgba8()
+  }
+}
+
+impl Default for TextRenderer {
+  fn default() -> Self { Self::new() }
+}
+
+
+fn premultiplied_linear(color: Srgba) -> [f32; 4] {
+  let a = color[3].clamp(0., 1.);
+  [srgb_to_linear(color[0])*a, srgb_to_linear(color[1])*a, srgb_to_linear(color[2])*a, a]
+}
+
+/// Linear, premultiplied RGBA image which shapes are blended onto with "over"
+struct Canvas {
+  width: usize,
+  height: usize,
+  pixels: Vec<[f32; 4]>,
+}
+
+impl Canvas {
+  fn new(width: usize, height: usize) -> Self {
+    Self { width, height, pixels: vec![[0.; 4]; width*height] }
+  }
+
+  fn blend(&mut self, x: usize, y: usize, src: [f32; 4]) {
+    let dst = &mut self.pixels[y*self.width + x];
+    for i in 0..4 {
+      dst[i] = src[i] + dst[i]*(1. - src[3]);
+    }
+  }
+
+  fn fill_rect(&mut self, min: [f32; 2], max: [f32; 2], color: [f32; 4]) {
+    let x0 = min[0].round().clamp(0., self.width as f32) as usize;
+    let x1 = max[0].round().clamp(0., self.width as f32) as usize;
+    let y0 = min[1].round().clamp(0., self.height as f32) as usize;
+    let y1 = max[1].round().clamp(0., self.height as f32) as usize;
+    for y in y0..y1 {
+      for x in x0..x1 {
+        self.blend(x, y, color)
+      }
+    }
+  }
+
+  /// Calls `shade` with the barycentric coordinates of every pixel center inside the triangle
+  fn fill_triangle(&mut self, pos: [[f32; 2]; 3], shade: impl Fn([f32; 3]) -> [f32; 4]) {
+    let [a, b, c] = pos;
+    let area = (b[0] - a[0])*(c[1] - a[1]) - (c[0] - a[0])*(b[1] - a[1]);
+    if area.abs() < f32::EPSILON {
+      return;
+    }
+    let min_x = a[0].min(b[0]).min(c[0]).floor().max(0.) as usize;
+    let max_x = a[0].max(b[0]).max(c[0]).ceil().clamp(0., self.width as f32) as usize;
+    let min_y = a[1].min(b[1]).min(c[1]).floor().max(0.) as usize;
+    let max_y = a[1].max(b[1]).max(c[1]).ceil().clamp(0., self.height as f32) as usize;
+    let edge = |p: [f32; 2], q: [f32; 2], r: [f32; 2]| (q[0] - p[0])*(r[1] - p[1]) - (r[0] - p[0])*(q[1] - p[1]);
+    for y in min_y..max_y {
+      for x in min_x..max_x {
+        let p = [x as f32 + 0.5, y as f32 + 0.5];
+        let bary = [edge(b, c, p) / area, edge(c, a, p) / area, edge(a, b, p) / area];
+        if bary.iter().all(|w| *w >= 0.) {
+          let color = shade(bary);
+          self.blend(x, y, color);
+        }
+      }
+    }
+  }
+
+  fn to_srgba8(&self) -> Vec<u8> {
+    self.pixels.iter().flat_map(|[r, g, b, a]| {
+      let unpremultiply = |c: f32| if *a > 0. { c / a } else { 0. };
+      [
+        (linear_to_srgb(unpremultiply(*r)).clamp(0., 1.)*255.).round() as u8,
+        (linear_to_srgb(unpremultiply(*g)).clamp(0., 1.)*255.).round() as u8,
+        (linear_to_srgb(unpremultiply(*b)).clamp(0., 1.)*255.).round() as u8,
+        (a.clamp(0., 1.)*255.).round() as u8,
+      ]
+    }).collect()
+  }
+}
+
+
+impl GeneratorSource {
+  pub fn new(generator: Generator, size: [u32; 2]) -> Self {
+    Self { generator, size, buffer: Vec::new(), text_renderer: None, dirty: true }
+  }
+
+  pub fn generator(&self) -> &Generator {
+    &self.generator
+  }
+
+  /// Mutable access to the generator, the frame is rendered again on the next `frame_at`
+  pub fn generator_mut(&mut self) -> &mut Generator {
+    self.dirty = true;
+    &mut self.generator
+  }
+
+  pub fn set_size(&mut self, size: [u32; 2]) {
+    if size != self.size {
+      self.size = size;
+      self.dirty = true;
+    }
+  }
+
+  /// Fonts used for text titles, created on first use
+  pub fn text_renderer(&mut self) -> &mut TextRenderer {
+    self.dirty = true;
+    self.text_renderer.get_or_insert_with(TextRenderer::new)
+  }
+}
+
+impl FrameSource for GeneratorSource {
+  fn size(&self) -> [u32; 2] {
+    match self.generator {
+      // new_dummy_rgba32 needs at least two pixels in each direction
+      Generator::TestGradient => self.size.map(|x| x.max(2)),
+      _ => self.size,
+    }
+  }
+
+  fn duration(&self) -> Option<RationalTime> { None }
+
+  fn frame_at(&mut self, _time: RationalTime) -> Result<RawImageRef<'_>, VideoStreamErr> {
+    let [w, h] = self.size();
+    if let Generator::TestGradient = self.generator {
+      return Ok(RawImageRef::new_dummy_rgba32(&mut self.buffer, w as _, h as _));
+    }
+    if self.dirty {
+      let text_renderer = match self.generator {
+        Generator::Text(_) => Some(self.text_renderer.get_or_insert_with(TextRenderer::new)),
+        _ => None,
+      };
+      self.buffer = self.generator.render(self.size, text_renderer);
+      self.dirty = false;
+    }
+    Ok(RawImageRef::new_rgba32(&self.buffer, w as _, h as _))
+  }
+}
@@ -1,8 +1,19 @@
 pub use escher_video as video;
 pub use escher_hierarchy as hierarchy;
+pub use escher_schedule as schedule;
 pub mod clip;
 pub mod ui;
 pub mod wgpustate;
 pub mod util;
 pub mod assets;
+pub mod effects;
+pub mod animation;
+pub mod transition;
+pub mod timeline;
+pub mod generator;
+pub mod audio;
+pub mod export;
+pub mod project;
+pub mod proxy;
 
+pub mod scrub;
@@ -2,7 +2,7 @@
 
 use egui_winit::winit::event_loop::EventLoopBuilder;
 
-use escher::{ui::{self, UIType}, assets::DummyAsset};
+use escher::{ui::{self, UIType}, assets::{DummyAsset, MediaAsset}};
 use escher_hierarchy::Hierarchy;
 
 
@@ -18,7 +18,14 @@ fn main() {
   let main_id = ui_hierarchy.get_toplevel_id();
   let main_ui = ui_hierarchy.access_entity(&main_id).unwrap();
   if let Some(UIType::Main(main_window)) = &mut main_ui.ui_impl {
-    main_window.asset_manager.add(DummyAsset::load_default(&main_ui.ctx)).unwrap()
+    main_window.asset_manager.add(DummyAsset::load_default(&main_ui.ctx)).unwrap();
+    // Media files passed on the command line become assets
+    for path in std::env::args_os().skip(1) {
+      match MediaAsset::load(path.as_ref(), &main_ui.ctx) {
+        Ok(media) => main_window.asset_manager.add(media).unwrap(),
+        Err(err) => eprintln!("Can't load {}: {:?}", path.to_string_lossy(), err),
+      }
+    }
   }
 
   event_loop.run(move |event, window_target, control_flow|
@@ -0,0 +1,78 @@
+use std::{fmt, fs, io, path::Path};
+
+use serde::{Serialize, Deserialize};
+use crate::{effects::EffectRegistry, timeline::Timeline, video::RationalTime};
+
+/// Version of the `.escher` format written by `Project::save`
+pub const PROJECT_VERSION: u32 = 1;
+
+/// Everything saved in a `.escher` file. The file is TOML, times are `[numerator, denominator]`
+/// seconds and effects are stored by name.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Project {
+  pub version: u32,
+  /// Size of the rendered frames
+  pub size: [u32; 2],
+  pub timeline: Timeline,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProjectError {
+  Io(io::ErrorKind),
+  /// The file isn't a valid project, with the reason
+  Parse(String),
+  /// The file was written by a newer version of Escher
+  UnsupportedVersion(u32),
+}
+
+
+impl fmt::Display for ProjectError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ProjectError::Io(kind) => write!(f, "can't access the project: {}", io::Error::from(*kind)),
+      ProjectError::Parse(reason) => write!(f, "invalid project: {}", reason),
+      ProjectError::UnsupportedVersion(version) => write!(f, "project version {} is newer than {}", version, PROJECT_VERSION),
+    }
+  }
+}
+
+impl From<io::Error> for ProjectError {
+  fn from(err: io::Error) -> Self {
+    ProjectError::Io(err.kind())
+  }
+}
+
+impl Project {
+  pub fn new(size: [u32; 2], timeline: Timeline) -> Self {
+    Self { version: PROJECT_VERSION, size, timeline }
+  }
+
+  /// Parses a project and resolves its effects with `registry`. Also returns the names of the
+  /// effects which aren't registered, they pass the image through.
+  pub fn parse(toml: &str, registry: &EffectRegistry) -> Result<(Self, Vec<String>), ProjectError> {
+    let mut project: Self = toml::from_str(toml).map_err(|err| ProjectError::Parse(err.to_string()))?;
+    if project.version > PROJECT_VERSION {
+      return Err(ProjectError::UnsupportedVersion(project.version));
+    }
+    let (rate_num, rate_den) = project.timeline.frame_rate;
+    if RationalTime::from_frame_rate(rate_num, rate_den).is_none() {
+      return Err(ProjectError::Parse(format!("invalid frame rate {}/{}", rate_num, rate_den)));
+    }
+    let missing = project.timeline.resolve_effects(registry);
+    Ok((project, missing))
+  }
+
+  /// See `parse`
+  pub fn load(path: &Path, registry: &EffectRegistry) -> Result<(Self, Vec<String>), ProjectError> {
+    Self::parse(&fs::read_to_string(path)?, registry)
+  }
+
+  pub fn to_toml(&self) -> Result<String, ProjectError> {
+    toml::to_string_pretty(self).map_err(|err| ProjectError::Parse(err.to_string()))
+  }
+
+  pub fn save(&self, path: &Path) -> Result<(), ProjectError> {
+    fs::write(path, self.to_toml()?)?;
+    Ok(())
+  }
+}
@@ -0,0 +1,513 @@
+use std::{
+  collections::{HashMap, hash_map::DefaultHasher},
+  fmt,
+  fs,
+  hash::{Hash, Hasher},
+  path::{Path, PathBuf},
+  sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}},
+  task::Waker,
+};
+
+use crate::{
+  clip::{packed_rgba, FileSource, FrameSource},
+  schedule::{self, BroadcastKind, CallbackSender, Priority, RequestKind, RequestOptions, Response, Scheduler, WorkerJobs},
+  timeline::{ClipSource, Timeline, TrackKind},
+  video::{
+    ffi::AVMediaType, probe::probe, AVPixelFormat, EncoderBuilder, RationalTime, VideoEncoding, VideoStreamErr,
+  },
+};
+
+/// How proxies are encoded. The defaults give small intra-only files which decode fast at any
+/// position.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProxySettings {
+  /// Height of the proxies, smaller sources keep their size
+  pub height: u32,
+  /// An intra-only encoder, e.g. `mjpeg` or `prores_ks`
+  pub encoder: String,
+  pub pix_fmt: AVPixelFormat,
+  pub bit_rate: u64,
+  /// Private options of the encoder, see `VideoEncoding::options`
+  pub options: String,
+  /// Extension of the proxy files, selects the muxer
+  pub extension: String,
+}
+
+/// State of a proxy as shown next to its asset
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProxyStatus {
+  /// Queued or transcoding, with the progress between 0 and 1
+  Running(f32),
+  Ready,
+  Failed(VideoStreamErr),
+  /// The worker panicked while transcoding, see `ProxyJob::panic_message`
+  Crashed,
+  Cancelled,
+}
+
+/// The transcoding of one video stream into a proxy. The job is shared between the UI and the
+/// worker, like `audio::LoudnessJob`.
+pub struct ProxyJob {
+  pub original: PathBuf,
+  pub stream_idx: u32,
+  pub proxy: PathBuf,
+  progress: Mutex<f32>,
+  cancelled: AtomicBool,
+  result: Mutex<Option<Result<(), VideoStreamErr>>>,
+  panic_message: Mutex<Option<String>>,
+}
+
+#[derive(Clone)]
+pub enum ProxyRequest {
+  Generate(Arc<ProxyJob>),
+}
+
+pub type ProxyError = schedule::RequestError<ProxyRequest>;
+
+/// Generates proxies in the background and pairs them with their originals. The viewer shows
+/// `viewer_timeline`, exports always read the originals.
+pub struct ProxyManager {
+  scheduler: Scheduler<ProxyRequest, ()>,
+  running: WorkerJobs<Arc<ProxyJob>>,
+  settings: ProxySettings,
+  cache_dir: PathBuf,
+  /// Proxies by original and stream, generated or being generated
+  proxies: HashMap<(PathBuf, u32), Arc<ProxyJob>>,
+  /// The global toggle, without it the viewer shows the originals too
+  pub use_proxies: bool,
+}
+
+
+/// Where the proxies of a project in `project_dir` are stored
+pub fn cache_dir(project_dir: &Path) -> PathBuf {
+  project_dir.join("escher-cache").join("proxies")
+}
+
+/// Size of the proxy of a `size` source, even for chroma subsampling
+fn proxy_size(size: [u32; 2], max_height: u32) -> [u32; 2] {
+  let [width, height] = size.map(|s| s.max(1) as u64);
+  let even = |x: u64| (x.div_ceil(2) * 2).max(2) as u32;
+  if height <= max_height as u64 {
+    return [even(width), even(height)];
+  }
+  [even(width * max_height as u64 / height), even(max_height as u64)]
+}
+
+
+impl Default for ProxySettings {
+  fn default() -> Self {
+    Self {
+      height: 540,
+      encoder: "mjpeg".to_string(),
+      pix_fmt: AVPixelFormat::AV_PIX_FMT_YUVJ420P,
+      bit_rate: 12_000_000,
+      options: String::new(),
+      extension: "mkv".to_string(),
+    }
+  }
+}
+
+impl ProxyJob {
+  pub fn new(original: PathBuf, stream_idx: u32, proxy: PathBuf) -> Self {
+    Self {
+      original,
+      stream_idx,
+      proxy,
+      progress: Mutex::new(0.),
+      cancelled: AtomicBool::new(false),
+      result: Mutex::new(None),
+      panic_message: Mutex::new(None),
+    }
+  }
+
+  /// A job for a proxy which was generated before
+  fn existing(original: PathBuf, stream_idx: u32, proxy: PathBuf) -> Self {
+    let res = Self::new(original, stream_idx, proxy);
+    *res.progress.lock().unwrap() = 1.;
+    *res.result.lock().unwrap() = Some(Ok(()));
+    res
+  }
+
+  pub fn cancel(&self) {
+    self.cancelled.store(true, Ordering::Relaxed)
+  }
+
+  pub fn is_cancelled(&self) -> bool {
+    self.cancelled.load(Ordering::Relaxed)
+  }
+
+  pub fn status(&self) -> ProxyStatus {
+    match *self.result.lock().unwrap() {
+      Some(Ok(())) => ProxyStatus::Ready,
+      Some(Err(err)) => ProxyStatus::Failed(err),
+      None if self.panic_message.lock().unwrap().is_some() => ProxyStatus::Crashed,
+      None if self.is_cancelled() => ProxyStatus::Cancelled,
+      None => ProxyStatus::Running(*self.progress.lock().unwrap()),
+    }
+  }
+
+  pub fn is_ready(&self) -> bool {
+    self.status() == ProxyStatus::Ready
+  }
+
+  /// What the worker panicked with if the job crashed it
+  pub fn panic_message(&self) -> Option<String> {
+    self.panic_message.lock().unwrap().clone()
+  }
+
+  fn set_progress(&self, progress: f32) -> bool {
+    *self.progress.lock().unwrap() = progress;
+    !self.is_cancelled()
+  }
+
+  /// Transcodes the original on the calling thread. The proxy is written under a temporary name
+  /// and renamed when it is complete, so a proxy file is never partial.
+  pub fn run(&self, settings: &ProxySettings) {
+    let res = match self.transcode(settings) {
+      Ok(true) => Ok(()),
+      Ok(false) => return,
+      Err(err) => {
+        fs::remove_file(self.partial_path(settings)).unwrap_or_default();
+        Err(err)
+      },
+    };
+    *self.result.lock().unwrap() = Some(res);
+  }
+
+  fn partial_path(&self, settings: &ProxySettings) -> PathBuf {
+    self.proxy.with_extension(format!("partial.{}", settings.extension))
+  }
+
+  /// Returns `Ok(false)` if the job was cancelled
+  fn transcode(&self, settings: &ProxySettings) -> Result<bool, VideoStreamErr> {
+    let info = probe(&self.original)?;
+    let stream = info.streams.get(self.stream_idx as usize)
+      .filter(|s| s.media_type == AVMediaType::AVMEDIA_TYPE_VIDEO)
+      .ok_or(VideoStreamErr::StreamNotFound)?;
+    let (frame_rate, frame_duration) = stream.frame_rate
+      .and_then(|(num, den)| Some(((num, den), RationalTime::from_frame_rate(num, den)?)))
+      .unwrap_or(((25, 1), RationalTime::new(1, 25)));
+    let duration = stream.duration.or(info.duration).ok_or(VideoStreamErr::TimeStampOutOfBounds)?;
+    let total_frames = stream.frames.unwrap_or_else(|| {
+      // The last frame may start before the end without filling a whole frame duration
+      let frames = duration.to_frames(frame_rate.0, frame_rate.1);
+      if RationalTime::from_frames(frames, frame_rate.0, frame_rate.1) < duration { frames + 1 } else { frames }
+    }).max(1);
+    let [width, height] = proxy_size([stream.width, stream.height], settings.height);
+
+    let mut source = FileSource::open(&self.original, self.stream_idx, [width, height], frame_duration, Some(duration))?;
+    if let Some(dir) = self.proxy.parent() {
+      fs::create_dir_all(dir).map_err(|_| VideoStreamErr::IO)?;
+    }
+    let partial = self.partial_path(settings);
+    let mut encoder = EncoderBuilder::default()
+      .set_path(&partial).map_err(|_| VideoStreamErr::IO)?
+      .set_video(VideoEncoding {
+        encoder: settings.encoder.clone(),
+        width,
+        height,
+        pix_fmt: settings.pix_fmt,
+        frame_rate,
+        bit_rate: settings.bit_rate,
+        options: settings.options.clone(),
+      })
+      .set_thread_to_all()
+      .finish()?;
+
+    for frame in 0..total_frames {
+      let time = RationalTime::from_frames(frame, frame_rate.0, frame_rate.1);
+      let rgba = match source.frame_at(time) {
+        Ok(frm) => packed_rgba(&frm),
+        // The container's duration may be longer than the stream
+        Err(VideoStreamErr::EOF) => break,
+        Err(err) => return Err(err),
+      };
+      encoder.write_rgba(&rgba)?;
+      if !self.set_progress((frame + 1) as f32 / total_frames as f32) {
+        drop(encoder);
+        fs::remove_file(&partial).unwrap_or_default();
+        return Ok(false);
+      }
+    }
+    encoder.finish()?;
+    fs::rename(&partial, &self.proxy).map_err(|_| VideoStreamErr::IO)?;
+    Ok(true)
+  }
+}
+
+impl fmt::Debug for ProxyJob {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("ProxyJob")
+      .field("original", &self.original)
+      .field("stream_idx", &self.stream_idx)
+      .field("proxy", &self.proxy)
+      .field("status", &self.status())
+      .finish()
+  }
+}
+
+impl fmt::Debug for ProxyRequest {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ProxyRequest::Generate(job) => f.debug_tuple("Generate").field(&job.original).finish(),
+    }
+  }
+}
+
+impl ProxyManager {
+  /// Stores the proxies in `cache_dir`, see `cache_dir`. Proxies found there are reused.
+  pub fn new(num_workers: usize, cache_dir: PathBuf, settings: ProxySettings) -> Self {
+    let worker_settings = settings.clone();
+    let running = WorkerJobs::default();
+    let worker_running = running.clone();
+    let mut scheduler = Scheduler::new(num_workers, move |idx| {
+      let (settings, running) = (worker_settings.clone(), worker_running.clone());
+      move |request: ProxyRequest, _: RequestKind, _: &mut CallbackSender<()>| {
+        let ProxyRequest::Generate(job) = request;
+        if !job.is_cancelled() {
+          running.run(idx, job.clone(), || job.run(&settings));
+        }
+        Response::Ok(())
+      }
+    });
+    scheduler.set_respawn(true);
+    scheduler.register_metrics("Proxies", |_| "Generate");
+    Self { scheduler, running, settings, cache_dir, proxies: HashMap::new(), use_proxies: true }
+  }
+
+  /// Path of the proxy of a stream. The name depends on the original's path, size and
+  /// modification time and on the settings, so changed originals get new proxies.
+  pub fn proxy_path(&self, original: &Path, stream_idx: u32) -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    fs::canonicalize(original).unwrap_or_else(|_| original.to_path_buf()).hash(&mut hasher);
+    stream_idx.hash(&mut hasher);
+    if let Ok(metadata) = fs::metadata(original) {
+      metadata.len().hash(&mut hasher);
+      metadata.modified().ok().hash(&mut hasher);
+    }
+    (self.settings.height, &self.settings.encoder, self.settings.bit_rate, &self.settings.options).hash(&mut hasher);
+    let stem = original.file_stem().map_or("proxy".into(), |stem| stem.to_string_lossy());
+    self.cache_dir.join(format!("{}_{}_{:016x}.{}", stem, stream_idx, hasher.finish(), self.settings.extension))
+  }
+
+  /// Queues the proxy of a video stream unless it exists or is being generated. Failed, crashed
+  /// and cancelled proxies are queued again.
+  pub fn generate(&mut self, original: &Path, stream_idx: u32) -> Result<Arc<ProxyJob>, ProxyError> {
+    let key = (original.to_path_buf(), stream_idx);
+    if let Some(job) = self.proxies.get(&key) {
+      if matches!(job.status(), ProxyStatus::Running(_) | ProxyStatus::Ready) {
+        return Ok(job.clone());
+      }
+    }
+    let proxy = self.proxy_path(original, stream_idx);
+    let job = if proxy.exists() {
+      Arc::new(ProxyJob::existing(key.0.clone(), stream_idx, proxy))
+    } else {
+      let job = Arc::new(ProxyJob::new(key.0.clone(), stream_idx, proxy));
+      let options = RequestOptions::new(Priority::Background);
+      self.scheduler.request_with(ProxyRequest::Generate(job.clone()), BroadcastKind::Any, options)?;
+      job
+    };
+    self.proxies.insert(key, job.clone());
+    Ok(job)
+  }
+
+  /// Cancels the generation and deletes the proxy of a stream
+  pub fn remove(&mut self, original: &Path, stream_idx: u32) {
+    if let Some(job) = self.proxies.remove(&(original.to_path_buf(), stream_idx)) {
+      job.cancel();
+      fs::remove_file(&job.proxy).unwrap_or_default();
+    }
+  }
+
+  pub fn job(&self, original: &Path, stream_idx: u32) -> Option<&Arc<ProxyJob>> {
+    self.proxies.get(&(original.to_path_buf(), stream_idx))
+  }
+
+  pub fn jobs(&self) -> impl Iterator<Item=&Arc<ProxyJob>> {
+    self.proxies.values()
+  }
+
+  pub fn is_busy(&self) -> bool {
+    self.proxies.values().any(|job| matches!(job.status(), ProxyStatus::Running(_)))
+  }
+
+  /// The ready proxy of a stream, regardless of `use_proxies`
+  pub fn proxy_for(&self, original: &Path, stream_idx: u32) -> Option<&Path> {
+    self.job(original, stream_idx).filter(|job| job.is_ready()).map(|job| job.proxy.as_path())
+  }
+
+  /// What the viewer decodes for `source`: the proxy if there is one and proxies are used
+  pub fn viewer_source(&self, source: &ClipSource) -> ClipSource {
+    match source {
+      ClipSource::File { path, stream_idx } if self.use_proxies => match self.proxy_for(path, *stream_idx) {
+        // Proxies only have the video stream
+        Some(proxy) => ClipSource::File { path: proxy.to_path_buf(), stream_idx: 0 },
+        None => source.clone(),
+      },
+      _ => source.clone(),
+    }
+  }
+
+  /// `timeline` with the video clips reading from their proxies, see `viewer_source`. Audio
+  /// clips keep their originals.
+  pub fn viewer_timeline(&self, timeline: &Timeline) -> Timeline {
+    let mut res = timeline.clone();
+    if self.use_proxies {
+      res.map_sources(TrackKind::Video, |source| self.viewer_source(source));
+    }
+    res
+  }
+
+  /// Woken when a proxy is done, progress in between doesn't wake it
+  pub fn set_waker(&self, waker: Option<Waker>) {
+    self.scheduler.set_waker(waker)
+  }
+
+  /// Call regularly to keep track of the workers' state. The job of a worker that panicked
+  /// crashes and its partial proxy is deleted, a new worker takes the next job.
+  pub fn handle_responses(&mut self) {
+    let (running, settings) = (&self.running, &self.settings);
+    self.scheduler.handle_respones(|resp, _| {
+      if let Response::WorkerDied { worker, payload } = resp {
+        let message = schedule::panic_message(payload.as_ref()).to_string();
+        match running.take(worker) {
+          Some(job) => {
+            fs::remove_file(job.partial_path(settings)).unwrap_or_default();
+            *job.panic_message.lock().unwrap() = Some(message);
+          },
+          None => eprintln!("Proxy worker {} died: {}", worker, message),
+        }
+      }
+      Ok::<(), ()>(())
+    }).unwrap_or_default()
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use crate::timeline::{TimelineClip, Track};
+  use super::*;
+
+  /// An empty directory for the test `name`
+  fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("escher_proxy_{}_{}", name, std::process::id()));
+    fs::remove_dir_all(&dir).unwrap_or_default();
+    fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  fn manager(dir: &Path, settings: ProxySettings) -> ProxyManager {
+    ProxyManager::new(1, cache_dir(dir), settings)
+  }
+
+  fn file_source(path: &Path, stream_idx: u32) -> ClipSource {
+    ClipSource::File { path: path.to_path_buf(), stream_idx }
+  }
+
+  #[test]
+  fn proxy_sizes_are_even() {
+    assert_eq!(proxy_size([1920, 1080], 540), [960, 540]);
+    assert_eq!(proxy_size([640, 360], 540), [640, 360]);
+    assert_eq!(proxy_size([1281, 719], 540), [962, 540]);
+    assert_eq!(proxy_size([101, 51], 540), [102, 52]);
+    assert_eq!(proxy_size([0, 0], 540), [2, 2]);
+  }
+
+  #[test]
+  fn proxy_paths_depend_on_source_and_settings() {
+    let dir = test_dir("paths");
+    let original = dir.join("shot.mov");
+    fs::write(&original, b"frames").unwrap();
+    let proxies = manager(&dir, ProxySettings::default());
+    let path = proxies.proxy_path(&original, 0);
+    assert_eq!(path.parent(), Some(cache_dir(&dir).as_path()));
+    let name = path.file_name().unwrap().to_str().unwrap();
+    assert!(name.starts_with("shot_0_") && name.ends_with(".mkv"), "{}", name);
+    assert_eq!(proxies.proxy_path(&original, 0), path);
+    assert_ne!(proxies.proxy_path(&original, 1), path);
+    assert_ne!(proxies.proxy_path(&dir.join("other.mov"), 0), path);
+    // Equal paths to the same file share the proxy
+    assert_eq!(proxies.proxy_path(&dir.join(".").join("shot.mov"), 0), path);
+
+    let smaller = manager(&dir, ProxySettings { height: 360, ..ProxySettings::default() });
+    assert_ne!(smaller.proxy_path(&original, 0), path);
+    let prores = ProxySettings { encoder: "prores_ks".to_string(), extension: "mov".to_string(), ..ProxySettings::default() };
+    assert!(manager(&dir, prores).proxy_path(&original, 0).to_str().unwrap().ends_with(".mov"));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn changed_originals_get_new_proxies() {
+    let dir = test_dir("stale");
+    let original = dir.join("shot.mov");
+    fs::write(&original, b"frames").unwrap();
+    let mut proxies = manager(&dir, ProxySettings::default());
+    let path = proxies.proxy_path(&original, 0);
+    fs::create_dir_all(cache_dir(&dir)).unwrap();
+    fs::write(&path, b"proxy").unwrap();
+
+    // An existing proxy is reused without transcoding
+    let job = proxies.generate(&original, 0).unwrap();
+    assert_eq!(job.status(), ProxyStatus::Ready);
+    assert_eq!(job.proxy, path);
+    assert_eq!(proxies.proxy_for(&original, 0), Some(path.as_path()));
+    assert!(!proxies.is_busy());
+
+    fs::write(&original, b"re-rendered frames").unwrap();
+    let new_path = proxies.proxy_path(&original, 0);
+    assert_ne!(new_path, path);
+    assert!(!new_path.exists());
+
+    proxies.remove(&original, 0);
+    assert!(!path.exists());
+    assert_eq!(proxies.proxy_for(&original, 0), None);
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn viewer_reads_ready_proxies() {
+    let dir = test_dir("viewer");
+    let original = dir.join("shot.mov");
+    fs::write(&original, b"frames").unwrap();
+    let mut proxies = manager(&dir, ProxySettings::default());
+    let path = proxies.proxy_path(&original, 1);
+    fs::create_dir_all(cache_dir(&dir)).unwrap();
+    fs::write(&path, b"proxy").unwrap();
+    proxies.generate(&original, 1).unwrap();
+
+    let mut timeline = Timeline::new((25, 1));
+    for kind in [TrackKind::Video, TrackKind::Audio] {
+      let mut track = Track::new("T", kind);
+      track.add_clip(TimelineClip::new(file_source(&original, 1), RationalTime::ZERO, RationalTime::from_secs(1))).unwrap();
+      timeline.tracks.push(track);
+    }
+    let sources = |timeline: &Timeline| -> Vec<ClipSource> {
+      timeline.tracks.iter().flat_map(|t| t.clips()).map(|c| c.source.clone()).collect()
+    };
+
+    // Proxies only have one stream
+    assert_eq!(proxies.viewer_source(&file_source(&original, 1)), file_source(&path, 0));
+    assert_eq!(proxies.viewer_source(&file_source(&original, 0)), file_source(&original, 0));
+    assert_eq!(sources(&proxies.viewer_timeline(&timeline)), [file_source(&path, 0), file_source(&original, 1)]);
+
+    proxies.use_proxies = false;
+    assert_eq!(proxies.viewer_source(&file_source(&original, 1)), file_source(&original, 1));
+    assert_eq!(sources(&proxies.viewer_timeline(&timeline)), sources(&timeline));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn job_status() {
+    let job = ProxyJob::new(PathBuf::from("a.mov"), 0, PathBuf::from("a.mkv"));
+    assert_eq!(job.status(), ProxyStatus::Running(0.));
+    assert!(job.set_progress(0.5));
+    assert_eq!(job.status(), ProxyStatus::Running(0.5));
+    job.cancel();
+    assert!(!job.set_progress(0.6));
+    assert_eq!(job.status(), ProxyStatus::Cancelled);
+    assert_eq!(job.partial_path(&ProxySettings::default()), Path::new("a.partial.mkv"));
+    assert!(ProxyJob::existing(PathBuf::from("a.mov"), 0, PathBuf::from("a.mkv")).is_ready());
+  }
+}
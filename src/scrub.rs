@@ -0,0 +1,235 @@
+use std::{
+  path::{Path, PathBuf},
+  sync::{Arc, Mutex},
+  task::Waker,
+};
+
+use crate::{
+  clip::{packed_rgba, ClipFrames, FileSource, FrameSource},
+  export::ExportFrames,
+  schedule::{self, CallbackSender, Coalesce, RequestKind, Response, Scheduler},
+  timeline::{ClipSource, Timeline, TimelineClip, TrackKind, TrackSample},
+  video::{probe::probe, FrameCache, RationalTime, RawImageRef, VideoStreamErr},
+};
+
+/// A frame decoded for the viewer
+#[derive(Clone, Debug)]
+pub struct ScrubFrame {
+  /// The scrubbed source
+  pub path: PathBuf,
+  pub stream_idx: u32,
+  /// Timeline time of the request
+  pub time: RationalTime,
+  /// Source time of the frame, the keyframe's time for imprecise seeks
+  pub frame_time: RationalTime,
+  /// Whether this is the exact frame at `time` and not only a keyframe near it
+  pub precise: bool,
+  pub size: [u32; 2],
+  /// Tightly packed RGBA
+  pub rgba: Vec<u8>,
+}
+
+#[derive(Clone, Debug)]
+pub enum ScrubRequest {
+  Seek { path: PathBuf, stream_idx: u32, time: RationalTime, source_time: RationalTime, precise: bool },
+}
+
+pub type ScrubError = schedule::RequestError<ScrubRequest>;
+
+/// Why no frame was decoded for a seek
+#[derive(Clone, Debug)]
+pub enum FrameError {
+  Decode(VideoStreamErr),
+  /// The worker panicked with this message, it is replaced for the next seek
+  WorkerPanicked(String),
+}
+
+/// Decodes the viewer's frames in the background. While the playhead is dragged only keyframes
+/// are decoded and a burst of seeks collapses into the latest one, on release the exact frame
+/// follows.
+pub struct Scrubber {
+  scheduler: Scheduler<ScrubRequest, ()>,
+  seeks: Coalesce,
+  frame: Arc<Mutex<Option<Result<ScrubFrame, FrameError>>>>,
+  dragging: bool,
+  /// Released, but the exact frame didn't arrive yet
+  refining: bool,
+}
+
+/// State of the worker thread. The decoder stays open until another source is scrubbed.
+struct ScrubWorker {
+  size: [u32; 2],
+  cache: Arc<FrameCache>,
+  frame: Arc<Mutex<Option<Result<ScrubFrame, FrameError>>>>,
+  source: Option<((PathBuf, u32), FileSource)>,
+}
+
+/// The viewer's frames: the scrubbed frame for clips of its source, every other clip is decoded
+/// like in the export
+pub struct ViewerFrames {
+  pub frames: ExportFrames,
+  /// Latest frame from the `Scrubber`, `None` while playing
+  pub scrubbed: Option<ScrubFrame>,
+}
+
+
+/// The topmost file clip of an unmuted video track at `time` and the source time there. Inside
+/// transitions the clip that covers more of the frame wins.
+pub fn source_at(timeline: &Timeline, time: RationalTime) -> Option<(&ClipSource, RationalTime)> {
+  timeline.tracks.iter()
+    .rev()
+    .filter(|track| track.kind == TrackKind::Video && !track.muted)
+    .find_map(|track| {
+      let (clip, source_time) = match track.sample_at(time) {
+        TrackSample::Gap => return None,
+        TrackSample::Clip { clip, source_time, .. } => (clip, source_time),
+        TrackSample::Transition { from, to, progress, .. } => if progress < 0.5 { (from.0, from.1) } else { (to.0, to.1) },
+      };
+      matches!(clip.source, ClipSource::File { .. }).then_some((&clip.source, source_time))
+    })
+}
+
+
+impl ScrubWorker {
+  fn handle(&mut self, request: ScrubRequest, kind: &RequestKind) {
+    let ScrubRequest::Seek { path, stream_idx, time, source_time, precise } = request;
+    let res = self.seek(&path, stream_idx, time, source_time, precise).map_err(FrameError::Decode);
+    // A newer seek is queued, its frame replaces this one anyway
+    if !kind.is_superseded() {
+      *self.frame.lock().unwrap() = Some(res);
+    }
+  }
+
+  fn seek(&mut self, path: &Path, stream_idx: u32, time: RationalTime, source_time: RationalTime, precise: bool) -> Result<ScrubFrame, VideoStreamErr> {
+    let size = self.size;
+    let source = self.open(path, stream_idx)?;
+    let (frame_time, frame) = if precise {
+      (source_time, source.frame_at(source_time)?)
+    } else {
+      source.keyframe_at(source_time)?
+    };
+    let rgba = packed_rgba(&frame);
+    Ok(ScrubFrame { path: path.to_path_buf(), stream_idx, time, frame_time, precise, size, rgba })
+  }
+
+  fn open(&mut self, path: &Path, stream_idx: u32) -> Result<&mut FileSource, VideoStreamErr> {
+    let key = (path.to_path_buf(), stream_idx);
+    let source = match self.source.take() {
+      Some((open_key, source)) if open_key == key => source,
+      _ => {
+        let info = probe(path)?;
+        let stream = info.streams.iter()
+          .find(|stream| stream.index == stream_idx)
+          .ok_or(VideoStreamErr::StreamNotFound)?;
+        let frame_duration = stream.frame_duration().unwrap_or(RationalTime::new(1, 25));
+        FileSource::open(path, stream_idx, self.size, frame_duration, stream.duration)?
+          .with_cache(self.cache.clone())
+      },
+    };
+    Ok(&mut self.source.insert((key, source)).1)
+  }
+}
+
+impl ClipFrames for ViewerFrames {
+  fn frame(&mut self, clip: &TimelineClip, source_time: RationalTime) -> Option<RawImageRef<'_>> {
+    match (&clip.source, &self.scrubbed) {
+      (ClipSource::File { path, stream_idx }, Some(frame)) if frame.path == *path && frame.stream_idx == *stream_idx => {
+        let [width, height] = frame.size;
+        Some(RawImageRef::new_rgba32(&frame.rgba, width as _, height as _))
+      },
+      _ => self.frames.frame(clip, source_time),
+    }
+  }
+}
+
+impl Scrubber {
+  /// Frames are scaled to `size` and shared with other decoders through `cache`
+  pub fn new(size: [u32; 2], cache: Arc<FrameCache>) -> Self {
+    let frame = Arc::new(Mutex::new(None));
+    let worker_frame = frame.clone();
+    let mut scheduler = Scheduler::new(1, move |_| {
+      let mut worker = ScrubWorker { size, cache: cache.clone(), frame: worker_frame.clone(), source: None };
+      move |request: ScrubRequest, kind: RequestKind, _: &mut CallbackSender<()>| {
+        worker.handle(request, &kind);
+        Response::Ok(())
+      }
+    });
+    // Seeks always go to worker 0, a panicked one is replaced in place
+    scheduler.set_respawn(true);
+    scheduler.register_metrics("Scrub", |_| "Seek");
+    Self { scheduler, seeks: Coalesce::new(), frame, dragging: false, refining: false }
+  }
+
+  fn request(&mut self, source: &ClipSource, time: RationalTime, source_time: RationalTime, precise: bool) -> Result<(), ScrubError> {
+    match source {
+      ClipSource::File { path, stream_idx } => {
+        let request = ScrubRequest::Seek { path: path.clone(), stream_idx: *stream_idx, time, source_time, precise };
+        self.scheduler.request_latest(request, &self.seeks, 0).map(|_| ())
+      },
+      // Generators render fast enough on the GPU, there is nothing to decode
+      ClipSource::Generator(_) => {
+        self.refining = false;
+        self.seeks.cancel_pending();
+        Ok(())
+      },
+    }
+  }
+
+  /// Shows the keyframe near `source_time` while the playhead is dragged
+  pub fn drag(&mut self, source: &ClipSource, time: RationalTime, source_time: RationalTime) -> Result<(), ScrubError> {
+    self.dragging = true;
+    self.request(source, time, source_time, false)
+  }
+
+  /// Refines to the exact frame, e.g. when the playhead is released or clicked
+  pub fn release(&mut self, source: &ClipSource, time: RationalTime, source_time: RationalTime) -> Result<(), ScrubError> {
+    self.dragging = false;
+    self.refining = true;
+    self.request(source, time, source_time, true)
+  }
+
+  /// Drops the seeks that weren't handled yet, e.g. when the playhead moves into a gap
+  pub fn cancel(&mut self) {
+    self.dragging = false;
+    self.refining = false;
+    self.seeks.cancel_pending();
+  }
+
+  pub fn is_dragging(&self) -> bool {
+    self.dragging
+  }
+
+  /// Whether frames are still to come, i.e. while dragging and until the exact frame arrived
+  pub fn is_busy(&self) -> bool {
+    self.dragging || self.refining
+  }
+
+  /// The latest decoded frame or why decoding it failed, once
+  pub fn take_frame(&mut self) -> Option<Result<ScrubFrame, FrameError>> {
+    let res = self.frame.lock().unwrap().take();
+    if matches!(res, Some(Ok(ScrubFrame { precise: true, .. })) | Some(Err(_))) {
+      self.refining = false;
+    }
+    res
+  }
+
+  /// Woken when a frame is decoded, e.g. to redraw the viewer
+  pub fn set_waker(&self, waker: Option<Waker>) {
+    self.scheduler.set_waker(waker)
+  }
+
+  /// Call regularly to keep track of the worker's state. A panic of the worker is reported by
+  /// `take_frame`.
+  pub fn handle_responses(&mut self) {
+    let (refining, frame) = (&mut self.refining, &self.frame);
+    self.scheduler.handle_respones(|resp, _| {
+      if let Response::WorkerDied { payload, .. } = resp {
+        // No frame is coming for the seek it handled
+        *refining = false;
+        let message = schedule::panic_message(payload.as_ref()).to_string();
+        *frame.lock().unwrap() = Some(Err(FrameError::WorkerPanicked(message)));
+      }
+      Ok::<(), ()>(())
+    }).unwrap_or_default()
+  }
+}
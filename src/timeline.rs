@@ -0,0 +1,619 @@
+use std::{fmt, path::PathBuf};
+
+use serde::{Serialize, Deserialize};
+
+use crate::{
+  video::RationalTime,
+  clip::ClipProperties,
+  generator::Generator,
+  effects::{EffectRegistry, EffectStack},
+  transition::Transition,
+  wgpustate::compositor::Layer,
+};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ClipSource {
+  File { path: PathBuf, stream_idx: u32 },
+  /// Rendered instead of decoded, see `generator::Generator`
+  Generator(Generator),
+}
+
+/// A clip placed on a track. It shows the source from `source_in` on for `duration`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TimelineClip {
+  pub source: ClipSource,
+  /// Position on the timeline
+  pub start: RationalTime,
+  pub duration: RationalTime,
+  pub source_in: RationalTime,
+  /// Length of the source media, `None` if it is unbounded. Limits the handles available to
+  /// transitions.
+  pub source_duration: Option<RationalTime>,
+  pub properties: ClipProperties,
+  pub effects: EffectStack,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TrackKind {
+  Video,
+  Audio,
+}
+
+/// Clips and transitions of a track in timeline order. A transition always sits between the two
+/// clips it connects, which touch at the cut.
+// Most items are clips, boxing them would only add an indirection
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum TrackItem {
+  Clip(TimelineClip),
+  Transition(Transition),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Track {
+  pub name: String,
+  pub kind: TrackKind,
+  pub muted: bool,
+  /// While any audio track is soloed, only soloed tracks are audible
+  pub solo: bool,
+  /// Linear gain of audio tracks, applied after the clip volumes
+  pub gain: f32,
+  /// Stereo position of audio tracks from -1 (left) to 1 (right)
+  pub pan: f32,
+  items: Vec<TrackItem>,
+}
+
+/// Tracks are ordered bottom to top, i.e. the first video track is the lowest layer
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Timeline {
+  /// Frames per second as `(numerator, denominator)`
+  pub frame_rate: (i64, i64),
+  pub tracks: Vec<Track>,
+}
+
+/// What a track shows at a point in time. Source times are relative to the start of the media,
+/// clip times are clip-local (for keyframes) and may leave the clip's range inside transitions.
+#[derive(Clone, Copy, Debug)]
+pub enum TrackSample<'a> {
+  Gap,
+  Clip { clip: &'a TimelineClip, source_time: RationalTime, clip_time: RationalTime },
+  Transition {
+    from: (&'a TimelineClip, RationalTime, RationalTime),
+    to: (&'a TimelineClip, RationalTime, RationalTime),
+    transition: &'a Transition,
+    progress: f32,
+  },
+}
+
+/// A part of a source which has to be decoded, see `Timeline::sources_in`
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceRange<'a> {
+  pub track_idx: usize,
+  pub source: &'a ClipSource,
+  pub from: RationalTime,
+  pub to: RationalTime,
+}
+
+/// An audio source that is audible at a point in time together with its gain from clip volume and
+/// crossfades
+#[derive(Clone, Copy, Debug)]
+pub struct AudioSample<'a> {
+  pub track_idx: usize,
+  pub clip: &'a TimelineClip,
+  pub source_time: RationalTime,
+  pub gain: f32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TimelineError {
+  /// The clip would overlap with the item at the given index
+  Overlap(usize),
+  InvalidDuration,
+  /// There are no two touching clips at the cut
+  NoCut(RationalTime),
+  /// One of the clips doesn't have enough media beyond its edit point
+  NotEnoughHandle { needed: RationalTime, available: RationalTime },
+  /// The transition would reach past the other end of a clip or into another transition
+  TransitionOverlap,
+}
+
+
+impl ClipSource {
+  /// Generators don't go through the decoder
+  pub fn is_decoded(&self) -> bool {
+    matches!(self, ClipSource::File { .. })
+  }
+}
+
+impl TimelineClip {
+  pub fn new(source: ClipSource, start: RationalTime, duration: RationalTime) -> Self {
+    Self {
+      source,
+      start,
+      duration,
+      source_in: RationalTime::ZERO,
+      source_duration: None,
+      properties: ClipProperties::default(),
+      effects: EffectStack::default(),
+    }
+  }
+
+  pub fn end(&self) -> RationalTime {
+    self.start + self.duration
+  }
+
+  pub fn source_time(&self, time: RationalTime) -> RationalTime {
+    self.source_in + (time - self.start)
+  }
+
+  pub fn clip_time(&self, time: RationalTime) -> RationalTime {
+    time - self.start
+  }
+
+  /// Media available before the in point
+  pub fn head_handle(&self) -> RationalTime {
+    self.source_in
+  }
+
+  /// Media available after the out point, `None` if it is unbounded
+  pub fn tail_handle(&self) -> Option<RationalTime> {
+    self.source_duration.map(|d| d - (self.source_in + self.duration))
+  }
+}
+
+
+impl Track {
+  pub fn new(name: impl Into<String>, kind: TrackKind) -> Self {
+    Self { name: name.into(), kind, muted: false, solo: false, gain: 1., pan: 0., items: Vec::new() }
+  }
+
+  pub fn items(&self) -> &[TrackItem] {
+    &self.items
+  }
+
+  pub fn clips(&self) -> impl Iterator<Item=&TimelineClip> + '_ {
+    self.items.iter().filter_map(|item| match item {
+      TrackItem::Clip(c) => Some(c),
+      TrackItem::Transition(_) => None,
+    })
+  }
+
+  /// Clip at `idx` of `items` mutably
+  pub fn clip_mut(&mut self, idx: usize) -> Option<&mut TimelineClip> {
+    match self.items.get_mut(idx) {
+      Some(TrackItem::Clip(c)) => Some(c),
+      _ => None,
+    }
+  }
+
+  /// Adds `clip` in timeline order and returns its index in `items`
+  pub fn add_clip(&mut self, clip: TimelineClip) -> Result<usize, TimelineError> {
+    if clip.duration <= RationalTime::ZERO {
+      return Err(TimelineError::InvalidDuration);
+    }
+    let mut idx = self.items.len();
+    for (i, item) in self.items.iter().enumerate() {
+      if let TrackItem::Clip(c) = item {
+        if c.start < clip.end() && clip.start < c.end() {
+          return Err(TimelineError::Overlap(i));
+        }
+        if clip.end() <= c.start {
+          idx = i;
+          break;
+        }
+      }
+    }
+    self.items.insert(idx, TrackItem::Clip(clip));
+    Ok(idx)
+  }
+
+  /// Removes the clip at `idx` of `items` together with its transitions
+  pub fn remove_clip(&mut self, idx: usize) -> Option<TimelineClip> {
+    if !matches!(self.items.get(idx), Some(TrackItem::Clip(_))) {
+      return None;
+    }
+    if matches!(self.items.get(idx + 1), Some(TrackItem::Transition(_))) {
+      self.items.remove(idx + 1);
+    }
+    let clip = match self.items.remove(idx) {
+      TrackItem::Clip(c) => c,
+      TrackItem::Transition(_) => unreachable!(),
+    };
+    if idx > 0 && matches!(self.items.get(idx - 1), Some(TrackItem::Transition(_))) {
+      self.items.remove(idx - 1);
+    }
+    Some(clip)
+  }
+
+  /// Places `transition` on the cut at `cut`. The clips ending and starting there need enough
+  /// handles, see `Transition`.
+  pub fn add_transition(&mut self, cut: RationalTime, transition: Transition) -> Result<usize, TimelineError> {
+    if transition.duration <= RationalTime::ZERO {
+      return Err(TimelineError::InvalidDuration);
+    }
+    let idx = self.items.windows(2)
+      .position(|w| matches!(w, [TrackItem::Clip(a), TrackItem::Clip(b)] if a.end() == cut && b.start == cut))
+      .ok_or(TimelineError::NoCut(cut))?;
+    let (before, after) = transition.split();
+    let (from, to) = match (&self.items[idx], &self.items[idx + 1]) {
+      (TrackItem::Clip(a), TrackItem::Clip(b)) => (a, b),
+      _ => unreachable!(),
+    };
+
+    if let Some(available) = from.tail_handle() {
+      if available < after {
+        return Err(TimelineError::NotEnoughHandle { needed: after, available });
+      }
+    }
+    if to.head_handle() < before {
+      return Err(TimelineError::NotEnoughHandle { needed: before, available: to.head_handle() });
+    }
+
+    // The transition must not reach into transitions at the other ends of both clips
+    let (start, end) = transition.range(cut);
+    let from_start = match idx.checked_sub(1).map(|i| &self.items[i]) {
+      Some(TrackItem::Transition(t)) => t.range(from.start).1,
+      _ => from.start,
+    };
+    let to_end = match self.items.get(idx + 2) {
+      Some(TrackItem::Transition(t)) => t.range(to.end()).0,
+      _ => to.end(),
+    };
+    if start < from_start || end > to_end {
+      return Err(TimelineError::TransitionOverlap);
+    }
+
+    self.items.insert(idx + 1, TrackItem::Transition(transition));
+    Ok(idx + 1)
+  }
+
+  pub fn remove_transition(&mut self, idx: usize) -> Option<Transition> {
+    match self.items.get(idx) {
+      Some(TrackItem::Transition(_)) => match self.items.remove(idx) {
+        TrackItem::Transition(t) => Some(t),
+        TrackItem::Clip(_) => unreachable!(),
+      },
+      _ => None,
+    }
+  }
+
+  /// Transitions with the clips they connect and their range on the timeline
+  fn transitions(&self) -> impl Iterator<Item=(&TimelineClip, &Transition, &TimelineClip, (RationalTime, RationalTime))> + '_ {
+    self.items.windows(3).filter_map(|w| match w {
+      [TrackItem::Clip(a), TrackItem::Transition(t), TrackItem::Clip(b)] => Some((a, t, b, t.range(a.end()))),
+      _ => None,
+    })
+  }
+
+  pub fn sample_at(&self, time: RationalTime) -> TrackSample<'_> {
+    for (from, transition, to, (start, end)) in self.transitions() {
+      if start <= time && time < end {
+        return TrackSample::Transition {
+          from: (from, from.source_time(time), from.clip_time(time)),
+          to: (to, to.source_time(time), to.clip_time(time)),
+          transition,
+          progress: time.fraction_between(start, end) as f32,
+        };
+      }
+    }
+    match self.clips().find(|c| c.start <= time && time < c.end()) {
+      Some(clip) => TrackSample::Clip { clip, source_time: clip.source_time(time), clip_time: clip.clip_time(time) },
+      None => TrackSample::Gap,
+    }
+  }
+
+  /// Time ranges of clips extended by the parts of their transitions
+  fn extended_ranges(&self) -> Vec<(&TimelineClip, RationalTime, RationalTime)> {
+    let mut ranges: Vec<_> = self.clips().map(|c| (c, c.start, c.end())).collect();
+    for (from, _, to, (start, end)) in self.transitions() {
+      for r in ranges.iter_mut() {
+        if std::ptr::eq(r.0, from) {
+          r.2 = r.2.max(end);
+        }
+        if std::ptr::eq(r.0, to) {
+          r.1 = r.1.min(start);
+        }
+      }
+    }
+    ranges
+  }
+}
+
+
+impl Timeline {
+  pub fn new(frame_rate: (i64, i64)) -> Self {
+    Self { frame_rate, tracks: Vec::new() }
+  }
+
+  pub fn frame_duration(&self) -> RationalTime {
+    RationalTime::new(self.frame_rate.1, self.frame_rate.0)
+  }
+
+  pub fn duration(&self) -> RationalTime {
+    self.tracks.iter()
+      .flat_map(|t| t.clips())
+      .map(|c| c.end())
+      .max()
+      .unwrap_or(RationalTime::ZERO)
+  }
+
+  /// Resolves the effects of all clips after loading, see `EffectStack::resolve`. Returns the
+  /// names of the effects which aren't registered, once each.
+  pub fn resolve_effects(&mut self, registry: &EffectRegistry) -> Vec<String> {
+    let mut missing = Vec::new();
+    for track in self.tracks.iter_mut() {
+      for item in track.items.iter_mut() {
+        if let TrackItem::Clip(clip) = item {
+          for name in clip.effects.resolve(registry) {
+            if !missing.contains(&name) {
+              missing.push(name);
+            }
+          }
+        }
+      }
+    }
+    missing
+  }
+
+  /// Replaces the sources of the clips on tracks of `kind`, e.g. with proxies
+  pub fn map_sources(&mut self, kind: TrackKind, mut f: impl FnMut(&ClipSource) -> ClipSource) {
+    for track in self.tracks.iter_mut().filter(|t| t.kind == kind) {
+      for item in track.items.iter_mut() {
+        if let TrackItem::Clip(clip) = item {
+          clip.source = f(&clip.source);
+        }
+      }
+    }
+  }
+
+  /// Layers of all video tracks at `time`, ready for the compositor. `resolve` returns the layer
+  /// showing a clip's source at the given source time, with the clip's effects already applied.
+  /// `fill_texture` is passed to `Transition::layers`.
+  pub fn layers_at(&self, time: RationalTime, target_size: [u32; 2], fill_texture: usize,
+    mut resolve: impl FnMut(&TimelineClip, RationalTime) -> Option<Layer>) -> Vec<Layer>
+  {
+    let mut layer_of = |clip: &TimelineClip, source_time, clip_time| {
+      let mut layer = resolve(clip, source_time)?;
+      clip.properties.apply_to_layer(clip_time, &mut layer);
+      Some(layer)
+    };
+    let mut layers = Vec::new();
+    for track in self.tracks.iter().filter(|t| t.kind == TrackKind::Video && !t.muted) {
+      match track.sample_at(time) {
+        TrackSample::Gap => {},
+        TrackSample::Clip { clip, source_time, clip_time } => layers.extend(layer_of(clip, source_time, clip_time)),
+        TrackSample::Transition { from, to, transition, progress } => {
+          match (layer_of(from.0, from.1, from.2), layer_of(to.0, to.1, to.2)) {
+            (Some(a), Some(b)) => layers.extend(transition.layers(progress, a, b, fill_texture, target_size)),
+            (a, b) => layers.extend(a.or(b)),
+          }
+        },
+      }
+    }
+    layers
+  }
+
+  /// Whether the audio of `track` is heard, taking mute and solo into account
+  pub fn is_audible(&self, track: &Track) -> bool {
+    let any_solo = self.tracks.iter().any(|t| t.kind == TrackKind::Audio && t.solo);
+    track.kind == TrackKind::Audio && !track.muted && (track.solo || !any_solo)
+  }
+
+  /// Audible sources at `time` over all audio tracks. Track gain and pan are not included, they
+  /// are applied by the mixer.
+  pub fn audio_at(&self, time: RationalTime) -> Vec<AudioSample<'_>> {
+    let mut res = Vec::new();
+    for (track_idx, track) in self.tracks.iter().enumerate().filter(|(_, t)| self.is_audible(t)) {
+      let sources = match track.sample_at(time) {
+        TrackSample::Gap => vec![],
+        TrackSample::Clip { clip, source_time, clip_time } => vec![(clip, source_time, clip_time, 1.)],
+        TrackSample::Transition { from, to, transition, progress } => {
+          let (gain_from, gain_to) = transition.audio_gains(progress);
+          vec![(from.0, from.1, from.2, gain_from), (to.0, to.1, to.2, gain_to)]
+        },
+      };
+      res.extend(sources.into_iter().filter(|s| s.0.source.is_decoded()).map(|(clip, source_time, clip_time, gain)| AudioSample {
+        track_idx,
+        clip,
+        source_time,
+        gain: gain*clip.properties.volume_at(clip_time),
+      }));
+    }
+    res
+  }
+
+  /// Parts of sources needed to play `[from, to)`. Within transitions two sources of the same
+  /// track overlap, so the decode-ahead has to serve both at once. Generators are left out.
+  pub fn sources_in(&self, from: RationalTime, to: RationalTime) -> Vec<SourceRange<'_>> {
+    let mut res = Vec::new();
+    for (track_idx, track) in self.tracks.iter().enumerate() {
+      for (clip, start, end) in track.extended_ranges() {
+        let (start, end) = (start.max(from), end.min(to));
+        if start < end && clip.source.is_decoded() {
+          res.push(SourceRange {
+            track_idx,
+            source: &clip.source,
+            from: clip.source_time(start),
+            to: clip.source_time(end),
+          });
+        }
+      }
+    }
+    res
+  }
+}
+
+
+impl fmt::Display for TimelineError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      TimelineError::Overlap(idx) => write!(f, "Overlaps with item {}", idx),
+      TimelineError::InvalidDuration => write!(f, "Duration has to be positive"),
+      TimelineError::NoCut(time) => write!(f, "No cut between two clips at {}", time),
+      TimelineError::NotEnoughHandle { needed, available } => write!(f, "Needs {} of handle, only {} available", needed, available),
+      TimelineError::TransitionOverlap => write!(f, "Longer than the clips or overlaps with another transition"),
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::transition::{Alignment, TransitionKind};
+
+  fn secs(secs: i64) -> RationalTime {
+    RationalTime::from_secs(secs)
+  }
+
+  /// A file clip whose path is the texture `resolve_texture` gives it
+  fn clip(texture: usize, start: i64, duration: i64, source_in: i64) -> TimelineClip {
+    let source = ClipSource::File { path: PathBuf::from(texture.to_string()), stream_idx: 0 };
+    TimelineClip { source_in: secs(source_in), ..TimelineClip::new(source, secs(start), secs(duration)) }
+  }
+
+  fn resolve_texture(clip: &TimelineClip, _: RationalTime) -> Option<Layer> {
+    match &clip.source {
+      ClipSource::File { path, .. } => Some(Layer::new(path.to_str()?.parse().ok()?, [16, 9])),
+      ClipSource::Generator(_) => None,
+    }
+  }
+
+  fn textures(timeline: &Timeline, time: RationalTime) -> Vec<usize> {
+    timeline.layers_at(time, [16, 9], 0, resolve_texture).iter().map(|l| l.texture).collect()
+  }
+
+  fn dissolve(duration: i64) -> Transition {
+    Transition::new(TransitionKind::CrossDissolve, secs(duration))
+  }
+
+  fn video_track(clips: impl IntoIterator<Item=TimelineClip>) -> Track {
+    let mut track = Track::new("V1", TrackKind::Video);
+    for clip in clips {
+      track.add_clip(clip).unwrap();
+    }
+    track
+  }
+
+  #[test]
+  fn transitions_need_a_cut() {
+    let mut track = video_track([clip(1, 0, 4, 0), clip(2, 4, 4, 2), clip(3, 10, 4, 2)]);
+    assert_eq!(track.add_transition(secs(3), dissolve(2)), Err(TimelineError::NoCut(secs(3))));
+    // There is a gap between the second and the third clip
+    assert_eq!(track.add_transition(secs(8), dissolve(2)), Err(TimelineError::NoCut(secs(8))));
+    assert_eq!(track.add_transition(secs(4), dissolve(0)), Err(TimelineError::InvalidDuration));
+    assert_eq!(track.add_transition(secs(4), dissolve(2)), Ok(1));
+    assert!(matches!(track.items()[1], TrackItem::Transition(_)));
+  }
+
+  #[test]
+  fn transitions_need_handles() {
+    // No media before the in point of the incoming clip
+    let mut track = video_track([clip(1, 0, 4, 0), clip(2, 4, 4, 0)]);
+    assert_eq!(track.add_transition(secs(4), dissolve(2)),
+      Err(TimelineError::NotEnoughHandle { needed: secs(1), available: secs(0) }));
+    // Starting at the cut only needs the outgoing clip's tail
+    let start_at_cut = Transition { alignment: Alignment::StartAtCut, ..dissolve(2) };
+    assert_eq!(track.add_transition(secs(4), start_at_cut), Ok(1));
+
+    // The outgoing clip's media ends half a second after its out point
+    let mut outgoing = clip(1, 0, 4, 0);
+    outgoing.source_duration = Some(RationalTime::new(9, 2));
+    let mut track = video_track([outgoing, clip(2, 4, 4, 5)]);
+    assert_eq!(track.add_transition(secs(4), dissolve(2)),
+      Err(TimelineError::NotEnoughHandle { needed: secs(1), available: RationalTime::new(1, 2) }));
+    assert_eq!(track.add_transition(secs(4), dissolve(1)), Ok(1));
+  }
+
+  #[test]
+  fn transitions_must_not_overlap() {
+    let mut track = video_track([clip(1, 0, 4, 5), clip(2, 4, 2, 5), clip(3, 6, 4, 5)]);
+    // 3s to 5s
+    assert_eq!(track.add_transition(secs(4), dissolve(2)), Ok(1));
+    // 4s to 8s would reach into the first one
+    assert_eq!(track.add_transition(secs(6), dissolve(4)), Err(TimelineError::TransitionOverlap));
+    // 5s to 7s touches it
+    assert_eq!(track.add_transition(secs(6), dissolve(2)), Ok(3));
+    // Reaching past the start of the first clip
+    let mut track = video_track([clip(1, 0, 1, 5), clip(2, 1, 4, 5)]);
+    assert_eq!(track.add_transition(secs(1), dissolve(4)), Err(TimelineError::TransitionOverlap));
+  }
+
+  #[test]
+  fn removing_a_clip_removes_its_transitions() {
+    let mut track = video_track([clip(1, 0, 4, 5), clip(2, 4, 2, 5), clip(3, 6, 4, 5), clip(4, 10, 4, 5)]);
+    track.add_transition(secs(4), dissolve(2)).unwrap();
+    track.add_transition(secs(6), dissolve(2)).unwrap();
+    track.add_transition(secs(10), dissolve(2)).unwrap();
+    assert_eq!(track.items().len(), 7);
+
+    // Not a clip
+    assert!(track.remove_clip(1).is_none());
+    assert!(track.remove_clip(7).is_none());
+    assert_eq!(track.items().len(), 7);
+
+    // The second clip is in two transitions
+    let removed = track.remove_clip(2).unwrap();
+    assert_eq!(removed.start, secs(4));
+    assert_eq!(track.items().len(), 4);
+    let starts: Vec<_> = track.clips().map(|c| c.start).collect();
+    assert_eq!(starts, [secs(0), secs(6), secs(10)]);
+    assert!(matches!(track.items()[2], TrackItem::Transition(_)));
+
+    // The last clip is in one
+    track.remove_clip(3).unwrap();
+    assert_eq!(track.items().len(), 2);
+    assert!(track.items().iter().all(|item| matches!(item, TrackItem::Clip(_))));
+  }
+
+  #[test]
+  fn layers_of_overlapping_tracks() {
+    let mut timeline = Timeline::new((25, 1));
+    timeline.tracks.push(video_track([clip(1, 0, 4, 0)]));
+    timeline.tracks.push(video_track([clip(2, 2, 4, 0)]));
+    let mut muted = video_track([clip(3, 0, 10, 0)]);
+    muted.muted = true;
+    timeline.tracks.push(muted);
+    let mut audio = Track::new("A1", TrackKind::Audio);
+    audio.add_clip(clip(4, 0, 10, 0)).unwrap();
+    timeline.tracks.push(audio);
+
+    assert_eq!(textures(&timeline, secs(1)), [1]);
+    // Bottom track first
+    assert_eq!(textures(&timeline, secs(2)), [1, 2]);
+    assert_eq!(textures(&timeline, secs(4)), [2]);
+    assert!(textures(&timeline, secs(6)).is_empty());
+
+    // A clip that can't be resolved is left out
+    timeline.tracks[0].clip_mut(0).unwrap().source = ClipSource::File { path: "none".into(), stream_idx: 0 };
+    assert_eq!(textures(&timeline, secs(2)), [2]);
+  }
+
+  #[test]
+  fn layers_at_transition_bounds() {
+    let mut timeline = Timeline::new((25, 1));
+    let mut video = video_track([clip(1, 0, 4, 0), clip(2, 4, 4, 5)]);
+    // 3s to 5s
+    video.add_transition(secs(4), dissolve(2)).unwrap();
+    timeline.tracks.push(video);
+
+    let layers = |time| timeline.layers_at(time, [16, 9], 0, resolve_texture);
+    let just_before = secs(3) - RationalTime::new(1, 25);
+    assert_eq!(layers(just_before).iter().map(|l| l.texture).collect::<Vec<_>>(), [1]);
+    // The start is part of the transition, the incoming clip is still invisible
+    let start = layers(secs(3));
+    assert_eq!(start.iter().map(|l| (l.texture, l.opacity)).collect::<Vec<_>>(), [(1, 1.), (2, 0.)]);
+    let cut = layers(secs(4));
+    assert_eq!(cut.iter().map(|l| (l.texture, l.opacity)).collect::<Vec<_>>(), [(1, 1.), (2, 0.5)]);
+    // The end isn't
+    assert_eq!(layers(secs(5)).iter().map(|l| (l.texture, l.opacity)).collect::<Vec<_>>(), [(2, 1.)]);
+
+    // Both clips play from their handles inside the transition
+    let mut source_times = Vec::new();
+    timeline.layers_at(secs(3), [16, 9], 0, |clip, source_time| {
+      source_times.push(source_time);
+      resolve_texture(clip, source_time)
+    });
+    assert_eq!(source_times, [secs(3), secs(4)]);
+  }
+}
@@ -0,0 +1,131 @@
+use std::f32::consts::{FRAC_PI_2, PI};
+
+use serde::{Serialize, Deserialize};
+
+use crate::{video::RationalTime, wgpustate::compositor::{Layer, WipeMask}};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TransitionKind {
+  CrossDissolve,
+  /// Fades the outgoing clip to `color` (linear RGBA) and from there to the incoming one
+  Dip { color: [f32; 4] },
+  /// The incoming clip is revealed by an edge moving in the direction `angle`, see `WipeMask`
+  Wipe { angle: f32, softness: f32 },
+}
+
+/// Gain curve of the audio crossfade
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AudioCrossfade {
+  Linear,
+  /// Keeps the perceived loudness constant for uncorrelated signals
+  #[default]
+  EqualPower,
+}
+
+/// Where the transition lies relative to the cut between the two clips
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Alignment {
+  #[default]
+  CenterOnCut,
+  StartAtCut,
+  EndAtCut,
+}
+
+/// A transition between two adjacent clips on a track. It covers the cut and uses the media
+/// beyond the out point of the outgoing clip and before the in point of the incoming clip, so
+/// both clips need handles of the respective length.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Transition {
+  pub kind: TransitionKind,
+  pub duration: RationalTime,
+  pub alignment: Alignment,
+  /// Used on audio tracks, where `kind` has no meaning
+  pub audio: AudioCrossfade,
+}
+
+
+impl TransitionKind {
+  pub const DIP_TO_BLACK: Self = TransitionKind::Dip { color: [0., 0., 0., 1.] };
+  pub const DIP_TO_WHITE: Self = TransitionKind::Dip { color: [1., 1., 1., 1.] };
+
+  pub const WIPE_LEFT_TO_RIGHT: f32 = 0.;
+  pub const WIPE_TOP_TO_BOTTOM: f32 = FRAC_PI_2;
+  pub const WIPE_RIGHT_TO_LEFT: f32 = PI;
+  pub const WIPE_BOTTOM_TO_TOP: f32 = -FRAC_PI_2;
+
+  pub fn wipe(angle: f32, softness: f32) -> Self {
+    TransitionKind::Wipe { angle, softness }
+  }
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      TransitionKind::CrossDissolve => "Cross Dissolve",
+      TransitionKind::Dip { .. } => "Dip to Color",
+      TransitionKind::Wipe { .. } => "Wipe",
+    }
+  }
+}
+
+impl AudioCrossfade {
+  /// Gains of the outgoing and incoming clip at `progress`
+  pub fn gains(&self, progress: f32) -> (f32, f32) {
+    let p = progress.clamp(0., 1.);
+    match self {
+      AudioCrossfade::Linear => (1. - p, p),
+      AudioCrossfade::EqualPower => ((p*FRAC_PI_2).cos(), (p*FRAC_PI_2).sin()),
+    }
+  }
+}
+
+impl Transition {
+  pub fn new(kind: TransitionKind, duration: RationalTime) -> Self {
+    Self { kind, duration, alignment: Alignment::default(), audio: AudioCrossfade::default() }
+  }
+
+  /// Lengths of the parts before and after the cut
+  pub fn split(&self) -> (RationalTime, RationalTime) {
+    let before = match self.alignment {
+      Alignment::CenterOnCut => RationalTime::new(self.duration.num(), self.duration.den() * 2),
+      Alignment::StartAtCut => RationalTime::ZERO,
+      Alignment::EndAtCut => self.duration,
+    };
+    (before, self.duration - before)
+  }
+
+  /// Start and end on the timeline for a cut at `cut`
+  pub fn range(&self, cut: RationalTime) -> (RationalTime, RationalTime) {
+    let (before, after) = self.split();
+    (cut - before, cut + after)
+  }
+
+  /// Compositor layers for the two clips, bottom to top. `from` and `to` are the fully set up
+  /// layers of the outgoing and the incoming clip. `fill_texture` can be any texture of the
+  /// atlas, it is needed for the solid color of a dip.
+  pub fn layers(&self, progress: f32, from: Layer, to: Layer, fill_texture: usize, target_size: [u32; 2]) -> Vec<Layer> {
+    let p = progress.clamp(0., 1.);
+    match self.kind {
+      // Drawing the incoming clip with opacity `p` over the outgoing one results in
+      // `(1 - p)*from + p*to` for opaque clips
+      TransitionKind::CrossDissolve => vec![
+        from,
+        Layer { opacity: to.opacity*p, ..to },
+      ],
+      TransitionKind::Dip { color } => {
+        let fill = |opacity| Layer { opacity, ..Layer::solid(fill_texture, target_size, color) };
+        if p < 0.5 {
+          vec![from, fill(p*2.)]
+        } else {
+          vec![fill(1.), Layer { opacity: to.opacity*(p*2. - 1.), ..to }]
+        }
+      },
+      TransitionKind::Wipe { angle, softness } => vec![
+        from,
+        Layer { mask: Some(WipeMask { angle, progress: p, softness }), ..to },
+      ],
+    }
+  }
+
+  pub fn audio_gains(&self, progress: f32) -> (f32, f32) {
+    self.audio.gains(progress)
+  }
+}
@@ -1,11 +1,14 @@
 pub mod event;
 pub mod main;
 pub mod dialogs;
+pub mod meter;
+pub mod render_queue;
+pub mod debug_overlay;
 mod error;
 mod simple;
 mod util;
 
-use std::{collections::HashMap, time};
+use std::{cell::RefCell, collections::HashMap, sync::{Arc, Mutex}, task::{Wake, Waker}, time};
 
 use egui_winit::{
   egui, 
@@ -19,7 +22,7 @@ use egui_winit::{
   }
 };
 
-use super::hierarchy::Entity;
+use super::hierarchy::{Entity, Tree};
 // use crate::wgpustate::WgpuState;
 
 
@@ -29,6 +32,8 @@ pub enum EscherEvent {
   Rescale(f32),
   Exit(u8),
   NewDialog,
+  OpenRenderQueue,
+  OpenDebugOverlay,
 }
 
 pub mod constants {
@@ -40,6 +45,8 @@ pub mod constants {
 pub enum UIType {
   Main(Box<main::MainWindow>),
   License(Box<dialogs::LicenseDialog>),
+  RenderQueue(Box<render_queue::RenderQueueWindow>),
+  DebugOverlay(Box<debug_overlay::DebugOverlayWindow>),
   // Dynamic(Box<dyn Entity>),
 } 
 
@@ -59,11 +66,26 @@ pub struct UIState {
   ui_scale: f32,
 
   current_time: time::Instant,
+  /// Exports running in the background, shared by all windows
+  pub render_queue: RefCell<crate::export::RenderQueue>,
+  /// The window showing `render_queue`. There is only one, the queue wakes a single window.
+  render_queue_window: Option<UIId>,
+  /// Decoded and scaled frames, shared by all viewers and decoders
+  pub frame_cache: Arc<crate::video::FrameCache>,
+}
+
+/// Posts `EscherEvent::RequestRedraw` for one window when it's woken, e.g. by a worker that
+/// finished something the window shows
+struct RedrawWaker {
+  proxy: Mutex<EventLoopProxy<EscherEvent>>,
+  id: UIId,
 }
 
 pub struct UIHierarchy {
   state: UIState,
   entities: HashMap<window::WindowId, UI>,
+  /// Windows own the dialogs they opened, closing a window closes them as well
+  tree: Tree<UIId>,
 }
 
 pub struct FullUIInput<'a> {
@@ -114,6 +136,12 @@ pub struct UIError;
 pub type UIId = window::WindowId;
 
 
+/// Wakes the event loop to redraw window `id` only, see `Scheduler::set_waker`
+pub fn redraw_waker(proxy: &EventLoopProxy<EscherEvent>, id: UIId) -> Waker {
+  Waker::from(Arc::new(RedrawWaker { proxy: Mutex::new(proxy.clone()), id }))
+}
+
+
 impl UI {
   pub fn new(partial_window: window::WindowBuilder, ui_impl: Option<UIType>, window_target: &EventLoopWindowTarget<EscherEvent>) -> Self {
     let window = partial_window.build(window_target).unwrap();
@@ -136,21 +164,49 @@ impl UI {
 }
 
 
+impl Wake for RedrawWaker {
+  fn wake(self: Arc<Self>) {
+    self.wake_by_ref()
+  }
+
+  fn wake_by_ref(self: &Arc<Self>) {
+    // Fails only once the event loop is gone
+    self.proxy.lock().unwrap().send_event(EscherEvent::RequestRedraw { id: self.id }).unwrap_or_default();
+  }
+}
+
 impl UIHierarchy {
   pub fn new_escher_ui(event_loop: &EventLoop<EscherEvent>, scale_factor: f32) -> Self {
-    let main_ui = main::MainWindow::new(&event_loop, scale_factor);
+    let event_loop_proxy = event_loop.create_proxy();
+    let frame_cache = Arc::new(crate::video::FrameCache::default());
+    let main_ui = main::MainWindow::new(&event_loop, scale_factor, frame_cache.clone());
     let main_id = main_ui.get_id();
+    if let Some(UIType::Main(main_window)) = &main_ui.ui_impl {
+      main_window.set_waker(redraw_waker(&event_loop_proxy, main_id));
+    }
     let entities = HashMap::from([(main_id, main_ui)]);
+    let mut tree = Tree::new();
+    tree.insert(main_id, None).unwrap();
 
     let state = UIState {
-      event_loop_proxy: event_loop.create_proxy(),
+      event_loop_proxy,
       modifier: util::EventModifier::default(),
       toplevel_id: main_id,
       ui_scale: scale_factor,
       current_time: time::Instant::now(),
+      render_queue: RefCell::new(crate::export::RenderQueue::new(1)),
+      render_queue_window: None,
+      frame_cache,
     };
 
-    Self { state, entities }
+    Self { state, entities, tree }
+  }
+
+  /// Adds a dialog owned by the main window
+  fn add_dialog(&mut self, dialog: UI) {
+    let id = dialog.get_id();
+    self.tree.insert(id, Some(&self.state.toplevel_id)).unwrap();
+    self.entities.insert(id, dialog);
   }
 
   pub fn get_toplevel_id(&self) -> UIId {
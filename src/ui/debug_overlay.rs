@@ -0,0 +1,235 @@
+use std::{
+  collections::{BTreeMap, VecDeque},
+  time::{Duration, Instant},
+};
+
+use egui_winit::{
+  egui::{
+    self,
+    Context,
+    CentralPanel
+  },
+  winit::{
+    self,
+    window,
+    event_loop::{
+      ControlFlow,
+      EventLoopWindowTarget
+    }
+  }
+};
+use epaint::{pos2, vec2};
+
+use super::{simple::{SimpleUI, SimpleWindow, WindowDrawRes}, UIState, EscherEvent, UI, UIType};
+use crate::schedule::{self, SchedulerMetrics};
+
+/// Graphs what the workers of the registered schedulers do and shows the stats of
+/// `UIState::frame_cache`, e.g. to tune the number of decode and scale workers
+pub struct DebugOverlayWindow {
+  pub(super) inner: SimpleWindow,
+  /// Samples of each scheduler, oldest first
+  history: BTreeMap<String, VecDeque<Sample>>,
+  /// The metrics the latest samples were taken from
+  latest: BTreeMap<String, SchedulerMetrics>,
+  last_sample: Option<Instant>,
+}
+
+/// One point of the graphs of a scheduler
+struct Sample {
+  /// Mean busy ratio of the workers since the previous sample
+  busy: f32,
+  queue_depth: usize,
+}
+
+
+/// Time between two samples
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(250);
+/// Samples kept per scheduler, 30 s
+const HISTORY_LEN: usize = 120;
+const GRAPH_HEIGHT: f32 = 48.;
+const BUSY_COLOR: egui::Color32 = egui::Color32::from_rgb(90, 170, 255);
+const QUEUE_COLOR: egui::Color32 = egui::Color32::from_rgb(255, 170, 60);
+
+
+fn format_ms(duration: Duration) -> String {
+  format!("{:.1} ms", duration.as_secs_f64() * 1000.)
+}
+
+fn format_mib(bytes: usize) -> String {
+  format!("{:.1} MiB", bytes as f64 / (1024. * 1024.))
+}
+
+/// Draws each line of values between 0 and its maximum over the available width
+fn graph(ui: &mut egui::Ui, lines: &[(Vec<f32>, f32, egui::Color32)]) {
+  let (rect, _) = ui.allocate_exact_size(vec2(ui.available_width(), GRAPH_HEIGHT), egui::Sense::hover());
+  let painter = ui.painter_at(rect);
+  painter.rect_filled(rect, 2., ui.visuals().extreme_bg_color);
+  let step = rect.width() / (HISTORY_LEN - 1) as f32;
+  for (values, max, color) in lines {
+    let points = values.iter()
+      .enumerate()
+      .map(|(i, value)| pos2(rect.left() + i as f32 * step, rect.bottom() - rect.height() * (value / max.max(f32::EPSILON)).min(1.)))
+      .collect();
+    painter.add(egui::Shape::line(points, egui::Stroke::new(1.5, *color)));
+  }
+}
+
+
+impl SimpleUI for DebugOverlayWindow {
+  fn simple_window(&mut self) -> &mut SimpleWindow {
+    &mut self.inner
+  }
+
+  fn redraw(&mut self, ctx: &Context, window: &window::Window, state: &UIState, control_flow: &mut ControlFlow) -> WindowDrawRes {
+    let inner = &mut unsafe {(self as *mut Self).as_mut()}.unwrap().inner;
+    inner.redraw(ctx, window, state, control_flow, |ctx, state| self.ui(ctx, state))
+  }
+}
+
+impl DebugOverlayWindow {
+  /// Takes a sample of every registered scheduler. Schedulers with the same name are numbered.
+  fn sample(&mut self) {
+    let mut latest = BTreeMap::new();
+    for metrics in schedule::registered_metrics() {
+      let mut name = metrics.name.clone();
+      for i in 2.. {
+        if !latest.contains_key(&name) {
+          break;
+        }
+        name = format!("{} #{}", metrics.name, i);
+      }
+      let busy = match self.latest.get(&name) {
+        Some(earlier) if !metrics.workers.is_empty() => {
+          let total: f32 = metrics.workers.keys().map(|&worker| metrics.busy_ratio_since(earlier, worker)).sum();
+          total / metrics.workers.len() as f32
+        },
+        _ => 0.,
+      };
+      let history = self.history.entry(name.clone()).or_default();
+      if history.len() == HISTORY_LEN {
+        history.pop_front();
+      }
+      history.push_back(Sample { busy, queue_depth: metrics.queue_depth() });
+      latest.insert(name, metrics);
+    }
+    // Dropped schedulers disappear
+    self.history.retain(|name, _| latest.contains_key(name));
+    self.latest = latest;
+  }
+
+  fn ui_scheduler(ui: &mut egui::Ui, name: &str, metrics: &SchedulerMetrics, history: &VecDeque<Sample>) {
+    ui.heading(name);
+    let queue_depth = metrics.queue_depth();
+    ui.horizontal(|ui| {
+      ui.colored_label(BUSY_COLOR, format!("Busy {:.0}%", history.back().map_or(0., |s| s.busy) * 100.));
+      ui.colored_label(QUEUE_COLOR, format!("Queued {}", queue_depth));
+      ui.label(format!("{} workers", metrics.workers.len()));
+    });
+    let busy = history.iter().map(|s| s.busy).collect();
+    let queued: Vec<_> = history.iter().map(|s| s.queue_depth as f32).collect();
+    let max_queued = queued.iter().copied().fold(1., f32::max);
+    graph(ui, &[(busy, 1., BUSY_COLOR), (queued, max_queued, QUEUE_COLOR)]);
+
+    egui::Grid::new((name, "workers")).striped(true).show(ui, |ui| {
+      for header in ["Worker", "Handled", "Busy", "Queued"] {
+        ui.strong(header);
+      }
+      ui.end_row();
+      for (worker, worker_metrics) in metrics.workers.iter() {
+        ui.label(worker.to_string());
+        ui.label(worker_metrics.handled.to_string());
+        ui.label(format!("{:.0}%", metrics.busy_ratio(*worker) * 100.));
+        ui.label(worker_metrics.queue_depth.to_string());
+        ui.end_row();
+      }
+    });
+    ui.add_space(4.);
+    egui::Grid::new((name, "kinds")).striped(true).show(ui, |ui| {
+      for header in ["Request", "Handled", "Failed", "Cancelled", "Expired", "Wait", "Max Wait", "Service", "Max Service"] {
+        ui.strong(header);
+      }
+      ui.end_row();
+      for (kind, kind_metrics) in metrics.kinds.iter() {
+        ui.label(*kind);
+        ui.label(kind_metrics.handled.to_string());
+        ui.label(kind_metrics.failed.to_string());
+        ui.label(kind_metrics.cancelled.to_string());
+        ui.label(kind_metrics.expired.to_string());
+        ui.label(format_ms(kind_metrics.mean_wait()));
+        ui.label(format_ms(kind_metrics.max_wait));
+        ui.label(format_ms(kind_metrics.mean_service()));
+        ui.label(format_ms(kind_metrics.max_service));
+        ui.end_row();
+      }
+    });
+  }
+
+  fn ui_frame_cache(ui: &mut egui::Ui, state: &UIState) {
+    let stats = state.frame_cache.stats();
+    ui.heading("Frame Cache");
+    ui.add(egui::ProgressBar::new(stats.bytes as f32 / stats.budget.max(1) as f32)
+      .text(format!("{} of {}", format_mib(stats.bytes), format_mib(stats.budget))));
+    egui::Grid::new("frame_cache").striped(true).show(ui, |ui| {
+      let rows = [
+        ("Frames", stats.frames.to_string()),
+        ("Hit rate", format!("{:.1}%", stats.hit_rate() * 100.)),
+        ("Hits", stats.hits.to_string()),
+        ("Misses", stats.misses.to_string()),
+        ("Insertions", stats.insertions.to_string()),
+        ("Evictions", stats.evictions.to_string()),
+      ];
+      for (label, value) in rows {
+        ui.label(label);
+        ui.label(value);
+        ui.end_row();
+      }
+    });
+    if ui.button("Reset Stats").clicked() {
+      state.frame_cache.reset_stats();
+    }
+  }
+
+  fn ui(&mut self, ctx: &Context, state: &UIState) {
+    if self.last_sample.map_or(true, |last| last.elapsed() >= SAMPLE_INTERVAL) {
+      self.sample();
+      self.last_sample = Some(Instant::now());
+    }
+    // Live graphs, the window samples on its own
+    ctx.request_repaint_after(SAMPLE_INTERVAL);
+
+    CentralPanel::default().show(ctx, |ui| {
+      egui::ScrollArea::vertical().show(ui, |ui| {
+        Self::ui_frame_cache(ui, state);
+        for (name, metrics) in self.latest.iter() {
+          ui.separator();
+          if let Some(history) = self.history.get(name) {
+            Self::ui_scheduler(ui, name, metrics, history);
+          }
+        }
+      });
+    });
+  }
+
+  pub fn new(window_target: &EventLoopWindowTarget<EscherEvent>, scale_factor: f32) -> UI {
+    let (mut res, inner) = SimpleWindow::new(
+      window::WindowBuilder::new()
+        .with_decorations(true)
+        .with_resizable(true)
+        .with_transparent(true)
+        .with_title("Debug Overlay")
+        .with_inner_size(winit::dpi::PhysicalSize {
+          width: 720,
+          height: 640,
+        }),
+      window_target,
+      scale_factor
+    );
+
+    res.ui_impl = Some(UIType::DebugOverlay(Box::new(
+      Self { inner, history: BTreeMap::new(), latest: BTreeMap::new(), last_sample: None }
+    )));
+    res
+  }
+
+
+}
@@ -13,23 +13,24 @@ use egui_winit::{
   }
 };
 
-use super::{simple::{SimpleWindow, WindowDrawRes}, UIState, EscherEvent, UI, UIType};
+use super::{simple::{SimpleUI, SimpleWindow, WindowDrawRes}, UIState, EscherEvent, UI, UIType};
 
 pub struct LicenseDialog {
   pub(super) inner: SimpleWindow,
 }
 
-impl LicenseDialog {
-  pub fn redraw(&mut self, ctx: &Context, window: &window::Window, state: &UIState, control_flow: &mut ControlFlow) -> WindowDrawRes {
-    let inner = &mut unsafe {(self as *mut Self).as_mut()}.unwrap().inner;
-    inner.redraw(ctx, window, state, control_flow, |ctx, state| self.ui(ctx, state))
+impl SimpleUI for LicenseDialog {
+  fn simple_window(&mut self) -> &mut SimpleWindow {
+    &mut self.inner
   }
 
-  pub fn resize(&mut self, width: Option<u32>, height: Option<u32>, scale: Option<f32>) {
-    self.inner.resize(width, height, scale)
+  fn redraw(&mut self, ctx: &Context, window: &window::Window, state: &UIState, control_flow: &mut ControlFlow) -> WindowDrawRes {
+    let inner = &mut unsafe {(self as *mut Self).as_mut()}.unwrap().inner;
+    inner.redraw(ctx, window, state, control_flow, |ctx, state| self.ui(ctx, state))
   }
+}
 
-
+impl LicenseDialog {
   fn ui(&mut self, ctx: &Context, _state: &UIState) {
     CentralPanel::default().show(ctx, |ui| {
       ui.label(include_str!("../../LICENSE"));
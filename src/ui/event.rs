@@ -9,12 +9,14 @@ use egui_winit::winit::{
   event_loop::ControlFlow,
   dpi::PhysicalSize
 };
-use escher_hierarchy::{Entity, Hierarchy, InteriorKind, InteriorRef};
+use escher_hierarchy::{Entity, Hierarchy, InteriorKind, InteriorRef, Tree};
 use std::{cmp, collections::{HashMap, HashSet}, time};
 
 use super::{
   FullUIResult,
   dialogs::LicenseDialog,
+  render_queue::RenderQueueWindow,
+  debug_overlay::DebugOverlayWindow,
   EscherEvent,
   UIId,
   UIInput,
@@ -23,120 +25,87 @@ use super::{
   UI,
   UIType,
   UIInputKind,
-  simple,
+  simple::{self, SimpleUI},
   FullUIInput,
   UIError,
   UIHierarchy,
+  redraw_waker,
   util,
   constants
 };
 
 
+impl UI {
+  /// Redraws, resizes and closes `simple_ui`, which is the `ui_impl` of this window
+  fn run_simple(&mut self, simple_ui: &mut dyn SimpleUI, state: &UIState, input: &UIInput) -> Option<UIResult> {
+    match input.kind {
+      UIInputKind::Redraw => match simple_ui.redraw(&self.ctx, &self.window, state, &mut self.control_flow) {
+        simple::WindowDrawRes::InvaldRenderFrame => {
+          // The surface is lost or outdated, e.g. after the window was minimized. Configure it
+          // again and try the next frame.
+          let PhysicalSize { width, height } = self.window.inner_size();
+          simple_ui.resize(Some(width), Some(height), None);
+          self.window.request_redraw();
+          None
+        },
+        simple::WindowDrawRes::NoRedrawScheduled(true) | simple::WindowDrawRes::RedrawNextFrame(true)
+          | simple::WindowDrawRes::RedrawScheduled(_) => Some(UIResult::with_new_control_flow(self.get_id())),
+        simple::WindowDrawRes::NoRedrawScheduled(false) | simple::WindowDrawRes::RedrawNextFrame(false) =>
+          None,
+      },
+      UIInputKind::WindowEvent(event) => {
+        let egui_winit_state_result = simple_ui.simple_window().egui_winit_state.on_event(&self.ctx, event);
+        let mut drop_window = false;
+        if !egui_winit_state_result.consumed {
+          match event {
+            WindowEvent::Resized(PhysicalSize { width, height}) =>
+              simple_ui.resize(Some(*width), Some(*height), None),
+            WindowEvent::ScaleFactorChanged { scale_factor, new_inner_size } =>
+              simple_ui.resize(Some(new_inner_size.width), Some(new_inner_size.height), Some(*scale_factor as _)),
+            WindowEvent::CloseRequested | WindowEvent::Destroyed => {
+              drop_window = true
+            },
+            _ => {}
+          }
+        }
+        if egui_winit_state_result.repaint {
+          self.window.request_redraw();
+        }
+        if drop_window || egui_winit_state_result.consumed {
+          Some(UIResult {
+            id: self.get_id(), 
+            mutate_control_flow: false,
+            fully_consumed_event: egui_winit_state_result.consumed,
+            drop: drop_window,
+          })
+        } else {
+          None
+        }
+      },
+      UIInputKind::Resize { width, height, scale } => {
+        simple_ui.resize(width, height, scale);
+        None
+      },
+    }
+  }
+}
+
 impl<'a> Entity<UIId, UIInput<'a>, UIState, UIResult> for UI {
   fn get_id(&self) -> UIId {
     self.window.id()
   }
 
   fn run(&mut self, state: &UIState, input: &UIInput) -> Option<UIResult> {
-    if let Some(ui_impl) = &mut self.ui_impl {
-      match ui_impl {
-        UIType::Main(main_window) => {
-          let main_window = main_window.as_mut();
-          match input.kind {
-            UIInputKind::Redraw => match main_window.redraw(&self.ctx, &self.window, state, &mut self.control_flow) {
-              simple::WindowDrawRes::InvaldRenderFrame => todo!(),
-              simple::WindowDrawRes::NoRedrawScheduled(true) | simple::WindowDrawRes::RedrawNextFrame(true)
-                | simple::WindowDrawRes::RedrawScheduled(_) => Some(UIResult::with_new_control_flow(self.get_id())),
-              simple::WindowDrawRes::NoRedrawScheduled(false) | simple::WindowDrawRes::RedrawNextFrame(false) =>
-                None,
-            },
-            UIInputKind::WindowEvent(event) => {
-              let egui_winit_state_result = main_window.inner.egui_winit_state.on_event(&self.ctx, &event);
-              let mut drop_window = false;
-              if !egui_winit_state_result.consumed {
-                match event {
-                  WindowEvent::Resized(PhysicalSize { width, height}) =>
-                    main_window.resize(Some(*width), Some(*height), None),
-                  WindowEvent::ScaleFactorChanged { scale_factor, new_inner_size } =>
-                    main_window.resize(Some(new_inner_size.width), Some(new_inner_size.height), Some(*scale_factor as _)),
-                  WindowEvent::CloseRequested | WindowEvent::Destroyed => {
-                    // state.event_loop_proxy.send_event(EscherEvent::Exit(0)).unwrap_or_default();
-                    drop_window = true
-                  },
-                  _ => {}
-                }
-              }
-              if egui_winit_state_result.repaint {
-                self.window.request_redraw();
-              }
-              if drop_window || egui_winit_state_result.consumed {
-                Some(UIResult {
-                  id: self.get_id(), 
-                  mutate_control_flow: false,
-                  fully_consumed_event: egui_winit_state_result.consumed,
-                  drop: drop_window,
-                })
-              } else {
-                None
-              }
-            },
-            UIInputKind::Resize { width, height, scale } => {
-              main_window.resize(width, height, scale);
-              None
-            },
-          }
-        },
-        
-        UIType::License(license_dialog) => {
-          let license_dialog = license_dialog.as_mut();
-          match input.kind {
-            UIInputKind::Redraw => match license_dialog.redraw(&self.ctx, &self.window, state, &mut self.control_flow) {
-              simple::WindowDrawRes::InvaldRenderFrame => todo!(),
-              simple::WindowDrawRes::NoRedrawScheduled(true) | simple::WindowDrawRes::RedrawNextFrame(true)
-                | simple::WindowDrawRes::RedrawScheduled(_) => Some(UIResult::with_new_control_flow(self.get_id())),
-              simple::WindowDrawRes::NoRedrawScheduled(false) | simple::WindowDrawRes::RedrawNextFrame(false) =>
-                None,
-            },
-            UIInputKind::WindowEvent(event) => {
-              let egui_winit_state_result = license_dialog.inner.egui_winit_state.on_event(&self.ctx, &event);
-              let mut drop_window = false;
-              if !egui_winit_state_result.consumed {
-                match event {
-                  WindowEvent::Resized(PhysicalSize { width, height}) =>
-                  license_dialog.resize(Some(*width), Some(*height), None),
-                  WindowEvent::ScaleFactorChanged { scale_factor, new_inner_size } =>
-                  license_dialog.resize(Some(new_inner_size.width), Some(new_inner_size.height), Some(*scale_factor as _)),
-                  WindowEvent::CloseRequested | WindowEvent::Destroyed => {
-                    drop_window = true
-                  },
-                  _ => {}
-                }
-              }
-              if egui_winit_state_result.repaint {
-                self.window.request_redraw();
-              }
-              if drop_window || egui_winit_state_result.consumed {
-                Some(UIResult {
-                  id: self.get_id(), 
-                  mutate_control_flow: false,
-                  fully_consumed_event: egui_winit_state_result.consumed,
-                  drop: drop_window,
-                })
-              } else {
-                None
-              }
-            },
-            UIInputKind::Resize { width, height, scale } => {
-              license_dialog.resize(width, height, scale);
-              None
-            },
-          }
-        },
-        
-      }
-    } else {
-      None
-    }
+    // Taken out while it runs, `run_simple` borrows the rest of `self`
+    let mut ui_impl = self.ui_impl.take()?;
+    let res = match &mut ui_impl {
+      UIType::Main(main_window) => self.run_simple(main_window.as_mut(), state, input),
+      UIType::License(license_dialog) => self.run_simple(license_dialog.as_mut(), state, input),
+      UIType::RenderQueue(render_queue_window) => self.run_simple(render_queue_window.as_mut(), state, input),
+      UIType::DebugOverlay(debug_overlay_window) => self.run_simple(debug_overlay_window.as_mut(), state, input),
+    };
+    self.ui_impl = Some(ui_impl);
+    res
   }
 }
 
@@ -172,13 +141,28 @@ impl<'event> Hierarchy<UIId, UI, UIInput<'event>, FullUIInput<'event>, FullUIInp
     (InteriorRef::AsMut(&mut self.state), InteriorRef::AsMut(&mut self.entities))
   }
 
+  fn tree(&self) -> Option<&Tree<UIId>> {
+    Some(&self.tree)
+  }
+
+  fn tree_mut(&mut self) -> Option<&mut Tree<UIId>> {
+    Some(&mut self.tree)
+  }
+
   fn accumulate_results(&mut self, results: Vec<UIResult>, input: FullUIInput<'event>) -> Result<Option<(Option<HashSet<UIId>>, FullUIInput<'event>)>, UIError> {
     let err = false;
     let mut control_flow = None;
     let entities: *mut HashMap<UIId, UI> = &mut self.entities as _;
     for UIResult {id, mutate_control_flow, drop, ..} in results {
       if drop {
-        unsafe{ entities.as_mut() }.unwrap().remove(&id);
+        // Dialogs close with the window that owns them
+        for id in self.tree.remove_subtree(&id) {
+          unsafe{ entities.as_mut() }.unwrap().remove(&id);
+          if self.state.render_queue_window == Some(id) {
+            self.state.render_queue_window = None;
+            self.state.render_queue.borrow().set_waker(None);
+          }
+        }
         if id == self.state.toplevel_id {
           control_flow = Some(&ControlFlow::Exit);
         }
@@ -275,8 +259,23 @@ impl<'event> Hierarchy<UIId, UI, UIInput<'event>, FullUIInput<'event>, FullUIInp
       },
       Event::UserEvent(EscherEvent::NewDialog) => {
         let new_dialog = LicenseDialog::new(input.window_target, self.state.ui_scale);
-        let id = new_dialog.get_id();
-        self.entities.insert(id, new_dialog);
+        self.add_dialog(new_dialog);
+      },
+      Event::UserEvent(EscherEvent::OpenDebugOverlay) => {
+        let debug_overlay_window = DebugOverlayWindow::new(input.window_target, self.state.ui_scale);
+        self.add_dialog(debug_overlay_window);
+      },
+      Event::UserEvent(EscherEvent::OpenRenderQueue) => {
+        match self.state.render_queue_window.and_then(|id| self.entities.get(&id)) {
+          Some(ui) => ui.window.focus_window(),
+          None => {
+            let render_queue_window = RenderQueueWindow::new(input.window_target, self.state.ui_scale);
+            let id = render_queue_window.get_id();
+            self.state.render_queue.borrow().set_waker(Some(redraw_waker(&self.state.event_loop_proxy, id)));
+            self.state.render_queue_window = Some(id);
+            self.add_dialog(render_queue_window);
+          },
+        }
       },
       Event::NewEvents(start_cause) => {
         let req_time = match start_cause {
@@ -1,53 +1,136 @@
-use std::{sync::Arc, path::Path, ffi::{OsStr, CStr, CString, OsString}};
+use std::{sync::Arc, ffi::{OsStr, CStr, CString, OsString}, task::Waker};
 
 use egui_winit::{
   egui,
   winit::{
     self,
     event_loop::{
-      EventLoopProxy,
       EventLoopWindowTarget, ControlFlow,
     },
     window,
   }
 };
 use epaint::vec2;
-use escher_video::{RawImageRef, VideoStream};
-use super::{EscherEvent, UIState, UIType, UI, simple::{SimpleWindow, WindowDrawRes}};
+use super::{EscherEvent, UIState, UIType, UI, simple::{SimpleUI, SimpleWindow, WindowDrawRes}};
 
-use crate::{assets::{self, AssetManager}, wgpustate::util::EscherWGPUCallbackFn};
+use crate::{
+  assets::{self, Asset, AssetManager},
+  audio::{AudioDecoders, AudioEngine, AnalysisTarget, LoudnessAnalyzer, LoudnessJob, PlaybackClock, output},
+  export::{self, ExportFrames, ExportPreset, PresetLibrary, RenderQueue},
+  project::Project,
+  proxy::{self, ProxyManager, ProxySettings},
+  scrub::{self, Scrubber, ViewerFrames},
+  timeline::Timeline,
+  video::{FrameCache, RationalTime, Timecode},
+  wgpustate::{util::EscherWGPUCallbackFn, compositor::Layer},
+};
+use super::meter::LevelMeter;
 
 
 static mut frame_buffer: Vec<u8> = Vec::new();
 
+/// Time between repaints while only progress bars change
+const PROGRESS_REPAINT: std::time::Duration = std::time::Duration::from_millis(100);
+
 pub struct MainWindow {
   pub expand_assets: bool,
   pub(super) inner: SimpleWindow,
   pub asset_manager: AssetManager,
   // pub active_frame: Option<RawImageRef<'static>>,
-  render_texture_id: usize,
-  video_stream: Option<escher_video::VideoStream>
+  pub project_size: [u32; 2],
+  pub timeline: Timeline,
+  pub clock: PlaybackClock,
+  pub expand_meters: bool,
+  audio_engine: AudioEngine,
+  audio_decoders: AudioDecoders,
+  loudness_analyzer: LoudnessAnalyzer,
+  /// The last requested timeline measurement
+  loudness_job: Option<Arc<LoudnessJob>>,
+  /// Export presets, validated against the linked FFmpeg once at startup
+  pub presets: PresetLibrary,
+  /// Proxies of the media assets, `proxies.use_proxies` is the viewer's toggle
+  pub proxies: ProxyManager,
+  /// Decodes the viewer's frames while the playhead is dragged
+  scrubber: Scrubber,
+  /// Frames of the program viewer, the last frame from `scrubber` while not playing
+  viewer_frames: ViewerFrames,
+  /// Layers of the program viewer's current frame, built before every redraw
+  viewer_layers: Vec<Layer>,
 }
 
 
-impl MainWindow {
-  pub fn redraw(&mut self, ctx: &egui::Context, window: &window::Window, state: &UIState, control_flow: &mut ControlFlow) -> WindowDrawRes {
+/// Plays through the default device if there is one, otherwise the mix goes nowhere
+fn new_audio_output() -> Box<dyn output::AudioOutput> {
+  #[cfg(feature = "cpal")]
+  match output::DeviceOutput::open_default(1 << 14) {
+    Ok(device) => return Box::new(device),
+    Err(err) => eprintln!("{}", err),
+  }
+  Box::new(output::NullOutput::new(48000))
+}
+
+
+impl SimpleUI for MainWindow {
+  fn simple_window(&mut self) -> &mut SimpleWindow {
+    &mut self.inner
+  }
+
+  fn redraw(&mut self, ctx: &egui::Context, window: &window::Window, state: &UIState, control_flow: &mut ControlFlow) -> WindowDrawRes {
+    self.update_viewer();
     let inner = &mut unsafe {(self as *mut Self).as_mut()}.unwrap().inner;
     inner.redraw(ctx, window, state, control_flow, |ctx, state| self.ui(ctx, state))
   }
+}
 
-  pub fn resize(&mut self, width: Option<u32>, height: Option<u32>, scale: Option<f32>) {
-    self.inner.resize(width, height, scale)
+impl MainWindow {
+  /// Builds the program viewer's layers at the playhead, the same way the export does
+  fn update_viewer(&mut self) {
+    self.scrubber.handle_responses();
+    match self.scrubber.take_frame() {
+      Some(Ok(frame)) => self.viewer_frames.scrubbed = Some(frame),
+      Some(Err(err)) => eprintln!("Scrubbing failed: {:?}", err),
+      None => {},
+    }
+    let time = self.clock.time();
+    // The playhead moved on without scrubbing, e.g. while playing
+    if !self.scrubber.is_busy() && self.viewer_frames.scrubbed.as_ref().map_or(false, |frame| frame.time != time) {
+      self.viewer_frames.scrubbed = None;
+    }
+    let timeline = self.proxies.viewer_timeline(&self.timeline);
+    if self.clock.is_playing() {
+      self.viewer_frames.frames.prepare(&timeline, time, time + RationalTime::from_secs(export::PREPARE_AHEAD_SECS));
+    }
+    self.viewer_layers = self.inner.render_state.timeline_layers(&timeline, time, &mut self.viewer_frames)
+      .unwrap_or_default();
+    if let Some(err) = self.viewer_frames.frames.last_error.take() {
+      eprintln!("Decoding the viewer's frame failed: {:?}", err);
+    }
   }
 
   fn ui(&mut self, ctx: &egui::Context, state: &UIState) {
+    self.audio_engine.update(&self.timeline, &self.clock, &mut self.audio_decoders);
+    self.loudness_analyzer.handle_responses();
+    self.loudness_analyzer.take_finished();
+    state.render_queue.borrow_mut().update();
+    self.proxies.handle_responses();
+    // Finished jobs and decoded frames wake the window themselves
+    if self.clock.is_playing() {
+      ctx.request_repaint();
+    } else if !self.loudness_analyzer.jobs().is_empty() || self.proxies.is_busy() {
+      ctx.request_repaint_after(PROGRESS_REPAINT);
+    }
+
     egui::TopBottomPanel::top("menu_bar").show(ctx, |ui|
-      self.ui_menu_bar(ui, &state.event_loop_proxy)
+      self.ui_menu_bar(ui, state)
     );
+    egui::SidePanel::right("Meters")
+      .resizable(false)
+      .show_animated(ctx, self.expand_meters, |ui| self.ui_meters(ui));
     
     egui::TopBottomPanel::bottom("Timeline")
       .resizable(true)
       .show(ctx, |ui| {
+        self.ui_playhead(ui);
         ui.centered_and_justified(|center_ui| center_ui.label("Timeline"));
     });
     egui::SidePanel::left("Assets")
@@ -56,7 +139,13 @@ impl MainWindow {
         egui::ScrollArea::vertical().always_show_scroll(true).show(ui, |ui| {
           ui.horizontal_wrapped(|ui| {
             for asset in self.asset_manager.iter() {
-              if let Ok(asset_lock) = asset.lock() {
+              if let Ok(mut asset_lock) = asset.lock() {
+                if let Some(media) = asset_lock.as_media_mut() {
+                  if ui.add(media.as_widget()).clicked() {
+                    media.selected = !media.selected;
+                  }
+                  continue;
+                }
                 for _ in 0..10 {
                   ui.add(asset_lock.as_widget());
                 }
@@ -64,27 +153,25 @@ impl MainWindow {
             }
       })})});
     egui::CentralPanel::default().show(ctx, |ui| {
-      // ui.centered_and_justified(|center_ui| center_ui.label("Video"));
-      let (rect, _resp) = ui.allocate_exact_size(vec2(128., 128.), egui::Sense::hover());
-      // self.video_stream.as_mut().unwrap().decode_frames(1, true).expect(".");
+      // Program viewer
+      let available = ui.available_size();
+      let aspect = self.project_size[0] as f32 / self.project_size[1] as f32;
+      let viewer_size = if available.x / available.y > aspect {
+        vec2(available.y * aspect, available.y)
+      } else {
+        vec2(available.x, available.x / aspect)
+      };
+      let (rect, _resp) = ui.allocate_exact_size(viewer_size, egui::Sense::hover());
       ui.painter_at(rect).add(egui::PaintCallback {
         rect,
-        callback: Arc::new(EscherWGPUCallbackFn::RenderFrame(
-          self.render_texture_id,
-          match &self.video_stream { //FIXME: BAD!
-            Some(vs) => Some(unsafe{std::mem::transmute::<RawImageRef<'_>, RawImageRef<'static>>(vs.decoded_frm())}),
-            // Some(vs) => Some(vs.decoded_frm()),
-            None => None
-          },
-        )),
+        callback: Arc::new(EscherWGPUCallbackFn::RenderComposite(self.viewer_layers.clone())),
       });
-      //TODO: Render frame with ffmpeg
     });
 
     // self.show_dialogs(ctx);
   }
 
-  pub fn new(window_target: &EventLoopWindowTarget<EscherEvent>, scale_factor: f32) -> UI {
+  pub fn new(window_target: &EventLoopWindowTarget<EscherEvent>, scale_factor: f32, frame_cache: Arc<FrameCache>) -> UI {
     let (mut res, mut inner) = SimpleWindow::new(
       window::WindowBuilder::new()
         .with_decorations(false)
@@ -110,27 +197,10 @@ impl MainWindow {
     // );
     // let active_frame = Some(active_frame);
     
-    let video_stream: VideoStream = Ok(escher_video::PartialVideoStream::new()).and_then(|pvs| {
-      pvs.open_format_context_from_path(Path::new("local/bunny_1080p_60fps.mp4"))?
-        .open_codec_context(0, 16, -1)?
-        .create_sws_context(1280, 720, escher_video::AVPixelFormat::AV_PIX_FMT_RGBA, escher_video::SWS_Scaling::Bilinear)?
-        .create_pkt_frm()?
-        .fmapMut(|vs| {
-          vs.seek(2., escher_video::Seek::empty())?;
-          vs.decode_frames(0, true)?;
-          Ok(())
-        })?
-        .try_into()
-    }).expect("ERROR");
-    let active_frame = video_stream.decoded_frm();
-    let render_texture_id = inner.render_state.new_user_texture(
-      wgpu::Extent3d { width: active_frame.width() as _, height: active_frame.height() as _, depth_or_array_layers: 1 },
-      match active_frame.pix_fmt() {
-        escher_video::AVPixelFormat::AV_PIX_FMT_RGBA => wgpu::TextureFormat::Rgba8Unorm,
-        _ => todo!()
-      },
-      active_frame.planes()[0]
-    );
+    let project_size = [1920, 1080];
+    inner.render_state.init_compositor(project_size[0], project_size[1]);
+    let mut viewer_frames = ExportFrames::new(8);
+    viewer_frames.size = project_size;
       
 
 
@@ -160,23 +230,189 @@ impl MainWindow {
 
     res.ui_impl = Some(UIType::Main(Box::new(
       Self {
-        video_stream: Some(video_stream),
-        render_texture_id,
+        project_size,
         // active_frame: Some(active_frame),
         expand_assets: true,
         inner,
-        asset_manager: AssetManager::default()
+        asset_manager: AssetManager::default(),
+        timeline: Timeline::new((25, 1)),
+        clock: PlaybackClock::new(),
+        expand_meters: true,
+        audio_engine: AudioEngine::new(new_audio_output()),
+        audio_decoders: AudioDecoders::new(16),
+        loudness_analyzer: LoudnessAnalyzer::new(1, 48000),
+        loudness_job: None,
+        presets: PresetLibrary::load(),
+        proxies: ProxyManager::new(1, proxy::cache_dir(&std::env::current_dir().unwrap_or_default()), ProxySettings::default()),
+        scrubber: Scrubber::new(project_size, frame_cache),
+        viewer_frames: ViewerFrames { frames: viewer_frames, scrubbed: None },
+        viewer_layers: Vec::new(),
       }
     )));
     res
   }
 
-  pub fn ui_menu_bar(&mut self, ui: &mut egui::Ui, event_proxy: &EventLoopProxy<EscherEvent>) {
+  /// Wakes the window when a background job finished or a frame was decoded
+  pub fn set_waker(&self, waker: Waker) {
+    self.loudness_analyzer.set_waker(Some(waker.clone()));
+    self.proxies.set_waker(Some(waker.clone()));
+    self.scrubber.set_waker(Some(waker));
+  }
+
+  pub fn analyze_timeline_loudness(&mut self) {
+    if let Some(job) = self.loudness_job.take() {
+      job.cancel();
+    }
+    let target = AnalysisTarget::Timeline {
+      timeline: self.timeline.clone(),
+      from: RationalTime::ZERO,
+      to: self.timeline.duration(),
+    };
+    match self.loudness_analyzer.analyze(target) {
+      Ok(job) => self.loudness_job = Some(job),
+      Err(err) => eprintln!("Loudness analysis failed: {:?}", err),
+    }
+  }
+
+  fn ui_meters(&mut self, ui: &mut egui::Ui) {
+    ui.heading("Master");
+    let mixer = &self.audio_engine.mixer;
+    ui.add(LevelMeter::new(&mixer.master_meter).loudness(&mixer.master_loudness));
+
+    if let Some(job) = &self.loudness_job {
+      ui.separator();
+      ui.label("Timeline loudness");
+      match job.result() {
+        None if job.is_cancelled() => {
+          ui.label("Cancelled");
+        },
+        None if job.is_finished() => {
+          ui.label(format!("Crashed: {}", job.panic_message().unwrap_or_default()));
+        },
+        None => {
+          ui.add(egui::ProgressBar::new(job.progress()).show_percentage());
+          if ui.button("Cancel").clicked() {
+            job.cancel();
+          }
+        },
+        Some(Ok(report)) => {
+          ui.monospace(format!("I   {:.1} LUFS", report.integrated));
+          ui.monospace(format!("LRA {:.1} LU", report.range));
+          ui.monospace(format!("TP  {:.1} dBTP", report.true_peak));
+          // EBU R128: -23 LUFS ±1 LU, true peak at most -1 dBTP
+          if report.complies(crate::audio::loudness::TARGET_LUFS, 1., -1.) {
+            ui.label("Complies with EBU R128");
+          } else {
+            ui.colored_label(egui::Color32::from_rgb(220, 50, 40), "Doesn't comply with EBU R128");
+          }
+        },
+        Some(Err(err)) => {
+          ui.label(format!("Failed: {:?}", err));
+        },
+      }
+    }
+  }
+
+  /// Ruler with the playhead. Dragging it scrubs through keyframes, releasing or clicking shows
+  /// the exact frame.
+  fn ui_playhead(&mut self, ui: &mut egui::Ui) {
+    let rate = self.timeline.frame_rate;
+    let duration = self.timeline.duration().max(RationalTime::from_secs(10));
+    ui.horizontal(|ui| {
+      ui.monospace(Timecode::from_time(self.clock.time(), rate, true).to_string());
+      let (rect, resp) = ui.allocate_exact_size(vec2(ui.available_width(), 20.), egui::Sense::click_and_drag());
+      let painter = ui.painter_at(rect);
+      painter.rect_filled(rect, 2., ui.visuals().extreme_bg_color);
+      let x = rect.left() + rect.width() * self.clock.time().fraction_between(RationalTime::ZERO, duration) as f32;
+      painter.vline(x, rect.y_range(), egui::Stroke::new(2., egui::Color32::from_rgb(220, 50, 40)));
+
+      let (dragged, released) = (resp.dragged(), resp.drag_released() || resp.clicked());
+      if let Some(pos) = resp.interact_pointer_pos().filter(|_| dragged || released) {
+        let fraction = ((pos.x - rect.left()) / rect.width()).clamp(0., 1.) as f64;
+        let time = RationalTime::from_secs_f64(fraction * duration.as_secs_f64());
+        // Snap to the start of the frame
+        let time = RationalTime::from_frames(time.to_frames(rate.0, rate.1), rate.0, rate.1);
+        self.clock.pause();
+        self.clock.seek(time);
+        self.scrub(time, !dragged);
+      }
+    });
+  }
+
+  /// Shows the frame at `time` in the viewer, only the nearest keyframe unless `precise`
+  fn scrub(&mut self, time: RationalTime, precise: bool) {
+    let res = match scrub::source_at(&self.timeline, time) {
+      Some((source, source_time)) => {
+        let source = self.proxies.viewer_source(source);
+        if precise {
+          self.scrubber.release(&source, time, source_time)
+        } else {
+          self.scrubber.drag(&source, time, source_time)
+        }
+      },
+      None => {
+        self.scrubber.cancel();
+        Ok(())
+      },
+    };
+    if let Err(err) = res {
+      eprintln!("Scrubbing failed: {:?}", err);
+    }
+  }
+
+  /// Adds the whole timeline to the render queue with the settings of `preset`, written to the
+  /// working directory
+  pub fn queue_timeline_export(&self, queue: &mut RenderQueue, preset: &ExportPreset) {
+    let settings = match preset.settings(self.project_size, self.timeline.frame_rate) {
+      Ok(settings) => settings,
+      Err(missing) => {
+        eprintln!("Export failed: missing {}", missing);
+        return;
+      },
+    };
+    let dir = std::env::current_dir().unwrap_or_default();
+    let output = (0..).map(|i| preset.output_path(&dir, &format!("export_{}", i)))
+      .find(|path| !path.exists() && !queue.jobs().iter().any(|j| &j.job.output == path))
+      .unwrap();
+    let res = queue.push(self.timeline.clone(), RationalTime::ZERO, self.timeline.duration(), settings, output);
+    if let Err(err) = res {
+      eprintln!("Export failed: {}", err);
+    }
+  }
+
+  /// Queues proxies for the selected media assets
+  fn generate_selected_proxies(&mut self) {
+    for asset in self.asset_manager.iter() {
+      if let Ok(mut asset_lock) = asset.lock() {
+        if let Some(media) = asset_lock.as_media_mut().filter(|media| media.selected) {
+          match self.proxies.generate(&media.path, media.stream_idx) {
+            Ok(job) => media.proxy = Some(job),
+            Err(err) => eprintln!("Proxy of {} failed: {:?}", media.path.display(), err),
+          }
+        }
+      }
+    }
+  }
+
+  /// Writes the project to `project.escher` in the working directory, e.g. for `escher-cli render`
+  fn save_project(&self) {
+    let path = std::env::current_dir().unwrap_or_default().join("project.escher");
+    if let Err(err) = Project::new(self.project_size, self.timeline.clone()).save(&path) {
+      eprintln!("Saving {} failed: {}", path.display(), err);
+    }
+  }
+
+  pub fn ui_menu_bar(&mut self, ui: &mut egui::Ui, state: &UIState) {
+    let event_proxy = &state.event_loop_proxy;
     egui::menu::bar(ui, |ui| {
       ui.menu_button("File", |ui| {
         if ui.button("Assets").clicked() {
           self.expand_assets = !self.expand_assets;
         }
+        if ui.button("Save Project").clicked() {
+          self.save_project();
+          ui.close_menu();
+        }
         ui.separator();
         if ui.button("Exit").clicked() {
           event_proxy.send_event(EscherEvent::Exit(0)).unwrap();
@@ -185,10 +421,65 @@ impl MainWindow {
 
       ui.menu_button("Edit", |_| {});
 
+      ui.menu_button("Media", |ui| {
+        ui.checkbox(&mut self.proxies.use_proxies, "Use Proxies")
+          .on_hover_text("The viewer shows proxies where they are ready, exports always use the originals");
+        if ui.button("Create Proxies for Selected Assets").clicked() {
+          self.generate_selected_proxies();
+          ui.close_menu();
+        }
+      });
+
+      ui.menu_button("Audio", |ui| {
+        if ui.button("Meters").clicked() {
+          self.expand_meters = !self.expand_meters;
+        }
+        if ui.button("Reset Loudness").clicked() {
+          self.audio_engine.mixer.master_loudness.reset();
+        }
+        ui.separator();
+        if ui.button("Analyze Timeline Loudness").clicked() {
+          self.analyze_timeline_loudness();
+          ui.close_menu();
+        }
+      });
+
+      ui.menu_button("Render", |ui| {
+        ui.menu_button("Add Timeline to Render Queue", |ui| {
+          let mut chosen = None;
+          for available in self.presets.iter() {
+            let button = ui.add_enabled(available.is_available(), egui::Button::new(&available.preset.name))
+              .on_disabled_hover_text(available.missing_text());
+            if button.clicked() {
+              chosen = Some(available.preset.clone());
+            }
+          }
+          if !self.presets.errors.is_empty() {
+            ui.separator();
+            for (path, err) in self.presets.errors.iter() {
+              ui.colored_label(egui::Color32::from_rgb(220, 50, 40), format!("{}: {}", path.display(), err));
+            }
+          }
+          if let Some(preset) = chosen {
+            self.queue_timeline_export(&mut state.render_queue.borrow_mut(), &preset);
+            event_proxy.send_event(EscherEvent::OpenRenderQueue).unwrap();
+            ui.close_menu();
+          }
+        });
+        if ui.button("Render Queue…").clicked() {
+          event_proxy.send_event(EscherEvent::OpenRenderQueue).unwrap();
+          ui.close_menu();
+        }
+      });
+
       ui.menu_button("Help", |ui| {
         if ui.button("License").clicked() {
           event_proxy.send_event(EscherEvent::NewDialog).unwrap()
         }
+        if ui.button("Debug Overlay…").clicked() {
+          event_proxy.send_event(EscherEvent::OpenDebugOverlay).unwrap();
+          ui.close_menu();
+        }
       });
     });
   }
@@ -0,0 +1,114 @@
+use egui_winit::egui::{self, Color32, Rect, Stroke, pos2, vec2, Align2, FontId};
+
+use crate::audio::{Meter, LoudnessMeter, loudness::TARGET_LUFS};
+
+/// Lowest level the bars show
+const FLOOR_DB: f32 = -60.;
+const TICKS_DB: [f32; 8] = [0., -6., -12., -18., -24., -36., -48., -60.];
+
+/// Vertical stereo level meter: RMS as filled bars, held peaks as lines, and optionally the EBU
+/// R128 readings below it
+pub struct LevelMeter<'a> {
+  meter: &'a Meter,
+  loudness: Option<&'a LoudnessMeter>,
+  height: f32,
+}
+
+
+fn level_color(db: f32) -> Color32 {
+  if db >= -6. {
+    Color32::from_rgb(220, 50, 40)
+  } else if db >= -18. {
+    Color32::from_rgb(230, 200, 40)
+  } else {
+    Color32::from_rgb(60, 190, 80)
+  }
+}
+
+/// LUFS or LU values, `-inf` as a dash
+fn format_loudness(value: f64) -> String {
+  if value.is_finite() { format!("{:.1}", value) } else { "–".to_string() }
+}
+
+impl<'a> LevelMeter<'a> {
+  pub fn new(meter: &'a Meter) -> Self {
+    Self { meter, loudness: None, height: 200. }
+  }
+
+  pub fn loudness(mut self, loudness: &'a LoudnessMeter) -> Self {
+    self.loudness = Some(loudness);
+    self
+  }
+
+  pub fn height(mut self, height: f32) -> Self {
+    self.height = height;
+    self
+  }
+}
+
+impl egui::Widget for LevelMeter<'_> {
+  fn ui(self, ui: &mut egui::Ui) -> egui::Response {
+    let (bar_width, scale_width, gap) = (10., 22., 2.);
+    let (rect, response) = ui.allocate_exact_size(vec2(scale_width + 2.*bar_width + gap, self.height), egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    let visuals = ui.visuals();
+    let y_of = |db: f32| rect.bottom() - (1. - db.clamp(FLOOR_DB, 0.) / FLOOR_DB) * rect.height();
+
+    for db in TICKS_DB {
+      let y = y_of(db);
+      painter.text(pos2(rect.left() + scale_width - 3., y), Align2::RIGHT_CENTER, format!("{}", db), FontId::monospace(8.), visuals.weak_text_color());
+    }
+
+    let [peak_l, peak_r] = self.meter.peak_db();
+    let [rms_l, rms_r] = self.meter.rms_db();
+    for (i, (peak, rms)) in [(peak_l, rms_l), (peak_r, rms_r)].into_iter().enumerate() {
+      let left = rect.left() + scale_width + i as f32 * (bar_width + gap);
+      let bar = Rect::from_min_max(pos2(left, rect.top()), pos2(left + bar_width, rect.bottom()));
+      painter.rect_filled(bar, 0., visuals.extreme_bg_color);
+      if rms > FLOOR_DB {
+        // Filled in segments so the colors mark the zones
+        let mut from = FLOOR_DB;
+        for to in [-18., -6., 0.] {
+          if rms > from {
+            let segment = Rect::from_min_max(pos2(bar.left(), y_of(rms.min(to))), pos2(bar.right(), y_of(from)));
+            painter.rect_filled(segment, 0., level_color(from));
+          }
+          from = to;
+        }
+      }
+      if peak > FLOOR_DB {
+        let y = y_of(peak);
+        painter.line_segment([pos2(bar.left(), y), pos2(bar.right(), y)], Stroke::new(2., level_color(peak)));
+      }
+    }
+    if self.meter.is_clipping() {
+      let led = Rect::from_min_max(pos2(rect.left() + scale_width, rect.top()), pos2(rect.right(), rect.top() + 3.));
+      painter.rect_filled(led, 0., level_color(0.));
+    }
+
+    if let Some(loudness) = self.loudness {
+      let integrated = loudness.integrated();
+      egui::Grid::new(response.id.with("loudness")).num_columns(2).show(ui, |ui| {
+        let rows = [
+          ("M", format_loudness(loudness.momentary()), "LUFS"),
+          ("S", format_loudness(loudness.short_term()), "LUFS"),
+          ("I", format_loudness(integrated), "LUFS"),
+          ("LRA", format_loudness(loudness.loudness_range()), "LU"),
+          ("TP", format_loudness(loudness.true_peak_db()), "dBTP"),
+        ];
+        for (name, value, unit) in rows {
+          ui.label(name);
+          let text = egui::RichText::new(format!("{} {}", value, unit)).monospace();
+          // Integrated loudness off the broadcast target by more than 1 LU is highlighted
+          if name == "I" && integrated.is_finite() && (integrated - TARGET_LUFS).abs() > 1. {
+            ui.label(text.color(level_color(-6.)));
+          } else {
+            ui.label(text);
+          }
+          ui.end_row();
+        }
+      });
+    }
+    response
+  }
+}
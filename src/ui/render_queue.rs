@@ -0,0 +1,171 @@
+use egui_winit::{
+  egui::{
+    self,
+    Context,
+    CentralPanel
+  },
+  winit::{
+    self,
+    window,
+    event_loop::{
+      ControlFlow,
+      EventLoopWindowTarget
+    }
+  }
+};
+
+use super::{simple::{SimpleUI, SimpleWindow, WindowDrawRes}, UIState, EscherEvent, UI, UIType};
+use crate::export::{ExportStatus, QueuedJob};
+
+/// Lists the jobs of `UIState::render_queue` with their progress
+pub struct RenderQueueWindow {
+  pub(super) inner: SimpleWindow,
+}
+
+/// What the user asked for in a row of the queue
+enum JobAction {
+  Pause(u64),
+  Resume(u64),
+  Cancel(u64),
+  Retry(u64),
+}
+
+
+fn format_duration(duration: std::time::Duration) -> String {
+  let secs = duration.as_secs();
+  format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+fn status_text(status: &ExportStatus) -> String {
+  match status {
+    ExportStatus::Queued => "Queued".to_string(),
+    ExportStatus::Running => "Running".to_string(),
+    ExportStatus::Paused => "Paused".to_string(),
+    ExportStatus::Finished => "Finished".to_string(),
+    ExportStatus::Failed(err) => format!("Failed: {}", err),
+    ExportStatus::Cancelled => "Cancelled".to_string(),
+  }
+}
+
+
+impl SimpleUI for RenderQueueWindow {
+  fn simple_window(&mut self) -> &mut SimpleWindow {
+    &mut self.inner
+  }
+
+  fn redraw(&mut self, ctx: &Context, window: &window::Window, state: &UIState, control_flow: &mut ControlFlow) -> WindowDrawRes {
+    let inner = &mut unsafe {(self as *mut Self).as_mut()}.unwrap().inner;
+    inner.redraw(ctx, window, state, control_flow, |ctx, state| self.ui(ctx, state))
+  }
+}
+
+impl RenderQueueWindow {
+  fn ui_job(ui: &mut egui::Ui, queued: &QueuedJob) -> Option<JobAction> {
+    let progress = &queued.progress;
+    let id = queued.job.id;
+    let mut action = None;
+    ui.label(queued.job.output.display().to_string());
+    ui.add(egui::ProgressBar::new(progress.fraction()).show_percentage());
+    ui.horizontal(|ui| {
+      ui.label(status_text(&progress.status));
+      if let Some(message) = &queued.message {
+        ui.label(message);
+      }
+      ui.label(format!("{} / {} frames", progress.frame, progress.total_frames));
+      if progress.status == ExportStatus::Running {
+        ui.label(format!("{:.1} fps", progress.fps));
+        if let Some(eta) = progress.eta {
+          ui.label(format!("ETA {}", format_duration(eta)));
+        }
+      }
+    });
+    ui.horizontal(|ui| {
+      match progress.status {
+        ExportStatus::Queued | ExportStatus::Running => {
+          if ui.button("Pause").clicked() {
+            action = Some(JobAction::Pause(id));
+          }
+        },
+        ExportStatus::Paused => {
+          if ui.button("Resume").clicked() {
+            action = Some(JobAction::Resume(id));
+          }
+        },
+        ExportStatus::Failed(_) | ExportStatus::Cancelled => {
+          if ui.button("Retry").clicked() {
+            action = Some(JobAction::Retry(id));
+          }
+        },
+        ExportStatus::Finished => {},
+      }
+      if !progress.status.is_done() && ui.button("Cancel").clicked() {
+        action = Some(JobAction::Cancel(id));
+      }
+    });
+    action
+  }
+
+  fn ui(&mut self, ctx: &Context, state: &UIState) {
+    let mut queue = state.render_queue.borrow_mut();
+    // Progress reports wake the window, see `RenderQueue::set_waker`
+    queue.update();
+
+    let mut action = None;
+    let mut clear_done = false;
+    CentralPanel::default().show(ctx, |ui| {
+      ui.horizontal(|ui| {
+        ui.heading("Render Queue");
+        clear_done = ui.button("Clear Done").clicked();
+      });
+      ui.separator();
+      if queue.jobs().is_empty() {
+        ui.label("No exports queued");
+      }
+      egui::ScrollArea::vertical().show(ui, |ui| {
+        for queued in queue.jobs() {
+          if let Some(job_action) = Self::ui_job(ui, queued) {
+            action = Some(job_action);
+          }
+          ui.separator();
+        }
+      });
+    });
+
+    let res = match action {
+      Some(JobAction::Pause(id)) => queue.pause(id),
+      Some(JobAction::Resume(id)) => queue.resume(id),
+      Some(JobAction::Cancel(id)) => queue.cancel(id),
+      Some(JobAction::Retry(id)) => queue.retry(id),
+      None => Ok(()),
+    };
+    if let Err(err) = res {
+      eprintln!("Render queue: {}", err);
+    }
+    if clear_done {
+      queue.clear_done();
+    }
+  }
+
+  pub fn new(window_target: &EventLoopWindowTarget<EscherEvent>, scale_factor: f32) -> UI {
+    let (mut res, inner) = SimpleWindow::new(
+      window::WindowBuilder::new()
+        .with_decorations(true)
+        .with_resizable(true)
+        .with_transparent(true)
+        .with_title("Render Queue")
+        .with_inner_size(winit::dpi::PhysicalSize {
+          width: 640,
+          height: 480,
+        }),
+      window_target,
+      scale_factor
+    );
+
+    res.ui_impl = Some(UIType::RenderQueue(Box::new(
+      Self { inner }
+    )));
+    res
+  }
+
+
+}
@@ -27,6 +27,19 @@ pub enum WindowDrawRes {
   RedrawScheduled(time::Duration)
 }
 
+/// A window drawn into a `SimpleWindow`. `UI::run` redraws, resizes and closes all of them the
+/// same way.
+pub trait SimpleUI {
+  fn simple_window(&mut self) -> &mut SimpleWindow;
+
+  /// Runs the window's UI and draws it
+  fn redraw(&mut self, ctx: &egui::Context, window: &window::Window, state: &UIState, control_flow: &mut ControlFlow) -> WindowDrawRes;
+
+  fn resize(&mut self, width: Option<u32>, height: Option<u32>, scale: Option<f32>) {
+    self.simple_window().resize(width, height, scale)
+  }
+}
+
 impl SimpleWindow {
   pub fn redraw(&mut self, ctx: &egui::Context, window: &window::Window, state: &UIState, control_flow: &mut ControlFlow, run_ui: impl FnOnce(&egui::Context, &UIState)) -> WindowDrawRes {
 
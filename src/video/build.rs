@@ -54,7 +54,9 @@ fn invoke_buildgen(){
     //Only public interface to library
     .allowlist_function("vs_.*")
     .allowlist_function("vf_.*")
+    .allowlist_function("ve_.*")
     .allowlist_function("av_buffer_get_ref_count")
+    .allowlist_function("avcodec_get_name")
 
     .allowlist_function("avformat_close_input")
     .allowlist_function("avcodec_close")
@@ -0,0 +1,72 @@
+#ifndef VIDEOENCODER_H
+#define VIDEOENCODER_H
+
+
+#include <libavformat/avformat.h>
+#include <libavcodec/avcodec.h>
+#include <libswscale/swscale.h>
+#include <stdbool.h>
+#include "VideoStream.h"
+
+
+/// @brief Allocates the muxer for a new file. Streams have to be added before ve_write_header.
+/// @param path Output file, or a pattern like "frame_%05d.png" for image sequences
+/// @param format_name Short name of the muxer, or NULL to guess it from the extension of path
+/// @return vs_muxer_not_found if there is no such muxer
+VideoStreamResult ve_open_output(const char *path, const char *format_name, AVFormatContext **fmt_ctx, int *err);
+
+/// @brief Adds a video stream and opens its encoder.
+/// @param time_base Duration of one frame. Frames are numbered in this unit, see ve_encode_frame
+/// @param bit_rate Target bit rate in bit/s, or 0 to leave rate control to the encoder
+/// @param options Private options of the encoder as "key=value:key=value", e.g. "crf=18:preset=slow", or NULL
+/// @return vs_encoder_not_found if FFmpeg was built without the encoder
+VideoStreamResult ve_add_video_stream(AVFormatContext *fmt_ctx, const char *encoder_name, int width, int height, enum AVPixelFormat pix_fmt,
+  AVRational time_base, int64_t bit_rate, uint32_t nThreads, const char *options, AVCodecContext **codec_ctx, AVStream **stream, int *err);
+
+/// @brief Adds an audio stream and opens its encoder. The sample format is the encoder's preferred one, float if it
+///        supports it, and can be read from codec_ctx->sample_fmt.
+/// @param time_base Is 1/sample_rate, frames are numbered by their first sample
+/// @see ve_add_video_stream
+VideoStreamResult ve_add_audio_stream(AVFormatContext *fmt_ctx, const char *encoder_name, int sample_rate, int channels, int64_t bit_rate,
+  const char *options, AVCodecContext **codec_ctx, AVStream **stream, int *err);
+
+/// @brief Allocates a frame with buffers matching the encoder. Audio frames hold codec_ctx->frame_size samples, or 1024 if
+///        the encoder accepts any number.
+VideoStreamResult ve_alloc_frame(AVCodecContext *codec_ctx, AVFrame **frm, int *err);
+
+/// @brief Makes sure the buffers of frm aren't shared with the encoder anymore, call before writing into it.
+VideoStreamResult ve_make_writable(AVFrame *frm, int *err);
+
+/// @brief Converts tightly packed RGBA rows with sws_ctx into frm.
+VideoStreamResult ve_scale_rgba(struct SwsContext *sws_ctx, const uint8_t *rgba, int width, int height, AVFrame *frm, int *err);
+
+/// @brief Opens the output file unless the muxer doesn't need one and writes the header.
+VideoStreamResult ve_write_header(AVFormatContext *fmt_ctx, const char *path, int *err);
+
+/// @brief Sends frm to the encoder and writes all packets it returns.
+/// @param frm Frame with pts in codec_ctx->time_base, or NULL to flush the encoder
+/// @return vs_eof after the encoder was flushed completely
+VideoStreamResult ve_encode_frame(AVFormatContext *fmt_ctx, AVCodecContext *codec_ctx, AVStream *stream, AVFrame *frm, AVPacket *pkt, int *err);
+
+/// @brief Writes the trailer. All encoders have to be flushed before.
+VideoStreamResult ve_write_trailer(AVFormatContext *fmt_ctx, int *err);
+
+/// @brief Closes the output file and frees the muxer with its streams.
+void ve_close_output(AVFormatContext **fmt_ctx);
+
+void ve_free_codec_context(AVCodecContext **codec_ctx);
+
+/// @brief Whether FFmpeg was built with an encoder of that name, e.g. "libx264"
+bool ve_has_encoder(const char *encoder_name);
+
+/// @brief Whether FFmpeg was built with a muxer of that name, e.g. "mp4"
+bool ve_has_muxer(const char *format_name);
+
+/// @brief Whether the encoder accepts frames in pix_fmt. Encoders which don't list their formats accept any.
+bool ve_encoder_supports_pix_fmt(const char *encoder_name, enum AVPixelFormat pix_fmt);
+
+/// @brief Pixel format with that name, e.g. "yuv420p", or AV_PIX_FMT_NONE
+enum AVPixelFormat ve_pix_fmt_from_name(const char *pix_fmt_name);
+
+
+#endif
@@ -50,6 +50,8 @@ enum VideoStreamResult{
   vs_stream_not_found,
   vs_decoder_not_found,
   vs_null_reference,
+  vs_encoder_not_found,
+  vs_muxer_not_found,
 };
 
 // typedef struct VideoStream VideoStream;
@@ -86,8 +88,8 @@ VideoStreamResult vs_create_pkt_frm(AVPacket **pkt, AVFrame **frm, AVFrame **sws
 VideoStreamResult vs_seek(AVFormatContext *fmt_ctx, AVStream *stream, int64_t timestamp, int flags, AVCodecContext *codec_ctx_if_decode_frames, AVPacket *pkt, AVFrame *frm, int* err);
 
 /// @brief See vs_seek
-/// @param seconds Timestamp as double
-VideoStreamResult vs_seek_at(AVFormatContext *fmt_ctx, AVStream *stream, double seconds, int flags, AVCodecContext *codec_ctx_if_decode_frames, AVPacket *pkt, AVFrame *frm, int* err);
+/// @param time_num, time_den Time in seconds as the fraction time_num/time_den, rounded down to the stream's time base
+VideoStreamResult vs_seek_at(AVFormatContext *fmt_ctx, AVStream *stream, int64_t time_num, int64_t time_den, int flags, AVCodecContext *codec_ctx_if_decode_frames, AVPacket *pkt, AVFrame *frm, int* err);
 
 
 /// @brief Decode nFrames number of new frames and optionally apply sws_context to last frame. If pkt->dts != frm->pkt_dts or frm->pkt_dts == AV_NOPTS_VALUE,
@@ -119,5 +121,10 @@ VideoStreamResult vs_decode(AVFormatContext *fmt_ctx, AVCodecContext *codec_ctx,
 
 
 
+/// @brief Name of a pixel or sample format as stored in AVCodecParameters.format, or NULL if it is unknown
+/// @param media_type Decides whether format is an AVPixelFormat or an AVSampleFormat
+const char *vs_format_name(enum AVMediaType media_type, int format);
+
+
 #endif
 
@@ -0,0 +1,209 @@
+#include "VideoEncoder.h"
+#include <libavutil/opt.h>
+#include <libavutil/pixdesc.h>
+
+
+/// Audio frame size for encoders which accept any number of samples per frame
+#define VE_VARIABLE_FRAME_SIZE 1024
+
+
+VideoStreamResult ve_open_output(const char *path, const char *format_name, AVFormatContext **fmt_ctx, int *err){
+  *err = avformat_alloc_output_context2(fmt_ctx, NULL, format_name, path);
+  if(*err == AVERROR(EINVAL) || (*err >= 0 && !*fmt_ctx))
+    return vs_muxer_not_found;
+  if(*err < 0)
+    return vs_ffmpeg_errorcode;
+  return vs_success;
+}
+
+static VideoStreamResult ve_open_encoder(AVFormatContext *fmt_ctx, const AVCodec *codec, const char *options, AVCodecContext *codec_ctx,
+  AVStream **stream, int *err){
+  AVDictionary *dict = NULL;
+  if(fmt_ctx->oformat->flags & AVFMT_GLOBALHEADER)
+    codec_ctx->flags |= AV_CODEC_FLAG_GLOBAL_HEADER;
+  if(options && (*err = av_dict_parse_string(&dict, options, "=", ":", 0)) < 0){
+    av_dict_free(&dict);
+    return vs_ffmpeg_errorcode;
+  }
+  *err = avcodec_open2(codec_ctx, codec, &dict);
+  av_dict_free(&dict);
+  if(*err < 0)
+    return vs_ffmpeg_errorcode;
+
+  if(!(*stream = avformat_new_stream(fmt_ctx, NULL)))
+    return vs_null_reference;
+  (*stream)->time_base = codec_ctx->time_base;
+  if((*err = avcodec_parameters_from_context((*stream)->codecpar, codec_ctx)) < 0)
+    return vs_ffmpeg_errorcode;
+  *err = 0;
+  return vs_success;
+}
+
+VideoStreamResult ve_add_video_stream(AVFormatContext *fmt_ctx, const char *encoder_name, int width, int height, enum AVPixelFormat pix_fmt,
+  AVRational time_base, int64_t bit_rate, uint32_t nThreads, const char *options, AVCodecContext **codec_ctx, AVStream **stream, int *err){
+  const AVCodec *codec = avcodec_find_encoder_by_name(encoder_name);
+  if(!codec || codec->type != AVMEDIA_TYPE_VIDEO)
+    return vs_encoder_not_found;
+  if(!(*codec_ctx = avcodec_alloc_context3(codec)))
+    return vs_null_reference;
+
+  (*codec_ctx)->width = width;
+  (*codec_ctx)->height = height;
+  (*codec_ctx)->pix_fmt = pix_fmt;
+  (*codec_ctx)->time_base = time_base;
+  (*codec_ctx)->framerate = av_inv_q(time_base);
+  (*codec_ctx)->sample_aspect_ratio = (AVRational){1, 1};
+  (*codec_ctx)->thread_count = nThreads;
+  if(bit_rate > 0)
+    (*codec_ctx)->bit_rate = bit_rate;
+
+  return ve_open_encoder(fmt_ctx, codec, options, *codec_ctx, stream, err);
+}
+
+/// Prefers float formats, the mixer works in float
+static enum AVSampleFormat ve_pick_sample_fmt(const AVCodec *codec){
+  const enum AVSampleFormat *fmt = codec->sample_fmts;
+  if(!fmt)
+    return AV_SAMPLE_FMT_FLTP;
+  for(; *fmt != AV_SAMPLE_FMT_NONE; fmt++)
+    if(*fmt == AV_SAMPLE_FMT_FLTP || *fmt == AV_SAMPLE_FMT_FLT)
+      return *fmt;
+  return codec->sample_fmts[0];
+}
+
+VideoStreamResult ve_add_audio_stream(AVFormatContext *fmt_ctx, const char *encoder_name, int sample_rate, int channels, int64_t bit_rate,
+  const char *options, AVCodecContext **codec_ctx, AVStream **stream, int *err){
+  const AVCodec *codec = avcodec_find_encoder_by_name(encoder_name);
+  if(!codec || codec->type != AVMEDIA_TYPE_AUDIO)
+    return vs_encoder_not_found;
+  if(!(*codec_ctx = avcodec_alloc_context3(codec)))
+    return vs_null_reference;
+
+  (*codec_ctx)->sample_fmt = ve_pick_sample_fmt(codec);
+  (*codec_ctx)->sample_rate = sample_rate;
+  (*codec_ctx)->time_base = (AVRational){1, sample_rate};
+  av_channel_layout_default(&(*codec_ctx)->ch_layout, channels);
+  if(bit_rate > 0)
+    (*codec_ctx)->bit_rate = bit_rate;
+
+  return ve_open_encoder(fmt_ctx, codec, options, *codec_ctx, stream, err);
+}
+
+VideoStreamResult ve_alloc_frame(AVCodecContext *codec_ctx, AVFrame **frm, int *err){
+  if(!(*frm = av_frame_alloc()))
+    return vs_null_reference;
+  if(codec_ctx->codec_type == AVMEDIA_TYPE_VIDEO){
+    (*frm)->width = codec_ctx->width;
+    (*frm)->height = codec_ctx->height;
+    (*frm)->format = codec_ctx->pix_fmt;
+  } else {
+    const bool variable = codec_ctx->codec->capabilities & AV_CODEC_CAP_VARIABLE_FRAME_SIZE;
+    (*frm)->nb_samples = variable || codec_ctx->frame_size <= 0 ? VE_VARIABLE_FRAME_SIZE : codec_ctx->frame_size;
+    (*frm)->format = codec_ctx->sample_fmt;
+    (*frm)->sample_rate = codec_ctx->sample_rate;
+    if((*err = av_channel_layout_copy(&(*frm)->ch_layout, &codec_ctx->ch_layout)) < 0)
+      return vs_ffmpeg_errorcode;
+  }
+  if((*err = av_frame_get_buffer(*frm, 0)) < 0)
+    return vs_ffmpeg_errorcode;
+  *err = 0;
+  return vs_success;
+}
+
+VideoStreamResult ve_make_writable(AVFrame *frm, int *err){
+  if(!frm)
+    return vs_null_reference;
+  if((*err = av_frame_make_writable(frm)) < 0)
+    return vs_ffmpeg_errorcode;
+  *err = 0;
+  return vs_success;
+}
+
+VideoStreamResult ve_scale_rgba(struct SwsContext *sws_ctx, const uint8_t *rgba, int width, int height, AVFrame *frm, int *err){
+  if(!(sws_ctx && rgba && frm))
+    return vs_null_reference;
+  VideoStreamResult res = ve_make_writable(frm, err);
+  if(res != vs_success)
+    return res;
+  const uint8_t *const src[1] = {rgba};
+  const int src_stride[1] = {4*width};
+  if((*err = sws_scale(sws_ctx, src, src_stride, 0, height, frm->data, frm->linesize)) < 0)
+    return vs_ffmpeg_errorcode;
+  *err = 0;
+  return vs_success;
+}
+
+VideoStreamResult ve_write_header(AVFormatContext *fmt_ctx, const char *path, int *err){
+  if(!(fmt_ctx->oformat->flags & AVFMT_NOFILE) && (*err = avio_open(&fmt_ctx->pb, path, AVIO_FLAG_WRITE)) < 0)
+    return vs_io;
+  if((*err = avformat_write_header(fmt_ctx, NULL)) < 0)
+    return vs_ffmpeg_errorcode;
+  *err = 0;
+  return vs_success;
+}
+
+VideoStreamResult ve_encode_frame(AVFormatContext *fmt_ctx, AVCodecContext *codec_ctx, AVStream *stream, AVFrame *frm, AVPacket *pkt, int *err){
+  if((*err = avcodec_send_frame(codec_ctx, frm)) < 0)
+    return *err == AVERROR_EOF ? vs_eof : vs_ffmpeg_errorcode;
+  while(true){
+    *err = avcodec_receive_packet(codec_ctx, pkt);
+    if(*err == AVERROR(EAGAIN)){
+      *err = 0;
+      return vs_success;
+    }
+    if(*err == AVERROR_EOF)
+      return vs_eof;
+    if(*err < 0)
+      return vs_ffmpeg_errorcode;
+
+    av_packet_rescale_ts(pkt, codec_ctx->time_base, stream->time_base);
+    pkt->stream_index = stream->index;
+    // Takes ownership of the packet's data and leaves pkt blank
+    if((*err = av_interleaved_write_frame(fmt_ctx, pkt)) < 0)
+      return vs_ffmpeg_errorcode;
+  }
+}
+
+VideoStreamResult ve_write_trailer(AVFormatContext *fmt_ctx, int *err){
+  if((*err = av_write_trailer(fmt_ctx)) < 0)
+    return vs_ffmpeg_errorcode;
+  *err = 0;
+  return vs_success;
+}
+
+void ve_close_output(AVFormatContext **fmt_ctx){
+  if(!*fmt_ctx)
+    return;
+  if(!((*fmt_ctx)->oformat->flags & AVFMT_NOFILE))
+    avio_closep(&(*fmt_ctx)->pb);
+  avformat_free_context(*fmt_ctx);
+  *fmt_ctx = NULL;
+}
+
+void ve_free_codec_context(AVCodecContext **codec_ctx){
+  avcodec_free_context(codec_ctx);
+}
+
+bool ve_has_encoder(const char *encoder_name){
+  return avcodec_find_encoder_by_name(encoder_name) != NULL;
+}
+
+bool ve_has_muxer(const char *format_name){
+  return av_guess_format(format_name, NULL, NULL) != NULL;
+}
+
+bool ve_encoder_supports_pix_fmt(const char *encoder_name, enum AVPixelFormat pix_fmt){
+  const AVCodec *codec = avcodec_find_encoder_by_name(encoder_name);
+  if(!codec || codec->type != AVMEDIA_TYPE_VIDEO)
+    return false;
+  if(!codec->pix_fmts)
+    return true;
+  for(const enum AVPixelFormat *p = codec->pix_fmts; *p != AV_PIX_FMT_NONE; p++)
+    if(*p == pix_fmt)
+      return true;
+  return false;
+}
+
+enum AVPixelFormat ve_pix_fmt_from_name(const char *pix_fmt_name){
+  return av_get_pix_fmt(pix_fmt_name);
+}
@@ -1,6 +1,9 @@
 #include "VideoStream.h"
 #include "priv_DecodingDecision.h"
 #include <assert.h>
+#include <libavutil/mathematics.h>
+#include <libavutil/pixdesc.h>
+#include <libavutil/samplefmt.h>
 
 
 VideoStreamResult vs_open_format_context_from_path(char *path, AVFormatContext **fmt_ctx, int *err){
@@ -182,8 +185,11 @@ VideoStreamResult vs_seek(AVFormatContext *fmt_ctx, AVStream *stream, int64_t ti
   return vs_success;
 }
 
-VideoStreamResult vs_seek_at(AVFormatContext *fmt_ctx, AVStream *stream, double seconds, int flags, AVCodecContext *codec_ctx_if_decode_frames, AVPacket *pkt, AVFrame *frm, int* err){
-  const int64_t timestamp = seconds * stream->time_base.den/stream->time_base.num;
+VideoStreamResult vs_seek_at(AVFormatContext *fmt_ctx, AVStream *stream, int64_t time_num, int64_t time_den, int flags, AVCodecContext *codec_ctx_if_decode_frames, AVPacket *pkt, AVFrame *frm, int* err){
+  if(time_den <= 0 || stream->time_base.num <= 0)
+    return vs_timestamp_out_of_bounds;
+  // time_num/time_den * tb.den/tb.num without the rounding errors of a double
+  const int64_t timestamp = av_rescale_rnd(time_num, stream->time_base.den, time_den * stream->time_base.num, AV_ROUND_DOWN);
   return vs_seek(fmt_ctx, stream, timestamp, flags, codec_ctx_if_decode_frames, pkt, frm, err);
 }
 
@@ -343,5 +349,15 @@ VideoStreamResult vf_decode_sws_frame(AVFrame *frm, struct SwsContext *sws_ctx,
   return vs_decode_frames(NULL, NULL, NULL, NULL, frm, sws_ctx, swsfrm, 0, err);
 }
 
-
-
+const char *vs_format_name(enum AVMediaType media_type, int format){
+  if(format < 0)
+    return NULL;
+  switch(media_type){
+    case AVMEDIA_TYPE_VIDEO:
+      return av_get_pix_fmt_name((enum AVPixelFormat)format);
+    case AVMEDIA_TYPE_AUDIO:
+      return av_get_sample_fmt_name((enum AVSampleFormat)format);
+    default:
+      return NULL;
+  }
+}
@@ -1,11 +1,12 @@
 #include "VideoStream.h"
 #include <stdio.h>
 #include <stdint.h>
+#include <libavutil/mathematics.h>
 
-int my_renderframe(char *path, double seconds, int *width, int *height, int *outwidth, int *outheight, uint8_t **rgb);
+int my_renderframe(char *path, int64_t time_num, int64_t time_den, int *width, int *height, int *outwidth, int *outheight, uint8_t **rgb);
 
-double parseTimeInSecs(const char *s);
-char *parseTimeFromSecs(double t);
+int parseTime(const char *s, int64_t *num, int64_t *den);
+char *formatTime(int64_t num, int64_t den);
 
 void dumbFctxInfo(AVFormatContext *fctx, int defaultIdx);
 void dumbCctxInfo(AVCodecContext *cctx);
@@ -14,30 +15,42 @@ size_t writeBMPHeader(FILE *f, uint32_t width, uint32_t height);
 
 
 int main(int argc, char** argv){
-  const double skip_secs_default = 10;
+  const int64_t skip_secs_default = 10;
 
   int width = -1, height = -1, outwidth = 1280, outheight = 720;
   char *path;
   uint8_t *data = NULL;
-  double skip_secs = 0;
+  // skip = skip_num/skip_den seconds
+  int64_t skip_num = 0, skip_den = 1;
 
   if(argc <= 1 || strcmp(argv[1], "--help") == 0){
-    printf("Usage: %s path-to-video-file [skip = %fs]\n", argv[0], skip_secs_default);
+    printf("Usage: %s path-to-video-file [skip = %llis]\n", argv[0], (long long)skip_secs_default);
     return 0;
   }
   if(argc >= 2)
     path = argv[1];
   if(argc >= 3)
-    for(int i=2; i<argc; ++i)
-      skip_secs += parseTimeInSecs(argv[i]);
+    for(int i=2; i<argc; ++i){
+      int64_t num, den;
+      if(parseTime(argv[i], &num, &den) < 0){
+        printf("Invalid time: %s\n", argv[i]);
+        return 1;
+      }
+      // Both denominators are powers of 10
+      if(den > skip_den){
+        skip_num *= den / skip_den;
+        skip_den = den;
+      }
+      skip_num += num * (skip_den / den);
+    }
   else
-    skip_secs = skip_secs_default;
+    skip_num = skip_secs_default;
 
-  char *skip_secs_str = parseTimeFromSecs(skip_secs);
+  char *skip_secs_str = formatTime(skip_num, skip_den);
   printf("Input:\n  Path:\t %s\n  skip:\t %s\n", path, skip_secs_str);
   free(skip_secs_str);
 
-  int res = my_renderframe(path, skip_secs, &width, &height, &outwidth, &outheight, &data);
+  int res = my_renderframe(path, skip_num, skip_den, &width, &height, &outwidth, &outheight, &data);
 
   printf("Result: %i\n  decoded size:\t %ix%i\n  final size:\t %ix%i\n", res, width, height, outwidth, outheight);
 
@@ -52,7 +65,7 @@ int main(int argc, char** argv){
   return res;
 }
 
-int my_renderframe(char *path, double seconds, int *width, int *height, int *outwidth, int *outheight, uint8_t **rgb){
+int my_renderframe(char *path, int64_t time_num, int64_t time_den, int *width, int *height, int *outwidth, int *outheight, uint8_t **rgb){
   AVFormatContext *fctx = NULL;
   AVCodecContext *cctx = NULL;
   struct SwsContext *swsctx = NULL;
@@ -85,7 +98,7 @@ int my_renderframe(char *path, double seconds, int *width, int *height, int *out
   vs_create_pkt_frm(&(vstream->pkt), &(vstream->frm), &(vframe->swsfrm));
 
 
-  vs_seek_at(vstream->fmt_ctx, vstream->stream, seconds, 0, vstream->codec_ctx, vstream->pkt, vstream->frm, &err);
+  vs_seek_at(vstream->fmt_ctx, vstream->stream, time_num, time_den, 0, vstream->codec_ctx, vstream->pkt, vstream->frm, &err);
   // vs_decode_current_frame(vstream->fmt_ctx, vstream->codec_ctx, vstream->stream, vstream->pkt, vstream->frm, vstream->sws_ctx, vstream->swsfrm, &err);
   vs_decode_current_frame(vstream->fmt_ctx, vstream->codec_ctx, vstream->stream, vstream->pkt, vstream->frm, &err);
   vs_decode_sws_frame(*vframe->frm_src, vframe->sws_ctx, vframe->swsfrm, &err);
@@ -102,68 +115,70 @@ int my_renderframe(char *path, double seconds, int *width, int *height, int *out
   return err;
 }
 
-double parseTimeInSecs(const char *s){
-  if(!s) return NAN;
-  const size_t len = strlen(s);
-  if(!len) return 0;
-
-  char *tmp;
-  double res;
-  for(int i=0; i< len; i++){
-    switch (s[i]) {
-      case 's':
-      case 'S':
-        tmp = malloc(i+1);
-        strncpy(tmp, s, i);
-        tmp[i] = 0;
-        res = atof(tmp);
-        res += parseTimeInSecs(s + (i+1));
-        free(tmp);
-        return res;
-      case 'm':
-      case 'M':
-        tmp = malloc(i+1);
-        strncpy(tmp, s, i);
-        tmp[i] = 0;
-        res = atof(tmp)*60;
-        res += parseTimeInSecs(s + (i+1));
-        free(tmp);
-        return res;
-      case 'h':
-      case 'H':
-        tmp = malloc(i+1);
-        strncpy(tmp, s, i);
-        tmp[i] = 0;
-        res = atof(tmp)*60*60;
-        res += parseTimeInSecs(s + (i+1));
-        free(tmp);
-        return res;
-      default:
+/// @brief Parses seconds ("90", "1.5") or units ("1h2m3.5s", "1m30s") exactly
+/// @param num, den The time as num/den seconds, den is a power of 10
+/// @return 0 on success, -1 if s isn't a time
+int parseTime(const char *s, int64_t *num, int64_t *den){
+  *num = 0;
+  *den = 1;
+  if(!s || !*s) return -1;
+
+  while(*s){
+    // One decimal number
+    int64_t value = 0, value_den = 1;
+    int digits = 0, fraction = 0;
+    for(; *s; s++){
+      if(*s >= '0' && *s <= '9'){
+        if(digits++ >= 18) return -1;
+        value = value*10 + (*s - '0');
+        if(fraction) value_den *= 10;
+      }
+      else if(*s == '.' && !fraction)
+        fraction = 1;
+      else
         break;
     }
+    if(!digits) return -1;
+
+    int64_t unit = 1;
+    switch(*s){
+      case 'h': case 'H': unit = 60*60; s++; break;
+      case 'm': case 'M': unit = 60;    s++; break;
+      case 's': case 'S': unit = 1;     s++; break;
+      case 0: break;
+      default: return -1;
+    }
+    value *= unit;
+    if(value_den > *den){
+      *num *= value_den / *den;
+      *den = value_den;
+    }
+    *num += value * (*den / value_den);
   }
-  return atof(s);
+  return 0;
 }
 
-char *parseTimeFromSecs(double t){
+/// @brief Formats num/den seconds like "1h02m03.50s", the result has to be freed
+char *formatTime(int64_t num, int64_t den){
   const char invalidStr[] = "???";
   char *ret = malloc(1024);
-  if(t < 0){
+  if(num < 0 || den <= 0){
     strcpy(ret, invalidStr);
     return ret;
   }
-  uint64_t tInt = t;
-  uint64_t h = tInt/(60*60), m = (tInt % (60*60))/(60);
-  double s = (tInt % 60) + (t - tInt);
-  const char fmtFull[] = "%ih%02im%02.2fs";
+  const int64_t tInt = num / den;
+  const int64_t h = tInt/(60*60), m = (tInt % (60*60))/(60);
+  // Hundredths of a second, rounded down
+  const int64_t cs = av_rescale_rnd(num % den, 100, den, AV_ROUND_DOWN);
+  const long long s = tInt % 60;
   if(h == 0){
     if(m == 0)
-      sprintf(ret, fmtFull + 3 + 5, s);
+      sprintf(ret, "%lli.%02llis", s, (long long)cs);
     else
-      sprintf(ret, fmtFull + 3, m, s);
+      sprintf(ret, "%llim%02lli.%02llis", (long long)m, s, (long long)cs);
   }
   else
-    sprintf(ret, fmtFull, h, m, s);
+    sprintf(ret, "%llih%02llim%02lli.%02llis", (long long)h, (long long)m, s, (long long)cs);
   return ret;
 }
 
@@ -173,10 +188,7 @@ void dumbFctxInfo(AVFormatContext *fctx, int defaultIdx){
   printf("  Streams (len = %i)\n", fctx->nb_streams);
   for(int i=0; i<fctx->nb_streams; i++){
     AVStream *s = fctx->streams[i];
-    double timebase = ((double)s->time_base.num)/s->time_base.den;
-    double dur = timebase * s->duration;
-    char *durStr = parseTimeFromSecs(dur);
-    double startTime = timebase * s->start_time;
+    char *durStr = formatTime(s->duration * s->time_base.num, s->time_base.den);
     int streamID = s->id;
     int64_t nFrames = s->nb_frames ? s->nb_frames : (s->duration ? -1 : 0);
     printf("  %s%i:\t nFrames = %i, dur = %s\n", (i==defaultIdx) ? "->":"  ", i, nFrames, durStr);
@@ -194,8 +206,8 @@ void dumbFctxInfo(AVFormatContext *fctx, int defaultIdx){
   printf("  Chapters (len = %i)\n", fctx->nb_chapters);
   for(int i=0; i<fctx->nb_chapters; i++){
     AVChapter *ch = fctx->chapters[i];
-    double timebase = ((double)ch->time_base.num)/ch->time_base.den;
-    char *t1 = parseTimeFromSecs(timebase * ch->start), *t2 = parseTimeFromSecs(timebase * ch->end);
+    char *t1 = formatTime(ch->start * ch->time_base.num, ch->time_base.den);
+    char *t2 = formatTime(ch->end * ch->time_base.num, ch->time_base.den);
     int chapterID = ch->id;
     printf("    %i:\t dur = %s - %s\n", i, t1, t2);
     AVDictionaryEntry *m = av_dict_get(ch->metadata, "", NULL, AV_DICT_IGNORE_SUFFIX);
@@ -0,0 +1,177 @@
+use std::{collections::VecDeque, path};
+
+use crate::{
+  ffi::{AVFrame, AVMediaType, AVSampleFormat, VideoStream, VideoStreamBuilder, VideoStreamErr, Seek},
+  time::RationalTime,
+};
+
+/// A stereo sample, left and right
+pub type StereoSample = [f32; 2];
+
+/// Decodes an audio stream to stereo `f32` samples at any sample rate. Mono is copied to both
+/// channels, streams with more channels use the first two.
+pub struct AudioStream {
+  stream: VideoStream,
+  sample_rate: u32,
+  /// Decoded samples at the stream's sample rate, the first one is sample `start`
+  samples: VecDeque<StereoSample>,
+  start: i64,
+  eof: bool,
+}
+
+/// Sequential reads farther ahead than this seek instead of decoding up to the position
+const MAX_SKIP_SECS: i64 = 1;
+
+
+impl AudioStream {
+  pub fn open(path: &path::Path, stream_idx: u32) -> Result<Self, VideoStreamErr> {
+    let stream = VideoStreamBuilder::default()
+      .set_path(path).map_err(|_| VideoStreamErr::IO)?
+      .set_stream_idx(stream_idx)
+      .finish()?;
+    if stream.media_type() != AVMediaType::AVMEDIA_TYPE_AUDIO || stream.sample_rate() == 0 {
+      return Err(VideoStreamErr::StreamNotFound);
+    }
+    let sample_rate = stream.sample_rate();
+    Ok(Self { stream, sample_rate, samples: VecDeque::new(), start: 0, eof: false })
+  }
+
+  pub fn sample_rate(&self) -> u32 {
+    self.sample_rate
+  }
+
+  pub fn channels(&self) -> u32 {
+    self.stream.channels()
+  }
+
+  /// Index of the sample after the last decoded one
+  fn end(&self) -> i64 {
+    self.start + self.samples.len() as i64
+  }
+
+  fn sample(&self, idx: i64) -> StereoSample {
+    match idx.checked_sub(self.start) {
+      Some(i) if i >= 0 => self.samples.get(i as usize).copied().unwrap_or([0.; 2]),
+      _ => [0.; 2],
+    }
+  }
+
+  fn seek(&mut self, time: RationalTime) -> Result<(), VideoStreamErr> {
+    self.samples.clear();
+    self.eof = false;
+    let time = time.max(RationalTime::ZERO);
+    self.start = time.to_frames(self.sample_rate as _, 1);
+    match self.stream.seek(time, Seek::empty()) {
+      Ok(()) => {
+        self.append_frame();
+        Ok(())
+      },
+      Err(VideoStreamErr::EOF) | Err(VideoStreamErr::TimeStampOutOfBounds) => {
+        self.eof = true;
+        Ok(())
+      },
+      Err(err) => Err(err),
+    }
+  }
+
+  fn decode_next(&mut self) -> Result<(), VideoStreamErr> {
+    match self.stream.decode_frames(1) {
+      Ok(()) => {
+        self.append_frame();
+        Ok(())
+      },
+      Err(VideoStreamErr::EOF) => {
+        self.eof = true;
+        Ok(())
+      },
+      Err(err) => Err(err),
+    }
+  }
+
+  /// Appends the samples of the current frame, placing them by the frame's timestamp
+  fn append_frame(&mut self) {
+    let frm = self.stream.get_frm();
+    let frame_start = self.stream.frame_time().map(|t| t.to_frames(self.sample_rate as _, 1));
+    match frame_start {
+      Some(frame_start) if self.samples.is_empty() => self.start = frame_start,
+      // Fill gaps between frames with silence
+      Some(frame_start) if frame_start > self.end() => {
+        let gap = (frame_start - self.end()).min(self.sample_rate as i64 * MAX_SKIP_SECS);
+        self.samples.extend(std::iter::repeat([0.; 2]).take(gap as usize));
+      },
+      _ => {},
+    }
+    self.samples.extend(frame_samples(&frm));
+  }
+
+  /// Fills `out` with the samples from `time` on, resampled to `sample_rate` with linear
+  /// interpolation. Positions before the start or after the end of the stream are silent.
+  pub fn read(&mut self, time: RationalTime, sample_rate: u32, out: &mut [StereoSample]) -> Result<(), VideoStreamErr> {
+    let ratio = self.sample_rate as f64 / sample_rate as f64;
+    let first = time.as_secs_f64() * self.sample_rate as f64;
+    let (first_idx, last_idx) = (first.floor() as i64, (first + out.len() as f64 * ratio).ceil() as i64 + 1);
+
+    let max_skip = self.sample_rate as i64 * MAX_SKIP_SECS;
+    if first_idx < self.start || first_idx > self.end() + max_skip {
+      self.seek(time)?;
+    }
+    // Keep one sample before the position for the interpolation
+    while self.start < first_idx - 1 && !self.samples.is_empty() {
+      self.samples.pop_front();
+      self.start += 1;
+    }
+    while self.end() < last_idx && !self.eof {
+      self.decode_next()?;
+      while self.start < first_idx - 1 && !self.samples.is_empty() {
+        self.samples.pop_front();
+        self.start += 1;
+      }
+    }
+
+    for (i, out) in out.iter_mut().enumerate() {
+      let pos = first + i as f64 * ratio;
+      let idx = pos.floor() as i64;
+      let t = (pos - idx as f64) as f32;
+      let (a, b) = (self.sample(idx), self.sample(idx + 1));
+      *out = [a[0] + (b[0] - a[0])*t, a[1] + (b[1] - a[1])*t];
+    }
+    Ok(())
+  }
+}
+
+
+/// Sample `i` of `plane` converted to `f32` in `[-1, 1]`
+unsafe fn read_sample(fmt: AVSampleFormat, plane: *const u8, i: usize) -> f32 {
+  use AVSampleFormat::*;
+  match fmt {
+    AV_SAMPLE_FMT_U8 | AV_SAMPLE_FMT_U8P => (*plane.add(i) as f32 - 128.) / 128.,
+    AV_SAMPLE_FMT_S16 | AV_SAMPLE_FMT_S16P => *(plane as *const i16).add(i) as f32 / 32768.,
+    AV_SAMPLE_FMT_S32 | AV_SAMPLE_FMT_S32P => *(plane as *const i32).add(i) as f32 / 2147483648.,
+    AV_SAMPLE_FMT_S64 | AV_SAMPLE_FMT_S64P => *(plane as *const i64).add(i) as f32 / 9223372036854775808.,
+    AV_SAMPLE_FMT_FLT | AV_SAMPLE_FMT_FLTP => *(plane as *const f32).add(i),
+    AV_SAMPLE_FMT_DBL | AV_SAMPLE_FMT_DBLP => *(plane as *const f64).add(i) as f32,
+    _ => 0.,
+  }
+}
+
+/// Stereo samples of a decoded audio frame
+fn frame_samples(frm: &AVFrame) -> Vec<StereoSample> {
+  use AVSampleFormat::*;
+  let n = frm.nb_samples.max(0) as usize;
+  let channels = frm.ch_layout.nb_channels.max(0) as usize;
+  if channels == 0 || frm.data[0].is_null() {
+    return vec![[0.; 2]; n];
+  }
+  // `format` of a decoded audio frame always holds a valid AVSampleFormat
+  let fmt: AVSampleFormat = unsafe { std::mem::transmute(frm.format) };
+  let planar = matches!(fmt,
+    AV_SAMPLE_FMT_U8P | AV_SAMPLE_FMT_S16P | AV_SAMPLE_FMT_S32P | AV_SAMPLE_FMT_S64P | AV_SAMPLE_FMT_FLTP | AV_SAMPLE_FMT_DBLP);
+  let right = channels.min(2) - 1;
+  (0..n).map(|i| unsafe {
+    if planar {
+      [read_sample(fmt, frm.data[0], i), read_sample(fmt, frm.data[right], i)]
+    } else {
+      [read_sample(fmt, frm.data[0], i*channels), read_sample(fmt, frm.data[0], i*channels + right)]
+    }
+  }).collect()
+}
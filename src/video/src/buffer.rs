@@ -1,6 +1,6 @@
-use crate::ffi::{rc::RcFrame, self, VideoStream, VideoFrameContext};
+use crate::{ffi::{rc::RcFrame, self, VideoStream, VideoStreamBuilder, VideoFrameContext}, FrameCache, FrameKey, RationalTime};
 
-use std::{sync::{Arc, Mutex, mpsc}};
+use std::{path::PathBuf, sync::Arc};
 
 use escher_schedule as schedule;
 
@@ -8,16 +8,18 @@ pub mod frame_protocol {
   use std::fmt::Debug;
   pub use escher_schedule::RequestKind; 
 
-  use crate::ffi;
+  use std::sync::Arc;
+
+  use crate::ffi::{self, rc::RcFrame};
 
   #[derive(Debug, Copy, Clone)]
   pub enum Request {
-    SetSwsContext {new_width: i32, new_height: i32, new_pix_fmt: ffi::AVPixelFormat, width: i32, height: i32, pix_fmt: ffi::AVPixelFormat, scaling: ffi::SWS_Scaling},
     // Ping(usize),
+    /// Decodes and scales the frame unless it is cached, responds with the cached frame
     RenderFrame {render_idx: usize, },
   }
   // type RequestKind = escher_schedule::RequestKind;
-  pub type Response = escher_schedule::Response<()>;
+  pub type Response = escher_schedule::Response<Arc<RcFrame>, ffi::VideoStreamErr>;
 
   // impl Clone for Request {
   //   fn clone(&self) -> Self {
@@ -47,101 +49,129 @@ pub use schedule::BroadcastKind;
 pub type RequestError = schedule::RequestError<frame_protocol::Request>;
 
 pub struct FrameBuffer {
-  pub frames: Vec<Arc<Mutex<Option<RcFrame>>>>,
-  scheduler: schedule::Scheduler<frame_protocol::Request, ()>
+  /// Rendered frames, shared with other buffers and decoders
+  pub cache: Arc<FrameCache>,
+  scheduler: schedule::Scheduler<frame_protocol::Request, Arc<RcFrame>, ffi::VideoStreamErr>
+}
+
+/// Stream a [`FrameBuffer`] renders and the size and format of the rendered frames
+#[derive(Clone, Debug)]
+pub struct BufferSource {
+  pub asset: PathBuf,
+  pub stream_idx: u32,
+  /// Duration of one frame, `render_idx` counts frames of this duration
+  pub frame_duration: RationalTime,
+  pub width: i32,
+  pub height: i32,
+  pub pix_fmt: ffi::AVPixelFormat,
+  pub scaling: ffi::SWS_Scaling,
 }
 
 
 struct WorkerState {
-  // sws_ctx: *mut ffi::SwsContext,
+  source: Arc<BufferSource>,
+  cache: Arc<FrameCache>,
+  /// Opened by the first `RenderFrame`
+  stream: Option<VideoStream>,
   vframe_ctx: VideoFrameContext,
 }
 
 impl FrameBuffer {
-  pub fn new(src: &RcFrame, num_workers: usize, new_width: i32, new_height: i32, new_pix_fmt: ffi::AVPixelFormat, width: i32, height: i32, pix_fmt: ffi::AVPixelFormat, scaling: ffi::SWS_Scaling) -> Arc<Self>
-  {
-    use frame_protocol::Request;
-    let src: &'static RcFrame = unsafe{std::mem::transmute(src)};
-    let scheduler = schedule::Scheduler::new(num_workers, |_| WorkerState {
-      // vframe_ctx: VideoFrameContext::new(RcFrame::wrap_null())
-      vframe_ctx: VideoFrameContext::new(src.clone())
+  pub fn new(source: BufferSource, cache: Arc<FrameCache>, num_workers: usize) -> Arc<Self> {
+    let source = Arc::new(source);
+    let worker_cache = cache.clone();
+    let mut scheduler = schedule::Scheduler::new(num_workers, move |_| WorkerState {
+      source: source.clone(),
+      cache: worker_cache.clone(),
+      stream: None,
+      vframe_ctx: VideoFrameContext::new(RcFrame::wrap_null()),
     });
-    // let (flags, param) = scaling.into();
-    for i in 0..num_workers {
-      // let param = Arc::new(Mutex::new(param.clone()));
-      scheduler.request(
-        Request::SetSwsContext { new_width, new_height, new_pix_fmt, width, height, pix_fmt, scaling },
-        BroadcastKind::Specific(i)
-      ).unwrap();
-    }
-    let res = Arc::new(Self { frames: Vec::default(), scheduler });
-    res
+    // Frames are independent, a frame that crashes a worker doesn't stop the others
+    scheduler.set_respawn(true);
+    scheduler.register_metrics("Frame Buffer", |_| "RenderFrame");
+    Arc::new(Self { cache, scheduler })
   }
 
-
-  pub fn request(&self, request: frame_protocol::Request, kind: BroadcastKind) -> Result<(), RequestError> {
+  pub fn request(&self, request: frame_protocol::Request, kind: BroadcastKind) -> Result<schedule::Ticket, RequestError> {
     self.scheduler.request(request, kind)
   }
 
+  /// Renders frame `render_idx` on the first free worker and stores it in `cache`. The handle
+  /// resolves with the frame when it is done.
+  pub fn render_frame(&self, render_idx: usize, options: schedule::RequestOptions) -> Result<schedule::RequestHandle<Arc<RcFrame>, ffi::VideoStreamErr>, RequestError> {
+    self.scheduler.request_handle(frame_protocol::Request::RenderFrame { render_idx }, BroadcastKind::Any, options)
+  }
+
   pub fn handle_respones(&mut self) {
     self.scheduler.handle_respones(Self::inner_handle_respones).unwrap()
   }
 
-  fn inner_handle_respones(resp: frame_protocol::Response, _tx: &mpsc::Sender<(frame_protocol::RequestKind, frame_protocol::Request)>) -> Result<(), ()> {
+  fn inner_handle_respones(resp: frame_protocol::Response, _tx: &schedule::WorkerSender<frame_protocol::Request, Arc<RcFrame>, ffi::VideoStreamErr>) -> Result<(), ()> {
     match resp {
       schedule::Response::Init => Ok(()),
-      schedule::Response::Ok(()) => Ok(()),
+      schedule::Response::Ok(_) | schedule::Response::Public(_) => Ok(()),
       schedule::Response::Ready(_) => Ok(()),
+      schedule::Response::Err(e) => {
+        eprintln!("FrameBuffer worker failed: {:?}", e);
+        Ok(())
+      },
+      // The handle of the frame resolved with `HandleError::WorkerDied` and the scheduler
+      // replaced the worker, the message is all that is left
+      schedule::Response::WorkerDied { worker, payload } => {
+        eprintln!("FrameBuffer worker {} died: {}", worker, schedule::panic_message(payload.as_ref()));
+        Ok(())
+      },
     }
   }
 }
 
 
-impl schedule::Worker<frame_protocol::Request, ()> for WorkerState {
-  fn handle(&mut self, request: frame_protocol::Request, kind: frame_protocol::RequestKind) -> frame_protocol::Response {
-    use frame_protocol::{Response, Request};
-    use std::os::raw::c_int;
-
-    match kind {
-      frame_protocol::RequestKind::Plain => {},
-      frame_protocol::RequestKind::Once(m) => {
-        //TODO: Is it really true that trylock is OK, if we assume that at least one threat should be able to lock the mutex?
-        if let Ok(mut guard) = m.try_lock() {
-          if *guard {
-            *guard = false;
-          } else {
-            return frame_protocol::Response::Ok(());
-          }
-        } else {
-          return frame_protocol::Response::Ok(());
-        }
-      },
+impl WorkerState {
+  fn stream(&mut self) -> Result<&mut VideoStream, ffi::VideoStreamErr> {
+    if self.stream.is_none() {
+      let stream = VideoStreamBuilder::default()
+        .set_path(&self.source.asset).map_err(|_| ffi::VideoStreamErr::IO)?
+        .set_stream_idx(self.source.stream_idx)
+        .finish()?;
+      self.stream = Some(stream);
     }
+    Ok(self.stream.as_mut().unwrap())
+  }
+
+  /// The cached frame `render_idx`, decoded, scaled and cached first if it isn't
+  fn render_frame(&mut self, render_idx: usize) -> Result<Arc<RcFrame>, ffi::VideoStreamErr> {
+    let source = self.source.clone();
+    let (num, den) = (source.frame_duration.den(), source.frame_duration.num());
+    let time = RationalTime::from_frames(render_idx as i64, num, den);
+    let stream = self.stream()?;
+    let key = FrameKey {
+      asset: source.asset.clone(),
+      stream_idx: source.stream_idx,
+      pts: time.to_pts(stream.time_base()),
+      size: [source.width as u32, source.height as u32],
+      pix_fmt: source.pix_fmt,
+    };
+    let Self { cache, stream, vframe_ctx, .. } = self;
+    let stream = stream.as_mut().unwrap();
+    cache.get_or_insert_with(key, || {
+      stream.seek(time, ffi::Seek::empty())?;
+      vframe_ctx.frm_src = stream.get_frm();
+      vframe_ctx.convert(source.width, source.height, source.pix_fmt, source.scaling)?;
+      Ok(Arc::new(vframe_ctx.converted_ref()))
+    })
+  }
+}
+
+impl schedule::Worker<frame_protocol::Request, Arc<RcFrame>, (), ffi::VideoStreamErr> for WorkerState {
+  fn handle(&mut self, request: frame_protocol::Request, _kind: frame_protocol::RequestKind, _pub_tx: &mut schedule::CallbackSender<()>) -> frame_protocol::Response {
+    use frame_protocol::{Response, Request};
 
     match request {
-      Request::SetSwsContext { new_width, new_height, new_pix_fmt, width, height, pix_fmt, scaling } => {
-        // let mut err: c_int = 0;
-        // let param = {
-        //   let param_lock = param.lock().unwrap();
-        //   param_lock.clone()
-        // };
-        // let param = &param[..];
-        // let param_ptr = if param.len() > 0 { param.as_ptr() } else { std::ptr::null() };
-        // let res = unsafe {
-        //   ffi::vs_create_sws_context(&mut self.sws_ctx, width, height, pix_fmt, new_width, new_height, new_pix_fmt, flags as _, param_ptr, &mut err as _)
-        // };
-        // let res = self.vframe_ctx.replace_sws_ctx(new_width, new_height, new_pix_fmt, width, height, pix_fmt, scaling);
-        match self.vframe_ctx.replace_sws_ctx(new_width, new_height, new_pix_fmt, width, height, pix_fmt, scaling) {
-          Ok(()) => Response::Ok(()),
-          Err(_) => todo!(),
-        }
+      Request::RenderFrame { render_idx } => match self.render_frame(render_idx) {
+        Ok(frame) => Response::Ok(frame),
+        Err(e) => Response::Err(e),
       },
       // Request::Ping(id) => Response::Pong(id),
-      _ => todo!()
     }
   }
 }
-
-
-
-
@@ -0,0 +1,351 @@
+use std::{
+  collections::{BTreeMap, HashMap},
+  hash::Hash,
+  path::{Path, PathBuf},
+  sync::{Arc, Mutex},
+};
+
+use crate::{ffi::rc::RcFrame, AVPixelFormat};
+
+
+/// Default budget of [`FrameCache`]
+pub const DEFAULT_BUDGET: usize = 512 * 1024 * 1024;
+
+/// Identifies a decoded or scaled frame
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FrameKey {
+  /// File of the asset
+  pub asset: PathBuf,
+  pub stream_idx: u32,
+  /// In the time base of the stream
+  pub pts: i64,
+  /// Size after scaling, the size of the stream for decoded frames
+  pub size: [u32; 2],
+  pub pix_fmt: AVPixelFormat,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+  pub hits: u64,
+  pub misses: u64,
+  pub insertions: u64,
+  pub evictions: u64,
+  /// Frames currently cached
+  pub frames: usize,
+  /// Bytes of the cached frames' buffers
+  pub bytes: usize,
+  pub budget: usize,
+}
+
+/// Frames shared between decoders, e.g. as `Arc<FrameCache>`, so that scrubbing over the same
+/// region doesn't decode again.
+///
+/// Cached frames are shared as `Arc<RcFrame>`, nothing is copied and no FFmpeg frames are allocated
+/// on hits. Evicting a frame only drops the cache's `Arc`, frames still in use elsewhere stay
+/// valid. Frames that get cached must not be written to afterwards, e.g.
+/// `VideoFrameContext::convert` allocates new buffers instead of reusing them.
+pub struct FrameCache {
+  inner: Mutex<CacheInner<FrameKey, Arc<RcFrame>>>,
+}
+
+struct CacheEntry<V> {
+  value: V,
+  bytes: usize,
+  last_used: u64,
+}
+
+/// LRU bookkeeping of [`FrameCache`], independent of FFmpeg
+struct CacheInner<K, V> {
+  entries: HashMap<K, CacheEntry<V>>,
+  /// Keys by `last_used`, least recently used first
+  lru: BTreeMap<u64, K>,
+  use_counter: u64,
+  stats: CacheStats,
+}
+
+
+impl CacheStats {
+  /// Fraction of lookups that were hits, 0 without lookups
+  pub fn hit_rate(&self) -> f32 {
+    match self.hits + self.misses {
+      0 => 0.,
+      total => self.hits as f32 / total as f32,
+    }
+  }
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> CacheInner<K, V> {
+  fn new(budget: usize) -> Self {
+    Self {
+      entries: HashMap::new(),
+      lru: BTreeMap::new(),
+      use_counter: 0,
+      stats: CacheStats { budget, ..Default::default() },
+    }
+  }
+
+  fn touch(&mut self, key: &K) {
+    let counter = self.use_counter + 1;
+    if let Some(entry) = self.entries.get_mut(key) {
+      self.use_counter = counter;
+      self.lru.remove(&entry.last_used);
+      entry.last_used = counter;
+      self.lru.insert(counter, key.clone());
+    }
+  }
+
+  /// Counts as a hit or miss
+  fn get(&mut self, key: &K) -> Option<V> {
+    match self.entries.get(key) {
+      Some(entry) => {
+        let value = entry.value.clone();
+        self.touch(key);
+        self.stats.hits += 1;
+        Some(value)
+      }
+      None => {
+        self.stats.misses += 1;
+        None
+      }
+    }
+  }
+
+  fn insert(&mut self, key: K, value: V, bytes: usize) -> bool {
+    self.remove(&key);
+    if bytes > self.stats.budget {
+      return false;
+    }
+    self.evict(bytes);
+    self.use_counter += 1;
+    let last_used = self.use_counter;
+    self.lru.insert(last_used, key.clone());
+    self.entries.insert(key, CacheEntry { value, bytes, last_used });
+    self.stats.insertions += 1;
+    self.stats.frames += 1;
+    self.stats.bytes += bytes;
+    true
+  }
+
+  fn remove(&mut self, key: &K) -> Option<CacheEntry<V>> {
+    let entry = self.entries.remove(key)?;
+    self.lru.remove(&entry.last_used);
+    self.stats.frames -= 1;
+    self.stats.bytes -= entry.bytes;
+    Some(entry)
+  }
+
+  fn remove_where(&mut self, f: impl Fn(&K) -> bool) {
+    let keys: Vec<_> = self.entries.keys()
+      .filter(|key| f(key))
+      .cloned()
+      .collect();
+    for key in keys {
+      self.remove(&key);
+    }
+  }
+
+  fn clear(&mut self) {
+    self.entries.clear();
+    self.lru.clear();
+    self.stats.frames = 0;
+    self.stats.bytes = 0;
+  }
+
+  /// Evicts least recently used frames until `extra` more bytes fit into the budget
+  fn evict(&mut self, extra: usize) {
+    while self.stats.bytes + extra > self.stats.budget {
+      let key = match self.lru.values().next() {
+        Some(key) => key.clone(),
+        None => break,
+      };
+      self.remove(&key);
+      self.stats.evictions += 1;
+    }
+  }
+
+  fn set_budget(&mut self, budget: usize) {
+    self.stats.budget = budget;
+    self.evict(0);
+  }
+
+  fn reset_stats(&mut self) {
+    self.stats = CacheStats {
+      frames: self.stats.frames,
+      bytes: self.stats.bytes,
+      budget: self.stats.budget,
+      ..Default::default()
+    };
+  }
+}
+
+impl FrameCache {
+  /// Cache holding frames with up to `budget` bytes of buffers
+  pub fn new(budget: usize) -> Self {
+    Self { inner: Mutex::new(CacheInner::new(budget)) }
+  }
+
+  /// The cached frame, counts as a hit or miss
+  pub fn get(&self, key: &FrameKey) -> Option<Arc<RcFrame>> {
+    self.inner.lock().unwrap().get(key)
+  }
+
+  pub fn contains(&self, key: &FrameKey) -> bool {
+    self.inner.lock().unwrap().entries.contains_key(key)
+  }
+
+  /// Caches `frame`, evicting the least recently used frames to stay within the budget. Returns
+  /// false if the frame is null or larger than the whole budget.
+  pub fn insert(&self, key: FrameKey, frame: Arc<RcFrame>) -> bool {
+    if frame.is_null() {
+      return false;
+    }
+    let bytes = frame.buffer_size();
+    self.inner.lock().unwrap().insert(key, frame, bytes)
+  }
+
+  /// The cached frame or the result of `f`, which gets cached. `f` runs without locking the cache.
+  pub fn get_or_insert_with<E>(&self, key: FrameKey, f: impl FnOnce() -> Result<Arc<RcFrame>, E>) -> Result<Arc<RcFrame>, E> {
+    if let Some(frame) = self.get(&key) {
+      return Ok(frame);
+    }
+    let frame = f()?;
+    self.insert(key, frame.clone());
+    Ok(frame)
+  }
+
+  /// Drops the cache's reference to the frame
+  pub fn remove(&self, key: &FrameKey) -> bool {
+    self.inner.lock().unwrap().remove(key).is_some()
+  }
+
+  /// Drops all frames of `asset`, e.g. after the file changed
+  pub fn remove_asset(&self, asset: &Path) {
+    self.inner.lock().unwrap().remove_where(|key| key.asset == asset);
+  }
+
+  pub fn clear(&self) {
+    self.inner.lock().unwrap().clear();
+  }
+
+  pub fn budget(&self) -> usize {
+    self.inner.lock().unwrap().stats.budget
+  }
+
+  /// Evicts frames right away if the cache is over the new budget
+  pub fn set_budget(&self, budget: usize) {
+    self.inner.lock().unwrap().set_budget(budget);
+  }
+
+  pub fn stats(&self) -> CacheStats {
+    self.inner.lock().unwrap().stats
+  }
+
+  /// Resets hits, misses, insertions and evictions
+  pub fn reset_stats(&self) {
+    self.inner.lock().unwrap().reset_stats();
+  }
+}
+
+impl Default for FrameCache {
+  fn default() -> Self {
+    Self::new(DEFAULT_BUDGET)
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn cache(budget: usize) -> CacheInner<u32, &'static str> {
+    CacheInner::new(budget)
+  }
+
+  #[test]
+  fn evicts_least_recently_used() {
+    let mut cache = cache(30);
+    assert!(cache.insert(1, "a", 10));
+    assert!(cache.insert(2, "b", 10));
+    assert!(cache.insert(3, "c", 10));
+    assert_eq!(cache.get(&1), Some("a"));
+    assert!(cache.insert(4, "d", 10));
+    assert_eq!(cache.get(&2), None);
+    assert_eq!(cache.get(&1), Some("a"));
+    assert_eq!(cache.get(&3), Some("c"));
+    assert_eq!(cache.get(&4), Some("d"));
+    assert_eq!(cache.lru.values().copied().collect::<Vec<_>>(), [1, 3, 4]);
+  }
+
+  #[test]
+  fn stays_within_budget() {
+    let mut cache = cache(25);
+    cache.insert(1, "a", 10);
+    cache.insert(2, "b", 10);
+    assert!(cache.insert(3, "c", 20));
+    assert_eq!(cache.stats.frames, 1);
+    assert_eq!(cache.stats.bytes, 20);
+    assert_eq!(cache.stats.evictions, 2);
+    assert!(!cache.insert(4, "d", 26));
+    assert_eq!(cache.get(&4), None);
+    assert_eq!(cache.get(&3), Some("c"));
+  }
+
+  #[test]
+  fn replacing_a_key_frees_its_bytes() {
+    let mut cache = cache(20);
+    cache.insert(1, "a", 10);
+    cache.insert(2, "b", 10);
+    assert!(cache.insert(1, "a2", 10));
+    assert_eq!(cache.stats.evictions, 0);
+    assert_eq!(cache.stats.bytes, 20);
+    assert_eq!(cache.get(&1), Some("a2"));
+  }
+
+  #[test]
+  fn set_budget_evicts() {
+    let mut cache = cache(40);
+    for key in 1..=4 {
+      cache.insert(key, "x", 10);
+    }
+    cache.get(&1);
+    cache.set_budget(20);
+    assert_eq!(cache.stats.budget, 20);
+    assert_eq!(cache.stats.frames, 2);
+    assert_eq!(cache.stats.bytes, 20);
+    assert_eq!(cache.stats.evictions, 2);
+    assert!(cache.entries.contains_key(&1));
+    assert!(cache.entries.contains_key(&4));
+    cache.set_budget(0);
+    assert_eq!(cache.stats.frames, 0);
+    assert!(cache.lru.is_empty());
+  }
+
+  #[test]
+  fn stats() {
+    let mut cache = cache(100);
+    assert_eq!(cache.stats.hit_rate(), 0.);
+    cache.insert(1, "a", 10);
+    cache.insert(2, "b", 15);
+    cache.get(&1);
+    cache.get(&1);
+    cache.get(&2);
+    cache.get(&3);
+    assert_eq!(cache.stats, CacheStats {
+      hits: 3,
+      misses: 1,
+      insertions: 2,
+      evictions: 0,
+      frames: 2,
+      bytes: 25,
+      budget: 100,
+    });
+    assert_eq!(cache.stats.hit_rate(), 0.75);
+
+    cache.remove(&2);
+    cache.reset_stats();
+    assert_eq!(cache.stats, CacheStats { frames: 1, bytes: 10, budget: 100, ..Default::default() });
+    cache.remove_where(|&key| key == 1);
+    assert_eq!(cache.stats.frames, 0);
+    assert_eq!(cache.stats.bytes, 0);
+  }
+}
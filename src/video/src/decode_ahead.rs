@@ -0,0 +1,228 @@
+use std::{collections::HashMap, hash::Hash};
+
+use crate::{
+  ffi::{rc::RcFrame, VideoStream, VideoStreamErr, Seek},
+  time::RationalTime,
+};
+
+/// Keeps an open decoder for every source around the playhead, so several streams can be read at
+/// the same time. Within a transition both clips of a track are visible, which means two sources
+/// have to be decoded in lockstep. Calling `prepare` with the sources of the look-ahead window
+/// opens and positions the second decoder before the transition starts.
+pub struct DecodeAhead<K> {
+  open: Box<dyn FnMut(&K) -> Result<VideoStream, VideoStreamErr>>,
+  slots: HashMap<K, Slot>,
+  /// Decoders beyond this number are closed, least recently used first
+  pub max_streams: usize,
+  use_counter: u64,
+}
+
+struct Slot {
+  stream: VideoStream,
+  /// Time of the frame currently held by the decoder
+  current: Option<RationalTime>,
+  /// Duration of a frame of the source, `None` if the stream has no constant frame rate
+  frame_duration: Option<RationalTime>,
+  last_used: u64,
+}
+
+/// How a decoder reaches the frame shown at some time, see `step_to`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Step {
+  /// The current frame is still shown
+  Keep,
+  /// Decode this many frames
+  Decode(u64),
+  Seek,
+}
+
+/// Frames that are decoded rather than seeking past them
+const MAX_DECODE_AHEAD: u64 = 8;
+
+/// Step from the frame at `current` to the frame shown at `time`. A frame is shown from its
+/// timestamp until the next frame's, so the number of decoded frames depends on the source's
+/// `frame_duration` and not on the rate `time` advances at.
+fn step_to(current: Option<RationalTime>, frame_duration: Option<RationalTime>, time: RationalTime) -> Step {
+  let (current, frame_duration) = match (current, frame_duration) {
+    (Some(current), Some(d)) if current <= time => (current, d),
+    (Some(current), None) if current == time => return Step::Keep,
+    _ => return Step::Seek,
+  };
+  match (time - current).to_frames(frame_duration.den(), frame_duration.num()) as u64 {
+    0 => Step::Keep,
+    n if n <= MAX_DECODE_AHEAD => Step::Decode(n),
+    _ => Step::Seek,
+  }
+}
+
+
+impl<K> DecodeAhead<K> where K: Eq + Hash + Clone {
+  /// `open` creates the decoder for a source key
+  pub fn new(max_streams: usize, open: impl FnMut(&K) -> Result<VideoStream, VideoStreamErr> + 'static) -> Self {
+    Self { open: Box::new(open), slots: HashMap::new(), max_streams: max_streams.max(1), use_counter: 0 }
+  }
+
+  pub fn len(&self) -> usize {
+    self.slots.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.slots.is_empty()
+  }
+
+  pub fn is_open(&self, key: &K) -> bool {
+    self.slots.contains_key(key)
+  }
+
+  pub fn close(&mut self, key: &K) {
+    self.slots.remove(key);
+  }
+
+  fn slot(&mut self, key: &K) -> Result<&mut Slot, VideoStreamErr> {
+    self.use_counter += 1;
+    if !self.slots.contains_key(key) {
+      let stream = (self.open)(key)?;
+      self.evict(1);
+      let frame_duration = stream.frame_duration();
+      self.slots.insert(key.clone(), Slot { stream, current: None, frame_duration, last_used: 0 });
+    }
+    let slot = self.slots.get_mut(key).unwrap();
+    slot.last_used = self.use_counter;
+    Ok(slot)
+  }
+
+  /// Closes least recently used decoders until `additional` more fit
+  fn evict(&mut self, additional: usize) {
+    while !self.slots.is_empty() && self.slots.len() + additional > self.max_streams {
+      let oldest = self.slots.iter()
+        .min_by_key(|(_, slot)| slot.last_used)
+        .map(|(key, _)| key.clone())
+        .unwrap();
+      self.slots.remove(&oldest);
+    }
+  }
+
+  /// Opens the decoders for `sources` and seeks them to the given start times, unless they are
+  /// already positioned there. Decoders that are not needed stay open as long as there is room.
+  /// Returns the sources that failed.
+  pub fn prepare<'a>(&mut self, sources: impl IntoIterator<Item=(&'a K, RationalTime)>) -> Vec<(K, VideoStreamErr)> where K: 'a {
+    let mut failed = Vec::new();
+    for (key, start) in sources {
+      let res = self.slot(key).and_then(|slot| {
+        if step_to(slot.current, slot.frame_duration, start) != Step::Seek {
+          return Ok(());
+        }
+        slot.current = None;
+        // A precise seek leaves the frame at `start` decoded
+        slot.stream.seek(start, Seek::empty())?;
+        slot.current = Some(slot.stream.frame_time().unwrap_or(start));
+        Ok(())
+      });
+      if let Err(err) = res {
+        failed.push((key.clone(), err));
+      }
+    }
+    failed
+  }
+
+  /// Frame of `key` shown at the source time `time`. Requests which move forward by less than a
+  /// few source frames only decode up to the frame shown at `time`, everything else seeks.
+  pub fn frame_at(&mut self, key: &K, time: RationalTime) -> Result<RcFrame, VideoStreamErr> {
+    let slot = self.slot(key)?;
+    let (res, expected) = match step_to(slot.current, slot.frame_duration, time) {
+      Step::Keep => return Ok(slot.stream.get_frm()),
+      Step::Decode(n) => {
+        let d = slot.frame_duration.unwrap();
+        (slot.stream.decode_frames(n), slot.current.unwrap() + RationalTime::new(d.num() * n as i64, d.den()))
+      },
+      Step::Seek => (slot.stream.seek(time, Seek::empty()), time),
+    };
+    match res {
+      Ok(()) => {
+        slot.current = Some(slot.stream.frame_time().unwrap_or(expected));
+        Ok(slot.stream.get_frm())
+      },
+      Err(err) => {
+        slot.current = None;
+        Err(err)
+      },
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Plays a `source_fps` source at `export_fps` from `start` and returns the source frame shown
+  /// at each export frame along with the steps taken
+  fn play(source_fps: i64, export_fps: i64, start: i64, frames: i64) -> (Vec<i64>, Vec<Step>) {
+    let frame_duration = RationalTime::new(1, source_fps);
+    let mut current = None;
+    let (mut shown, mut steps) = (Vec::new(), Vec::new());
+    for i in start..start + frames {
+      let time = RationalTime::from_frames(i, export_fps, 1);
+      let step = step_to(current, Some(frame_duration), time);
+      current = match step {
+        Step::Keep => current,
+        Step::Decode(n) => Some(current.unwrap() + RationalTime::new(n as i64, source_fps)),
+        // The decoder lands on the frame shown at `time`
+        Step::Seek => Some(RationalTime::from_frames(time.to_frames(source_fps, 1), source_fps, 1)),
+      };
+      shown.push(current.unwrap().to_frames(source_fps, 1));
+      steps.push(step);
+    }
+    (shown, steps)
+  }
+
+  #[test]
+  fn same_rate_decodes_one_frame_each() {
+    let (shown, steps) = play(30, 30, 0, 10);
+    assert_eq!(shown, (0..10).collect::<Vec<_>>());
+    assert_eq!(steps[0], Step::Seek);
+    assert!(steps[1..].iter().all(|&step| step == Step::Decode(1)));
+  }
+
+  #[test]
+  fn slower_source_repeats_frames() {
+    // 24 fps in a 30 fps export: 4 source frames for every 5 export frames
+    let (shown, steps) = play(24, 30, 0, 10);
+    assert_eq!(shown, [0, 0, 1, 2, 3, 4, 4, 5, 6, 7]);
+    assert!(steps[1..].iter().all(|&step| step == Step::Keep || step == Step::Decode(1)));
+  }
+
+  #[test]
+  fn faster_source_skips_frames() {
+    // 60 fps in a 24 fps export
+    let (shown, steps) = play(60, 24, 0, 5);
+    assert_eq!(shown, [0, 2, 5, 7, 10]);
+    assert_eq!(&steps[1..], [Step::Decode(2), Step::Decode(3), Step::Decode(2), Step::Decode(3)]);
+  }
+
+  #[test]
+  fn source_time_matches_export_time() {
+    for (source_fps, export_fps) in [(24, 30), (30, 24), (25, 60), (60, 25)] {
+      let (shown, _) = play(source_fps, export_fps, 7, 100);
+      for (i, frame) in (7..).zip(shown) {
+        assert_eq!(frame, RationalTime::from_frames(i, export_fps, 1).to_frames(source_fps, 1));
+      }
+    }
+  }
+
+  #[test]
+  fn seeks_backwards_and_far_ahead() {
+    let d = Some(RationalTime::new(1, 24));
+    let current = Some(RationalTime::from_secs(2));
+    assert_eq!(step_to(current, d, RationalTime::new(47, 24)), Step::Seek);
+    assert_eq!(step_to(current, d, RationalTime::from_secs(3)), Step::Seek);
+    assert_eq!(step_to(None, d, RationalTime::from_secs(2)), Step::Seek);
+  }
+
+  #[test]
+  fn unknown_frame_rate_only_keeps_exact_matches() {
+    let current = Some(RationalTime::from_secs(1));
+    assert_eq!(step_to(current, None, RationalTime::from_secs(1)), Step::Keep);
+    assert_eq!(step_to(current, None, RationalTime::new(31, 30)), Step::Seek);
+  }
+}
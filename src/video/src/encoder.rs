@@ -0,0 +1,382 @@
+use std::{ffi::{CString, NulError}, path, ptr};
+
+use crate::{
+  audio::StereoSample,
+  ffi::{
+    self, AVCodecContext, AVFormatContext, AVFrame, AVPacket, AVPixelFormat, AVRational, AVSampleFormat, AVStream,
+    SwsContext, SWS_Scaling, VideoStreamErr, c_string_from_path, wrap_VSResult,
+  },
+};
+
+type VSResult<T> = Result<T, VideoStreamErr>;
+type UnitRes = VSResult<()>;
+
+/// Settings of the video stream of an `Encoder`
+#[derive(Clone, Debug, PartialEq)]
+pub struct VideoEncoding {
+  /// Name of the FFmpeg encoder, e.g. `libx264` or `prores_ks`
+  pub encoder: String,
+  pub width: u32,
+  pub height: u32,
+  pub pix_fmt: AVPixelFormat,
+  /// Frames per second as `(numerator, denominator)`
+  pub frame_rate: (i64, i64),
+  /// In bit/s, 0 leaves rate control to the encoder
+  pub bit_rate: u64,
+  /// Private options of the encoder as `key=value:key=value`, e.g. `crf=18:preset=slow`
+  pub options: String,
+}
+
+/// Settings of the audio stream of an `Encoder`
+#[derive(Clone, Debug, PartialEq)]
+pub struct AudioEncoding {
+  pub encoder: String,
+  pub sample_rate: u32,
+  /// 1 or 2, mono is the average of both channels
+  pub channels: u32,
+  pub bit_rate: u64,
+  pub options: String,
+}
+
+#[derive(Debug, Default)]
+pub struct EncoderBuilder {
+  path_cstr: Option<CString>,
+  format: Option<CString>,
+  video: Option<VideoEncoding>,
+  audio: Option<AudioEncoding>,
+  n_threads: u32,
+}
+
+struct EncoderStream {
+  codec_ctx: *mut AVCodecContext,
+  stream: *mut AVStream,
+  frm: *mut AVFrame,
+}
+
+/// Writes a file with up to one video and one audio stream. Video frames are passed as RGBA and
+/// converted to the encoder's pixel format, audio as stereo `f32` samples. `finish` has to be
+/// called to get a valid file.
+pub struct Encoder {
+  fmt_ctx: *mut AVFormatContext,
+  pkt: *mut AVPacket,
+  video: Option<EncoderStream>,
+  audio: Option<EncoderStream>,
+  sws_ctx: *mut SwsContext,
+  /// Number of the next video frame, in units of the frame duration
+  next_frame: i64,
+  /// Samples which don't fill a whole audio frame yet
+  pending: Vec<StereoSample>,
+  /// Index of the first sample of the next audio frame
+  next_sample: i64,
+}
+
+
+/// Whether FFmpeg was built with an encoder of that name
+pub fn has_encoder(encoder: &str) -> bool {
+  match CString::new(encoder) {
+    Ok(name) => unsafe { ffi::ve_has_encoder(name.as_ptr()) },
+    Err(_) => false,
+  }
+}
+
+/// Whether FFmpeg was built with a muxer of that name
+pub fn has_muxer(format: &str) -> bool {
+  match CString::new(format) {
+    Ok(name) => unsafe { ffi::ve_has_muxer(name.as_ptr()) },
+    Err(_) => false,
+  }
+}
+
+/// Whether the encoder takes frames in `pix_fmt`, `false` if there is no such video encoder
+pub fn encoder_supports_pix_fmt(encoder: &str, pix_fmt: AVPixelFormat) -> bool {
+  match CString::new(encoder) {
+    Ok(name) => unsafe { ffi::ve_encoder_supports_pix_fmt(name.as_ptr(), pix_fmt) },
+    Err(_) => false,
+  }
+}
+
+/// Pixel format with FFmpeg's name for it, e.g. `yuv420p`
+pub fn pix_fmt_from_name(name: &str) -> Option<AVPixelFormat> {
+  let name = CString::new(name).ok()?;
+  match unsafe { ffi::ve_pix_fmt_from_name(name.as_ptr()) } {
+    AVPixelFormat::AV_PIX_FMT_NONE => None,
+    pix_fmt => Some(pix_fmt),
+  }
+}
+
+/// Empty options are passed as NULL
+fn options_cstr(options: &str) -> VSResult<Option<CString>> {
+  if options.is_empty() {
+    Ok(None)
+  } else {
+    CString::new(options).map(Some).map_err(|_| VideoStreamErr::NullReference)
+  }
+}
+
+impl EncoderBuilder {
+  pub fn set_path(mut self, path: &path::Path) -> Result<Self, NulError> {
+    self.path_cstr = Some(c_string_from_path(path)?);
+    Ok(self)
+  }
+
+  /// Short name of the muxer, e.g. `mp4` or `image2`. Without it the muxer is guessed from the
+  /// extension of the path.
+  pub fn set_format(mut self, format: &str) -> Result<Self, NulError> {
+    self.format = Some(CString::new(format)?);
+    Ok(self)
+  }
+
+  pub fn set_video(mut self, video: VideoEncoding) -> Self {
+    self.video = Some(video);
+    self
+  }
+
+  pub fn set_audio(mut self, audio: AudioEncoding) -> Self {
+    self.audio = Some(audio);
+    self
+  }
+
+  pub fn set_threads(mut self, n_threads: u32) -> Self {
+    self.n_threads = n_threads;
+    self
+  }
+
+  pub fn set_thread_to_all(self) -> Self {
+    self.set_threads(0)
+  }
+
+  pub fn finish(self) -> VSResult<Encoder> {
+    let path_cstr = match self.path_cstr {
+      Some(path_cstr) => path_cstr,
+      None => return Err(VideoStreamErr::NullReference),
+    };
+    let mut err = 0;
+    let mut fmt_ctx = ptr::null_mut();
+    let format = self.format.as_ref().map_or(ptr::null(), |f| f.as_ptr());
+    let res = unsafe { ffi::ve_open_output(path_cstr.as_ptr(), format, &mut fmt_ctx, &mut err) };
+    wrap_VSResult(res, err, ())?;
+
+    // From here on `Drop` cleans up
+    let mut encoder = Encoder {
+      fmt_ctx,
+      pkt: unsafe { ffi::av_packet_alloc() },
+      video: None,
+      audio: None,
+      sws_ctx: ptr::null_mut(),
+      next_frame: 0,
+      pending: Vec::new(),
+      next_sample: 0,
+    };
+
+    if let Some(video) = &self.video {
+      let options = options_cstr(&video.options)?;
+      let time_base = AVRational { num: video.frame_rate.1 as _, den: video.frame_rate.0 as _ };
+      let mut stream = EncoderStream { codec_ctx: ptr::null_mut(), stream: ptr::null_mut(), frm: ptr::null_mut() };
+      let res = unsafe {
+        ffi::ve_add_video_stream(fmt_ctx, CString::new(video.encoder.as_str()).map_err(|_| VideoStreamErr::EncoderNotFound)?.as_ptr(),
+          video.width as _, video.height as _, video.pix_fmt, time_base, video.bit_rate as _, self.n_threads,
+          options.as_ref().map_or(ptr::null(), |o| o.as_ptr()), &mut stream.codec_ctx, &mut stream.stream, &mut err)
+      };
+      // The codec context is freed with the stream even if opening failed
+      let res = wrap_VSResult(res, err, ());
+      encoder.video = Some(stream);
+      res?;
+      let stream = encoder.video.as_mut().unwrap();
+      let res = unsafe { ffi::ve_alloc_frame(stream.codec_ctx, &mut stream.frm, &mut err) };
+      wrap_VSResult(res, err, ())?;
+
+      let (flags, param) = SWS_Scaling::Bicubic { p1: 0., p2: 0.6 }.into();
+      let res = unsafe {
+        ffi::vs_create_sws_context(&mut encoder.sws_ctx, video.width as _, video.height as _, AVPixelFormat::AV_PIX_FMT_RGBA,
+          video.width as _, video.height as _, video.pix_fmt, flags as _, param.as_ptr(), &mut err)
+      };
+      wrap_VSResult(res, err, ())?;
+    }
+
+    if let Some(audio) = &self.audio {
+      let options = options_cstr(&audio.options)?;
+      let mut stream = EncoderStream { codec_ctx: ptr::null_mut(), stream: ptr::null_mut(), frm: ptr::null_mut() };
+      let res = unsafe {
+        ffi::ve_add_audio_stream(fmt_ctx, CString::new(audio.encoder.as_str()).map_err(|_| VideoStreamErr::EncoderNotFound)?.as_ptr(),
+          audio.sample_rate as _, audio.channels.clamp(1, 2) as _, audio.bit_rate as _,
+          options.as_ref().map_or(ptr::null(), |o| o.as_ptr()), &mut stream.codec_ctx, &mut stream.stream, &mut err)
+      };
+      let res = wrap_VSResult(res, err, ());
+      encoder.audio = Some(stream);
+      res?;
+      let stream = encoder.audio.as_mut().unwrap();
+      let res = unsafe { ffi::ve_alloc_frame(stream.codec_ctx, &mut stream.frm, &mut err) };
+      wrap_VSResult(res, err, ())?;
+    }
+
+    let res = unsafe { ffi::ve_write_header(fmt_ctx, path_cstr.as_ptr(), &mut err) };
+    wrap_VSResult(res, err, ())?;
+    Ok(encoder)
+  }
+}
+
+
+impl EncoderStream {
+  fn encode(&mut self, fmt_ctx: *mut AVFormatContext, frm: *mut AVFrame, pkt: *mut AVPacket) -> UnitRes {
+    let mut err = 0;
+    let res = unsafe { ffi::ve_encode_frame(fmt_ctx, self.codec_ctx, self.stream, frm, pkt, &mut err) };
+    wrap_VSResult(res, err, ())
+  }
+
+  /// Sends the remaining frames to the muxer
+  fn flush(&mut self, fmt_ctx: *mut AVFormatContext, pkt: *mut AVPacket) -> UnitRes {
+    match self.encode(fmt_ctx, ptr::null_mut(), pkt) {
+      Ok(()) | Err(VideoStreamErr::EOF) => Ok(()),
+      Err(err) => Err(err),
+    }
+  }
+
+  fn make_writable(&mut self) -> UnitRes {
+    let mut err = 0;
+    let res = unsafe { ffi::ve_make_writable(self.frm, &mut err) };
+    wrap_VSResult(res, err, ())
+  }
+}
+
+impl Drop for EncoderStream {
+  fn drop(&mut self) {
+    unsafe {
+      if !self.frm.is_null() {
+        ffi::av_frame_free(&mut self.frm);
+      }
+      if !self.codec_ctx.is_null() {
+        ffi::ve_free_codec_context(&mut self.codec_ctx);
+      }
+    }
+  }
+}
+
+
+/// Writes `samples` into the frame at sample `offset`, converted to the frame's sample format
+fn write_samples(frm: &mut AVFrame, offset: usize, samples: &[StereoSample]) {
+  let channels = frm.ch_layout.nb_channels.clamp(1, 2) as usize;
+  let fmt: AVSampleFormat = unsafe { std::mem::transmute(frm.format) };
+  let planar = matches!(fmt,
+    AVSampleFormat::AV_SAMPLE_FMT_U8P | AVSampleFormat::AV_SAMPLE_FMT_S16P | AVSampleFormat::AV_SAMPLE_FMT_S32P
+    | AVSampleFormat::AV_SAMPLE_FMT_S64P | AVSampleFormat::AV_SAMPLE_FMT_FLTP | AVSampleFormat::AV_SAMPLE_FMT_DBLP);
+  for (i, s) in samples.iter().enumerate() {
+    let values = match channels {
+      1 => [(s[0] + s[1]) * 0.5, 0.],
+      _ => *s,
+    };
+    for (c, x) in values.iter().take(channels).map(|x| x.clamp(-1., 1.)).enumerate() {
+      let (plane, idx) = if planar { (c, offset + i) } else { (0, (offset + i)*channels + c) };
+      unsafe {
+        let data = frm.data[plane];
+        match fmt {
+          AVSampleFormat::AV_SAMPLE_FMT_U8 | AVSampleFormat::AV_SAMPLE_FMT_U8P =>
+            *data.add(idx) = (x*127. + 128.).round() as u8,
+          AVSampleFormat::AV_SAMPLE_FMT_S16 | AVSampleFormat::AV_SAMPLE_FMT_S16P =>
+            *(data as *mut i16).add(idx) = (x*i16::MAX as f32).round() as i16,
+          AVSampleFormat::AV_SAMPLE_FMT_S32 | AVSampleFormat::AV_SAMPLE_FMT_S32P =>
+            *(data as *mut i32).add(idx) = (x as f64 * i32::MAX as f64).round() as i32,
+          AVSampleFormat::AV_SAMPLE_FMT_S64 | AVSampleFormat::AV_SAMPLE_FMT_S64P =>
+            *(data as *mut i64).add(idx) = (x as f64 * i64::MAX as f64).round() as i64,
+          AVSampleFormat::AV_SAMPLE_FMT_FLT | AVSampleFormat::AV_SAMPLE_FMT_FLTP =>
+            *(data as *mut f32).add(idx) = x,
+          AVSampleFormat::AV_SAMPLE_FMT_DBL | AVSampleFormat::AV_SAMPLE_FMT_DBLP =>
+            *(data as *mut f64).add(idx) = x as f64,
+          _ => {},
+        }
+      }
+    }
+  }
+}
+
+impl Encoder {
+  /// Size of the video frames `write_rgba` expects
+  pub fn video_size(&self) -> Option<[u32; 2]> {
+    self.video.as_ref().map(|v| unsafe { [(*v.codec_ctx).width as _, (*v.codec_ctx).height as _] })
+  }
+
+  pub fn sample_rate(&self) -> Option<u32> {
+    self.audio.as_ref().map(|a| unsafe { (*a.codec_ctx).sample_rate as _ })
+  }
+
+  /// Number of video frames written so far
+  pub fn frames_written(&self) -> i64 {
+    self.next_frame
+  }
+
+  /// Encodes the next video frame from tightly packed RGBA rows of `video_size`
+  pub fn write_rgba(&mut self, rgba: &[u8]) -> UnitRes {
+    let [width, height] = self.video_size().ok_or(VideoStreamErr::StreamNotFound)?;
+    if rgba.len() < (width*height*4) as usize {
+      return Err(VideoStreamErr::IndexOutOfBounds);
+    }
+    let video = self.video.as_mut().unwrap();
+    let mut err = 0;
+    let res = unsafe { ffi::ve_scale_rgba(self.sws_ctx, rgba.as_ptr(), width as _, height as _, video.frm, &mut err) };
+    wrap_VSResult(res, err, ())?;
+    unsafe { (*video.frm).pts = self.next_frame };
+    video.encode(self.fmt_ctx, video.frm, self.pkt)?;
+    self.next_frame += 1;
+    Ok(())
+  }
+
+  /// Queues samples at the encoder's sample rate. Whole frames are encoded right away, the rest
+  /// waits for more samples or `finish`.
+  pub fn write_audio(&mut self, samples: &[StereoSample]) -> UnitRes {
+    if self.audio.is_none() {
+      return Err(VideoStreamErr::StreamNotFound);
+    }
+    self.pending.extend_from_slice(samples);
+    let frame_size = unsafe { (*self.audio.as_ref().unwrap().frm).nb_samples as usize };
+    let mut written = 0;
+    while self.pending.len() - written >= frame_size {
+      self.encode_audio_frame(written, frame_size)?;
+      written += frame_size;
+    }
+    self.pending.drain(..written);
+    Ok(())
+  }
+
+  /// Encodes `len` pending samples from `offset` on as one frame, padded with silence
+  fn encode_audio_frame(&mut self, offset: usize, len: usize) -> UnitRes {
+    let audio = self.audio.as_mut().unwrap();
+    audio.make_writable()?;
+    let frm = unsafe { &mut *audio.frm };
+    let frame_size = frm.nb_samples as usize;
+    write_samples(frm, 0, &self.pending[offset..offset + len]);
+    write_samples(frm, len, &vec![[0.; 2]; frame_size - len]);
+    frm.pts = self.next_sample;
+    audio.encode(self.fmt_ctx, audio.frm, self.pkt)?;
+    self.next_sample += frame_size as i64;
+    Ok(())
+  }
+
+  /// Encodes the remaining audio, flushes the encoders and writes the trailer
+  pub fn finish(mut self) -> UnitRes {
+    if self.audio.is_some() && !self.pending.is_empty() {
+      self.encode_audio_frame(0, self.pending.len())?;
+      self.pending.clear();
+    }
+    for stream in [self.video.as_mut(), self.audio.as_mut()].into_iter().flatten() {
+      stream.flush(self.fmt_ctx, self.pkt)?;
+    }
+    let mut err = 0;
+    let res = unsafe { ffi::ve_write_trailer(self.fmt_ctx, &mut err) };
+    wrap_VSResult(res, err, ())
+  }
+}
+
+impl Drop for Encoder {
+  fn drop(&mut self) {
+    self.video = None;
+    self.audio = None;
+    unsafe {
+      if !self.sws_ctx.is_null() {
+        ffi::sws_freeContext(self.sws_ctx);
+      }
+      if !self.pkt.is_null() {
+        ffi::av_packet_free(&mut self.pkt);
+      }
+      ffi::ve_close_output(&mut self.fmt_ctx);
+    }
+  }
+}
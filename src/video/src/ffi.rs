@@ -40,11 +40,14 @@ pub struct VideoFrameContext {
   pub frm_src: rc::RcFrame,
   pub(crate) sws_ctx: *mut SwsContext,
   pub(crate) sws_frm: rc::RcFrame,
+  /// Output and source size and format `sws_ctx` was created for, see `convert`
+  sws_params: Option<(i32, i32, AVPixelFormat, i32, i32, i32)>,
 }
 
 
 impl VideoStream{
-  pub fn seek(&mut self, seconds: f64, flags: Seek) -> UnitRes {
+  /// Seeks to the frame at `time`. Without `Seek::NoPreciseMode` the frame is decoded.
+  pub fn seek(&mut self, time: crate::RationalTime, flags: Seek) -> UnitRes {
     let codec_ctx = if (flags & Seek::NoPreciseMode).is_empty() {
         self.codec_ctx
       } else {
@@ -55,16 +58,19 @@ impl VideoStream{
       } else {
         -1
       };
+    let timestamp = time.to_pts(self.time_base());
+    let _span = tracing::debug_span!("seek", pts = timestamp, precise = !codec_ctx.is_null()).entered();
     let mut err: i32 = 0;
     let res;
     unsafe{
-      res = vs_seek_at(self.fmt_ctx, self.stream,
-        seconds, flags, codec_ctx, self.pkt, self.frm.leak_mut(), (&mut err) as _)
+      res = vs_seek(self.fmt_ctx, self.stream,
+        timestamp, flags, codec_ctx, self.pkt, self.frm.leak_mut(), (&mut err) as _)
     }
     wrap_VSResult(res, err, ())
   }
 
   pub fn decode_frames(&mut self, n: u64) -> UnitRes {
+    let _span = tracing::debug_span!("decode_frames", n).entered();
     let mut err = 0;
     let res = unsafe{
       let (sws_ctx, swsfrm) = (std::ptr::null_mut(), std::ptr::null_mut());
@@ -76,16 +82,46 @@ impl VideoStream{
   pub fn get_frm(&self) -> rc::RcFrame {
     self.frm.clone()
   }
+
+  pub fn media_type(&self) -> AVMediaType {
+    unsafe { (*self.codec_ctx).codec_type }
+  }
+
+  /// Unit of the timestamps of the stream's packets and frames
+  pub fn time_base(&self) -> crate::RationalTime {
+    let tb = unsafe { (*self.stream).time_base };
+    crate::RationalTime::new(tb.num as _, tb.den as _)
+  }
+
+  /// Duration of a frame from the stream's average frame rate, `None` if it isn't known
+  pub fn frame_duration(&self) -> Option<crate::RationalTime> {
+    let fr = unsafe { (*self.stream).avg_frame_rate };
+    (fr.num > 0 && fr.den > 0).then(|| crate::RationalTime::new(fr.den as _, fr.num as _))
+  }
+
+  /// Presentation time of the current frame, `None` if the frame has no timestamp
+  pub fn frame_time(&self) -> Option<crate::RationalTime> {
+    // AV_NOPTS_VALUE
+    match self.frm.best_effort_timestamp {
+      i64::MIN => None,
+      ts => Some(crate::RationalTime::from_pts(ts, self.time_base())),
+    }
+  }
+
+  /// Samples per second of an audio stream
+  pub fn sample_rate(&self) -> u32 {
+    unsafe { (*self.codec_ctx).sample_rate as _ }
+  }
+
+  /// Number of channels of an audio stream
+  pub fn channels(&self) -> u32 {
+    unsafe { (*self.codec_ctx).ch_layout.nb_channels as _ }
+  }
 }
 
 impl Drop for VideoStream{
   fn drop(&mut self) {
     unsafe {
-      let mut frm = self.frm.leak_mut();
-      if !frm.is_null() {
-        av_frame_unref(frm);
-        av_frame_free(&mut frm);
-      }
       if !self.pkt.is_null() {
         av_packet_unref(self.pkt);
         av_packet_free(&mut self.pkt);
@@ -104,7 +140,7 @@ impl Drop for VideoStream{
 impl VideoFrameContext {
   pub fn new(src: rc::RcFrame) -> Self {
     let sws_frm = rc::RcFrame::wrap_raw(unsafe{av_frame_alloc()});
-    Self { frm_src: src, sws_ctx: std::ptr::null_mut(), sws_frm }
+    Self { frm_src: src, sws_ctx: std::ptr::null_mut(), sws_frm, sws_params: None }
   }
   pub fn new_init(src: rc::RcFrame, new_width: i32, new_height: i32, new_pix_fmt: AVPixelFormat, width: i32, height: i32, pix_fmt: AVPixelFormat, scaling: SWS_Scaling) -> VSResult<Self> {
     let mut res = Self::new(src);
@@ -128,13 +164,46 @@ impl VideoFrameContext {
       Ok(sws_ctx) => {
         // let sws_frm = rc::RcFrame::wrap_raw(unsafe{av_frame_alloc()});
         // Ok(Self { frm_src: src, sws_ctx, sws_frm })
-        self.sws_ctx = sws_ctx;
+        let old = std::mem::replace(&mut self.sws_ctx, sws_ctx);
+        if !old.is_null() {
+          unsafe { sws_freeContext(old) }
+        }
+        self.sws_params = None;
         Ok(())
       },
       Err(err) => Err(err)
     }
   }
 
+  /// Scales and converts `frm_src` to the given size and format. Unlike `decode` this creates the
+  /// scaling context itself and recreates it whenever the source frames change size or format.
+  pub fn convert(&mut self, width: i32, height: i32, pix_fmt: AVPixelFormat, scaling: SWS_Scaling) -> UnitRes {
+    if self.frm_src.leak().is_null() {
+      return Err(VideoStreamErr::NullReference);
+    }
+    let (src_width, src_height, src_fmt) = (self.frm_src.width, self.frm_src.height, self.frm_src.format);
+    let params = (width, height, pix_fmt, src_width, src_height, src_fmt);
+    let _span = tracing::debug_span!("convert", width, height, src_width, src_height).entered();
+    if self.sws_ctx.is_null() || self.sws_params != Some(params) {
+      // `format` of a decoded video frame always holds a valid AVPixelFormat
+      let src_pix_fmt: AVPixelFormat = unsafe { std::mem::transmute(src_fmt) };
+      self.replace_sws_ctx(width, height, pix_fmt, src_width, src_height, src_pix_fmt, scaling)?;
+      self.sws_params = Some(params);
+    }
+    self.decode()
+  }
+
+  /// Result of the last `decode` or `convert`
+  pub fn converted_frm(&self) -> video_stream::RawImageRef {
+    video_stream::RawImageRef::new(&self.sws_frm)
+  }
+
+  /// New reference to the frame `converted_frm` refers to, e.g. to keep it in a `FrameCache`.
+  /// `convert` allocates new buffers, references to previous frames stay valid.
+  pub fn converted_ref(&self) -> rc::RcFrame {
+    self.sws_frm.clone()
+  }
+
   pub fn decode(&mut self) -> UnitRes {
     let mut err = 0;
     let res = unsafe {
@@ -151,8 +220,6 @@ impl Drop for VideoFrameContext {
       if !self.sws_ctx.is_null() {
         sws_freeContext(self.sws_ctx);
       }
-      let mut sws_frm = std::mem::replace(&mut self.sws_frm, rc::RcFrame::wrap_null());
-      av_frame_free(&mut sws_frm.leak_mut())
       // if !self.sws_frm.is_null() {
       //   av_frame_unref(self.sws_frm);
       //   av_frame_free(&mut self.sws_frm);
@@ -188,7 +255,9 @@ pub enum VideoStreamErr{
   IndexOutOfBounds,
   StreamNotFound,
   DecoderNotFound,
-  NullReference
+  NullReference,
+  EncoderNotFound,
+  MuxerNotFound,
 }
 
 pub fn wrap_VSResult<T>(res: VideoStreamResult, err: i32, x: T) -> Result<T, VideoStreamErr> {
@@ -204,6 +273,8 @@ pub fn wrap_VSResult<T>(res: VideoStreamResult, err: i32, x: T) -> Result<T, Vid
     VideoStreamResult::vs_stream_not_found => Err(VideoStreamErr::StreamNotFound),
     VideoStreamResult::vs_decoder_not_found => Err(VideoStreamErr::DecoderNotFound),
     VideoStreamResult::vs_null_reference => Err(VideoStreamErr::NullReference),
+    VideoStreamResult::vs_encoder_not_found => Err(VideoStreamErr::EncoderNotFound),
+    VideoStreamResult::vs_muxer_not_found => Err(VideoStreamErr::MuxerNotFound),
   }
 }
 
@@ -214,12 +285,12 @@ type UnitRes = VSResult<()>;
 
 
 #[cfg(target_family = "unix")]
-fn c_string_from_path(path: &path::Path) -> Result<CString, std::ffi::NulError> {
+pub(crate) fn c_string_from_path(path: &path::Path) -> Result<CString, std::ffi::NulError> {
   use std::os::unix::prelude::OsStrExt;
   CString::new(path.as_os_str().as_bytes())
 }
 #[cfg(not(target_family = "unix"))]
-fn c_string_from_path(path: &path::Path) -> Result<CString, std::ffi::NulError> {
+pub(crate) fn c_string_from_path(path: &path::Path) -> Result<CString, std::ffi::NulError> {
   let s = path.to_string_lossy().to_string();
   CString::new(s.as_bytes())
 }
@@ -1,6 +1,6 @@
 use std::ops::{Deref, DerefMut};
 
-use super::{AVFrame, av_frame_ref, av_buffer_get_ref_count, av_frame_unref, av_frame_clone};
+use super::{AVFrame, av_frame_ref, av_buffer_get_ref_count, av_frame_unref, av_frame_clone, av_frame_free};
 
 
 pub struct RcFrame {
@@ -16,8 +16,21 @@ impl RcFrame {
     Self { frm: std::ptr::null_mut() }
   }
 
+  pub fn is_null(&self) -> bool {
+    self.frm.is_null()
+  }
+
+  /// References to each buffer of the frame, including this one. 0 for unused buffers.
   pub fn ref_count(&self) -> [i32; 8] {
-    self.buf.map(|b| unsafe{av_buffer_get_ref_count(b)})
+    self.buf.map(|b| if b.is_null() { 0 } else { unsafe{av_buffer_get_ref_count(b)} })
+  }
+
+  /// Bytes of all buffers the frame references
+  pub fn buffer_size(&self) -> usize {
+    self.buf.iter()
+      .filter(|b| !b.is_null())
+      .map(|&b| unsafe{(*b).size} as usize)
+      .sum()
   }
 
   pub fn clone_from_raw(&mut self, src: *const AVFrame) -> super::UnitRes {
@@ -56,9 +69,12 @@ fn clone_from(&mut self, source: &Self) {
   }
 }
 
+/// Unreferences the buffers and frees the frame itself, every `RcFrame` owns its `AVFrame`
 impl Drop for RcFrame {
   fn drop(&mut self) {
-    unsafe {av_frame_unref(self.frm)}
+    if !self.frm.is_null() {
+      unsafe {av_frame_free(&mut self.frm)}
+    }
   }
 }
 
@@ -62,6 +62,18 @@ impl RawImageRef<'_> {
   pub fn height(&self) -> usize { self.height }
   pub fn pix_fmt(&self) -> AVPixelFormat { self.pix_fmt }
 
+  /// Wraps tightly packed RGBA rows
+  pub fn new_rgba32(data: &[u8], width: usize, height: usize) -> RawImageRef {
+    assert!(data.len() >= width*height*4);
+    RawImageRef {
+      planes: [data, &[], &[], &[], &[], &[], &[], &[]],
+      linesize: [width*4, 0, 0, 0, 0, 0, 0, 0],
+      width,
+      height,
+      pix_fmt: AVPixelFormat::AV_PIX_FMT_RGBA
+    }
+  }
+
   pub fn new_dummy_rgba32<'a>(data_store: &'a mut Vec<u8>, width: usize, height: usize) -> RawImageRef<'a> {
     let linesize = width*4;
     *data_store = Vec::with_capacity(linesize*height);
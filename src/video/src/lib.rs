@@ -1,5 +1,12 @@
 pub mod ffi;
 pub mod buffer;
+pub mod time;
+pub mod timecode;
+pub mod decode_ahead;
+pub mod audio;
+pub mod encoder;
+pub mod probe;
+pub mod cache;
 
 pub use ffi::{
   VideoStream,
@@ -11,6 +18,14 @@ pub use ffi::{
   VideoFrameContext,
 };
 
+pub use time::RationalTime;
+pub use timecode::Timecode;
+pub use decode_ahead::DecodeAhead;
+pub use audio::{AudioStream, StereoSample};
+pub use encoder::{Encoder, EncoderBuilder, VideoEncoding, AudioEncoding};
+pub use probe::{MediaInfo, StreamInfo};
+pub use cache::{FrameCache, FrameKey, CacheStats};
+
 pub use ffi::video_stream::{
   RawImageRef
 };
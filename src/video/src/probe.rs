@@ -0,0 +1,132 @@
+use std::{ffi::CStr, os::raw::c_char, path};
+
+use crate::{
+  ffi::{self, AVFormatContext, AVMediaType, VideoStreamErr, c_string_from_path, wrap_VSResult},
+  RationalTime,
+};
+
+/// Container timestamps are in microseconds (`AV_TIME_BASE`)
+const AV_TIME_BASE: i64 = 1_000_000;
+/// `AV_NOPTS_VALUE`
+const NO_TIMESTAMP: i64 = i64::MIN;
+
+/// Properties of a stream as stored in the container, nothing is decoded
+#[derive(Clone, Debug, PartialEq)]
+pub struct StreamInfo {
+  pub index: u32,
+  pub media_type: AVMediaType,
+  /// Short name of the codec, e.g. `h264`
+  pub codec: String,
+  /// Pixel format of video streams or sample format of audio streams
+  pub format: Option<String>,
+  pub width: u32,
+  pub height: u32,
+  /// Average frames per second as `(numerator, denominator)`, `None` if unknown
+  pub frame_rate: Option<(i64, i64)>,
+  pub sample_rate: u32,
+  pub channels: u32,
+  /// In bit/s, 0 if unknown
+  pub bit_rate: i64,
+  pub duration: Option<RationalTime>,
+  /// Number of frames if the container stores it
+  pub frames: Option<i64>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MediaInfo {
+  /// Short name of the demuxer, e.g. `mov,mp4,m4a,3gp,3g2,mj2`
+  pub format: String,
+  pub duration: Option<RationalTime>,
+  pub bit_rate: i64,
+  pub streams: Vec<StreamInfo>,
+}
+
+
+fn string_from_c(s: *const c_char) -> Option<String> {
+  if s.is_null() {
+    None
+  } else {
+    Some(unsafe { CStr::from_ptr(s) }.to_string_lossy().into_owned())
+  }
+}
+
+/// Reads the container of `path` and describes its streams
+pub fn probe(path: &path::Path) -> Result<MediaInfo, VideoStreamErr> {
+  let path_cstr = c_string_from_path(path).map_err(|_| VideoStreamErr::IO)?;
+  let mut err = 0;
+  let mut fmt_ctx: *mut AVFormatContext = std::ptr::null_mut();
+  let res = unsafe { ffi::vs_open_format_context_from_path(path_cstr.as_ptr() as _, &mut fmt_ctx, &mut err) };
+  if let Err(err) = wrap_VSResult(res, err, ()) {
+    if !fmt_ctx.is_null() {
+      unsafe { ffi::avformat_close_input(&mut fmt_ctx) };
+    }
+    return Err(err);
+  }
+  if fmt_ctx.is_null() {
+    return Err(VideoStreamErr::NullReference);
+  }
+
+  let info = unsafe {
+    let ctx = &*fmt_ctx;
+    let streams = if ctx.streams.is_null() {
+      &[][..]
+    } else {
+      std::slice::from_raw_parts(ctx.streams, ctx.nb_streams as _)
+    };
+    MediaInfo {
+      format: if ctx.iformat.is_null() { None } else { string_from_c((*ctx.iformat).name) }.unwrap_or_default(),
+      duration: match ctx.duration {
+        NO_TIMESTAMP => None,
+        duration => Some(RationalTime::new(duration, AV_TIME_BASE)),
+      },
+      bit_rate: ctx.bit_rate,
+      streams: streams.iter().map(|&stream| stream_info(&*stream)).collect(),
+    }
+  };
+  unsafe { ffi::avformat_close_input(&mut fmt_ctx) };
+  Ok(info)
+}
+
+unsafe fn stream_info(stream: &ffi::AVStream) -> StreamInfo {
+  let par = &*stream.codecpar;
+  let tb = stream.time_base;
+  let fr = stream.avg_frame_rate;
+  StreamInfo {
+    index: stream.index as _,
+    media_type: par.codec_type,
+    codec: string_from_c(ffi::avcodec_get_name(par.codec_id)).unwrap_or_default(),
+    format: string_from_c(ffi::vs_format_name(par.codec_type, par.format)),
+    width: par.width.max(0) as _,
+    height: par.height.max(0) as _,
+    frame_rate: if fr.num > 0 && fr.den > 0 { Some((fr.num as _, fr.den as _)) } else { None },
+    sample_rate: par.sample_rate.max(0) as _,
+    channels: par.ch_layout.nb_channels.max(0) as _,
+    bit_rate: par.bit_rate,
+    duration: match stream.duration {
+      NO_TIMESTAMP => None,
+      _ if tb.den == 0 => None,
+      duration => Some(RationalTime::from_pts(duration, RationalTime::new(tb.num as _, tb.den as _))),
+    },
+    frames: if stream.nb_frames > 0 { Some(stream.nb_frames) } else { None },
+  }
+}
+
+
+impl MediaInfo {
+  /// The first stream of that type, usually the one players pick
+  pub fn first_stream(&self, media_type: AVMediaType) -> Option<&StreamInfo> {
+    self.streams.iter().find(|s| s.media_type == media_type)
+  }
+
+  /// Duration of the container, or of its longest stream if the container doesn't know it
+  pub fn best_duration(&self) -> Option<RationalTime> {
+    self.duration.or_else(|| self.streams.iter().filter_map(|s| s.duration).max())
+  }
+}
+
+impl StreamInfo {
+  /// Duration of one frame of a video stream
+  pub fn frame_duration(&self) -> Option<RationalTime> {
+    self.frame_rate.map(|(num, den)| RationalTime::new(den, num))
+  }
+}
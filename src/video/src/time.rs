@@ -0,0 +1,337 @@
+use std::{cmp::Ordering, fmt, ops, str::FromStr};
+
+use serde::{Serialize, Deserialize};
+
+/// Exact point in (or span of) time in seconds, stored as a reduced fraction. The denominator is
+/// always positive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "(i64, i64)", into = "(i64, i64)")]
+pub struct RationalTime {
+  num: i64,
+  den: i64,
+}
+
+/// A string which isn't a time, see `RationalTime::from_str`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseTimeError(pub String);
+
+
+fn gcd(mut a: i128, mut b: i128) -> i128 {
+  while b != 0 {
+    (a, b) = (b, a % b);
+  }
+  a.abs()
+}
+
+impl RationalTime {
+  pub const ZERO: Self = Self { num: 0, den: 1 };
+
+  /// `num/den` seconds. Panics if `den` is 0, see `checked_new`.
+  pub fn new(num: i64, den: i64) -> Self {
+    Self::checked_new(num, den).expect("RationalTime with denominator 0")
+  }
+
+  /// `num/den` seconds, `None` if `den` is 0. For values from files and users.
+  pub fn checked_new(num: i64, den: i64) -> Option<Self> {
+    (den != 0).then(|| Self::reduced(num as i128, den as i128))
+  }
+
+  /// Duration of a frame at `rate_num/rate_den` frames per second, `None` unless the rate is
+  /// positive
+  pub fn from_frame_rate(rate_num: i64, rate_den: i64) -> Option<Self> {
+    if rate_num > 0 && rate_den > 0 { Self::checked_new(rate_den, rate_num) } else { None }
+  }
+
+  pub fn from_secs(secs: i64) -> Self {
+    Self { num: secs, den: 1 }
+  }
+
+  /// Frame `frame` of a stream with `rate_num/rate_den` frames per second
+  pub fn from_frames(frame: i64, rate_num: i64, rate_den: i64) -> Self {
+    Self::new(frame * rate_den, rate_num)
+  }
+
+  /// Timestamp `pts` of a stream with the given time base
+  pub fn from_pts(pts: i64, time_base: Self) -> Self {
+    Self::reduced(pts as i128 * time_base.num as i128, time_base.den as i128)
+  }
+
+  /// Approximates `secs` with microsecond precision
+  pub fn from_secs_f64(secs: f64) -> Self {
+    Self::new((secs * 1_000_000.).round() as i64, 1_000_000)
+  }
+
+  fn reduced(num: i128, den: i128) -> Self {
+    let (num, den) = if den < 0 { (-num, -den) } else { (num, den) };
+    let d = gcd(num, den).max(1);
+    let (num, den) = (num / d, den / d);
+    Self {
+      num: num.clamp(i64::MIN as i128, i64::MAX as i128) as i64,
+      den: den.min(i64::MAX as i128) as i64,
+    }
+  }
+
+  pub fn num(&self) -> i64 { self.num }
+  pub fn den(&self) -> i64 { self.den }
+
+  pub fn as_secs_f64(&self) -> f64 {
+    self.num as f64 / self.den as f64
+  }
+
+  /// Index of the frame containing this point in time for the given frame rate
+  pub fn to_frames(&self, rate_num: i64, rate_den: i64) -> i64 {
+    let n = self.num as i128 * rate_num as i128;
+    let d = self.den as i128 * rate_den as i128;
+    n.div_euclid(d) as i64
+  }
+
+  /// Last timestamp of a stream with the given time base at or before this point in time
+  pub fn to_pts(&self, time_base: Self) -> i64 {
+    let n = self.num as i128 * time_base.den as i128;
+    let d = self.den as i128 * time_base.num as i128;
+    n.div_euclid(d).clamp(i64::MIN as i128, i64::MAX as i128) as i64
+  }
+
+  /// Position of `self` in `[start, end]` as a value between 0 and 1
+  pub fn fraction_between(&self, start: Self, end: Self) -> f64 {
+    let span = end - start;
+    if span.num == 0 {
+      return 0.;
+    }
+    ((*self - start).as_secs_f64() / span.as_secs_f64()).clamp(0., 1.)
+  }
+
+  pub fn is_negative(&self) -> bool {
+    self.num < 0
+  }
+}
+
+/// Exact value of a decimal number like `12` or `1.25`
+fn parse_decimal(s: &str) -> Option<RationalTime> {
+  let (int, frac) = s.split_once('.').unwrap_or((s, ""));
+  if int.is_empty() && frac.is_empty() || !int.chars().chain(frac.chars()).all(|c| c.is_ascii_digit()) || frac.len() > 18 {
+    return None;
+  }
+  let int: i64 = if int.is_empty() { 0 } else { int.parse().ok()? };
+  let den = 10_i64.pow(frac.len() as u32);
+  let frac: i64 = if frac.is_empty() { 0 } else { frac.parse().ok()? };
+  Some(RationalTime::new(int.checked_mul(den)?.checked_add(frac)?, den))
+}
+
+impl Default for RationalTime {
+  fn default() -> Self { Self::ZERO }
+}
+
+impl Ord for RationalTime {
+  fn cmp(&self, other: &Self) -> Ordering {
+    (self.num as i128 * other.den as i128).cmp(&(other.num as i128 * self.den as i128))
+  }
+}
+
+impl PartialOrd for RationalTime {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl ops::Add for RationalTime {
+  type Output = Self;
+  fn add(self, rhs: Self) -> Self {
+    Self::reduced(
+      self.num as i128 * rhs.den as i128 + rhs.num as i128 * self.den as i128,
+      self.den as i128 * rhs.den as i128)
+  }
+}
+
+impl ops::Sub for RationalTime {
+  type Output = Self;
+  fn sub(self, rhs: Self) -> Self {
+    self + -rhs
+  }
+}
+
+impl ops::Neg for RationalTime {
+  type Output = Self;
+  fn neg(self) -> Self {
+    Self { num: -self.num, den: self.den }
+  }
+}
+
+impl ops::AddAssign for RationalTime {
+  fn add_assign(&mut self, rhs: Self) { *self = *self + rhs }
+}
+
+impl ops::SubAssign for RationalTime {
+  fn sub_assign(&mut self, rhs: Self) { *self = *self - rhs }
+}
+
+impl TryFrom<(i64, i64)> for RationalTime {
+  type Error = &'static str;
+  fn try_from((num, den): (i64, i64)) -> Result<Self, Self::Error> {
+    Self::checked_new(num, den).ok_or("denominator is 0")
+  }
+}
+
+impl From<RationalTime> for (i64, i64) {
+  fn from(t: RationalTime) -> Self {
+    (t.num, t.den)
+  }
+}
+
+impl fmt::Display for RationalTime {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}/{}s", self.num, self.den)
+  }
+}
+
+/// Parses seconds (`90`, `1.5`), units (`1h2m3.5s`, `1m30s`, `250ms`) and clock times (`1:30`,
+/// `01:02:03.5`). A leading `-` negates the time. Numbers are exact decimals.
+impl FromStr for RationalTime {
+  type Err = ParseTimeError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let err = || ParseTimeError(s.to_string());
+    let trimmed = s.trim();
+    let (negative, rest) = match trimmed.strip_prefix('-') {
+      Some(rest) => (true, rest),
+      None => (false, trimmed),
+    };
+    let time = if rest.contains(':') {
+      let parts: Vec<&str> = rest.split(':').collect();
+      if parts.len() > 3 || parts[..parts.len() - 1].iter().any(|p| p.is_empty() || !p.chars().all(|c| c.is_ascii_digit())) {
+        return Err(err());
+      }
+      let mut time = Self::ZERO;
+      for (i, part) in parts.iter().enumerate() {
+        let unit = Self::from_secs(60_i64.pow((parts.len() - 1 - i) as u32));
+        let value = parse_decimal(part).ok_or_else(err)?;
+        time += Self::reduced(value.num as i128 * unit.num as i128, value.den as i128);
+      }
+      time
+    } else if rest.ends_with(|c: char| c.is_ascii_alphabetic()) {
+      let mut time = Self::ZERO;
+      let mut rest = rest;
+      while !rest.is_empty() {
+        let unit_start = rest.find(|c: char| c.is_ascii_alphabetic()).ok_or_else(err)?;
+        let unit_end = rest[unit_start..].find(|c: char| !c.is_ascii_alphabetic()).map_or(rest.len(), |i| unit_start + i);
+        let value = parse_decimal(&rest[..unit_start]).ok_or_else(err)?;
+        let (unit_num, unit_den) = match &rest[unit_start..unit_end] {
+          "h" | "H" => (3600, 1),
+          "m" | "M" | "min" => (60, 1),
+          "s" | "S" => (1, 1),
+          "ms" => (1, 1000),
+          _ => return Err(err()),
+        };
+        time += Self::reduced(value.num as i128 * unit_num, value.den as i128 * unit_den);
+        rest = &rest[unit_end..];
+      }
+      time
+    } else {
+      parse_decimal(rest).ok_or_else(err)?
+    };
+    Ok(if negative { -time } else { time })
+  }
+}
+
+impl fmt::Display for ParseTimeError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "invalid time {:?}, expected e.g. 90, 1.5, 1m30s or 1:30", self.0)
+  }
+}
+
+impl std::error::Error for ParseTimeError {}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn parse(s: &str) -> RationalTime {
+    s.parse().unwrap()
+  }
+
+  #[test]
+  fn fractions_are_reduced() {
+    let t = RationalTime::new(6, 4);
+    assert_eq!((t.num(), t.den()), (3, 2));
+    // The sign is on the numerator
+    let t = RationalTime::new(3, -6);
+    assert_eq!((t.num(), t.den()), (-1, 2));
+    assert_eq!(RationalTime::new(-3, -6), RationalTime::new(1, 2));
+    assert_eq!(RationalTime::new(0, -5), RationalTime::ZERO);
+    assert_eq!(RationalTime::from_frames(50, 25, 1), RationalTime::from_secs(2));
+    assert_eq!(RationalTime::from_frames(1001, 30000, 1001), RationalTime::new(1001 * 1001, 30000));
+  }
+
+  #[test]
+  fn checked_constructors() {
+    assert_eq!(RationalTime::checked_new(1, 0), None);
+    assert_eq!(RationalTime::checked_new(2, 4), Some(RationalTime::new(1, 2)));
+    assert_eq!(RationalTime::from_frame_rate(30000, 1001), Some(RationalTime::new(1001, 30000)));
+    assert_eq!(RationalTime::from_frame_rate(25, 0), None);
+    assert_eq!(RationalTime::from_frame_rate(0, 1), None);
+    assert_eq!(RationalTime::from_frame_rate(-25, 1), None);
+    assert!(RationalTime::try_from((1, 0)).is_err());
+    assert_eq!(RationalTime::try_from((2, -4)), Ok(RationalTime::new(-1, 2)));
+  }
+
+  #[test]
+  #[should_panic]
+  fn zero_denominator_panics() {
+    RationalTime::new(1, 0);
+  }
+
+  #[test]
+  fn arithmetic_is_exact() {
+    let third = RationalTime::new(1, 3);
+    assert_eq!(third + third + third, RationalTime::from_secs(1));
+    assert_eq!(RationalTime::new(1, 2) - RationalTime::new(1, 3), RationalTime::new(1, 6));
+    assert_eq!(-third, RationalTime::new(-1, 3));
+    let mut t = RationalTime::ZERO;
+    for _ in 0..30000 {
+      t += RationalTime::new(1001, 30000);
+    }
+    assert_eq!(t, RationalTime::from_secs(1001));
+    t -= RationalTime::from_secs(1001);
+    assert_eq!(t, RationalTime::ZERO);
+    assert!(RationalTime::new(1, 3) < RationalTime::new(34, 100));
+    assert!(RationalTime::new(-1, 2).is_negative());
+  }
+
+  #[test]
+  fn frames_and_timestamps() {
+    // Floors, also for negative times
+    assert_eq!(RationalTime::new(99, 100).to_frames(1, 1), 0);
+    assert_eq!(RationalTime::new(-1, 100).to_frames(25, 1), -1);
+    assert_eq!(RationalTime::from_frames(7, 30000, 1001).to_frames(30000, 1001), 7);
+    let time_base = RationalTime::new(1, 90000);
+    assert_eq!(RationalTime::from_pts(180000, time_base), RationalTime::from_secs(2));
+    assert_eq!(RationalTime::new(1, 3).to_pts(time_base), 30000);
+    assert_eq!(RationalTime::new(1, 7).to_pts(RationalTime::new(1, 1000)), 142);
+    assert_eq!(RationalTime::new(1, 4).fraction_between(RationalTime::ZERO, RationalTime::new(1, 2)), 0.5);
+    assert_eq!(RationalTime::from_secs(2).fraction_between(RationalTime::ZERO, RationalTime::from_secs(1)), 1.);
+  }
+
+  #[test]
+  fn parses_seconds_units_and_clock_times() {
+    assert_eq!(parse("90"), RationalTime::from_secs(90));
+    assert_eq!(parse("1.25"), RationalTime::new(5, 4));
+    assert_eq!(parse(".5"), RationalTime::new(1, 2));
+    assert_eq!(parse(" -2.5 "), RationalTime::new(-5, 2));
+    assert_eq!(parse("1h2m3.5s"), RationalTime::new(7447, 2));
+    assert_eq!(parse("1m30s"), RationalTime::from_secs(90));
+    assert_eq!(parse("2min"), RationalTime::from_secs(120));
+    assert_eq!(parse("250ms"), RationalTime::new(1, 4));
+    assert_eq!(parse("1:30"), RationalTime::from_secs(90));
+    assert_eq!(parse("01:02:03.5"), RationalTime::new(7447, 2));
+    // Exact, unlike floats
+    assert_eq!(parse("0.1") + parse("0.2"), parse("0.3"));
+  }
+
+  #[test]
+  fn rejects_invalid_times() {
+    for s in ["", "-", ".", "1.2.3", "abc", "1x", "5 s", "1:2:3:4", ":30", "1::30", "1:-30", "1e3", "0.1234567890123456789"] {
+      assert_eq!(s.parse::<RationalTime>(), Err(ParseTimeError(s.to_string())), "{:?}", s);
+    }
+  }
+}
@@ -0,0 +1,239 @@
+use std::fmt;
+
+use crate::{time::ParseTimeError, RationalTime};
+
+/// A frame of a stream with a fixed frame rate, displayed as SMPTE timecode `HH:MM:SS:FF`.
+///
+/// Drop-frame timecode (`HH:MM:SS;FF`) skips the labels of the first two frames of every minute
+/// except every tenth one, four at 59.94 fps, so that the labels of 29.97 fps stay in sync with the
+/// wall clock. Only the labels are skipped, frames are never dropped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Timecode {
+  frame: i64,
+  rate: (i64, i64),
+  drop_frame: bool,
+}
+
+/// Hours, minutes, seconds and frames of a timecode label
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TimecodeLabel {
+  pub hours: i64,
+  pub minutes: i64,
+  pub seconds: i64,
+  pub frames: i64,
+}
+
+
+/// Whether drop-frame timecode exists for `rate`, i.e. 29.97 fps and its multiples
+pub fn is_drop_frame_rate(rate: (i64, i64)) -> bool {
+  let (num, den) = rate;
+  den == 1001 && num > 0 && num % 30000 == 0
+}
+
+/// Frames per second of the labels, e.g. 30 for 29.97 fps
+pub fn nominal_fps(rate: (i64, i64)) -> i64 {
+  let (num, den) = rate;
+  ((num + den / 2) / den).max(1)
+}
+
+
+impl Timecode {
+  /// Frame `frame` of a stream with `rate` frames per second as `(numerator, denominator)`.
+  /// `drop_frame` is ignored for rates without drop-frame timecode.
+  pub fn new(frame: i64, rate: (i64, i64), drop_frame: bool) -> Self {
+    assert!(rate.0 > 0 && rate.1 > 0, "Timecode with frame rate {}/{}", rate.0, rate.1);
+    Self { frame, rate, drop_frame: drop_frame && is_drop_frame_rate(rate) }
+  }
+
+  /// The frame containing `time`
+  pub fn from_time(time: RationalTime, rate: (i64, i64), drop_frame: bool) -> Self {
+    Self::new(time.to_frames(rate.0, rate.1), rate, drop_frame)
+  }
+
+  /// Start of the frame
+  pub fn to_time(&self) -> RationalTime {
+    RationalTime::from_frames(self.frame, self.rate.0, self.rate.1)
+  }
+
+  pub fn frame(&self) -> i64 { self.frame }
+  pub fn rate(&self) -> (i64, i64) { self.rate }
+  pub fn is_drop_frame(&self) -> bool { self.drop_frame }
+
+  /// Labels skipped at the start of a minute
+  fn dropped_per_minute(&self) -> i64 {
+    if self.drop_frame { nominal_fps(self.rate) / 15 } else { 0 }
+  }
+
+  /// Label of the frame. Negative frames are labeled like their absolute value.
+  pub fn label(&self) -> TimecodeLabel {
+    let fps = nominal_fps(self.rate);
+    let drop = self.dropped_per_minute();
+    let mut frame = self.frame.abs();
+    if drop > 0 {
+      let per_minute = fps * 60 - drop;
+      let per_ten_minutes = fps * 600 - drop * 9;
+      let (tens, rest) = (frame / per_ten_minutes, frame % per_ten_minutes);
+      frame += drop * 9 * tens;
+      if rest >= drop {
+        frame += drop * ((rest - drop) / per_minute);
+      }
+    }
+    TimecodeLabel {
+      hours: frame / (fps * 3600),
+      minutes: frame / (fps * 60) % 60,
+      seconds: frame / fps % 60,
+      frames: frame % fps,
+    }
+  }
+
+  /// Frame with the label `label`, `None` if drop-frame timecode skips it or a field is out of range
+  pub fn from_label(label: TimecodeLabel, rate: (i64, i64), drop_frame: bool) -> Option<Self> {
+    let mut res = Self::new(0, rate, drop_frame);
+    let fps = nominal_fps(rate);
+    let drop = res.dropped_per_minute();
+    let TimecodeLabel { hours, minutes, seconds, frames } = label;
+    if hours < 0 || !(0..60).contains(&minutes) || !(0..60).contains(&seconds) || !(0..fps).contains(&frames) {
+      return None;
+    }
+    if seconds == 0 && frames < drop && minutes % 10 != 0 {
+      return None;
+    }
+    let total_minutes = hours * 60 + minutes;
+    res.frame = fps * (total_minutes * 60 + seconds) + frames - drop * (total_minutes - total_minutes / 10);
+    Some(res)
+  }
+
+  /// Parses `HH:MM:SS:FF`, or `HH:MM:SS;FF` for drop-frame timecode. `.` and `,` also mark
+  /// drop-frame timecode, a leading `-` negates it.
+  pub fn parse(s: &str, rate: (i64, i64)) -> Result<Self, ParseTimeError> {
+    let err = || ParseTimeError(s.to_string());
+    let trimmed = s.trim();
+    let (negative, rest) = match trimmed.strip_prefix('-') {
+      Some(rest) => (true, rest),
+      None => (false, trimmed),
+    };
+    let drop_frame = rest.contains([';', '.', ',']);
+    let fields: Vec<i64> = rest.split([':', ';', '.', ','])
+      .map(|field| if !field.is_empty() && field.chars().all(|c| c.is_ascii_digit()) { field.parse().ok() } else { None })
+      .collect::<Option<_>>()
+      .ok_or_else(err)?;
+    let label = match fields[..] {
+      [hours, minutes, seconds, frames] => TimecodeLabel { hours, minutes, seconds, frames },
+      _ => return Err(err()),
+    };
+    if drop_frame && !is_drop_frame_rate(rate) {
+      return Err(err());
+    }
+    let res = Self::from_label(label, rate, drop_frame).ok_or_else(err)?;
+    Ok(if negative { Self { frame: -res.frame, ..res } } else { res })
+  }
+}
+
+impl fmt::Display for Timecode {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let TimecodeLabel { hours, minutes, seconds, frames } = self.label();
+    let sign = if self.frame < 0 { "-" } else { "" };
+    let separator = if self.drop_frame { ';' } else { ':' };
+    let width = nominal_fps(self.rate).saturating_sub(1).to_string().len().max(2);
+    write!(f, "{}{:02}:{:02}:{:02}{}{:0width$}", sign, hours, minutes, seconds, separator, frames, width = width)
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const NTSC: (i64, i64) = (30000, 1001);
+  const NTSC_60: (i64, i64) = (60000, 1001);
+
+  fn label(hours: i64, minutes: i64, seconds: i64, frames: i64) -> TimecodeLabel {
+    TimecodeLabel { hours, minutes, seconds, frames }
+  }
+
+  /// `frame` is labeled `text` and both convert back
+  fn assert_round_trip(frame: i64, rate: (i64, i64), text: &str) {
+    let timecode = Timecode::new(frame, rate, true);
+    assert_eq!(timecode.to_string(), text, "frame {}", frame);
+    assert_eq!(Timecode::parse(text, rate), Ok(timecode));
+    assert_eq!(Timecode::from_label(timecode.label(), rate, true), Some(timecode));
+  }
+
+  #[test]
+  fn drop_frame_labels_at_29_97() {
+    assert_round_trip(0, NTSC, "00:00:00;00");
+    assert_round_trip(1799, NTSC, "00:00:59;29");
+    assert_round_trip(1800, NTSC, "00:01:00;02");
+    assert_round_trip(3597, NTSC, "00:01:59;29");
+    assert_round_trip(3598, NTSC, "00:02:00;02");
+    // Every tenth minute keeps its first labels
+    assert_round_trip(17981, NTSC, "00:09:59;29");
+    assert_round_trip(17982, NTSC, "00:10:00;00");
+    assert_round_trip(17983, NTSC, "00:10:00;01");
+    assert_round_trip(19782, NTSC, "00:11:00;02");
+    // One hour of labels is 3.6 s short of an hour of frames
+    assert_round_trip(107892, NTSC, "01:00:00;00");
+    assert_eq!(Timecode::new(1800, NTSC, true).label(), label(0, 1, 0, 2));
+  }
+
+  #[test]
+  fn drop_frame_labels_at_59_94() {
+    assert_round_trip(3599, NTSC_60, "00:00:59;59");
+    assert_round_trip(3600, NTSC_60, "00:01:00;04");
+    assert_round_trip(35963, NTSC_60, "00:09:59;59");
+    assert_round_trip(35964, NTSC_60, "00:10:00;00");
+    assert_round_trip(215784, NTSC_60, "01:00:00;00");
+  }
+
+  #[test]
+  fn skipped_labels_are_rejected() {
+    for frames in 0..2 {
+      assert_eq!(Timecode::from_label(label(0, 1, 0, frames), NTSC, true), None);
+      assert!(Timecode::parse(&format!("00:01:00;{:02}", frames), NTSC).is_err());
+    }
+    for frames in 0..4 {
+      assert!(Timecode::parse(&format!("00:11:00;{:02}", frames), NTSC_60).is_err());
+    }
+    assert!(Timecode::parse("00:01:00;02", NTSC).is_ok());
+    assert!(Timecode::parse("00:10:00;00", NTSC).is_ok());
+    // Without drop-frame every label exists
+    assert_eq!(Timecode::parse("00:01:00:00", NTSC).map(|tc| tc.frame()), Ok(1800));
+  }
+
+  #[test]
+  fn labels_count_every_frame() {
+    for rate in [NTSC, NTSC_60] {
+      let mut prev = Timecode::new(0, rate, true).label();
+      for frame in 1..nominal_fps(rate) * 60 * 11 {
+        let timecode = Timecode::new(frame, rate, true);
+        let next = timecode.label();
+        assert!((next.minutes, next.seconds, next.frames) > (prev.minutes, prev.seconds, prev.frames));
+        assert_eq!(Timecode::from_label(next, rate, true), Some(timecode));
+        prev = next;
+      }
+    }
+  }
+
+  #[test]
+  fn parse_accepts_separators_and_signs() {
+    assert_eq!(Timecode::parse("00:01:00.02", NTSC), Timecode::parse("00:01:00;02", NTSC));
+    assert_eq!(Timecode::parse("00:01:00,02", NTSC), Timecode::parse("00:01:00;02", NTSC));
+    assert_eq!(Timecode::parse(" -00:01:00;02 ", NTSC).map(|tc| tc.frame()), Ok(-1800));
+    assert_eq!(Timecode::new(-1800, NTSC, true).to_string(), "-00:01:00;02");
+    // Drop-frame only exists for 29.97 fps and its multiples
+    assert!(Timecode::parse("00:01:00;02", (25, 1)).is_err());
+    assert!(!Timecode::new(1800, (25, 1), true).is_drop_frame());
+    for invalid in ["", "00:01:00", "00:60:00:00", "00:00:00:30", "00:00:00:+1", "aa:00:00:00"] {
+      assert!(Timecode::parse(invalid, NTSC).is_err(), "{:?}", invalid);
+    }
+  }
+
+  #[test]
+  fn non_drop_frame_labels() {
+    let timecode = Timecode::new(90_000, (25, 1), false);
+    assert_eq!(timecode.to_string(), "01:00:00:00");
+    assert_eq!(Timecode::new(119, (120, 1), false).to_string(), "00:00:00:119");
+    assert_eq!(Timecode::from_time(RationalTime::new(3, 2), (25, 1), false).frame(), 37);
+    assert_eq!(Timecode::new(37, (25, 1), false).to_time(), RationalTime::new(37, 25));
+  }
+}
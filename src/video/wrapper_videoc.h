@@ -1,3 +1,4 @@
 #include "libvideoc/include/VideoStream.h"
 #include "libvideoc/include/DecodingDecision.h"
 
+#include "libvideoc/include/VideoEncoder.h"
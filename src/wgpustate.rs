@@ -4,8 +4,16 @@ use wgpu::{Device, Queue, Surface, TextureViewDescriptor, CommandEncoderDescript
 use std::{ops::FnOnce, num::{NonZeroU64, NonZeroU32}};
 
 pub mod util;
+pub mod compositor;
+pub mod effect_pipeline;
+pub mod layers;
+pub mod offscreen;
 mod texture_atlas;
 pub use texture_atlas::TextureAtlas;
+pub use compositor::Compositor;
+pub use effect_pipeline::EffectRenderer;
+pub use layers::TimelineLayers;
+pub use offscreen::OffscreenRenderer;
 
 pub struct WgpuState {
   device: Device,
@@ -14,10 +22,16 @@ pub struct WgpuState {
   surface_config: SurfaceConfiguration,
   egui_textures: HashMap<TextureId, (wgpu::Texture, wgpu::BindGroup)>,
   pub user_textures: TextureAtlas,
+  pub compositor: Option<Compositor>,
+  effect_renderer: Option<EffectRenderer>,
+  /// Textures of the program viewer's layers, see `timeline_layers`
+  timeline_layers: TimelineLayers,
 
   window_size_bind_group_layout: wgpu::BindGroupLayout,
   window_size_bind_group: Option<wgpu::BindGroup>,
   surface_update_pipeline: wgpu::RenderPipeline,
+  /// Surface pipeline for the compositor's target, see `Compositor::viewer_bind_group`
+  composite_viewer_pipeline: wgpu::RenderPipeline,
   surface_update_binding_layout: wgpu::BindGroupLayout,
   surface_scale: f32,
 }
@@ -78,10 +92,63 @@ pub fn epaint_vertex_buffer_description<'a>() -> wgpu::VertexBufferLayout<'a> {
 
 
 
+/// Layout of the bind groups of all user textures: the texture and a filtering sampler. The
+/// compositor and the effect renderer sample textures through it.
+pub fn texture_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+  device.create_bind_group_layout(
+    &wgpu::BindGroupLayoutDescriptor {
+      label: Some("surface_textures_bind_group_layout"),
+      entries: &[
+        wgpu::BindGroupLayoutEntry {
+          binding: 0,
+          visibility: wgpu::ShaderStages::FRAGMENT,
+          ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false
+          },
+          count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+          binding: 1,
+          visibility: wgpu::ShaderStages::FRAGMENT,
+          ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+          count: None,
+        },
+      ],
+  })
+}
+
+/// Bind group of `texture` for `texture_bind_group_layout`
+pub fn texture_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, texture: &wgpu::Texture, filter: wgpu::FilterMode) -> wgpu::BindGroup {
+  device.create_bind_group(
+    &wgpu::BindGroupDescriptor {
+      label: Some("texture_bind_group"),
+      layout,
+      entries: &[
+        wgpu::BindGroupEntry {
+          binding: 0,
+          resource: wgpu::BindingResource::TextureView(
+            &texture.create_view(&wgpu::TextureViewDescriptor::default())),
+        },
+        wgpu::BindGroupEntry {
+          binding: 1,
+          resource: wgpu::BindingResource::Sampler(
+            &device.create_sampler(&wgpu::SamplerDescriptor {
+              mag_filter: filter,
+              min_filter: filter,
+              ..wgpu::SamplerDescriptor::default()
+          })),
+        },
+      ],
+  })
+}
+
+
 impl WgpuState {
   pub fn new(window: &Window, surface_scale: f32) -> Option<Self> {
     let (device, queue, surface, surface_config) = Self::setup_wgpu(window)?;
-    let (surface_update_pipeline, surface_update_binding_layout, window_size_bind_group_layout) =
+    let (surface_update_pipeline, composite_viewer_pipeline, surface_update_binding_layout, window_size_bind_group_layout) =
       Self::create_surface_pipeline(&device, &surface_config);
 
     Some(Self {
@@ -91,10 +158,14 @@ impl WgpuState {
       surface_config,
       egui_textures: HashMap::default(),
       user_textures: TextureAtlas::default(),
+      compositor: None,
+      effect_renderer: None,
+      timeline_layers: TimelineLayers::default(),
 
       window_size_bind_group_layout,
       window_size_bind_group: None,
       surface_update_pipeline,
+      composite_viewer_pipeline,
       surface_update_binding_layout,
       surface_scale,
     })
@@ -118,6 +189,18 @@ impl WgpuState {
 
   pub fn get_surface_scale(&self) -> f32 { self.surface_scale }
 
+  pub fn device(&self) -> &Device { &self.device }
+
+  pub fn queue(&self) -> &Queue { &self.queue }
+
+  /// Creates the compositor with the project resolution or resizes it if it already exists
+  pub fn init_compositor(&mut self, width: u32, height: u32) {
+    match &mut self.compositor {
+      Some(compositor) => compositor.resize(&self.device, &self.surface_update_binding_layout, width, height),
+      None => self.compositor = Some(Compositor::new(&self.device, &self.surface_update_binding_layout, width, height)),
+    }
+  }
+
   fn setup_wgpu(window: &Window) -> Option<(Device, Queue, Surface, SurfaceConfiguration)> {
     let instance = wgpu::Instance::new(wgpu::Backends::all());
     let surface = unsafe { instance.create_surface(&window) };
@@ -161,8 +244,10 @@ impl WgpuState {
     Some((device, queue, surface, surface_config))
   }
 
+  /// Returns the pipeline for egui's meshes and the one for the compositor's target, which only
+  /// differ in their fragment shader, and the layouts of their bind groups
   fn create_surface_pipeline(device: &wgpu::Device, surface_config: &wgpu::SurfaceConfiguration)
-    -> (wgpu::RenderPipeline, wgpu::BindGroupLayout, wgpu::BindGroupLayout)
+    -> (wgpu::RenderPipeline, wgpu::RenderPipeline, wgpu::BindGroupLayout, wgpu::BindGroupLayout)
   {
     //shader
     let shader = device.create_shader_module(
@@ -172,28 +257,7 @@ impl WgpuState {
     });
 
     //Binding_layout
-    let texture_bind_group_layout = device.create_bind_group_layout(
-      &wgpu::BindGroupLayoutDescriptor {
-        label: Some("surface_textures_bind_group_layout"),
-        entries: &[
-          wgpu::BindGroupLayoutEntry {
-            binding: 0,
-            visibility: wgpu::ShaderStages::FRAGMENT,
-            ty: wgpu::BindingType::Texture {
-              sample_type: wgpu::TextureSampleType::Float { filterable: true },
-              view_dimension: wgpu::TextureViewDimension::D2,
-              multisampled: false
-            },
-            count: None,
-          },
-          wgpu::BindGroupLayoutEntry {
-            binding: 1,
-            visibility: wgpu::ShaderStages::FRAGMENT,
-            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-            count: None,
-          },
-        ],
-    });
+    let texture_bind_group_layout = texture_bind_group_layout(device);
 
     let window_size_bind_group_layout = WindowSize::get_bind_group_layout(&device);
 
@@ -208,9 +272,9 @@ impl WgpuState {
         push_constant_ranges: &[],
     });
 
-    let pipeline = device.create_render_pipeline(
+    let create_pipeline = |label: &str, fs_entry_point: &str| device.create_render_pipeline(
       &wgpu::RenderPipelineDescriptor {
-        label: Some("surface_update_pipeline"),
+        label: Some(label),
         layout: Some(&pipeline_layout),
         vertex: wgpu::VertexState {
           module: &shader,
@@ -234,7 +298,7 @@ impl WgpuState {
         },
         fragment: Some(wgpu::FragmentState {
           module: &shader,
-          entry_point: "main_fs",
+          entry_point: fs_entry_point,
           targets: &[Some(wgpu::ColorTargetState {
             format: surface_config.format,
             blend: Some(wgpu::BlendState {
@@ -254,7 +318,9 @@ impl WgpuState {
         }),
         multiview: None,
     });
-    (pipeline, texture_bind_group_layout, window_size_bind_group_layout)
+    let pipeline = create_pipeline("surface_update_pipeline", "main_fs");
+    let composite_viewer_pipeline = create_pipeline("composite_viewer_pipeline", "composite_fs");
+    (pipeline, composite_viewer_pipeline, texture_bind_group_layout, window_size_bind_group_layout)
   }
 
   fn new_surface_update_binding(&self, texture_id: &TextureId, texture: &wgpu::Texture, min_filter: wgpu::FilterMode, mag_filter: wgpu::FilterMode) -> wgpu::BindGroup {
@@ -413,18 +479,19 @@ impl WgpuState {
       //   },
       // };
       // let (_texture, bind_group) = self.textures.get(&mesh.texture_id)?;
-      let (vertices, indices, bind_group) = match primitive {
+      let (vertices, indices, bind_group, pipeline) = match primitive {
         Primitive::Mesh(mesh) => {
           let (_texture, bind_group) = self.egui_textures.get(&mesh.texture_id)?;
-          (mesh.vertices, mesh.indices, bind_group)
+          (mesh.vertices, mesh.indices, bind_group, &self.surface_update_pipeline)
         },
         Primitive::Callback(f) => {
           //TODO: The callback is backend dependent. Meaning since I directly use wgpu
           // to render egui, I have to define it myself.
           if let Some(callback) = f.callback.downcast_ref::<util::EscherWGPUCallbackFn>() {
             match callback {
-              util::EscherWGPUCallbackFn::RenderFrame(tex_id, render_frame) => {
-                // let (_texture, bind_group) = self.user_textures.get(&tex_id);
+              util::EscherWGPUCallbackFn::RenderComposite(layers) => {
+                let compositor = self.compositor.as_ref()?;
+                compositor.render(&self.device, encoder, layers, &self.user_textures)?;
                 let rect = f.rect;
                 let indices = vec![0, 1, 2, 2, 3, 0];
                 let vertices = vec![
@@ -433,32 +500,8 @@ impl WgpuState {
                   epaint::Vertex {pos: rect.right_bottom(), uv: pos2(1., 1.), color: epaint::Color32::WHITE},
                   epaint::Vertex {pos: rect.right_top(), uv: pos2(1., 0.), color: epaint::Color32::WHITE},
                 ];
-                let (texture, bind_group) = self.user_textures.get(tex_id)?;
-                if let Some(new_frame) = render_frame {
-                  // let (buffer, buffer_layout, copy_size) = match new_frame.pix_fmt() {
-                  //   escher_video::AVPixelFormat::AV_PIX_FMT_RGB24 => {
-                  //     let (stride, height) = (new_frame.linesize()[0], new_frame.height());
-                  //     let frame_dimensions = wgpu::Extent3d {
-                  //       width: new_frame.width() as _,
-                  //       height: height as _,
-                  //       depth_or_array_layers: 1
-                  //     };
-                  //     let (buffer, layout) = util::create_padded_copy_buffer_init(
-                  //       &self.device,
-                  //       None,
-                  //       new_frame.planes()[0],
-                  //       stride,
-                  //       height as _
-                  //     );
-                  //     (buffer, layout, frame_dimensions)
-                  //   }
-                  //   _ => todo!()
-                  // };
-                  // let copy_src_buffer = wgpu::ImageCopyBuffer { buffer: &buffer, layout: buffer_layout };
-                  // encoder.copy_buffer_to_texture(copy_src_buffer, texture.as_image_copy(), copy_size)
-                  self.queue.write_texture(texture.as_image_copy(), new_frame.planes()[0], new_frame.get_image_data_layout(), new_frame.get_image_size());
-                }
-                (vertices, indices, bind_group)
+                // The target is linear and premultiplied, `composite_fs` converts it for the surface
+                (vertices, indices, compositor.viewer_bind_group(), &self.composite_viewer_pipeline)
               },
             }
           } else {
@@ -502,7 +545,7 @@ impl WgpuState {
           depth_stencil_attachment: None,
       });
 
-      render_pass.set_pipeline(&self.surface_update_pipeline);
+      render_pass.set_pipeline(pipeline);
       if (clip_rect_bottom_right.0 < full_size.0 && clip_rect_bottom_right.1 <= full_size.1) || (clip_rect_bottom_right.0 <= full_size.0 && clip_rect_bottom_right.1 < full_size.1) {
         render_pass.set_scissor_rect(clip_rect_top_left.0, clip_rect_top_left.1, clip_rect_size.0, clip_rect_size.1);
         // eprintln!("scissor_rect: {:?}", (clip_rect_top_left.0, clip_rect_top_left.1, clip_rect_size.0, clip_rect_size.1));
@@ -610,6 +653,49 @@ impl WgpuState {
     self.surface.configure(&self.device, &self.surface_config);
   }
 
+  /// Creates a user texture which effects and the compositor can render into
+  pub fn new_render_texture(&mut self, width: u32, height: u32) -> usize {
+    let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+      label: Some("render_texture"),
+      size: wgpu::Extent3d { width: width.max(1), height: height.max(1), depth_or_array_layers: 1 },
+      mip_level_count: 1,
+      sample_count: 1,
+      dimension: wgpu::TextureDimension::D2,
+      format: compositor::COMPOSITE_FORMAT,
+      usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING
+        | wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::COPY_DST,
+    });
+    let bind_group = self.new_surface_update_binding(&TextureId::User(0), &texture, wgpu::FilterMode::Linear, wgpu::FilterMode::Linear);
+    self.user_textures.insert(texture, bind_group)
+  }
+
+  /// The layers of `timeline` at `time` for the compositor, built like the export's, see
+  /// `TimelineLayers`. `None` before `init_compositor`.
+  pub fn timeline_layers(&mut self, timeline: &crate::timeline::Timeline, time: crate::video::RationalTime, frames: &mut impl crate::clip::ClipFrames) -> Option<Vec<compositor::Layer>> {
+    let size = self.compositor.as_ref()?.size();
+    let effect_renderer = self.effect_renderer.get_or_insert_with(||
+      EffectRenderer::new(&self.device, &self.queue, &self.surface_update_binding_layout));
+    let target = layers::LayerTarget {
+      device: &self.device,
+      queue: &self.queue,
+      layout: &self.surface_update_binding_layout,
+      textures: &mut self.user_textures,
+      effect_renderer,
+    };
+    Some(self.timeline_layers.build(target, timeline, time, size, frames))
+  }
+
+  /// Applies `stack` to the user texture `input` and stores the result in the user texture
+  /// `output`, which has to be created with `new_render_texture`. The resulting texture is in
+  /// linear light, so a `compositor::Layer` showing it must not decode sRGB.
+  pub fn apply_effects(&mut self, input: usize, decode_srgb: bool, size: [u32; 2], stack: &crate::effects::EffectStack, output: usize) -> Option<()> {
+    let renderer = self.effect_renderer.get_or_insert_with(||
+      EffectRenderer::new(&self.device, &self.queue, &self.surface_update_binding_layout));
+    let (_, input_bind_group) = self.user_textures.get(&input)?;
+    let (output_texture, _) = self.user_textures.get(&output)?;
+    renderer.render_stack(&self.device, &self.queue, &self.surface_update_binding_layout, input_bind_group, decode_srgb, size, stack, output_texture)
+  }
+
   pub fn new_user_texture(&mut self, size: wgpu::Extent3d, format: wgpu::TextureFormat, data: &[u8]) -> usize {
     // let texture = self.device.create_texture_with_data(&self.queue, texture_descriptor, data);
     // let bind_group = self.device.create_bind_group(bind_group_descriptor);
@@ -0,0 +1,475 @@
+use std::num::NonZeroU64;
+
+use wgpu::util::DeviceExt;
+
+use super::TextureAtlas;
+
+
+/// Format of the offscreen target. Layers are blended in linear light, so 8 bit per channel isn't
+/// enough precision.
+pub const COMPOSITE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+  Normal,
+  Add,
+  Multiply,
+  Screen,
+}
+
+/// Part of the layer which is cut away at each edge. Values are fractions of the layer's size.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Crop {
+  pub left: f32,
+  pub top: f32,
+  pub right: f32,
+  pub bottom: f32,
+}
+
+/// Transform of a layer in the pixel space of the composite target. With the default transform a
+/// layer is centered and drawn with its native pixel size.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LayerTransform {
+  /// Offset of the layer's center from the target's center in pixels
+  pub position: [f32; 2],
+  pub scale: [f32; 2],
+  /// Clockwise rotation around the layer's center in radians
+  pub rotation: f32,
+  pub crop: Crop,
+}
+
+/// Reveals a layer along a direction in the space of the composite target, e.g. for wipes. The
+/// layer is fully hidden at `progress` 0 and fully visible at 1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WipeMask {
+  /// Clockwise angle of the direction the edge moves in, 0 means from left to right
+  pub angle: f32,
+  pub progress: f32,
+  /// Width of the edge as fraction of the target's size
+  pub softness: f32,
+}
+
+/// A single entry of the layer stack. The texture is a user texture of the `TextureAtlas`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Layer {
+  pub texture: usize,
+  pub size: [u32; 2],
+  pub transform: LayerTransform,
+  pub opacity: f32,
+  pub blend: BlendMode,
+  /// Whether the texture stores sRGB encoded values in a non-sRGB format (which is the case for
+  /// decoded video frames) and has to be converted to linear light first.
+  pub decode_srgb: bool,
+  pub mask: Option<WipeMask>,
+  /// Draws the layer's quad with this color (linear, unpremultiplied) instead of the texture
+  pub fill: Option<[f32; 4]>,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CompositeVertex {
+  pub pos: [f32; 2],
+  pub uv: [f32; 2],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LayerUniform {
+  pub uv_min: [f32; 2],
+  pub uv_max: [f32; 2],
+  pub opacity: f32,
+  pub decode_srgb: u32,
+  pub use_fill: u32,
+  pub mask_softness: f32,
+  /// Direction scaled by the inverse target size, threshold and whether the mask is enabled
+  pub mask: [f32; 4],
+  pub fill: [f32; 4],
+}
+
+/// Renders a stack of layers into an offscreen target of the project resolution. The program
+/// viewer samples the target through `viewer_bind_group` and the export reads it back with
+/// `read_target`.
+pub struct Compositor {
+  width: u32,
+  height: u32,
+  target: wgpu::Texture,
+  target_view: wgpu::TextureView,
+  viewer_bind_group: wgpu::BindGroup,
+  layer_bind_group_layout: wgpu::BindGroupLayout,
+  pipelines: [wgpu::RenderPipeline; 4],
+  pub clear_color: wgpu::Color,
+}
+
+
+impl BlendMode {
+  pub const ALL: [BlendMode; 4] = [BlendMode::Normal, BlendMode::Add, BlendMode::Multiply, BlendMode::Screen];
+
+  fn idx(&self) -> usize {
+    match self {
+      BlendMode::Normal => 0,
+      BlendMode::Add => 1,
+      BlendMode::Multiply => 2,
+      BlendMode::Screen => 3,
+    }
+  }
+
+  /// Blend states operating on premultiplied colors. `Multiply` is exact as long as the destination
+  /// is opaque.
+  pub fn blend_state(&self) -> wgpu::BlendState {
+    let alpha = wgpu::BlendComponent {
+      src_factor: wgpu::BlendFactor::One,
+      dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+      operation: wgpu::BlendOperation::Add
+    };
+    let (src_factor, dst_factor) = match self {
+      BlendMode::Normal => (wgpu::BlendFactor::One, wgpu::BlendFactor::OneMinusSrcAlpha),
+      BlendMode::Add => (wgpu::BlendFactor::One, wgpu::BlendFactor::One),
+      BlendMode::Multiply => (wgpu::BlendFactor::Dst, wgpu::BlendFactor::OneMinusSrcAlpha),
+      BlendMode::Screen => (wgpu::BlendFactor::One, wgpu::BlendFactor::OneMinusSrc),
+    };
+    wgpu::BlendState {
+      color: wgpu::BlendComponent { src_factor, dst_factor, operation: wgpu::BlendOperation::Add },
+      alpha,
+    }
+  }
+}
+
+impl Default for LayerTransform {
+  fn default() -> Self {
+    Self { position: [0., 0.], scale: [1., 1.], rotation: 0., crop: Crop::default() }
+  }
+}
+
+impl Layer {
+  pub fn new(texture: usize, size: [u32; 2]) -> Self {
+    Self {
+      texture,
+      size,
+      transform: LayerTransform::default(),
+      opacity: 1.,
+      blend: BlendMode::Normal,
+      decode_srgb: true,
+      mask: None,
+      fill: None,
+    }
+  }
+
+  /// Layer covering the whole target with a solid color. `texture` only has to exist in the atlas.
+  pub fn solid(texture: usize, target_size: [u32; 2], color: [f32; 4]) -> Self {
+    Self { fill: Some(color), ..Self::new(texture, target_size) }
+  }
+
+  /// Scales the layer uniformly such that it fits into a target of the given size
+  pub fn fit_into(mut self, target_size: [u32; 2]) -> Self {
+    let sx = target_size[0] as f32 / self.size[0].max(1) as f32;
+    let sy = target_size[1] as f32 / self.size[1].max(1) as f32;
+    let s = sx.min(sy);
+    self.transform.scale = [s, s];
+    self
+  }
+
+  /// Corners of the (cropped) layer in clip space of a target with the given size. Order is
+  /// left-top, left-bottom, right-bottom, right-top.
+  pub fn vertices(&self, target_size: [u32; 2]) -> [CompositeVertex; 4] {
+    let LayerTransform { position, scale, rotation, crop } = self.transform;
+    let (w, h) = (self.size[0] as f32, self.size[1] as f32);
+    let (x0, x1) = (-w/2. + crop.left*w, w/2. - crop.right*w);
+    let (y0, y1) = (-h/2. + crop.top*h, h/2. - crop.bottom*h);
+    let (sin, cos) = rotation.sin_cos();
+    let (tw, th) = (target_size[0] as f32, target_size[1] as f32);
+    let to_clip = |x: f32, y: f32| {
+      let (x, y) = (x*scale[0], y*scale[1]);
+      let (x, y) = (x*cos - y*sin, x*sin + y*cos);
+      let (x, y) = (x + tw/2. + position[0], y + th/2. + position[1]);
+      [x/tw*2. - 1., 1. - y/th*2.]
+    };
+    [
+      CompositeVertex { pos: to_clip(x0, y0), uv: [0., 0.] },
+      CompositeVertex { pos: to_clip(x0, y1), uv: [0., 1.] },
+      CompositeVertex { pos: to_clip(x1, y1), uv: [1., 1.] },
+      CompositeVertex { pos: to_clip(x1, y0), uv: [1., 0.] },
+    ]
+  }
+
+  pub fn uniform(&self, target_size: [u32; 2]) -> LayerUniform {
+    let crop = self.transform.crop;
+    let (mask, mask_softness) = match self.mask {
+      Some(WipeMask { angle, progress, softness }) => {
+        let (sin, cos) = angle.sin_cos();
+        let softness = softness.max(0.);
+        // Projection of the target onto the direction covers [0.5 - extent, 0.5 + extent]
+        let extent = (cos.abs() + sin.abs()) / 2.;
+        let edge = 0.5 - extent - softness/2. + progress.clamp(0., 1.)*(2.*extent + softness);
+        let threshold = edge - 0.5 + (cos + sin)/2.;
+        ([cos / target_size[0].max(1) as f32, sin / target_size[1].max(1) as f32, threshold, 1.], softness)
+      },
+      None => ([0.; 4], 0.),
+    };
+    LayerUniform {
+      uv_min: [crop.left, crop.top],
+      uv_max: [1. - crop.right, 1. - crop.bottom],
+      opacity: self.opacity.clamp(0., 1.),
+      decode_srgb: self.decode_srgb as _,
+      use_fill: self.fill.is_some() as _,
+      mask_softness,
+      mask,
+      fill: self.fill.unwrap_or_default(),
+    }
+  }
+}
+
+
+impl CompositeVertex {
+  pub fn vertex_buffer_description<'a>() -> wgpu::VertexBufferLayout<'a> {
+    use std::mem::size_of;
+    wgpu::VertexBufferLayout {
+      array_stride: size_of::<CompositeVertex>() as _,
+      step_mode: wgpu::VertexStepMode::Vertex,
+      attributes: &[
+        wgpu::VertexAttribute {
+          format: wgpu::VertexFormat::Float32x2,
+          offset: 0,
+          shader_location: 0,
+        },
+        wgpu::VertexAttribute {
+          format: wgpu::VertexFormat::Float32x2,
+          offset: size_of::<[f32;2]>() as _,
+          shader_location: 1,
+        },
+      ]
+    }
+  }
+}
+
+
+impl Compositor {
+  /// `texture_bind_group_layout` has to be the layout the bind groups of the `TextureAtlas` were
+  /// created with, see `WgpuState::create_surface_pipeline`.
+  pub fn new(device: &wgpu::Device, texture_bind_group_layout: &wgpu::BindGroupLayout, width: u32, height: u32) -> Self {
+    let layer_bind_group_layout = device.create_bind_group_layout(
+      &wgpu::BindGroupLayoutDescriptor {
+        label: Some("composite_layer_bind_group_layout"),
+        entries: &[
+          wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+              ty: wgpu::BufferBindingType::Uniform,
+              has_dynamic_offset: false,
+              min_binding_size: NonZeroU64::new(std::mem::size_of::<LayerUniform>() as _),
+            },
+            count: None,
+          },
+        ],
+    });
+    let pipelines = Self::create_composite_pipelines(device, texture_bind_group_layout, &layer_bind_group_layout);
+    let (target, target_view, viewer_bind_group) = Self::create_target(device, texture_bind_group_layout, width, height);
+
+    Self {
+      width,
+      height,
+      target,
+      target_view,
+      viewer_bind_group,
+      layer_bind_group_layout,
+      pipelines,
+      clear_color: wgpu::Color::BLACK,
+    }
+  }
+
+  fn create_target(device: &wgpu::Device, texture_bind_group_layout: &wgpu::BindGroupLayout, width: u32, height: u32)
+    -> (wgpu::Texture, wgpu::TextureView, wgpu::BindGroup)
+  {
+    let target = device.create_texture(&wgpu::TextureDescriptor {
+      label: Some("composite_target"),
+      size: wgpu::Extent3d { width: width.max(1), height: height.max(1), depth_or_array_layers: 1 },
+      mip_level_count: 1,
+      sample_count: 1,
+      dimension: wgpu::TextureDimension::D2,
+      format: COMPOSITE_FORMAT,
+      usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_SRC,
+    });
+    let target_view = target.create_view(&wgpu::TextureViewDescriptor::default());
+    let viewer_bind_group = device.create_bind_group(
+      &wgpu::BindGroupDescriptor {
+        label: Some("composite_viewer_bind_group"),
+        layout: texture_bind_group_layout,
+        entries: &[
+          wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::TextureView(&target_view),
+          },
+          wgpu::BindGroupEntry {
+            binding: 1,
+            resource: wgpu::BindingResource::Sampler(
+              &device.create_sampler(&wgpu::SamplerDescriptor {
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                ..wgpu::SamplerDescriptor::default()
+            })),
+          },
+        ],
+    });
+    (target, target_view, viewer_bind_group)
+  }
+
+  fn create_composite_pipelines(device: &wgpu::Device, texture_bind_group_layout: &wgpu::BindGroupLayout, layer_bind_group_layout: &wgpu::BindGroupLayout)
+    -> [wgpu::RenderPipeline; 4]
+  {
+    let shader = device.create_shader_module(
+      wgpu::ShaderModuleDescriptor {
+        label: Some("compositor.wgsl"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("../compositor.wgsl").into()),
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(
+      &wgpu::PipelineLayoutDescriptor {
+        label: Some("composite_pipeline_descriptor"),
+        bind_group_layouts: &[
+          texture_bind_group_layout,
+          layer_bind_group_layout,
+        ],
+        push_constant_ranges: &[],
+    });
+
+    BlendMode::ALL.map(|blend| device.create_render_pipeline(
+      &wgpu::RenderPipelineDescriptor {
+        label: Some(format!("composite_pipeline {:?}", blend).as_str()),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+          module: &shader,
+          entry_point: "main_vs",
+          buffers: &[ CompositeVertex::vertex_buffer_description() ]
+        },
+        primitive: wgpu::PrimitiveState {
+          topology: wgpu::PrimitiveTopology::TriangleList,
+          strip_index_format: None,
+          front_face: wgpu::FrontFace::Cw,
+          cull_mode: None,
+          unclipped_depth: false,
+          polygon_mode: wgpu::PolygonMode::Fill,
+          conservative: false
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        fragment: Some(wgpu::FragmentState {
+          module: &shader,
+          entry_point: "main_fs",
+          targets: &[Some(wgpu::ColorTargetState {
+            format: COMPOSITE_FORMAT,
+            blend: Some(blend.blend_state()),
+            write_mask: wgpu::ColorWrites::ALL,
+          })],
+        }),
+        multiview: None,
+    }))
+  }
+
+  pub fn size(&self) -> [u32; 2] { [self.width, self.height] }
+
+  pub fn target(&self) -> &wgpu::Texture { &self.target }
+
+  pub fn target_view(&self) -> &wgpu::TextureView { &self.target_view }
+
+  /// Bind group for sampling the target with the surface pipeline. The target is premultiplied
+  /// linear light, so it has to be drawn with the `composite_fs` entry point of egui.wgsl.
+  pub fn viewer_bind_group(&self) -> &wgpu::BindGroup { &self.viewer_bind_group }
+
+  /// Changes the project resolution. The content of the target is lost.
+  pub fn resize(&mut self, device: &wgpu::Device, texture_bind_group_layout: &wgpu::BindGroupLayout, width: u32, height: u32) {
+    if (width, height) == (self.width, self.height) {
+      return;
+    }
+    let (target, target_view, viewer_bind_group) = Self::create_target(device, texture_bind_group_layout, width, height);
+    std::mem::replace(&mut self.target, target).destroy();
+    self.target_view = target_view;
+    self.viewer_bind_group = viewer_bind_group;
+    self.width = width;
+    self.height = height;
+  }
+
+  /// Records the composition of `layers` into the target. Layers are drawn bottom to top, i.e. the
+  /// first layer is the lowest one. Returns `None` if a layer's texture is not in `textures`.
+  pub fn render(&self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, layers: &[Layer], textures: &TextureAtlas) -> Option<()> {
+    let target_size = self.size();
+    let indices: [u32; 6] = [0, 1, 2, 2, 3, 0];
+    let index_buffer = device.create_buffer_init(
+      &wgpu::util::BufferInitDescriptor {
+        label: Some("composite_index_buffer"),
+        contents: bytemuck::cast_slice(&indices),
+        usage: wgpu::BufferUsages::INDEX,
+    });
+
+    let mut draws = Vec::with_capacity(layers.len());
+    for layer in layers {
+      if layer.opacity <= 0. {
+        continue;
+      }
+      let (_texture, texture_bind_group) = textures.get(&layer.texture)?;
+      let vertex_buffer = device.create_buffer_init(
+        &wgpu::util::BufferInitDescriptor {
+          label: Some("composite_vertex_buffer"),
+          contents: bytemuck::cast_slice(&layer.vertices(target_size)),
+          usage: wgpu::BufferUsages::VERTEX,
+      });
+      let uniform_buffer = device.create_buffer_init(
+        &wgpu::util::BufferInitDescriptor {
+          label: Some("composite_layer_buffer"),
+          contents: bytemuck::bytes_of(&layer.uniform(target_size)),
+          usage: wgpu::BufferUsages::UNIFORM,
+      });
+      let layer_bind_group = device.create_bind_group(
+        &wgpu::BindGroupDescriptor {
+          label: Some("composite_layer_bind_group"),
+          layout: &self.layer_bind_group_layout,
+          entries: &[
+            wgpu::BindGroupEntry {
+              binding: 0,
+              resource: uniform_buffer.as_entire_binding(),
+            },
+          ],
+      });
+      draws.push((layer.blend, texture_bind_group, vertex_buffer, layer_bind_group));
+    }
+
+    let mut render_pass = encoder.begin_render_pass(
+      &wgpu::RenderPassDescriptor {
+        label: Some("composite_render_pass"),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+          view: &self.target_view,
+          resolve_target: None,
+          ops: wgpu::Operations { load: wgpu::LoadOp::Clear(self.clear_color), store: true }
+        })],
+        depth_stencil_attachment: None,
+    });
+    render_pass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+    for (blend, texture_bind_group, vertex_buffer, layer_bind_group) in draws.iter() {
+      render_pass.set_pipeline(&self.pipelines[blend.idx()]);
+      render_pass.set_bind_group(0, texture_bind_group, &[]);
+      render_pass.set_bind_group(1, layer_bind_group, &[]);
+      render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+      render_pass.draw_indexed(0..indices.len() as _, 0, 0..1);
+    }
+    Some(())
+  }
+
+  /// Composes `layers` and submits the work immediately. Used when there is no surface frame to
+  /// piggyback on, e.g. during export.
+  pub fn render_and_submit(&self, device: &wgpu::Device, queue: &wgpu::Queue, layers: &[Layer], textures: &TextureAtlas) -> Option<()> {
+    let mut encoder = device.create_command_encoder(
+      &wgpu::CommandEncoderDescriptor { label: Some("composite_encoder") }
+    );
+    self.render(device, &mut encoder, layers, textures)?;
+    queue.submit(std::iter::once(encoder.finish()));
+    Some(())
+  }
+
+  /// Copies the target back to the CPU. The result are unpremultiplied, linear RGBA values in row
+  /// major order without padding. Blocks until the GPU is done.
+  pub fn read_target(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Vec<[f32; 4]>> {
+    let premultiplied = super::util::read_texture_rgba16f(device, queue, &self.target, self.width, self.height)?;
+    Some(premultiplied.into_iter()
+      .map(|[r, g, b, a]| if a > 0. { [r/a, g/a, b/a, a] } else { [0., 0., 0., 0.] })
+      .collect())
+  }
+}
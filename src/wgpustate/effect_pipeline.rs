@@ -0,0 +1,374 @@
+use std::{collections::HashMap, num::{NonZeroU32, NonZeroU64}};
+
+use wgpu::util::DeviceExt;
+
+use crate::effects::{self, EffectShader, EffectStack, CpuImage, lut::Lut3d};
+use super::{compositor::COMPOSITE_FORMAT, util};
+
+
+/// Uniform of every effect pass, see `effects/prelude.wgsl`
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct EffectFrame {
+  pub size: [f32; 2],
+  pub decode_srgb: u32,
+  pub pass_idx: u32,
+  pub params: effects::PackedParams,
+}
+
+const PASSTHROUGH: EffectShader = EffectShader {
+  id: "passthrough",
+  fragment: "fn effect(uv: vec2<f32>) -> vec4<f32> { return load(uv); }",
+  passes: 1,
+};
+
+struct PingPong {
+  size: [u32; 2],
+  textures: [(wgpu::Texture, wgpu::BindGroup); 2],
+}
+
+/// Runs an `EffectStack` on the GPU. Every pass renders a fullscreen triangle from one
+/// intermediate `Rgba16Float` texture into the other. Effects without a shader are applied on the
+/// CPU in between, which requires a round trip to the CPU.
+pub struct EffectRenderer {
+  frame_bind_group_layout: wgpu::BindGroupLayout,
+  lut_bind_group_layout: wgpu::BindGroupLayout,
+  pipeline_layout: wgpu::PipelineLayout,
+  pipelines: HashMap<&'static str, wgpu::RenderPipeline>,
+  identity_lut: wgpu::BindGroup,
+  ping_pong: Option<PingPong>,
+}
+
+
+impl EffectRenderer {
+  pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, texture_bind_group_layout: &wgpu::BindGroupLayout) -> Self {
+    let frame_bind_group_layout = device.create_bind_group_layout(
+      &wgpu::BindGroupLayoutDescriptor {
+        label: Some("effect_frame_bind_group_layout"),
+        entries: &[
+          wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+              ty: wgpu::BufferBindingType::Uniform,
+              has_dynamic_offset: false,
+              min_binding_size: NonZeroU64::new(std::mem::size_of::<EffectFrame>() as _),
+            },
+            count: None,
+          },
+        ],
+    });
+
+    let lut_bind_group_layout = device.create_bind_group_layout(
+      &wgpu::BindGroupLayoutDescriptor {
+        label: Some("effect_lut_bind_group_layout"),
+        entries: &[
+          wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+              sample_type: wgpu::TextureSampleType::Float { filterable: true },
+              view_dimension: wgpu::TextureViewDimension::D3,
+              multisampled: false
+            },
+            count: None,
+          },
+          wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+          },
+        ],
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(
+      &wgpu::PipelineLayoutDescriptor {
+        label: Some("effect_pipeline_descriptor"),
+        bind_group_layouts: &[
+          texture_bind_group_layout,
+          &frame_bind_group_layout,
+          &lut_bind_group_layout,
+        ],
+        push_constant_ranges: &[],
+    });
+
+    let mut res = Self {
+      identity_lut: Self::create_lut_bind_group(device, queue, &lut_bind_group_layout, &Lut3d::identity(2)),
+      frame_bind_group_layout,
+      lut_bind_group_layout,
+      pipeline_layout,
+      pipelines: HashMap::new(),
+      ping_pong: None,
+    };
+    res.prepare_shader(device, &PASSTHROUGH);
+    res
+  }
+
+  /// Compiles the pipeline for `shader` unless it is already cached
+  pub fn prepare_shader(&mut self, device: &wgpu::Device, shader: &EffectShader) {
+    if self.pipelines.contains_key(shader.id) {
+      return;
+    }
+    let module = device.create_shader_module(
+      wgpu::ShaderModuleDescriptor {
+        label: Some(shader.id),
+        source: wgpu::ShaderSource::Wgsl(shader.source().into()),
+    });
+    let pipeline = device.create_render_pipeline(
+      &wgpu::RenderPipelineDescriptor {
+        label: Some(format!("effect_pipeline {}", shader.id).as_str()),
+        layout: Some(&self.pipeline_layout),
+        vertex: wgpu::VertexState {
+          module: &module,
+          entry_point: "main_vs",
+          buffers: &[]
+        },
+        primitive: wgpu::PrimitiveState {
+          topology: wgpu::PrimitiveTopology::TriangleList,
+          strip_index_format: None,
+          front_face: wgpu::FrontFace::Cw,
+          cull_mode: None,
+          unclipped_depth: false,
+          polygon_mode: wgpu::PolygonMode::Fill,
+          conservative: false
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        fragment: Some(wgpu::FragmentState {
+          module: &module,
+          entry_point: "main_fs",
+          targets: &[Some(wgpu::ColorTargetState {
+            format: COMPOSITE_FORMAT,
+            blend: None,
+            write_mask: wgpu::ColorWrites::ALL,
+          })],
+        }),
+        multiview: None,
+    });
+    self.pipelines.insert(shader.id, pipeline);
+  }
+
+  fn create_lut_bind_group(device: &wgpu::Device, queue: &wgpu::Queue, layout: &wgpu::BindGroupLayout, lut: &Lut3d) -> wgpu::BindGroup {
+    let n = lut.size as u32;
+    let data: Vec<u16> = lut.data.iter()
+      .flat_map(|[r, g, b]| [*r, *g, *b, 1.])
+      .map(util::f32_to_f16)
+      .collect();
+    let texture = device.create_texture_with_data(
+      queue,
+      &wgpu::TextureDescriptor {
+        label: Some("effect_lut"),
+        size: wgpu::Extent3d { width: n, height: n, depth_or_array_layers: n },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D3,
+        format: wgpu::TextureFormat::Rgba16Float,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+      },
+      bytemuck::cast_slice(&data)
+    );
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    device.create_bind_group(
+      &wgpu::BindGroupDescriptor {
+        label: Some("effect_lut_bind_group"),
+        layout,
+        entries: &[
+          wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::TextureView(&view),
+          },
+          wgpu::BindGroupEntry {
+            binding: 1,
+            resource: wgpu::BindingResource::Sampler(
+              &device.create_sampler(&wgpu::SamplerDescriptor {
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                ..wgpu::SamplerDescriptor::default()
+            })),
+          },
+        ],
+    })
+  }
+
+  fn ensure_ping_pong(&mut self, device: &wgpu::Device, texture_bind_group_layout: &wgpu::BindGroupLayout, size: [u32; 2]) {
+    if matches!(&self.ping_pong, Some(pp) if pp.size == size) {
+      return;
+    }
+    let textures = [0, 1].map(|i| {
+      let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(format!("effect_ping_pong {}", i).as_str()),
+        size: wgpu::Extent3d { width: size[0], height: size[1], depth_or_array_layers: 1 },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: COMPOSITE_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING
+          | wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::COPY_DST,
+      });
+      let bind_group = new_texture_bind_group(device, texture_bind_group_layout, &texture);
+      (texture, bind_group)
+    });
+    if let Some(old) = std::mem::replace(&mut self.ping_pong, Some(PingPong { size, textures })) {
+      for (texture, _) in old.textures {
+        texture.destroy();
+      }
+    }
+  }
+
+  fn run_pass(&self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, shader_id: &str, input: &wgpu::BindGroup, frame: EffectFrame, lut: &wgpu::BindGroup, output: &wgpu::TextureView) -> Option<()> {
+    let pipeline = self.pipelines.get(shader_id)?;
+    let frame_buffer = device.create_buffer_init(
+      &wgpu::util::BufferInitDescriptor {
+        label: Some("effect_frame_buffer"),
+        contents: bytemuck::bytes_of(&frame),
+        usage: wgpu::BufferUsages::UNIFORM,
+    });
+    let frame_bind_group = device.create_bind_group(
+      &wgpu::BindGroupDescriptor {
+        label: Some("effect_frame_bind_group"),
+        layout: &self.frame_bind_group_layout,
+        entries: &[
+          wgpu::BindGroupEntry {
+            binding: 0,
+            resource: frame_buffer.as_entire_binding(),
+          },
+        ],
+    });
+    let mut render_pass = encoder.begin_render_pass(
+      &wgpu::RenderPassDescriptor {
+        label: Some(format!("effect_render_pass {}", shader_id).as_str()),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+          view: output,
+          resolve_target: None,
+          ops: wgpu::Operations { load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT), store: true }
+        })],
+        depth_stencil_attachment: None,
+    });
+    render_pass.set_pipeline(pipeline);
+    render_pass.set_bind_group(0, input, &[]);
+    render_pass.set_bind_group(1, &frame_bind_group, &[]);
+    render_pass.set_bind_group(2, lut, &[]);
+    render_pass.draw(0..3, 0..1);
+    Some(())
+  }
+
+  /// Applies `stack` to the texture bound by `input` and writes the result, linear and
+  /// unpremultiplied, to `output`. `output` needs the format `COMPOSITE_FORMAT`, the size `size`
+  /// and `COPY_DST` as usage. `decode_srgb` has the same meaning as for `compositor::Layer`.
+  pub fn render_stack(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, texture_bind_group_layout: &wgpu::BindGroupLayout,
+    input: &wgpu::BindGroup, decode_srgb: bool, size: [u32; 2], stack: &EffectStack, output: &wgpu::Texture) -> Option<()>
+  {
+    self.ensure_ping_pong(device, texture_bind_group_layout, size);
+    for e in stack.iter_enabled() {
+      if let Some(shader) = e.effect.shader() {
+        self.prepare_shader(device, &shader);
+      }
+    }
+    let ping_pong = self.ping_pong.as_ref()?;
+    let views = [0, 1].map(|i| ping_pong.textures[i].0.create_view(&wgpu::TextureViewDescriptor::default()));
+
+    let mut encoder = device.create_command_encoder(
+      &wgpu::CommandEncoderDescriptor { label: Some("effect_encoder") }
+    );
+    // Index of the ping pong texture holding the latest result, `None` while it's still `input`
+    let mut current: Option<usize> = None;
+    let next_frame = |params, pass_idx, current: Option<usize>| EffectFrame {
+      size: [size[0] as _, size[1] as _],
+      decode_srgb: (current.is_none() && decode_srgb) as _,
+      pass_idx,
+      params,
+    };
+
+    for e in stack.iter_enabled() {
+      match e.effect.shader() {
+        Some(shader) => {
+          let lut_store;
+          let lut = match e.effect.lut() {
+            Some(lut) => {
+              lut_store = Self::create_lut_bind_group(device, queue, &self.lut_bind_group_layout, lut);
+              &lut_store
+            },
+            None => &self.identity_lut,
+          };
+          let params = e.effect.pack_params(&e.values);
+          for pass_idx in 0..shader.passes.max(1) {
+            let target = current.map_or(0, |i| 1 - i);
+            let src = current.map_or(input, |i| &ping_pong.textures[i].1);
+            self.run_pass(device, &mut encoder, shader.id, src, next_frame(params, pass_idx, current), lut, &views[target])?;
+            current = Some(target);
+          }
+        },
+        None => {
+          // CPU fallback: flush, read back, apply and upload again
+          if current.is_none() {
+            self.run_pass(device, &mut encoder, PASSTHROUGH.id, input, next_frame(effects::pack_params_default(&[]), 0, current), &self.identity_lut, &views[0])?;
+            current = Some(0);
+          }
+          let i = current?;
+          queue.submit(std::iter::once(std::mem::replace(&mut encoder, device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor { label: Some("effect_encoder") }
+          )).finish()));
+          let pixels = util::read_texture_rgba16f(device, queue, &ping_pong.textures[i].0, size[0], size[1])?;
+          let mut image = CpuImage { width: size[0] as _, height: size[1] as _, pixels };
+          e.effect.apply_cpu(&e.values, &mut image);
+          write_texture_rgba16f(queue, &ping_pong.textures[i].0, &image);
+        },
+      }
+    }
+
+    let src = match current {
+      Some(i) => &ping_pong.textures[i].0,
+      None => {
+        self.run_pass(device, &mut encoder, PASSTHROUGH.id, input, next_frame(effects::pack_params_default(&[]), 0, current), &self.identity_lut, &views[0])?;
+        &ping_pong.textures[0].0
+      }
+    };
+    encoder.copy_texture_to_texture(
+      src.as_image_copy(),
+      output.as_image_copy(),
+      wgpu::Extent3d { width: size[0], height: size[1], depth_or_array_layers: 1 }
+    );
+    queue.submit(std::iter::once(encoder.finish()));
+    Some(())
+  }
+}
+
+
+fn new_texture_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, texture: &wgpu::Texture) -> wgpu::BindGroup {
+  device.create_bind_group(
+    &wgpu::BindGroupDescriptor {
+      label: None,
+      layout,
+      entries: &[
+        wgpu::BindGroupEntry {
+          binding: 0,
+          resource: wgpu::BindingResource::TextureView(
+            &texture.create_view(&wgpu::TextureViewDescriptor::default())),
+        },
+        wgpu::BindGroupEntry {
+          binding: 1,
+          resource: wgpu::BindingResource::Sampler(
+            &device.create_sampler(&wgpu::SamplerDescriptor::default()
+          )),
+        },
+      ],
+  })
+}
+
+fn write_texture_rgba16f(queue: &wgpu::Queue, texture: &wgpu::Texture, image: &CpuImage) {
+  let data: Vec<u16> = image.pixels.iter()
+    .flat_map(|px| *px)
+    .map(util::f32_to_f16)
+    .collect();
+  queue.write_texture(
+    texture.as_image_copy(),
+    bytemuck::cast_slice(&data),
+    wgpu::ImageDataLayout {
+      offset: 0,
+      bytes_per_row: NonZeroU32::new(image.width as u32 * 8),
+      rows_per_image: NonZeroU32::new(image.height as _),
+    },
+    wgpu::Extent3d { width: image.width as _, height: image.height as _, depth_or_array_layers: 1 }
+  );
+}
@@ -0,0 +1,112 @@
+use crate::{
+  clip::ClipFrames,
+  timeline::Timeline,
+  video::RationalTime,
+};
+use super::{compositor::{self, Layer}, EffectRenderer, TextureAtlas};
+
+/// A texture which is reused between frames as long as the size fits
+struct PooledTexture {
+  id: usize,
+  size: [u32; 2],
+}
+
+/// What `TimelineLayers` uploads to and renders with. `layout` is the bind group layout of the
+/// compositor's textures.
+pub struct LayerTarget<'a> {
+  pub device: &'a wgpu::Device,
+  pub queue: &'a wgpu::Queue,
+  pub layout: &'a wgpu::BindGroupLayout,
+  pub textures: &'a mut TextureAtlas,
+  pub effect_renderer: &'a mut EffectRenderer,
+}
+
+/// Turns the visible clips of a timeline frame into compositor layers: uploads their frames and
+/// applies their effects. The viewer and the export both build their layers with it, so both
+/// show the same frame.
+#[derive(Default)]
+pub struct TimelineLayers {
+  /// Decoded frames, one texture per layer of the current frame
+  uploads: Vec<PooledTexture>,
+  /// Results of effect stacks, one texture per layer of the current frame
+  effect_outputs: Vec<PooledTexture>,
+  /// Any texture for layers that only use a fill color
+  fill_texture: Option<usize>,
+}
+
+fn create_texture(target: &mut LayerTarget, size: [u32; 2], format: wgpu::TextureFormat) -> usize {
+  let texture = target.device.create_texture(&wgpu::TextureDescriptor {
+    label: Some("layer_texture"),
+    size: wgpu::Extent3d { width: size[0].max(1), height: size[1].max(1), depth_or_array_layers: 1 },
+    mip_level_count: 1,
+    sample_count: 1,
+    dimension: wgpu::TextureDimension::D2,
+    format,
+    usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING
+      | wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::COPY_DST,
+  });
+  let bind_group = super::texture_bind_group(target.device, target.layout, &texture, wgpu::FilterMode::Linear);
+  target.textures.insert(texture, bind_group)
+}
+
+impl TimelineLayers {
+  /// Texture `idx` of a pool with the given size, created or replaced if necessary
+  fn pooled(&mut self, target: &mut LayerTarget, effect_output: bool, idx: usize, size: [u32; 2]) -> usize {
+    let pool = if effect_output { &self.effect_outputs } else { &self.uploads };
+    match pool.get(idx) {
+      Some(t) if t.size == size => return t.id,
+      _ => {},
+    }
+    let format = if effect_output { compositor::COMPOSITE_FORMAT } else { wgpu::TextureFormat::Rgba8Unorm };
+    let id = create_texture(target, size, format);
+    let pool = if effect_output { &mut self.effect_outputs } else { &mut self.uploads };
+    if let Some(old) = pool.get_mut(idx) {
+      let old = std::mem::replace(old, PooledTexture { id, size });
+      if let Some((texture, _)) = target.textures.remove(&old.id) {
+        texture.destroy();
+      }
+    } else {
+      pool.push(PooledTexture { id, size });
+    }
+    id
+  }
+
+  /// The layers of `timeline` at `time` for a compositor of `size`, bottom first
+  pub fn build(&mut self, mut target: LayerTarget, timeline: &Timeline, time: RationalTime, size: [u32; 2], frames: &mut impl ClipFrames) -> Vec<Layer> {
+    let fill_texture = match self.fill_texture {
+      Some(id) => id,
+      None => *self.fill_texture.insert(create_texture(&mut target, [1, 1], wgpu::TextureFormat::Rgba8Unorm)),
+    };
+    let mut next_layer = 0;
+    timeline.layers_at(time, size, fill_texture, |clip, source_time| {
+      let frame = frames.frame(clip, source_time)?;
+      let frame_size = [frame.width() as u32, frame.height() as u32];
+      let upload = self.pooled(&mut target, false, next_layer, frame_size);
+      let stack = clip.effects.evaluate(source_time - clip.source_in);
+      let output = if stack.is_empty() { None } else { Some(self.pooled(&mut target, true, next_layer, frame_size)) };
+
+      let (texture, bind_group) = target.textures.get(&upload)?;
+      target.queue.write_texture(
+        texture.as_image_copy(),
+        frame.planes()[0],
+        wgpu::ImageDataLayout {
+          offset: 0,
+          bytes_per_row: std::num::NonZeroU32::new(frame.linesize()[0] as _),
+          rows_per_image: std::num::NonZeroU32::new(frame_size[1]),
+        },
+        wgpu::Extent3d { width: frame_size[0], height: frame_size[1], depth_or_array_layers: 1 },
+      );
+      let layer = match output {
+        None => Layer::new(upload, frame_size),
+        Some(output) => {
+          let (output_texture, _) = target.textures.get(&output)?;
+          target.effect_renderer.render_stack(target.device, target.queue, target.layout, bind_group, true,
+            frame_size, &stack, output_texture)?;
+          Layer { decode_srgb: false, ..Layer::new(output, frame_size) }
+        },
+      };
+      next_layer += 1;
+      Some(layer.fit_into(size))
+    })
+  }
+}
@@ -0,0 +1,76 @@
+use crate::{
+  clip::ClipFrames,
+  effects::CpuImage,
+  timeline::Timeline,
+  video::RationalTime,
+};
+use super::{layers::{LayerTarget, TimelineLayers}, Compositor, EffectRenderer, TextureAtlas};
+
+/// Renders timeline frames without a window, e.g. for the export. It has its own device, so it can
+/// be created on any thread.
+pub struct OffscreenRenderer {
+  device: wgpu::Device,
+  queue: wgpu::Queue,
+  texture_bind_group_layout: wgpu::BindGroupLayout,
+  textures: TextureAtlas,
+  compositor: Compositor,
+  effect_renderer: EffectRenderer,
+  size: [u32; 2],
+  layers: TimelineLayers,
+}
+
+
+impl OffscreenRenderer {
+  /// `None` if there is no adapter
+  pub fn new(width: u32, height: u32) -> Option<Self> {
+    let instance = wgpu::Instance::new(wgpu::Backends::all());
+    let adapter = pollster::block_on(instance.request_adapter(
+      &wgpu::RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::default(),
+        force_fallback_adapter: false,
+        compatible_surface: None,
+    }))?;
+    let (device, queue) = pollster::block_on(adapter.request_device(
+      &wgpu::DeviceDescriptor {
+        label: Some("offscreen_device"),
+        features: wgpu::Features::default(),
+        limits: wgpu::Limits::default(),
+      },
+      None,
+    )).ok()?;
+
+    let texture_bind_group_layout = super::texture_bind_group_layout(&device);
+    let compositor = Compositor::new(&device, &texture_bind_group_layout, width, height);
+    let effect_renderer = EffectRenderer::new(&device, &queue, &texture_bind_group_layout);
+    Some(Self {
+      device,
+      queue,
+      texture_bind_group_layout,
+      textures: TextureAtlas::default(),
+      compositor,
+      effect_renderer,
+      size: [width, height],
+      layers: TimelineLayers::default(),
+    })
+  }
+
+  pub fn size(&self) -> [u32; 2] {
+    self.size
+  }
+
+  /// Renders the timeline at `time` and returns the frame as 8 bit sRGBA rows without padding
+  pub fn render_timeline(&mut self, timeline: &Timeline, time: RationalTime, frames: &mut impl ClipFrames) -> Option<Vec<u8>> {
+    let target = LayerTarget {
+      device: &self.device,
+      queue: &self.queue,
+      layout: &self.texture_bind_group_layout,
+      textures: &mut self.textures,
+      effect_renderer: &mut self.effect_renderer,
+    };
+    let size = self.size;
+    let layers = self.layers.build(target, timeline, time, size, frames);
+    self.compositor.render_and_submit(&self.device, &self.queue, &layers, &self.textures)?;
+    let pixels = self.compositor.read_target(&self.device, &self.queue)?;
+    Some(CpuImage { width: size[0] as _, height: size[1] as _, pixels }.to_srgba8())
+  }
+}
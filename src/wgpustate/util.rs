@@ -2,12 +2,9 @@ use std::num::NonZeroU32;
 
 use wgpu::util::DeviceExt;
 
-use crate::video;
-
-
-pub enum EscherWGPUCallbackFn<'a> {
-  // The callback function for Video Rendering in FFMPEG <-> WGPU
-  RenderFrame(usize, Option<video::RawImageRef<'a>>),
+pub enum EscherWGPUCallbackFn {
+  // Composes the layers with the `Compositor` and shows its target (program viewer)
+  RenderComposite(Vec<super::compositor::Layer>),
 }
 
 /// Creates a new buffer with `COPY_SRC` as usage and pads the data to align with `COPY_BYTES_PER_ROW_ALIGNMENT`
@@ -42,3 +39,87 @@ pub fn create_padded_copy_buffer_init(device: &wgpu::Device, label: Option<&str>
   (buffer, buffer_layout)
 }
 
+
+/// Converts an IEEE 754 half precision float, e.g. read back from a `Rgba16Float` texture
+pub fn f16_to_f32(bits: u16) -> f32 {
+  let sign = if bits & 0x8000 != 0 { -1. } else { 1. };
+  let exponent = ((bits >> 10) & 0x1f) as i32;
+  let mantissa = (bits & 0x3ff) as f32;
+  match exponent {
+    0 => sign * mantissa * 2f32.powi(-24),
+    0x1f if mantissa == 0. => sign * f32::INFINITY,
+    0x1f => f32::NAN,
+    _ => sign * (1. + mantissa / 1024.) * 2f32.powi(exponent - 15),
+  }
+}
+
+/// Inverse of `f16_to_f32`. Rounds to nearest, overflows to infinity.
+pub fn f32_to_f16(x: f32) -> u16 {
+  let bits = x.to_bits();
+  let sign = ((bits >> 16) & 0x8000) as u16;
+  if x.is_nan() {
+    return sign | 0x7e00;
+  }
+  let abs = x.abs();
+  if abs >= 65520. {
+    sign | 0x7c00
+  } else if abs < 2f32.powi(-14) {
+    sign | (abs * 2f32.powi(24)).round() as u16
+  } else {
+    let exponent = abs.log2().floor() as i32;
+    let mantissa = ((abs / 2f32.powi(exponent) - 1.) * 1024.).round() as u32;
+    // Rounding the mantissa up may carry into the exponent
+    let (exponent, mantissa) = if mantissa == 1024 { (exponent + 1, 0) } else { (exponent, mantissa) };
+    sign | (((exponent + 15) as u16) << 10) | mantissa as u16
+  }
+}
+
+/// Copies a 2D `Rgba16Float` texture back to the CPU and blocks until the GPU is done. The texture
+/// needs `COPY_SRC` as usage. The result is in row major order without padding.
+pub fn read_texture_rgba16f(device: &wgpu::Device, queue: &wgpu::Queue, texture: &wgpu::Texture, width: u32, height: u32) -> Option<Vec<[f32; 4]>> {
+  let bytes_per_pixel = 8;
+  let unpadded_bytes_per_row = width as usize * bytes_per_pixel;
+  let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as usize;
+  let padded_bytes_per_row = (unpadded_bytes_per_row + align - 1) / align * align;
+
+  let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+    label: Some("rgba16f_readback_buffer"),
+    size: (padded_bytes_per_row * height as usize) as _,
+    usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+    mapped_at_creation: false,
+  });
+  let mut encoder = device.create_command_encoder(
+    &wgpu::CommandEncoderDescriptor { label: Some("rgba16f_readback_encoder") }
+  );
+  encoder.copy_texture_to_buffer(
+    texture.as_image_copy(),
+    wgpu::ImageCopyBuffer {
+      buffer: &buffer,
+      layout: wgpu::ImageDataLayout {
+        offset: 0,
+        bytes_per_row: NonZeroU32::new(padded_bytes_per_row as _),
+        rows_per_image: NonZeroU32::new(height),
+      },
+    },
+    wgpu::Extent3d { width, height, depth_or_array_layers: 1 }
+  );
+  queue.submit(std::iter::once(encoder.finish()));
+
+  let slice = buffer.slice(..);
+  let (tx, rx) = std::sync::mpsc::channel();
+  slice.map_async(wgpu::MapMode::Read, move |res| tx.send(res).unwrap_or_default());
+  device.poll(wgpu::Maintain::Wait);
+  rx.recv().ok()?.ok()?;
+
+  let mut res = Vec::with_capacity((width * height) as _);
+  {
+    let data = slice.get_mapped_range();
+    for row in data.chunks_exact(padded_bytes_per_row) {
+      for px in row[..unpadded_bytes_per_row].chunks_exact(bytes_per_pixel) {
+        res.push([0, 1, 2, 3].map(|i| f16_to_f32(u16::from_le_bytes([px[2*i], px[2*i + 1]]))));
+      }
+    }
+  }
+  buffer.unmap();
+  Some(res)
+}